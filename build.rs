@@ -1,5 +1,17 @@
+use std::{env, fs, path::PathBuf};
+
 fn main() {
-     println!("cargo::rerun-if-changed=./script.ld");
-     println!("cargo::rerun-if-changed=./src/init.S");
-     println!("cargo::rustc-link-arg-bins=-T./script.ld");
+    println!("cargo::rerun-if-changed=./script.ld");
+    println!("cargo::rerun-if-changed=./link");
+    println!("cargo::rerun-if-changed=./src/init.S");
+
+    // RAM of the board feature, see src/board.rs
+    let board = match env::var_os("CARGO_FEATURE_RASPI3B") {
+        Some(_) => "raspi3b",
+        None => "raspi4b",
+    };
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy(format!("link/{board}.ld"), out.join("memory.ld")).unwrap();
+    println!("cargo::rustc-link-search={}", out.display());
+    println!("cargo::rustc-link-arg-bins=-T./script.ld");
 }
@@ -1,65 +1,79 @@
 /// BCM2835
-use crate::aux::peripherals::MiniUart;
-use crate::utils::bits::*;
+use crate::aux::peripherals::{AuxSpi, MiniUart};
+use crate::registers::*;
 use core::option::Option;
-use core::ptr::read_volatile;
+
+register_bitfields! {u32,
+    pub AUX_IRQ [
+        MINI_UART OFFSET(0) NUMBITS(1) [],
+        SPI1 OFFSET(1) NUMBITS(1) [],
+        SPI2 OFFSET(2) NUMBITS(1) [],
+    ],
+    pub AUX_ENABLES [
+        MINI_UART OFFSET(0) NUMBITS(1) [],
+        SPI1 OFFSET(1) NUMBITS(1) [],
+        SPI2 OFFSET(2) NUMBITS(1) [],
+    ],
+}
 
 #[repr(C)]
 pub struct AUXRegisters {
-    irq: u32,    /* 0x00 AUX_IRQ Auxiliary Interrupt status */
-    enable: u32, /* 0x04 AUX_ENABLES Auxiliary enables */
+    irq: ReadOnly<u32, AUX_IRQ::Register>, /* 0x00 AUX_IRQ Auxiliary Interrupt status */
+    enable: ReadWrite<u32, AUX_ENABLES::Register>, /* 0x04 AUX_ENABLES Auxiliary enables */
+}
+
+register_layout! {
+    AUXRegisters @ 0x00 .. 0x08 {
+        0x00 => irq,
+        0x04 => enable,
+    }
 }
 
 impl AUXRegisters {
-    const BASE: usize = 0xfe215000;
+    const BASE: usize = crate::board::peripheral(0x21_5000);
     pub const fn new() -> *mut AUXRegisters {
-        Self::BASE as *mut AUXRegisters
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut AUXRegisters {
+        base as *mut AUXRegisters
     }
 
     pub fn enable_mini_uart(&mut self) {
-        register_volatile_or(u32_register_mut!(self.enable), BITu32!(0));
+        self.enable.modify(AUX_ENABLES::MINI_UART::SET);
     }
 
-    /* UNSUPPORTED */
     pub fn enable_spi(&mut self) {
-        register_volatile_or(u32_register_mut!(self.enable), BITu32!(1));
+        self.enable.modify(AUX_ENABLES::SPI1::SET);
     }
 
-    /* UNSUPPORTED */
     pub fn enable_spi2(&mut self) {
-        register_volatile_or(u32_register_mut!(self.enable), BITu32!(2));
+        self.enable.modify(AUX_ENABLES::SPI2::SET);
     }
 
-    /* UNSUPPORTED */
     pub fn disable_mini_uart(&mut self) {
-        register_volatile_and(u32_register_mut!(self.enable), !BITu32!(0));
+        self.enable.modify(AUX_ENABLES::MINI_UART::CLEAR);
     }
 
-    /* UNSUPPORTED */
     pub fn disable_spi(&mut self) {
-        register_volatile_and(u32_register_mut!(self.enable), !BITu32!(1));
+        self.enable.modify(AUX_ENABLES::SPI1::CLEAR);
     }
 
-    /* UNSUPPORTED */
     pub fn disable_spi2(&mut self) {
-        register_volatile_and(u32_register_mut!(self.enable), !BITu32!(2));
+        self.enable.modify(AUX_ENABLES::SPI2::CLEAR);
     }
 
     pub fn irq_pending_mini_uart(&self) -> bool {
-        let irq = unsafe { read_volatile(u32_register!(self.irq)) };
-        (irq & BITu32!(0)) > 0
+        self.irq.is_set(AUX_IRQ::MINI_UART)
     }
 
-    /* UNSUPPORTED */
     pub fn irq_pending_spi(&self) -> bool {
-        let irq = unsafe { read_volatile(u32_register!(self.irq)) };
-        (irq & BITu32!(1)) > 0
+        self.irq.is_set(AUX_IRQ::SPI1)
     }
 
-    /* UNSUPPORTED */
     pub fn irq_pending_spi2(&self) -> bool {
-        let irq = unsafe { read_volatile(u32_register!(self.irq)) };
-        (irq & BITu32!(2)) > 0
+        self.irq.is_set(AUX_IRQ::SPI2)
     }
 }
 
@@ -67,6 +81,8 @@ pub static mut AUX_PERIPHERALS: AUXPeripherals = AUXPeripherals::new();
 pub struct AUXPeripherals {
     registers: Option<*mut AUXRegisters>,
     mini_uart: Option<*mut MiniUart>,
+    spi1: Option<*mut AuxSpi>,
+    spi2: Option<*mut AuxSpi>,
 }
 
 impl AUXPeripherals {
@@ -74,6 +90,8 @@ impl AUXPeripherals {
         let periph: AUXPeripherals = AUXPeripherals {
             registers: Some(AUXRegisters::new()),
             mini_uart: Some(MiniUart::new()),
+            spi1: Some(AuxSpi::spi1()),
+            spi2: Some(AuxSpi::spi2()),
         };
         periph
     }
@@ -95,30 +113,123 @@ impl AUXPeripherals {
     pub fn return_aux_registers(&mut self, aux_registers: *mut AUXRegisters) {
         self.registers.replace(aux_registers);
     }
+
+    pub fn take_spi1(&mut self) -> *mut AuxSpi {
+        let p = self.spi1.take();
+        p.unwrap()
+    }
+
+    pub fn take_spi2(&mut self) -> *mut AuxSpi {
+        let p = self.spi2.take();
+        p.unwrap()
+    }
+
+    pub fn return_spi1(&mut self, spi: *mut AuxSpi) {
+        self.spi1.replace(spi);
+    }
+
+    pub fn return_spi2(&mut self, spi: *mut AuxSpi) {
+        self.spi2.replace(spi);
+    }
 }
 
 pub mod peripherals {
-    use crate::utils::bits::*;
-    use core::ptr::{read_volatile, write_volatile};
+    use crate::registers::*;
+    pub use crate::spi::SPIMode;
+
+    use crate::board::CORE_CLOCK;
+
+    register_bitfields! {u32,
+        pub AUX_MU_IO [
+            DATA OFFSET(0) NUMBITS(8) [],
+        ],
+        pub AUX_MU_IER [
+            RX_INTERRUPT OFFSET(0) NUMBITS(1) [],
+            TX_INTERRUPT OFFSET(1) NUMBITS(1) [],
+        ],
+        pub AUX_MU_IIR [
+            /// cleared while an interrupt is pending
+            PENDING_N OFFSET(0) NUMBITS(1) [],
+            /// reads
+            INTERRUPT_ID OFFSET(1) NUMBITS(2) [
+                None = 0,
+                TransmitEmpty = 1,
+                ReceiveValid = 2,
+            ],
+            /// writes
+            FIFO_CLEAR OFFSET(1) NUMBITS(2) [
+                Receive = 1,
+                Transmit = 2,
+                Both = 3,
+            ],
+        ],
+        pub AUX_MU_LCR [
+            DATA_SIZE OFFSET(0) NUMBITS(2) [
+                SevenBit = 0,
+                EightBit = 3,
+            ],
+            BREAK OFFSET(6) NUMBITS(1) [],
+            DLAB OFFSET(7) NUMBITS(1) [],
+        ],
+        pub AUX_MU_MCR [
+            RTS OFFSET(1) NUMBITS(1) [],
+        ],
+        pub AUX_MU_LSR [
+            DATA_READY OFFSET(0) NUMBITS(1) [],
+            RX_OVERRUN OFFSET(1) NUMBITS(1) [],
+            TX_EMPTY OFFSET(5) NUMBITS(1) [],
+            TX_IDLE OFFSET(6) NUMBITS(1) [],
+        ],
+        pub AUX_MU_MSR [
+            CTS OFFSET(5) NUMBITS(1) [],
+        ],
+        pub AUX_MU_CNTL [
+            RX_ENABLE OFFSET(0) NUMBITS(1) [],
+            TX_ENABLE OFFSET(1) NUMBITS(1) [],
+            RTS_FLOW OFFSET(2) NUMBITS(1) [],
+            CTS_FLOW OFFSET(3) NUMBITS(1) [],
+            RTS_LEVEL OFFSET(4) NUMBITS(2) [
+                Free3 = 0,
+                Free2 = 1,
+                Free1 = 2,
+                Free4 = 3,
+            ],
+            RTS_ASSERT_LOW OFFSET(6) NUMBITS(1) [],
+            CTS_ASSERT_LOW OFFSET(7) NUMBITS(1) [],
+        ],
+        pub AUX_MU_STAT [
+            SYMBOL_AVAILABLE OFFSET(0) NUMBITS(1) [],
+            SPACE_AVAILABLE OFFSET(1) NUMBITS(1) [],
+            RX_IDLE OFFSET(2) NUMBITS(1) [],
+            TX_IDLE OFFSET(3) NUMBITS(1) [],
+            RX_OVERRUN OFFSET(4) NUMBITS(1) [],
+            TX_FULL OFFSET(5) NUMBITS(1) [],
+            RTS OFFSET(6) NUMBITS(1) [],
+            CTS OFFSET(7) NUMBITS(1) [],
+            TX_EMPTY OFFSET(8) NUMBITS(1) [],
+            TX_DONE OFFSET(9) NUMBITS(1) [],
+            RX_FIFO_LEVEL OFFSET(16) NUMBITS(4) [],
+            TX_FIFO_LEVEL OFFSET(24) NUMBITS(4) [],
+        ],
+        pub AUX_MU_BAUD [
+            BAUDRATE OFFSET(0) NUMBITS(16) [],
+        ],
+    }
 
+    /// registers QEMU lacks are listed in [`crate::platform::Feature`]
     #[repr(C)]
     pub struct MiniUart {
-        io: u32,  /* 0x40 AUX_MU_IO_REG Mini UART I/O Data */
-        ier: u32, /* 0x44 AUX_MU_IER_REG Mini UART Interrupt Enable */
-        iir: u32, /* 0x48 AUX_MU_IIR_REG Mini UART Interrupt Identify */
-        /* UNSUPPORTED in QEMU */
-        lcr: u32, /* 0x4c AUX_MU_LCR_REG Mini UART Line Control */
-        /* UNSUPPORTED in QEMU */
-        mcr: u32, /* 0x50 AUX_MU_MCR_REG Mini UART Modem Control */
-        lsr: u32, /* 0x54 AUX_MU_LSR_REG Mini UART Line Status */
-        /* UNSUPPORTED in QEMU */
-        msr: u32, /* 0x58 AUX_MU_MSR_REG Mini UART Modem Status */
-        /* UNSUPPORTED in QEMU */
-        scratch: u32, /* 0x5c AUX_MU_SCRATCH Mini UART Scratch */
-        cntl: u32,    /* 0x60 AUX_MU_CNTL_REG Mini UART Extra Control */
-        stat: u32,    /* 0x64 AUX_MU_STAT_REG Mini UART Extra Status */
-        /* UNSUPPORTED in QEMU */
-        baud: u32, /* 0x68 AUX_MU_BAUD_REG Mini UART Baudrate */
+        io: ReadWrite<u32, AUX_MU_IO::Register>, /* 0x40 AUX_MU_IO_REG Mini UART I/O Data */
+        ier: ReadWrite<u32, AUX_MU_IER::Register>, /* 0x44 AUX_MU_IER_REG Mini UART Interrupt Enable */
+        iir: ReadWrite<u32, AUX_MU_IIR::Register>, /* 0x48 AUX_MU_IIR_REG Mini UART Interrupt Identify */
+        lcr: ReadWrite<u32, AUX_MU_LCR::Register>, /* 0x4c AUX_MU_LCR_REG Mini UART Line Control */
+        mcr: ReadWrite<u32, AUX_MU_MCR::Register>, /* 0x50 AUX_MU_MCR_REG Mini UART Modem Control */
+        lsr: ReadOnly<u32, AUX_MU_LSR::Register>,  /* 0x54 AUX_MU_LSR_REG Mini UART Line Status */
+        msr: ReadOnly<u32, AUX_MU_MSR::Register>,  /* 0x58 AUX_MU_MSR_REG Mini UART Modem Status */
+        scratch: ReadWrite<u32>,                   /* 0x5c AUX_MU_SCRATCH Mini UART Scratch */
+        cntl: ReadWrite<u32, AUX_MU_CNTL::Register>, /* 0x60 AUX_MU_CNTL_REG Mini UART Extra Control */
+        stat: ReadOnly<u32, AUX_MU_STAT::Register>, /* 0x64 AUX_MU_STAT_REG Mini UART Extra Status */
+        baud: ReadWrite<u32, AUX_MU_BAUD::Register>, /* 0x68 AUX_MU_BAUD_REG Mini UART Baudrate */
     }
 
     #[derive(PartialEq, Eq, Clone, Copy)]
@@ -137,134 +248,700 @@ pub mod peripherals {
         Baud921600 = 921600,
     }
 
+    impl TryFrom<u32> for BaudRate {
+        type Error = u32;
+
+        fn try_from(value: u32) -> Result<Self, Self::Error> {
+            [
+                BaudRate::Baud476,
+                BaudRate::Baud1200,
+                BaudRate::Baud2400,
+                BaudRate::Baud4800,
+                BaudRate::Baud9600,
+                BaudRate::Baud19200,
+                BaudRate::Baud38400,
+                BaudRate::Baud57600,
+                BaudRate::Baud115200,
+                BaudRate::Baud230400,
+                BaudRate::Baud460800,
+                BaudRate::Baud921600,
+            ]
+            .into_iter()
+            .find(|rate| *rate as u32 == value)
+            .ok_or(value)
+        }
+    }
+
+    register_layout! {
+        MiniUart @ 0x40 .. 0x6c {
+            0x40 => io,
+            0x44 => ier,
+            0x48 => iir,
+            0x4c => lcr,
+            0x50 => mcr,
+            0x54 => lsr,
+            0x58 => msr,
+            0x5c => scratch,
+            0x60 => cntl,
+            0x64 => stat,
+            0x68 => baud,
+        }
+    }
+
     impl MiniUart {
-        const BASE: usize = 0xfe215040;
+        const BASE: usize = crate::board::peripheral(0x21_5040);
         pub const fn new() -> *mut Self {
-            Self::BASE as *mut Self
+            Self::at(Self::BASE)
+        }
+
+        /// register block placed at `base` instead of the peripheral address
+        pub const fn at(base: usize) -> *mut Self {
+            base as *mut Self
         }
 
         pub fn transmit(&mut self, byte: u32) {
-            unsafe {
-                write_volatile(u32_register_mut!(self.io), byte);
-            }
+            self.io.write(AUX_MU_IO::DATA.val(byte));
         }
 
         pub fn receive(&self) -> u32 {
-            unsafe { read_volatile(u32_register!(self.io)) }
+            self.io.read(AUX_MU_IO::DATA)
         }
 
         pub fn enable_receive_interrupt(&mut self) {
-            register_volatile_or(u32_register_mut!(self.ier), BITu32!(1));
+            self.ier.modify(AUX_MU_IER::RX_INTERRUPT::SET);
         }
 
         pub fn disable_receive_interrupt(&mut self) {
-            register_volatile_and(u32_register_mut!(self.ier), !BITu32!(1));
+            self.ier.modify(AUX_MU_IER::RX_INTERRUPT::CLEAR);
         }
 
         pub fn enable_transmit_interrupt(&mut self) {
-            register_volatile_or(u32_register_mut!(self.ier), BITu32!(0));
+            self.ier.modify(AUX_MU_IER::TX_INTERRUPT::SET);
         }
 
         pub fn disable_transmit_interrupt(&mut self) {
-            register_volatile_and(u32_register_mut!(self.ier), !BITu32!(0));
+            self.ier.modify(AUX_MU_IER::TX_INTERRUPT::CLEAR);
         }
 
         pub fn clear_receive_fifo(&mut self) {
-            register_volatile_or(u32_register_mut!(self.iir), BITu32!(1));
+            self.iir.write(AUX_MU_IIR::FIFO_CLEAR::Receive);
         }
 
         pub fn clear_transmit_fifo(&mut self) {
-            register_volatile_or(u32_register_mut!(self.iir), BITu32!(2));
+            self.iir.write(AUX_MU_IIR::FIFO_CLEAR::Transmit);
         }
 
-        pub fn interrupt_id(&self) -> u32 {
-            let value = unsafe { read_volatile(u32_register!(self.iir)) };
-            (value & (BITu32!(1) | BITu32!(2))) >> 1u32
+        pub fn interrupt_id(&self) -> Option<AUX_MU_IIR::INTERRUPT_ID::Value> {
+            self.iir.read_as_enum(AUX_MU_IIR::INTERRUPT_ID)
         }
 
         pub fn interrupt_pending(&self) -> bool {
-            unsafe { !((read_volatile(u32_register!(self.iir)) & BITu32!(0)) > 0) }
+            !self.iir.is_set(AUX_MU_IIR::PENDING_N)
         }
 
         pub fn set_8bit_mode(&mut self) {
-            register_volatile_or(u32_register_mut!(self.lcr), BITu32!(0));
+            self.lcr.modify(AUX_MU_LCR::DATA_SIZE::EightBit);
+        }
+
+        /// drive UART1_RTS low, telling the other end it may send
+        pub fn set_request_to_send(&mut self, asserted: bool) {
+            self.mcr.modify(AUX_MU_MCR::RTS.val(asserted as u32));
+        }
+
+        /// UART1_CTS is low, the other end is ready to receive
+        pub fn clear_to_send(&self) -> bool {
+            self.msr.is_set(AUX_MU_MSR::CTS)
         }
 
         pub fn set_7bit_mode(&mut self) {
-            register_volatile_and(u32_register_mut!(self.lcr), !BITu32!(0));
+            self.lcr.modify(AUX_MU_LCR::DATA_SIZE::SevenBit);
         }
 
         pub fn receive_overrun_clear(&mut self) {
-            unsafe {
-                let _ = read_volatile(u32_register!(self.lsr));
-            }
+            let _ = self.lsr.get();
         }
 
         pub fn transmitter_enable(&mut self) {
-            register_volatile_or(u32_register_mut!(self.cntl), BITu32!(1));
+            self.cntl.modify(AUX_MU_CNTL::TX_ENABLE::SET);
         }
 
         pub fn receiver_enable(&mut self) {
-            register_volatile_or(u32_register_mut!(self.cntl), BITu32!(0));
+            self.cntl.modify(AUX_MU_CNTL::RX_ENABLE::SET);
         }
 
         pub fn transmitter_disable(&mut self) {
-            register_volatile_and(u32_register_mut!(self.cntl), !BITu32!(1));
+            self.cntl.modify(AUX_MU_CNTL::TX_ENABLE::CLEAR);
         }
 
         pub fn receiver_disable(&mut self) {
-            register_volatile_and(u32_register_mut!(self.cntl), !BITu32!(0));
+            self.cntl.modify(AUX_MU_CNTL::RX_ENABLE::CLEAR);
         }
 
         pub fn receiver_symbol_avaliable(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(0)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::SYMBOL_AVAILABLE)
         }
 
         pub fn transmitter_space_avaliable(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(1)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::SPACE_AVAILABLE)
         }
 
         pub fn receiver_idle(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(2)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::RX_IDLE)
         }
 
         pub fn tranmitter_idle(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(3)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::TX_IDLE)
         }
 
         pub fn receive_overrun(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(4)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::RX_OVERRUN)
         }
 
         pub fn transmit_fifo_empty(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(8)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::TX_EMPTY)
         }
 
         pub fn transmitter_done(&self) -> bool {
-            unsafe { (read_volatile(u32_register!(self.stat)) & BITu32!(9)) > 0 }
+            self.stat.is_set(AUX_MU_STAT::TX_DONE)
         }
 
         pub fn receive_fifo_level(&self) -> u32 {
-            unsafe { (read_volatile(u32_register!(self.stat)) >> 16u32) & 0x7 }
+            self.stat.read(AUX_MU_STAT::RX_FIFO_LEVEL)
         }
 
         pub fn transmit_fifo_level(&self) -> u32 {
-            unsafe { (read_volatile(u32_register!(self.stat)) >> 24u32) & 0x7 }
+            self.stat.read(AUX_MU_STAT::TX_FIFO_LEVEL)
         }
 
         // well, that's a fun one
         // https://github.com/qemu/qemu/blob/d9a4282c4b690e45d25c2b933f318bb41eeb271d/hw/char/bcm2835_aux.c#L147
         pub fn set_baudrate(&mut self, baudrate: BaudRate) {
-            const UART_CLOCK: u32 = 250_000_000;
-            let baudrate_reg: u32 = (UART_CLOCK / (8 * baudrate as u32)) - 1;
-            unsafe {
-                write_volatile(u32_register_mut!(self.baud), baudrate_reg);
-            }
+            let baudrate_reg: u32 = (CORE_CLOCK / (8 * baudrate as u32)) - 1;
+            self.baud.write(AUX_MU_BAUD::BAUDRATE.val(baudrate_reg));
         }
 
         pub fn get_baudrate(&self) -> u32 {
-            const UART_CLOCK: u32 = 250_000_000;
-            unsafe { UART_CLOCK / (8 * (read_volatile(u32_register!(self.baud)) + 1)) }
+            CORE_CLOCK / (8 * (self.baud.read(AUX_MU_BAUD::BAUDRATE) + 1))
+        }
+    }
+    register_bitfields! {u32,
+        pub AUX_SPI_CNTL0 [
+            SHIFT_LENGTH OFFSET(0) NUMBITS(6) [],
+            MSB_FIRST_OUT OFFSET(6) NUMBITS(1) [],
+            INVERT_CLOCK OFFSET(7) NUMBITS(1) [],
+            OUT_RISING OFFSET(8) NUMBITS(1) [],
+            CLEAR_FIFOS OFFSET(9) NUMBITS(1) [],
+            IN_RISING OFFSET(10) NUMBITS(1) [],
+            ENABLE OFFSET(11) NUMBITS(1) [],
+            DOUT_HOLD OFFSET(12) NUMBITS(2) [
+                None = 0,
+                Hold1 = 1,
+                Hold4 = 2,
+                Hold7 = 3,
+            ],
+            /// shift length is taken from bits 28:24 of every FIFO entry
+            VARIABLE_WIDTH OFFSET(14) NUMBITS(1) [],
+            VARIABLE_CS OFFSET(15) NUMBITS(1) [],
+            POST_INPUT OFFSET(16) NUMBITS(1) [],
+            /// pattern driven on CS2-0 while a transfer is active, chip selects are active low
+            CHIP_SELECT OFFSET(17) NUMBITS(3) [
+                CS0 = 0b110,
+                CS1 = 0b101,
+                CS2 = 0b011,
+            ],
+            SPEED OFFSET(20) NUMBITS(12) [],
+        ],
+        pub AUX_SPI_CNTL1 [
+            KEEP_INPUT OFFSET(0) NUMBITS(1) [],
+            MSB_FIRST_IN OFFSET(1) NUMBITS(1) [],
+            DONE_IRQ OFFSET(6) NUMBITS(1) [],
+            TX_EMPTY_IRQ OFFSET(7) NUMBITS(1) [],
+            CS_HIGH_TIME OFFSET(8) NUMBITS(3) [],
+        ],
+        pub AUX_SPI_STAT [
+            BIT_COUNT OFFSET(0) NUMBITS(6) [],
+            BUSY OFFSET(6) NUMBITS(1) [],
+            RX_EMPTY OFFSET(7) NUMBITS(1) [],
+            RX_FULL OFFSET(8) NUMBITS(1) [],
+            TX_EMPTY OFFSET(9) NUMBITS(1) [],
+            TX_FULL OFFSET(10) NUMBITS(1) [],
+            RX_FIFO_LEVEL OFFSET(16) NUMBITS(8) [],
+            TX_FIFO_LEVEL OFFSET(24) NUMBITS(8) [],
+        ],
+        /// variable width FIFO entry
+        pub AUX_SPI_DATA [
+            DATA OFFSET(0) NUMBITS(24) [],
+            WIDTH OFFSET(24) NUMBITS(5) [],
+        ],
+    }
+
+    /// SPI1 and SPI2 share this layout, at 0x80 and 0xc0 of the AUX block
+    #[repr(C)]
+    pub struct AuxSpi {
+        cntl0: ReadWrite<u32, AUX_SPI_CNTL0::Register>, /* 0x80 AUX_SPI1_CNTL0_REG SPI1 Control register 0 */
+        cntl1: ReadWrite<u32, AUX_SPI_CNTL1::Register>, /* 0x84 AUX_SPI1_CNTL1_REG SPI1 Control register 1 */
+        stat: ReadOnly<u32, AUX_SPI_STAT::Register>,    /* 0x88 AUX_SPI1_STAT_REG SPI1 Status */
+        peek: ReadOnly<u32, AUX_SPI_DATA::Register>,    /* 0x8c AUX_SPI1_PEEK_REG SPI1 Peek */
+        padding0: [u8; 0x10],                           /* 0x90 padding */
+        io: [ReadWrite<u32, AUX_SPI_DATA::Register>; 4], /* 0xa0 AUX_SPI1_IO_REG SPI1 Data, CS is released after the entry */
+        txhold: [ReadWrite<u32, AUX_SPI_DATA::Register>; 4], /* 0xb0 AUX_SPI1_TXHOLD_REG SPI1 Extended Data, CS is kept asserted */
+    }
+
+    register_layout! {
+        AuxSpi @ 0x80 .. 0xc0 {
+            0x80 => cntl0,
+            0x84 => cntl1,
+            0x88 => stat,
+            0x8c => peek,
+            0x90 => padding0,
+            0xa0 => io,
+            0xb0 => txhold,
+        }
+    }
+
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    pub enum AuxSpiChipSelect {
+        CS0,
+        CS1,
+        CS2,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct AuxSpiConfig {
+        pub mode: SPIMode,
+        pub speed_hz: u32,
+        pub chip_select: AuxSpiChipSelect,
+        /// extra bit times CS stays high between transfers, 0-7
+        pub cs_high_time: u8,
+    }
+
+    impl AuxSpiConfig {
+        pub const fn new() -> AuxSpiConfig {
+            AuxSpiConfig {
+                mode: SPIMode::Mode0,
+                speed_hz: 1_000_000,
+                chip_select: AuxSpiChipSelect::CS0,
+                cs_high_time: 0,
+            }
+        }
+    }
+
+    impl Default for AuxSpiConfig {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl AuxSpi {
+        const SPI1_BASE: usize = crate::board::peripheral(0x21_5080);
+        const SPI2_BASE: usize = crate::board::peripheral(0x21_50c0);
+        const FIFO_DEPTH: usize = 4;
+        /// bytes packed into one variable width FIFO entry
+        const BYTES_PER_ENTRY: usize = 3;
+
+        pub const fn spi1() -> *mut Self {
+            Self::at(Self::SPI1_BASE)
+        }
+
+        pub const fn spi2() -> *mut Self {
+            Self::at(Self::SPI2_BASE)
+        }
+
+        /// register block placed at `base` instead of the peripheral address
+        pub const fn at(base: usize) -> *mut Self {
+            base as *mut Self
+        }
+
+        /// set up the master, the controller must already be enabled in `AUXRegisters`;
+        /// returns the achieved clock in Hz
+        pub fn configure(&mut self, config: &AuxSpiConfig) -> u32 {
+            let divider = CORE_CLOCK / (2 * config.speed_hz.max(1));
+            let speed = divider.saturating_sub(1).min(AUX_SPI_CNTL0::SPEED.mask);
+
+            // sample on the rising edge in modes 0 and 3, shift out on the other one
+            let (in_rising, out_rising) = match config.mode.polarity() == config.mode.phase() {
+                true => (
+                    AUX_SPI_CNTL0::IN_RISING::SET,
+                    AUX_SPI_CNTL0::OUT_RISING::CLEAR,
+                ),
+                false => (
+                    AUX_SPI_CNTL0::IN_RISING::CLEAR,
+                    AUX_SPI_CNTL0::OUT_RISING::SET,
+                ),
+            };
+            let chip_select = match config.chip_select {
+                AuxSpiChipSelect::CS0 => AUX_SPI_CNTL0::CHIP_SELECT::CS0,
+                AuxSpiChipSelect::CS1 => AUX_SPI_CNTL0::CHIP_SELECT::CS1,
+                AuxSpiChipSelect::CS2 => AUX_SPI_CNTL0::CHIP_SELECT::CS2,
+            };
+
+            self.cntl0.write(AUX_SPI_CNTL0::CLEAR_FIFOS::SET);
+            self.cntl1.write(
+                AUX_SPI_CNTL1::MSB_FIRST_IN::SET
+                    + AUX_SPI_CNTL1::CS_HIGH_TIME.val(config.cs_high_time as u32),
+            );
+            self.cntl0.write(
+                AUX_SPI_CNTL0::ENABLE::SET
+                    + AUX_SPI_CNTL0::MSB_FIRST_OUT::SET
+                    + AUX_SPI_CNTL0::VARIABLE_WIDTH::SET
+                    + AUX_SPI_CNTL0::INVERT_CLOCK.val(config.mode.polarity() as u32)
+                    + in_rising
+                    + out_rising
+                    + chip_select
+                    + AUX_SPI_CNTL0::SPEED.val(speed),
+            );
+            CORE_CLOCK / (2 * (speed + 1))
+        }
+
+        pub fn disable(&mut self) {
+            self.cntl0.modify(AUX_SPI_CNTL0::ENABLE::CLEAR);
+        }
+
+        pub fn busy(&self) -> bool {
+            self.stat.is_set(AUX_SPI_STAT::BUSY)
+        }
+
+        /// shift a single `bits` long word (1-24) out while shifting one in
+        pub fn transfer_word(&mut self, word: u32, bits: u8) -> u32 {
+            let bits = bits.clamp(1, 24) as usize;
+            while self.stat.is_set(AUX_SPI_STAT::TX_FULL) {}
+            self.io[0].write(
+                AUX_SPI_DATA::WIDTH.val(bits as u32)
+                    + AUX_SPI_DATA::DATA.val(word << (AUX_SPI_DATA::WIDTH.shift - bits)),
+            );
+            while self.stat.is_set(AUX_SPI_STAT::RX_EMPTY) {}
+            self.io[0].read(AUX_SPI_DATA::DATA) & (AUX_SPI_DATA::DATA.mask >> (24 - bits))
+        }
+
+        /// full duplex transfer of `max(tx.len(), rx.len())` bytes with CS held active
+        /// throughout, missing `tx` bytes are sent as zeroes and surplus received bytes
+        /// are dropped
+        pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) {
+            let mut transfer = AuxSpiTransfer::new(tx, rx);
+            transfer.start(self, false);
+            while !transfer.poll(self) {}
+        }
+
+        pub fn write(&mut self, tx: &[u8]) {
+            self.transfer(tx, &mut []);
+        }
+
+        pub fn read(&mut self, rx: &mut [u8]) {
+            self.transfer(&[], rx);
+        }
+
+        fn set_interrupts(&mut self, tx_empty: bool, done: bool) {
+            self.cntl1.modify(
+                AUX_SPI_CNTL1::TX_EMPTY_IRQ.val(tx_empty as u32)
+                    + AUX_SPI_CNTL1::DONE_IRQ.val(done as u32),
+            );
+        }
+    }
+
+    /// Transfer progress, drives both polled and interrupt driven transfers.
+    ///
+    /// For interrupt driven completion `start` the transfer with interrupts and call
+    /// `handle_interrupt` whenever `AUXRegisters::irq_pending_spi` (or `_spi2`) is set.
+    pub struct AuxSpiTransfer<'a> {
+        tx: &'a [u8],
+        rx: &'a mut [u8],
+        len: usize,
+        sent: usize,
+        received: usize,
+        interrupts: bool,
+    }
+
+    impl<'a> AuxSpiTransfer<'a> {
+        pub fn new(tx: &'a [u8], rx: &'a mut [u8]) -> AuxSpiTransfer<'a> {
+            let len = tx.len().max(rx.len());
+            AuxSpiTransfer {
+                tx,
+                rx,
+                len,
+                sent: 0,
+                received: 0,
+                interrupts: false,
+            }
+        }
+
+        pub fn is_done(&self) -> bool {
+            self.received == self.len
+        }
+
+        fn entry_len(&self, position: usize) -> usize {
+            (self.len - position).min(AuxSpi::BYTES_PER_ENTRY)
+        }
+
+        fn entries_in_flight(&self) -> usize {
+            (self.sent - self.received).div_ceil(AuxSpi::BYTES_PER_ENTRY)
+        }
+
+        pub fn start(&mut self, spi: &mut AuxSpi, interrupts: bool) {
+            self.interrupts = interrupts;
+            self.fill(spi);
+            if interrupts {
+                spi.set_interrupts(!self.all_sent(), self.all_sent());
+            }
+        }
+
+        fn all_sent(&self) -> bool {
+            self.sent == self.len
+        }
+
+        fn fill(&mut self, spi: &mut AuxSpi) {
+            while !self.all_sent()
+                && self.entries_in_flight() < AuxSpi::FIFO_DEPTH
+                && !spi.stat.is_set(AUX_SPI_STAT::TX_FULL)
+            {
+                let len = self.entry_len(self.sent);
+                let mut data = 0;
+                for i in 0..len {
+                    let byte = self.tx.get(self.sent + i).copied().unwrap_or(0);
+                    data |= (byte as u32) << (16 - 8 * i);
+                }
+                let entry = AUX_SPI_DATA::WIDTH.val(8 * len as u32) + AUX_SPI_DATA::DATA.val(data);
+                self.sent += len;
+                match self.all_sent() {
+                    true => spi.io[0].write(entry),
+                    false => spi.txhold[0].write(entry),
+                }
+            }
+        }
+
+        fn drain(&mut self, spi: &mut AuxSpi) {
+            while self.received < self.sent && !spi.stat.is_set(AUX_SPI_STAT::RX_EMPTY) {
+                let len = self.entry_len(self.received);
+                let data = spi.io[0].read(AUX_SPI_DATA::DATA);
+                for i in 0..len {
+                    if let Some(byte) = self.rx.get_mut(self.received + i) {
+                        *byte = (data >> (8 * (len - 1 - i))) as u8;
+                    }
+                }
+                self.received += len;
+            }
+        }
+
+        /// move data between the buffers and the FIFOs, true once the transfer completed
+        pub fn poll(&mut self, spi: &mut AuxSpi) -> bool {
+            self.drain(spi);
+            self.fill(spi);
+            self.is_done()
+        }
+
+        /// service SPI interrupt, true once the transfer completed and interrupts are off
+        pub fn handle_interrupt(&mut self, spi: &mut AuxSpi) -> bool {
+            let done = self.poll(spi);
+            if self.interrupts {
+                // refill on TX empty while sending, wait for idle for the last bytes
+                spi.set_interrupts(!self.all_sent(), self.all_sent() && !done);
+                self.interrupts = !done;
+            }
+            done
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::peripherals::*;
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const AUX_ENABLES: usize = 0x04;
+    const AUX_MU_IO: usize = 0x40;
+    const AUX_MU_IER: usize = 0x44;
+    const AUX_MU_IIR: usize = 0x48;
+    const AUX_MU_MCR: usize = 0x50;
+    const AUX_MU_MSR: usize = 0x58;
+    const AUX_MU_STAT: usize = 0x64;
+    const AUX_MU_BAUD: usize = 0x68;
+
+    /// mini UART with an 8 symbol receive FIFO and a transmitter that is always done
+    fn aux() -> RegisterFile {
+        let file = RegisterFile::new(0x6c);
+        file.on(AUX_MU_IO, Behavior::Fifo)
+            .on(
+                AUX_MU_IIR,
+                Behavior::Custom(Box::new(|storage, access| {
+                    if let Access::Write(value) = access {
+                        if value & 0b010 != 0 {
+                            storage.rx(AUX_MU_IO).clear();
+                        }
+                        if value & 0b100 != 0 {
+                            storage.tx(AUX_MU_IO).clear();
+                        }
+                    }
+                    0b1
+                })),
+            )
+            .on(
+                AUX_MU_STAT,
+                Behavior::Custom(Box::new(|storage, _| {
+                    let level = storage.rx(AUX_MU_IO).len().min(8) as u32;
+                    (level << 16) | (1 << 9) | (1 << 8) | (1 << 3) | (1 << 1) | (level > 0) as u32
+                })),
+            );
+        file
+    }
+
+    #[test]
+    fn enable() {
+        let file = aux();
+        let registers = unsafe { &mut *file.block::<AUXRegisters>(0) };
+        registers.enable_mini_uart();
+        registers.enable_spi2();
+        assert_eq!(file.peek(AUX_ENABLES), 0b101);
+        registers.disable_spi2();
+        assert_eq!(file.peek(AUX_ENABLES), 0b001);
+    }
+
+    #[test]
+    fn transmit_receive() {
+        let file = aux();
+        let uart = unsafe { &mut *file.block::<MiniUart>(AUX_MU_IO) };
+
+        for byte in b"hi" {
+            uart.transmit(*byte as u32);
+        }
+        assert_eq!(file.take_tx(AUX_MU_IO), [b'h' as u32, b'i' as u32]);
+        assert!(uart.tranmitter_idle());
+
+        assert!(!uart.receiver_symbol_avaliable());
+        file.push_rx(AUX_MU_IO, &[1, 2, 3]);
+        assert_eq!(uart.receive_fifo_level(), 3);
+        assert_eq!(uart.receive(), 1);
+        assert_eq!(uart.receive_fifo_level(), 2);
+
+        uart.clear_receive_fifo();
+        assert!(!uart.receiver_symbol_avaliable());
+        assert!(!uart.interrupt_pending());
+    }
+
+    #[test]
+    fn configuration() {
+        let file = aux();
+        let uart = unsafe { &mut *file.block::<MiniUart>(AUX_MU_IO) };
+
+        uart.enable_receive_interrupt();
+        uart.enable_transmit_interrupt();
+        uart.disable_transmit_interrupt();
+        assert_eq!(file.peek(AUX_MU_IER), 0b01);
+
+        uart.set_baudrate(BaudRate::Baud115200);
+        assert_eq!(file.peek(AUX_MU_BAUD), 270);
+        assert_eq!(uart.get_baudrate(), 115313);
+
+        uart.set_request_to_send(true);
+        assert_eq!(file.peek(AUX_MU_MCR), 1 << 1);
+        uart.set_request_to_send(false);
+        assert_eq!(file.peek(AUX_MU_MCR), 0);
+        assert!(!uart.clear_to_send());
+        file.poke(AUX_MU_MSR, 1 << 5);
+        assert!(uart.clear_to_send());
+    }
+
+    const AUX_SPI1: usize = 0x80;
+    const AUX_SPI1_CNTL0: usize = AUX_SPI1;
+    const AUX_SPI1_CNTL1: usize = AUX_SPI1 + 0x04;
+    const AUX_SPI1_STAT: usize = AUX_SPI1 + 0x08;
+    const AUX_SPI1_IO: usize = AUX_SPI1 + 0x20;
+    const AUX_SPI1_TXHOLD: usize = AUX_SPI1 + 0x30;
+
+    /// SPI1 with MOSI wired to MISO, every FIFO entry written is logged at its register
+    fn spi_loopback() -> RegisterFile {
+        let file = RegisterFile::new(0x100);
+        for offset in [AUX_SPI1_IO, AUX_SPI1_TXHOLD] {
+            file.on(
+                offset,
+                Behavior::Custom(Box::new(move |storage, access| match access {
+                    Access::Write(value) => {
+                        let width = value >> 24;
+                        storage.tx(offset).push(value);
+                        storage
+                            .rx(AUX_SPI1_IO)
+                            .push_back((value & 0xff_ffff) >> (24 - width));
+                        0
+                    }
+                    Access::Read => storage.rx(AUX_SPI1_IO).pop_front().unwrap_or(0),
+                })),
+            );
+        }
+        file.on(
+            AUX_SPI1_STAT,
+            Behavior::Custom(Box::new(|storage, _| {
+                let level = storage.rx(AUX_SPI1_IO).len() as u32;
+                (level << 16) | (1 << 9) | (((level == 0) as u32) << 7)
+            })),
+        );
+        file
+    }
+
+    #[test]
+    fn spi_configure() {
+        let file = spi_loopback();
+        let spi = unsafe { &mut *file.block::<AuxSpi>(AUX_SPI1) };
+
+        let config = AuxSpiConfig {
+            mode: SPIMode::Mode3,
+            speed_hz: 10_000_000,
+            chip_select: AuxSpiChipSelect::CS1,
+            cs_high_time: 2,
+        };
+        assert_eq!(spi.configure(&config), 10_416_666);
+        // speed 11, CS1, enable, in rising, inverted clock, msb first, variable width
+        assert_eq!(
+            file.peek(AUX_SPI1_CNTL0),
+            (11 << 20) | (0b101 << 17) | (1 << 14) | (1 << 11) | (1 << 10) | (1 << 7) | (1 << 6)
+        );
+        assert_eq!(file.peek(AUX_SPI1_CNTL1), (2 << 8) | (1 << 1));
+
+        spi.configure(&AuxSpiConfig::new());
+        assert_eq!(file.peek(AUX_SPI1_CNTL0) & 0x780, (1 << 10));
+    }
+
+    #[test]
+    fn spi_transfer() {
+        let file = spi_loopback();
+        let spi = unsafe { &mut *file.block::<AuxSpi>(AUX_SPI1) };
+        spi.configure(&AuxSpiConfig::new());
+
+        let mut rx = [0u8; 5];
+        spi.transfer(b"hello", &mut rx);
+        assert_eq!(&rx, b"hello");
+        // CS held through the first entry, released after the last one
+        assert_eq!(file.take_tx(AUX_SPI1_TXHOLD), [0x1868656c]);
+        assert_eq!(file.take_tx(AUX_SPI1_IO), [0x106c6f00]);
+
+        let mut rx = [0u8; 8];
+        spi.transfer(&[1, 2], &mut rx);
+        assert_eq!(rx, [1, 2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(file.take_tx(AUX_SPI1_TXHOLD).len(), 2);
+        assert_eq!(file.take_tx(AUX_SPI1_IO), [0x10000000]);
+
+        assert_eq!(spi.transfer_word(0x1a5, 9), 0x1a5);
+        assert_eq!(file.take_tx(AUX_SPI1_IO), [0x09d28000]);
+    }
+
+    #[test]
+    fn spi_interrupt_transfer() {
+        let file = spi_loopback();
+        let spi = unsafe { &mut *file.block::<AuxSpi>(AUX_SPI1) };
+        spi.configure(&AuxSpiConfig::new());
+
+        let tx: Vec<u8> = (0..20).collect();
+        let mut rx = [0u8; 20];
+        let mut transfer = AuxSpiTransfer::new(&tx, &mut rx);
+        transfer.start(spi, true);
+        // FIFO holds 4 entries of 3 bytes
+        assert_eq!(file.take_tx(AUX_SPI1_TXHOLD).len(), 4);
+        assert_eq!(file.peek(AUX_SPI1_CNTL1) & 0xc0, 1 << 7);
+
+        let mut interrupts = 0;
+        while !transfer.handle_interrupt(spi) {
+            interrupts += 1;
         }
+        assert_eq!(interrupts, 1);
+        assert_eq!(file.peek(AUX_SPI1_CNTL1) & 0xc0, 0);
+        assert_eq!(rx.as_slice(), tx.as_slice());
     }
 }
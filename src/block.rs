@@ -0,0 +1,130 @@
+//! Block devices, storage addressed in fixed size blocks.
+//!
+//! Filesystems and partition tables only see the [`BlockDevice`] trait, so they work the
+//! same on an SD card, a partition of it or a [`RamDisk`] in tests.
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BlockError {
+    /// access past the last block
+    OutOfRange,
+    /// buffer length is not a multiple of the block size
+    InvalidBuffer,
+    /// device failed, with a device specific code
+    Device(u32),
+}
+
+pub trait BlockDevice {
+    /// bytes per block
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn block_count(&self) -> u64;
+
+    /// read `buf.len() / block_size()` blocks starting at `lba`
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// write `buf.len() / block_size()` blocks starting at `lba`
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// number of blocks `len` bytes cover, checking they fit the device from `lba` on
+    fn check_range(&self, lba: u64, len: usize) -> Result<u64, BlockError> {
+        if !len.is_multiple_of(self.block_size()) {
+            return Err(BlockError::InvalidBuffer);
+        }
+        let count = (len / self.block_size()) as u64;
+        match lba.checked_add(count) {
+            Some(end) if end <= self.block_count() => Ok(count),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        (**self).read_blocks(lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        (**self).write_blocks(lba, buf)
+    }
+}
+
+/// block device backed by memory
+pub struct RamDisk<'a> {
+    data: &'a mut [u8],
+    block_size: usize,
+}
+
+impl<'a> RamDisk<'a> {
+    /// whole blocks of `data`, trailing bytes are not used
+    pub fn new(data: &'a mut [u8], block_size: usize) -> RamDisk<'a> {
+        RamDisk { data, block_size }
+    }
+
+    fn range(&self, lba: u64, len: usize) -> Result<core::ops::Range<usize>, BlockError> {
+        self.check_range(lba, len)?;
+        let start = lba as usize * self.block_size;
+        Ok(start..start + len)
+    }
+}
+
+impl BlockDevice for RamDisk<'_> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let range = self.range(lba, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let range = self.range(lba, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_disk() {
+        let mut data = [0u8; 4 * 512 + 100];
+        let mut disk = RamDisk::new(&mut data, 512);
+        assert_eq!(disk.block_count(), 4);
+
+        disk.write_blocks(2, &[0x5a; 1024]).unwrap();
+        let mut buf = [0u8; 512];
+        disk.read_blocks(3, &mut buf).unwrap();
+        assert_eq!(buf, [0x5a; 512]);
+
+        assert_eq!(
+            disk.read_blocks(3, &mut [0; 1024]),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.read_blocks(u64::MAX, &mut buf),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            disk.write_blocks(0, &[0; 100]),
+            Err(BlockError::InvalidBuffer)
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Board the kernel is built for, chosen with exactly one cargo feature:
+//!
+//! - `raspi4b`, the default: BCM2711 in low peripheral mode, peripherals at 0xfe000000
+//! - `raspi4b-high`: BCM2711 with `arm_peri_high=1`, peripherals at 0x4_7e000000
+//! - `raspi3b`: BCM2837, peripherals at 0x3f000000 and no GIC
+//!
+//! Drivers take their addresses, interrupt numbers and clocks from here. Blocks sit at the
+//! same offset from [`PERIPHERAL_BASE`] on every board unless an `_OFFSET` constant says
+//! otherwise. The Pi 3 lacks some blocks altogether: UART2-5, SPI3-6, I2C3-6, PWM1, the
+//! DMA4 engines and the RNG200, whose drivers must not be used there. Clock rates are per
+//! board too: the mini UART, SPI and I2C divide [`CORE_CLOCK`], the PL011s [`UART_CLOCK`]
+//! and the clock manager [`OSCILLATOR_CLOCK`] or [`PLLD_CLOCK`].
+//!
+//! The RAM the kernel is linked for, and the stack at its top, come from `link/<board>.ld`.
+
+#[cfg(any(
+    all(feature = "raspi3b", feature = "raspi4b"),
+    all(feature = "raspi3b", feature = "raspi4b-high"),
+    all(feature = "raspi4b", feature = "raspi4b-high"),
+    not(any(feature = "raspi3b", feature = "raspi4b", feature = "raspi4b-high")),
+))]
+compile_error!(
+    "select exactly one board feature: raspi4b, raspi4b-high or raspi3b \
+     (the others need --no-default-features)"
+);
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Board {
+    Raspi3B,
+    Raspi4B,
+}
+
+/// how pull-up and pull-down resistors are configured
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PullStyle {
+    /// GPPUD holds the pull and GPPUDCLK clocks it into the pins, it cannot be read back
+    Clocked,
+    /// two bits per pin in GPIO_PUP_PDN_CNTRL, readable
+    Direct,
+}
+
+#[cfg(feature = "raspi3b")]
+mod config {
+    use super::*;
+
+    pub const BOARD: Board = Board::Raspi3B;
+    pub const PERIPHERAL_BASE: usize = 0x3f00_0000;
+    /// distributor and CPU interface
+    pub const GIC: Option<(usize, usize)> = None;
+    /// first interrupt number of the VideoCore interrupts, those of the legacy controller
+    pub const VC_IRQ_BASE: u32 = 0;
+    /// Arasan SDHCI, the one wired to the card slot by default
+    pub const EMMC_OFFSET: usize = 0x30_0000;
+    pub const EMMC_CLOCK: u32 = 200_000_000;
+    pub const PULL_STYLE: PullStyle = PullStyle::Clocked;
+    /// crystal, clock manager source 1
+    pub const OSCILLATOR_CLOCK: u32 = 19_200_000;
+    /// PLLD per channel, clock manager source 6
+    pub const PLLD_CLOCK: u32 = 500_000_000;
+    /// VPU core clock, the firmware fixes it at this rate when the mini UART is enabled
+    pub const CORE_CLOCK: u32 = 250_000_000;
+    /// PL011 reference clock, `init_uart_clock` in config.txt
+    pub const UART_CLOCK: u32 = 48_000_000;
+}
+
+#[cfg(any(feature = "raspi4b", feature = "raspi4b-high"))]
+mod config {
+    use super::*;
+
+    pub const BOARD: Board = Board::Raspi4B;
+    #[cfg(not(feature = "raspi4b-high"))]
+    pub const PERIPHERAL_BASE: usize = 0xfe00_0000;
+    #[cfg(feature = "raspi4b-high")]
+    pub const PERIPHERAL_BASE: usize = 0x4_7e00_0000;
+    /// distributor and CPU interface
+    #[cfg(not(feature = "raspi4b-high"))]
+    pub const GIC: Option<(usize, usize)> = Some((0xff84_1000, 0xff84_2000));
+    #[cfg(feature = "raspi4b-high")]
+    pub const GIC: Option<(usize, usize)> = Some((0x4_c004_1000, 0x4_c004_2000));
+    /// first interrupt number of the VideoCore interrupts, SPI 64 onwards of the GIC
+    pub const VC_IRQ_BASE: u32 = 96;
+    /// EMMC2, the controller of the card slot
+    pub const EMMC_OFFSET: usize = 0x34_0000;
+    pub const EMMC_CLOCK: u32 = 100_000_000;
+    pub const PULL_STYLE: PullStyle = PullStyle::Direct;
+    /// crystal, clock manager source 1
+    pub const OSCILLATOR_CLOCK: u32 = 54_000_000;
+    /// PLLD per channel, clock manager source 6
+    pub const PLLD_CLOCK: u32 = 750_000_000;
+    /// VPU core clock, asked from the firmware at boot. The Pi 4 firmware runs it at 500MHz
+    /// by default, 250MHz keeps the dividers of the Pi 3.
+    pub const CORE_CLOCK: u32 = 250_000_000;
+    /// PL011 reference clock, `init_uart_clock` in config.txt
+    pub const UART_CLOCK: u32 = 48_000_000;
+}
+
+pub use config::*;
+
+/// ARM physical address of the peripheral at `offset` from the start of the peripherals
+pub const fn peripheral(offset: usize) -> usize {
+    PERIPHERAL_BASE + offset
+}
+
+/// interrupt number of VideoCore interrupt `irq` on the interrupt controller of the board
+pub const fn vc_irq(irq: u32) -> u32 {
+    VC_IRQ_BASE + irq
+}
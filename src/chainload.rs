@@ -0,0 +1,336 @@
+//! Serial chainloader, boots kernels sent over a serial port instead of the SD card.
+//!
+//! The loader asks for an image with [`REQUEST`], the sender answers with a [`Header`]
+//! followed by the image. Each step is acknowledged with [`ACK`] or [`NAK`] and an
+//! [`ChainloadError`] code, after an error the loader asks again.
+//!
+//! ```text
+//! loader  REQUEST                     0x03 0x03 0x03
+//! sender  header                      "BOOT" length:u32 load address:u64 crc32:u32
+//! loader  ACK or NAK code
+//! sender  image                       length bytes
+//! loader  ACK or NAK code
+//! ```
+//!
+//! The image is staged in RAM clear of the running kernel and checked against its CRC32.
+//! Copying it to the load address is left to a trampoline that first moves itself out of the
+//! way, so images may replace the loader. `tools/chainload` is the sender.
+use crate::serial::Serial;
+use crate::timer::Timeout;
+use crate::utils::crc32::{self, Crc32};
+
+pub const REQUEST: [u8; 3] = [0x03; 3];
+pub const MAGIC: [u8; 4] = *b"BOOT";
+pub const HEADER_LEN: usize = 20;
+pub const ACK: [u8; 2] = *b"OK";
+/// error response, followed by the error code
+pub const NAK: u8 = b'E';
+
+/// received images are staged here
+const STAGING: usize = 0x2000_0000;
+pub const MAX_IMAGE: usize = 0x0800_0000;
+/// the trampoline runs from just below the staging area, images have to end before it
+const TRAMPOLINE: usize = STAGING - 0x1000;
+/// longest pause within a header or an image
+const BYTE_TIMEOUT_US: u64 = 1_000_000;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(u8)]
+pub enum ChainloadError {
+    /// sender stopped in the middle of a header or image
+    Timeout = 1,
+    /// empty image or larger than the staging area
+    Length = 2,
+    /// load address not 8 byte aligned, or the image would overwrite the staging area
+    Address = 3,
+    Checksum = 4,
+}
+
+impl ChainloadError {
+    pub fn from_code(code: u8) -> Option<ChainloadError> {
+        [
+            ChainloadError::Timeout,
+            ChainloadError::Length,
+            ChainloadError::Address,
+            ChainloadError::Checksum,
+        ]
+        .into_iter()
+        .find(|e| *e as u8 == code)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Header {
+    pub length: u32,
+    /// where the image is copied and entered
+    pub load_address: u64,
+    pub crc: u32,
+}
+
+impl Header {
+    pub fn new(image: &[u8], load_address: u64) -> Header {
+        Header {
+            length: image.len() as u32,
+            load_address,
+            crc: crc32::checksum(image),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.load_address.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Option<Header> {
+        (bytes[..4] == MAGIC).then(|| Header {
+            length: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            load_address: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            crc: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
+        })
+    }
+
+    /// the image has to fit `staging` and end before `limit`
+    fn check(&self, staging: usize, limit: u64) -> Result<(), ChainloadError> {
+        if self.length == 0 || self.length as usize > staging {
+            return Err(ChainloadError::Length);
+        }
+        match self.load_address.checked_add(self.length as u64) {
+            Some(end) if end <= limit && self.load_address.is_multiple_of(8) => Ok(()),
+            _ => Err(ChainloadError::Address),
+        }
+    }
+}
+
+/// one request for an image, received into `staging` and checked, loading below `limit`
+pub fn receive<S: Serial + ?Sized>(
+    serial: &mut S,
+    staging: &mut [u8],
+    limit: u64,
+) -> Result<Header, ChainloadError> {
+    serial.write_bytes(&REQUEST);
+    let result = receive_image(serial, staging, limit);
+    match result {
+        Ok(_) => serial.write_bytes(&ACK),
+        Err(error) => serial.write_bytes(&[NAK, error as u8]),
+    }
+    serial.flush();
+    result
+}
+
+fn receive_image<S: Serial + ?Sized>(
+    serial: &mut S,
+    staging: &mut [u8],
+    limit: u64,
+) -> Result<Header, ChainloadError> {
+    // anything in front of the magic is skipped, the sender may have been started first
+    let mut bytes = [0u8; HEADER_LEN];
+    while bytes[..4] != MAGIC {
+        bytes.copy_within(1..4, 0);
+        bytes[3] = serial.read_byte_blocking();
+    }
+    read_exact(serial, &mut bytes[4..])?;
+    let header = Header::from_bytes(&bytes).unwrap();
+    header.check(staging.len(), limit)?;
+    serial.write_bytes(&ACK);
+
+    let image = &mut staging[..header.length as usize];
+    read_exact(serial, image)?;
+    let mut crc = Crc32::new();
+    crc.update(image);
+    match crc.finish() == header.crc {
+        true => Ok(header),
+        false => Err(ChainloadError::Checksum),
+    }
+}
+
+fn read_exact<S: Serial + ?Sized>(serial: &mut S, buf: &mut [u8]) -> Result<(), ChainloadError> {
+    for byte in buf {
+        let timeout = Timeout::after_us(BYTE_TIMEOUT_US);
+        *byte = loop {
+            if let Some(byte) = serial.read_byte() {
+                break byte;
+            }
+            if timeout.expired() {
+                return Err(ChainloadError::Timeout);
+            }
+        };
+    }
+    Ok(())
+}
+
+/// wait for an image on `serial` and boot it, handing on the `device_tree` the loader got
+#[cfg(target_arch = "aarch64")]
+pub fn run<S: Serial + ?Sized>(serial: &mut S, device_tree: usize) -> ! {
+    let staging = unsafe { core::slice::from_raw_parts_mut(STAGING as *mut u8, MAX_IMAGE) };
+    loop {
+        if let Ok(header) = receive(serial, staging, TRAMPOLINE as u64) {
+            let size = crate::fdt::device_tree()
+                .filter(|_| crate::fdt::address() == device_tree)
+                .map_or(0, |fdt| fdt.total_size());
+            let device_tree = keep_device_tree(&header, device_tree, size);
+            unsafe { boot(&header, STAGING as *const u8, device_tree) }
+        }
+    }
+}
+
+/// `device_tree` of `size` bytes, or zero when there is none or the staging area or the
+/// image would overwrite any of it
+fn keep_device_tree(header: &Header, device_tree: usize, size: usize) -> usize {
+    let overlaps = |start: usize, len: usize| {
+        start < device_tree.saturating_add(size) && device_tree < start.saturating_add(len)
+    };
+    if device_tree == 0
+        || size == 0
+        || overlaps(TRAMPOLINE, STAGING + MAX_IMAGE - TRAMPOLINE)
+        || overlaps(header.load_address as usize, header.length as usize)
+    {
+        return 0;
+    }
+    device_tree
+}
+
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    ".balign 8",
+    ".global chainload_trampoline",
+    ".global chainload_trampoline_end",
+    // x0 destination, x1 source, x2 length, x3 device tree, enters the destination with
+    // x0 = device tree like the firmware does
+    "chainload_trampoline:",
+    "  mov x5, x0",
+    "0:",
+    "  cmp x2, #8",
+    "  b.lo 1f",
+    "  ldr x4, [x1], #8",
+    "  str x4, [x0], #8",
+    "  sub x2, x2, #8",
+    "  b 0b",
+    "1:",
+    "  cbz x2, 2f",
+    "  ldrb w4, [x1], #1",
+    "  strb w4, [x0], #1",
+    "  sub x2, x2, #1",
+    "  b 1b",
+    "2:",
+    "  dsb sy",
+    "  ic iallu",
+    "  dsb sy",
+    "  isb",
+    "  mov x0, x3",
+    "  br x5",
+    "chainload_trampoline_end:",
+);
+
+#[cfg(target_arch = "aarch64")]
+unsafe extern "C" {
+    fn chainload_trampoline();
+    fn chainload_trampoline_end();
+}
+
+/// copy the trampoline below the staging area and let it move the image into place
+///
+/// # Safety
+/// `image` holds `header.length` bytes outside of the load area and the trampoline
+#[cfg(target_arch = "aarch64")]
+pub unsafe fn boot(header: &Header, image: *const u8, device_tree: usize) -> ! {
+    use core::arch::asm;
+    let start = chainload_trampoline as *const u8;
+    let len = chainload_trampoline_end as *const u8 as usize - start as usize;
+    unsafe {
+        core::ptr::copy_nonoverlapping(start, TRAMPOLINE as *mut u8, len);
+        asm!("dsb sy", "ic iallu", "dsb sy", "isb");
+        let trampoline: extern "C" fn(u64, *const u8, usize, usize) -> ! =
+            core::mem::transmute(TRAMPOLINE);
+        trampoline(
+            header.load_address,
+            image,
+            header.length as usize,
+            device_tree,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockSerial;
+
+    fn frame(header: &Header, image: &[u8]) -> Vec<u8> {
+        let mut data = header.to_bytes().to_vec();
+        data.extend_from_slice(image);
+        data
+    }
+
+    #[test]
+    fn header() {
+        let header = Header::new(b"123456789", 0x8_0000);
+        assert_eq!(header.crc, 0xcbf4_3926);
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..8], b"BOOT\x09\x00\x00\x00");
+        assert_eq!(Header::from_bytes(&bytes), Some(header));
+        assert_eq!(Header::from_bytes(&[0; HEADER_LEN]), None);
+        assert_eq!(ChainloadError::from_code(4), Some(ChainloadError::Checksum));
+        assert_eq!(ChainloadError::from_code(0), None);
+
+        // a device tree in the way of the copy is not handed on
+        assert_eq!(keep_device_tree(&header, 0x2eff_7a00, 0x100), 0x2eff_7a00);
+        assert_eq!(keep_device_tree(&header, 0x8_0004, 0x100), 0);
+        assert_eq!(keep_device_tree(&header, STAGING + 0x100, 0x100), 0);
+        assert_eq!(keep_device_tree(&header, 0, 0x100), 0);
+        assert_eq!(keep_device_tree(&header, 0x2eff_7a00, 0), 0);
+        // the image only covers the end of the blob
+        assert_eq!(keep_device_tree(&header, 0x7_ff00, 0x100), 0x7_ff00);
+        assert_eq!(keep_device_tree(&header, 0x7_ff00, 0x101), 0);
+        assert_eq!(keep_device_tree(&header, TRAMPOLINE - 0x100, 0x101), 0);
+    }
+
+    #[test]
+    fn receive_image() {
+        let image: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let header = Header::new(&image, 0x8_0000);
+        // leftover console output in front of the frame
+        let mut rx = b"garbage BOO".to_vec();
+        rx.extend(frame(&header, &image));
+        let mut serial = MockSerial::new(&rx);
+        let mut staging = vec![0u8; 4096];
+
+        assert_eq!(receive(&mut serial, &mut staging, 0x1000_0000), Ok(header));
+        assert_eq!(&staging[..1000], &image[..]);
+        assert_eq!(serial.tx, b"\x03\x03\x03OKOK");
+    }
+
+    #[test]
+    fn errors() {
+        let image = [0x5au8; 64];
+        let mut staging = vec![0u8; 128];
+        let mut check = |header: Header, image: &[u8]| {
+            let mut serial = MockSerial::new(&frame(&header, image));
+            let result = receive(&mut serial, &mut staging, 0x1000);
+            (result, serial.tx)
+        };
+
+        let mut bad = Header::new(&image, 0);
+        bad.crc ^= 1;
+        let (result, tx) = check(bad, &image);
+        assert_eq!(result, Err(ChainloadError::Checksum));
+        assert_eq!(tx, b"\x03\x03\x03OKE\x04");
+
+        let (result, tx) = check(Header::new(&[0; 256], 0), &[]);
+        assert_eq!(result, Err(ChainloadError::Length));
+        assert_eq!(tx, b"\x03\x03\x03E\x02");
+
+        for address in [0xfe0, 4, u64::MAX - 8] {
+            let (result, _) = check(Header::new(&image, address), &image);
+            assert_eq!(result, Err(ChainloadError::Address));
+        }
+
+        // image cut short
+        let (result, tx) = check(Header::new(&image, 0), &image[..10]);
+        assert_eq!(result, Err(ChainloadError::Timeout));
+        assert_eq!(tx, b"\x03\x03\x03OKE\x01");
+    }
+}
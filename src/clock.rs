@@ -0,0 +1,511 @@
+//! BCM2711 clock manager, generates the PWM, PCM and general purpose clocks (GPCLK0-2)
+use crate::board;
+use crate::gpio::{GPIOFunction, GPIOPin, GPIORegisters};
+use crate::registers::*;
+use crate::timer::Timeout;
+
+/// time a clock gets to stop by itself before it is killed
+const STOP_TIMEOUT_US: u64 = 10_000;
+
+register_bitfields! {u32,
+    pub CM_CTL [
+        SRC OFFSET(0) NUMBITS(4) [
+            GND = 0,
+            Oscillator = 1,
+            TestDebug0 = 2,
+            TestDebug1 = 3,
+            PLLA = 4,
+            PLLC = 5,
+            PLLD = 6,
+            HDMIAux = 7,
+        ],
+        ENAB OFFSET(4) NUMBITS(1) [],
+        /// stop the generator immediately, may glitch the output
+        KILL OFFSET(5) NUMBITS(1) [],
+        /// clock generator is running
+        BUSY OFFSET(7) NUMBITS(1) [],
+        FLIP OFFSET(8) NUMBITS(1) [],
+        MASH OFFSET(9) NUMBITS(2) [
+            Integer = 0,
+            Stage1 = 1,
+            Stage2 = 2,
+            Stage3 = 3,
+        ],
+        /// writes without the password are ignored
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a,
+        ],
+    ],
+    pub CM_DIV [
+        DIVF OFFSET(0) NUMBITS(12) [],
+        DIVI OFFSET(12) NUMBITS(12) [],
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a,
+        ],
+    ],
+}
+
+#[repr(C)]
+pub struct ClockRegisters {
+    ctl: ReadWrite<u32, CM_CTL::Register>, /* 0x00 CTL Clock Control */
+    div: ReadWrite<u32, CM_DIV::Register>, /* 0x04 DIV Clock Divisor */
+}
+
+register_layout! {
+    ClockRegisters @ 0x00 .. 0x08 {
+        0x00 => ctl,
+        0x04 => div,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Clock {
+    GP0,
+    GP1,
+    GP2,
+    PCM,
+    PWM,
+}
+
+impl Clock {
+    const BASE: usize = crate::board::peripheral(0x10_1000);
+
+    const fn offset(self) -> usize {
+        match self {
+            Clock::GP0 => 0x70,
+            Clock::GP1 => 0x78,
+            Clock::GP2 => 0x80,
+            Clock::PCM => 0x98,
+            Clock::PWM => 0xa0,
+        }
+    }
+}
+
+/// general purpose clocks, output on GPIO pins through ALT0
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GPClock {
+    GPCLK0,
+    GPCLK1,
+    GPCLK2,
+}
+
+impl GPClock {
+    pub const fn clock(self) -> Clock {
+        match self {
+            GPClock::GPCLK0 => Clock::GP0,
+            GPClock::GPCLK1 => Clock::GP1,
+            GPClock::GPCLK2 => Clock::GP2,
+        }
+    }
+
+    /// pins carrying the clock as their ALT0 function
+    pub const fn pins(self) -> &'static [GPIOPin] {
+        match self {
+            GPClock::GPCLK0 => &[GPIOPin::PIN4, GPIOPin::PIN32, GPIOPin::PIN34],
+            GPClock::GPCLK1 => &[GPIOPin::PIN5, GPIOPin::PIN42, GPIOPin::PIN44],
+            GPClock::GPCLK2 => &[GPIOPin::PIN6, GPIOPin::PIN43],
+        }
+    }
+
+    /// switch `pin` to this clock's output
+    pub fn route(self, gpio: &mut GPIORegisters, pin: GPIOPin) -> Result<(), ClockError> {
+        if !self.pins().contains(&pin) {
+            return Err(ClockError::InvalidPin);
+        }
+        gpio.pin_function_set(pin, GPIOFunction::ALT0);
+        Ok(())
+    }
+
+    /// Start `clock`, the register block of this output, close to `hz` and route it to
+    /// `pin`. Returns the achieved frequency in Hz.
+    pub fn output(
+        self,
+        clock: &mut ClockRegisters,
+        gpio: &mut GPIORegisters,
+        pin: GPIOPin,
+        source: ClockSource,
+        hz: u32,
+        mash: Mash,
+    ) -> Result<u32, ClockError> {
+        if !self.pins().contains(&pin) {
+            return Err(ClockError::InvalidPin);
+        }
+        let achieved = clock.set_frequency(source, hz, mash)?;
+        self.route(gpio, pin)?;
+        Ok(achieved)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ClockSource {
+    GND,
+    Oscillator,
+    PLLA,
+    PLLC,
+    PLLD,
+    HDMIAux,
+}
+
+impl ClockSource {
+    /// source frequency in Hz, `None` when it depends on firmware configuration
+    pub const fn frequency(self) -> Option<u32> {
+        match self {
+            ClockSource::GND => Some(0),
+            ClockSource::Oscillator => Some(board::OSCILLATOR_CLOCK),
+            ClockSource::PLLD => Some(board::PLLD_CLOCK),
+            ClockSource::PLLA | ClockSource::PLLC | ClockSource::HDMIAux => None,
+        }
+    }
+
+    fn field(self) -> FieldValue<u32, CM_CTL::Register> {
+        match self {
+            ClockSource::GND => CM_CTL::SRC::GND,
+            ClockSource::Oscillator => CM_CTL::SRC::Oscillator,
+            ClockSource::PLLA => CM_CTL::SRC::PLLA,
+            ClockSource::PLLC => CM_CTL::SRC::PLLC,
+            ClockSource::PLLD => CM_CTL::SRC::PLLD,
+            ClockSource::HDMIAux => CM_CTL::SRC::HDMIAux,
+        }
+    }
+
+    fn from_field(value: CM_CTL::SRC::Value) -> Option<ClockSource> {
+        match value {
+            CM_CTL::SRC::Value::GND => Some(ClockSource::GND),
+            CM_CTL::SRC::Value::Oscillator => Some(ClockSource::Oscillator),
+            CM_CTL::SRC::Value::PLLA => Some(ClockSource::PLLA),
+            CM_CTL::SRC::Value::PLLC => Some(ClockSource::PLLC),
+            CM_CTL::SRC::Value::PLLD => Some(ClockSource::PLLD),
+            CM_CTL::SRC::Value::HDMIAux => Some(ClockSource::HDMIAux),
+            _ => None,
+        }
+    }
+}
+
+/// noise shaping of fractional dividers, higher stages need larger divisors
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Mash {
+    /// fraction ignored
+    Integer,
+    Stage1,
+    Stage2,
+    Stage3,
+}
+
+impl Mash {
+    /// smallest integer divisor the filter works with
+    pub const fn min_divisor(self) -> u32 {
+        match self {
+            Mash::Integer => 1,
+            Mash::Stage1 => 2,
+            Mash::Stage2 => 3,
+            Mash::Stage3 => 5,
+        }
+    }
+
+    fn field(self) -> FieldValue<u32, CM_CTL::Register> {
+        match self {
+            Mash::Integer => CM_CTL::MASH::Integer,
+            Mash::Stage1 => CM_CTL::MASH::Stage1,
+            Mash::Stage2 => CM_CTL::MASH::Stage2,
+            Mash::Stage3 => CM_CTL::MASH::Stage3,
+        }
+    }
+}
+
+/// `integer + fraction / 4096`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Divisor {
+    pub integer: u32,
+    pub fraction: u32,
+}
+
+impl Divisor {
+    pub const MAX_INTEGER: u32 = 0xfff;
+
+    /// closest divisor bringing `source` Hz down to `target` Hz
+    pub fn for_frequency(source: u32, target: u32, mash: Mash) -> Divisor {
+        let scaled = ((source as u64) << 12) / target.max(1) as u64;
+        let (integer, fraction) = ((scaled >> 12) as u32, scaled as u32 & 0xfff);
+        match mash {
+            // round to the nearest integer
+            Mash::Integer => Divisor {
+                integer: integer + (fraction >= 0x800) as u32,
+                fraction: 0,
+            },
+            _ => Divisor { integer, fraction },
+        }
+    }
+
+    /// output frequency for a `source` Hz input, averaged for fractional divisors
+    pub fn apply(self, source: u32, mash: Mash) -> u32 {
+        let fraction = match mash {
+            Mash::Integer => 0,
+            _ => self.fraction,
+        };
+        let scaled = ((self.integer as u64) << 12) + fraction as u64;
+        (((source as u64) << 12) / scaled.max(1)) as u32
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ClockError {
+    /// generator kept running after being killed
+    Busy,
+    /// divisor out of range for the MASH stage
+    InvalidDivisor,
+    /// source frequency is not known
+    UnknownSource,
+    /// clock is not available on the pin
+    InvalidPin,
+}
+
+impl ClockRegisters {
+    pub const fn new(clock: Clock) -> *mut ClockRegisters {
+        Self::at(Clock::BASE + clock.offset())
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut ClockRegisters {
+        base as *mut ClockRegisters
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.ctl.is_set(CM_CTL::BUSY)
+    }
+
+    /// Disable the generator and wait for it to stop at the end of the current cycle,
+    /// killing it when it does not.
+    pub fn stop(&mut self) -> Result<(), ClockError> {
+        let ctl = (CM_CTL::PASSWD::Password + CM_CTL::ENAB::CLEAR).modify(self.ctl.get());
+        self.ctl.set(ctl);
+        if self.wait_idle() {
+            return Ok(());
+        }
+
+        self.ctl
+            .set((CM_CTL::PASSWD::Password + CM_CTL::KILL::SET).modify(ctl));
+        let idle = self.wait_idle();
+        self.ctl.set(CM_CTL::PASSWD::Password.modify(ctl));
+        match idle {
+            true => Ok(()),
+            false => Err(ClockError::Busy),
+        }
+    }
+
+    /// Stop the generator, program the divisor and restart it from `source`. Source and
+    /// MASH are changed while the generator is disabled, as the manual requires.
+    pub fn start(
+        &mut self,
+        source: ClockSource,
+        divisor: Divisor,
+        mash: Mash,
+    ) -> Result<(), ClockError> {
+        if divisor.integer < mash.min_divisor()
+            || divisor.integer > Divisor::MAX_INTEGER
+            || divisor.fraction > 0xfff
+        {
+            return Err(ClockError::InvalidDivisor);
+        }
+
+        self.stop()?;
+        self.div.write(
+            CM_DIV::PASSWD::Password
+                + CM_DIV::DIVI.val(divisor.integer)
+                + CM_DIV::DIVF.val(divisor.fraction),
+        );
+        self.ctl
+            .write(CM_CTL::PASSWD::Password + source.field() + mash.field());
+        self.ctl
+            .write(CM_CTL::PASSWD::Password + source.field() + mash.field() + CM_CTL::ENAB::SET);
+        Ok(())
+    }
+
+    /// run at the frequency closest to `hz`, returns the achieved frequency in Hz
+    pub fn set_frequency(
+        &mut self,
+        source: ClockSource,
+        hz: u32,
+        mash: Mash,
+    ) -> Result<u32, ClockError> {
+        let input = source.frequency().ok_or(ClockError::UnknownSource)?;
+        let divisor = Divisor::for_frequency(input, hz, mash);
+        self.start(source, divisor, mash)?;
+        Ok(divisor.apply(input, mash))
+    }
+
+    /// current output frequency in Hz, `None` when stopped or the source is unknown
+    pub fn frequency(&self) -> Option<u32> {
+        let ctl = self.ctl.get();
+        if !CM_CTL::ENAB.is_set(ctl) {
+            return None;
+        }
+        let source = ClockSource::from_field(CM_CTL::SRC.read_as_enum(ctl)?)?;
+        let mash = match CM_CTL::MASH.read(ctl) {
+            0 => Mash::Integer,
+            1 => Mash::Stage1,
+            2 => Mash::Stage2,
+            _ => Mash::Stage3,
+        };
+        let divisor = Divisor {
+            integer: self.div.read(CM_DIV::DIVI),
+            fraction: self.div.read(CM_DIV::DIVF),
+        };
+        Some(divisor.apply(source.frequency()?, mash))
+    }
+
+    fn wait_idle(&self) -> bool {
+        let timeout = Timeout::after_us(STOP_TIMEOUT_US);
+        while self.is_busy() {
+            if timeout.expired() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const CTL: usize = 0x00;
+    const DIV: usize = 0x04;
+    const PASSWORD: u32 = 0x5a << 24;
+
+    /// generator follows ENAB unless `stuck`, which only KILL gets out of
+    fn clock(stuck: Rc<Cell<bool>>) -> RegisterFile {
+        let file = RegisterFile::new(0x08);
+        for offset in [CTL, DIV] {
+            let stuck = stuck.clone();
+            file.on(
+                offset,
+                Behavior::Custom(Box::new(move |storage, access| {
+                    if let Access::Write(value) = access {
+                        // BUSY is read only
+                        let mask = if offset == CTL {
+                            0x00ff_ff7f
+                        } else {
+                            0x00ff_ffff
+                        };
+                        if value & 0xff00_0000 == PASSWORD {
+                            storage.set(offset, value & mask);
+                        }
+                    }
+                    let value = storage.get(offset);
+                    if offset == DIV {
+                        return value;
+                    }
+                    if value & (1 << 5) != 0 {
+                        stuck.set(false);
+                    }
+                    let busy = value & (1 << 4) != 0 || stuck.get();
+                    value | ((busy as u32) << 7)
+                })),
+            );
+        }
+        file
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let file = clock(Rc::new(Cell::new(false)));
+        let clock = unsafe { &mut *file.block::<ClockRegisters>(0) };
+
+        let achieved = clock.set_frequency(ClockSource::Oscillator, 1_000_000, Mash::Integer);
+        assert_eq!(achieved, Ok(1_000_000));
+        assert_eq!(file.peek(DIV), 54 << 12);
+        assert_eq!(file.peek(CTL), (1 << 4) | 1);
+        assert!(clock.is_busy());
+        assert_eq!(clock.frequency(), Some(1_000_000));
+
+        assert_eq!(clock.stop(), Ok(()));
+        assert!(!clock.is_busy());
+        assert_eq!(clock.frequency(), None);
+    }
+
+    #[test]
+    fn fractional_divisor() {
+        let file = clock(Rc::new(Cell::new(false)));
+        let clock = unsafe { &mut *file.block::<ClockRegisters>(0) };
+
+        // 750 MHz / 19.2 MHz = 39.0625
+        let achieved = clock.set_frequency(ClockSource::PLLD, 19_200_000, Mash::Stage1);
+        assert_eq!(achieved, Ok(19_200_000));
+        assert_eq!(file.peek(DIV), (39 << 12) | 256);
+        assert_eq!(file.peek(CTL), (1 << 9) | (1 << 4) | 6);
+
+        // without MASH the divisor is rounded
+        let achieved = clock.set_frequency(ClockSource::PLLD, 19_200_000, Mash::Integer);
+        assert_eq!(achieved, Ok(19_230_769));
+
+        assert_eq!(
+            clock.start(
+                ClockSource::Oscillator,
+                Divisor {
+                    integer: 4,
+                    fraction: 0
+                },
+                Mash::Stage3
+            ),
+            Err(ClockError::InvalidDivisor)
+        );
+        assert_eq!(
+            clock.set_frequency(ClockSource::PLLC, 1_000_000, Mash::Integer),
+            Err(ClockError::UnknownSource)
+        );
+    }
+
+    #[test]
+    fn kill_stuck_generator() {
+        let stuck = Rc::new(Cell::new(true));
+        let file = clock(stuck.clone());
+        let clock = unsafe { &mut *file.block::<ClockRegisters>(0) };
+
+        assert_eq!(clock.stop(), Ok(()));
+        assert!(!stuck.get());
+        // KILL is released again
+        assert_eq!(file.peek(CTL) & (1 << 5), 0);
+    }
+
+    #[test]
+    fn general_purpose_output() {
+        let file = clock(Rc::new(Cell::new(false)));
+        let clock = unsafe { &mut *file.block::<ClockRegisters>(0) };
+        let gpio_file = RegisterFile::new(core::mem::size_of::<GPIORegisters>());
+        let gpio = unsafe { &mut *gpio_file.block::<GPIORegisters>(0) };
+
+        let achieved = GPClock::GPCLK0.output(
+            clock,
+            gpio,
+            GPIOPin::PIN4,
+            ClockSource::PLLD,
+            24_000_000,
+            Mash::Stage1,
+        );
+        assert_eq!(achieved, Ok(24_000_000));
+        assert_eq!(clock.frequency(), Some(24_000_000));
+        assert!(gpio.pin_function_get(GPIOPin::PIN4) == GPIOFunction::ALT0);
+
+        assert_eq!(
+            GPClock::GPCLK2.route(gpio, GPIOPin::PIN4),
+            Err(ClockError::InvalidPin)
+        );
+        assert_eq!(
+            GPClock::GPCLK1.output(
+                clock,
+                gpio,
+                GPIOPin::PIN6,
+                ClockSource::Oscillator,
+                1_000_000,
+                Mash::Integer
+            ),
+            Err(ClockError::InvalidPin)
+        );
+        // left running at the previous frequency
+        assert_eq!(clock.frequency(), Some(24_000_000));
+        assert!(GPClock::GPCLK2.pins().contains(&GPIOPin::PIN43));
+    }
+}
@@ -0,0 +1,579 @@
+//! Kernel command line, the `bootargs` of the device tree's `/chosen` node. The firmware
+//! puts cmdline.txt there, after parameters of its own.
+//!
+//! Parameters are declared in tables of [`Param`] and registered with
+//! [`CommandLine::register`], [`PARAMS`] are the kernel's own. Words are `name=value`, or a
+//! bare `name` for flags, and values may be double quoted. Unknown names are skipped as the
+//! line is shared with Linux parameters. A value failing validation leaves the default in
+//! place and is kept in [`CommandLine::errors`] to be reported once a console is up.
+use crate::aux::peripherals::BaudRate;
+use crate::board::{self, Board};
+use crate::shell::{Command, CommandError, Shell};
+use core::fmt::{self, Write};
+
+pub const MAX_PARAMS: usize = 32;
+/// errors kept for reporting, later ones are only counted
+const MAX_ERRORS: usize = 8;
+
+/// `loglevel` of the messages printed at boot
+pub const LOG_ERROR: u32 = 3;
+pub const LOG_WARNING: u32 = 4;
+pub const LOG_INFO: u32 = 6;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Kind {
+    /// bare `name`, or `name=` on/off, yes/no, true/false, 1/0
+    Flag,
+    /// decimal, or hexadecimal with 0x
+    Number {
+        min: u32,
+        max: u32,
+    },
+    /// one of the listed words
+    Choice(&'static [&'static str]),
+    Text,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Value {
+    Flag(bool),
+    Number(u32),
+    /// index into the choices of the parameter
+    Choice(usize),
+    Text(&'static str),
+}
+
+/// further check of a parsed value, the error says what is wrong with it
+pub type Check = fn(Value) -> Result<(), &'static str>;
+
+pub struct Param {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: Kind,
+    /// of the same type as `kind`
+    pub default: Value,
+    /// further checks of a value of the right type
+    pub check: Option<Check>,
+}
+
+impl Param {
+    fn parse(&self, value: Option<&'static str>) -> Result<Value, Reason> {
+        match (self.kind, value) {
+            (Kind::Flag, None) => Ok(Value::Flag(true)),
+            (Kind::Flag, Some("1" | "on" | "yes" | "true")) => Ok(Value::Flag(true)),
+            (Kind::Flag, Some("0" | "off" | "no" | "false")) => Ok(Value::Flag(false)),
+            (Kind::Flag, Some(_)) => Err(Reason::NotAFlag),
+            (_, None) => Err(Reason::Missing),
+            (Kind::Number { min, max }, Some(value)) => {
+                let parsed = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                match parsed.map_err(|_| Reason::NotANumber)? {
+                    number if number < min || number > max => Err(Reason::OutOfRange { min, max }),
+                    number => Ok(Value::Number(number)),
+                }
+            }
+            (Kind::Choice(choices), Some(value)) => choices
+                .iter()
+                .position(|choice| *choice == value)
+                .map(Value::Choice)
+                .ok_or(Reason::NotAChoice(choices)),
+            (Kind::Text, Some(value)) => Ok(Value::Text(value)),
+        }
+    }
+}
+
+/// why a value was rejected
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Reason {
+    /// `name` without a value for a parameter that is not a flag
+    Missing,
+    NotAFlag,
+    NotANumber,
+    OutOfRange {
+        min: u32,
+        max: u32,
+    },
+    NotAChoice(&'static [&'static str]),
+    /// refused by [`Param::check`]
+    Check(&'static str),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CmdlineError {
+    /// more than [`MAX_PARAMS`] parameters registered
+    TooMany,
+    /// a parameter of that name is already registered
+    Duplicate(&'static str),
+    Invalid {
+        name: &'static str,
+        value: Option<&'static str>,
+        reason: Reason,
+    },
+    /// a quote is not closed, the rest of the line was taken as the last value
+    Unterminated,
+}
+
+impl fmt::Display for CmdlineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CmdlineError::TooMany => write!(f, "more than {MAX_PARAMS} parameters"),
+            CmdlineError::Duplicate(name) => write!(f, "{name} registered twice"),
+            CmdlineError::Unterminated => write!(f, "missing closing quote"),
+            CmdlineError::Invalid {
+                name,
+                value,
+                reason,
+            } => {
+                write!(f, "{name}")?;
+                if let Some(value) = value {
+                    write!(f, "={value}")?;
+                }
+                match reason {
+                    Reason::Missing => write!(f, ": needs a value"),
+                    Reason::NotAFlag => write!(f, ": expected on or off"),
+                    Reason::NotANumber => write!(f, ": not a number"),
+                    Reason::OutOfRange { min, max } => write!(f, ": outside {min}..{max}"),
+                    Reason::NotAChoice(choices) => {
+                        write!(f, ": expected one of")?;
+                        choices.iter().try_for_each(|choice| write!(f, " {choice}"))
+                    }
+                    Reason::Check(reason) => write!(f, ": {reason}"),
+                }
+            }
+        }
+    }
+}
+
+/// `name` and the value of each word of `line`, and whether a quote was left open
+fn words(line: &'static str) -> impl Iterator<Item = (&'static str, Option<&'static str>, bool)> {
+    let mut rest = line;
+    core::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let mut quoted = false;
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    quoted = !quoted;
+                }
+                c.is_whitespace() && !quoted
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (word, next) = rest.split_at(end);
+        rest = next;
+        let (name, value) = match word.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (word, None),
+        };
+        let value = value.map(|value| {
+            let value = value.strip_prefix('"').unwrap_or(value);
+            value.strip_suffix('"').unwrap_or(value)
+        });
+        Some((name, value, quoted))
+    })
+}
+
+pub struct CommandLine {
+    line: &'static str,
+    params: [Option<&'static Param>; MAX_PARAMS],
+    /// given on the command line, `None` for the default
+    values: [Option<Value>; MAX_PARAMS],
+    count: usize,
+    errors: [Option<CmdlineError>; MAX_ERRORS],
+    error_count: usize,
+}
+
+impl CommandLine {
+    pub const fn new() -> CommandLine {
+        CommandLine {
+            line: "",
+            params: [None; MAX_PARAMS],
+            values: [None; MAX_PARAMS],
+            count: 0,
+            errors: [None; MAX_ERRORS],
+            error_count: 0,
+        }
+    }
+
+    /// add `params`, values already on the command line apply to them right away
+    pub fn register(&mut self, params: &'static [Param]) -> Result<(), CmdlineError> {
+        if self.count + params.len() > MAX_PARAMS {
+            return Err(CmdlineError::TooMany);
+        }
+        if let Some(param) = params
+            .iter()
+            .enumerate()
+            .find(|(i, p)| {
+                self.find(p.name).is_some() || params[..*i].iter().any(|q| q.name == p.name)
+            })
+            .map(|(_, p)| p)
+        {
+            return Err(CmdlineError::Duplicate(param.name));
+        }
+        let first = self.count;
+        for param in params {
+            self.params[self.count] = Some(param);
+            self.count += 1;
+        }
+        self.apply(first);
+        Ok(())
+    }
+
+    /// Take the values of `line`, replacing those of an earlier line.
+    pub fn parse(&mut self, line: &'static str) {
+        self.line = line;
+        self.values = [None; MAX_PARAMS];
+        self.errors = [None; MAX_ERRORS];
+        self.error_count = 0;
+        if words(line).any(|(_, _, open)| open) {
+            self.error(CmdlineError::Unterminated);
+        }
+        self.apply(0);
+    }
+
+    /// set the parameters from index `first` on from the words of the line, the last
+    /// occurrence of a name wins
+    fn apply(&mut self, first: usize) {
+        for (name, value, _) in words(self.line) {
+            let Some(index) = self.find(name).filter(|index| *index >= first) else {
+                continue;
+            };
+            let param = self.params[index].unwrap();
+            let checked = param.parse(value).and_then(|parsed| {
+                match param.check.map_or(Ok(()), |check| check(parsed)) {
+                    Ok(()) => Ok(parsed),
+                    Err(reason) => Err(Reason::Check(reason)),
+                }
+            });
+            match checked {
+                Ok(parsed) => self.values[index] = Some(parsed),
+                Err(reason) => self.error(CmdlineError::Invalid {
+                    name: param.name,
+                    value,
+                    reason,
+                }),
+            }
+        }
+    }
+
+    fn error(&mut self, error: CmdlineError) {
+        if let Some(slot) = self.errors.get_mut(self.error_count) {
+            *slot = Some(error);
+        }
+        self.error_count += 1;
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.params[..self.count]
+            .iter()
+            .position(|param| param.is_some_and(|param| param.name == name))
+    }
+
+    pub fn line(&self) -> &'static str {
+        self.line
+    }
+
+    /// rejected values in the order of the line, the first [`MAX_ERRORS`] of them
+    pub fn errors(&self) -> impl Iterator<Item = CmdlineError> + '_ {
+        self.errors.iter().flatten().copied()
+    }
+
+    /// errors including those not kept
+    pub fn error_count(&self) -> usize {
+        self.error_count
+    }
+
+    /// registered parameters with their effective values and whether the line set them
+    pub fn params(&self) -> impl Iterator<Item = (&'static Param, Value, bool)> + '_ {
+        self.params[..self.count]
+            .iter()
+            .zip(&self.values)
+            .map(|(param, value)| {
+                let param = param.unwrap();
+                (param, value.unwrap_or(param.default), value.is_some())
+            })
+    }
+
+    /// effective value of `name`, `None` when it is not registered
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let index = self.find(name)?;
+        Some(self.values[index].unwrap_or(self.params[index]?.default))
+    }
+
+    /// whether the line gave a valid value for `name`
+    pub fn is_set(&self, name: &str) -> bool {
+        self.find(name)
+            .is_some_and(|index| self.values[index].is_some())
+    }
+
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            Value::Flag(flag) => Some(flag),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self, name: &str) -> Option<u32> {
+        match self.get(name)? {
+            Value::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    /// the chosen word of a [`Kind::Choice`]
+    pub fn choice(&self, name: &str) -> Option<&'static str> {
+        let param = self.params[self.find(name)?]?;
+        match (param.kind, self.get(name)?) {
+            (Kind::Choice(choices), Value::Choice(index)) => Some(choices[index]),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, name: &str) -> Option<&'static str> {
+        match self.get(name)? {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the PL011s of the board, see [`crate::pl011::UARTInstance`]
+const SHELL_UARTS: &[&str] = match board::BOARD {
+    Board::Raspi3B => &["mini", "uart0"],
+    Board::Raspi4B => &["mini", "uart0", "uart2", "uart3", "uart4", "uart5"],
+};
+
+fn check_baud(value: Value) -> Result<(), &'static str> {
+    match value {
+        Value::Number(rate) if BaudRate::try_from(rate).is_ok() => Ok(()),
+        _ => Err("not a rate of the mini UART"),
+    }
+}
+
+/// parameters of the kernel itself
+pub const PARAMS: &[Param] = &[
+    Param {
+        name: "loglevel",
+        help: "boot messages up to this level are printed, 3 errors, 4 warnings, 6 info",
+        kind: Kind::Number { min: 0, max: 7 },
+        default: Value::Number(7),
+        check: None,
+    },
+    Param {
+        name: "shell",
+        help: "UART the shell runs on",
+        kind: Kind::Choice(SHELL_UARTS),
+        default: Value::Choice(0),
+        check: None,
+    },
+    Param {
+        name: "baud",
+        help: "baud rate of the shell's UART",
+        kind: Kind::Number {
+            min: 476,
+            max: 921_600,
+        },
+        default: Value::Number(115_200),
+        check: Some(check_baud),
+    },
+    Param {
+        name: "ktest",
+        help: "run only the on-target tests whose name contains this",
+        kind: Kind::Text,
+        default: Value::Text(""),
+        check: None,
+    },
+];
+
+pub static mut COMMAND_LINE: CommandLine = CommandLine::new();
+
+/// the command line of the running kernel
+pub fn command_line() -> &'static CommandLine {
+    let line = &raw const COMMAND_LINE;
+    unsafe { &*line }
+}
+
+/// rate of the mini UART, `baud` when the shell runs on it and 115200 otherwise
+pub fn mini_uart_baud(line: &CommandLine) -> BaudRate {
+    let rate = match line.choice("shell") {
+        Some("mini") | None => line.number("baud"),
+        Some(_) => None,
+    };
+    rate.and_then(|rate| BaudRate::try_from(rate).ok())
+        .unwrap_or(BaudRate::Baud115200)
+}
+
+/// whether messages of `level` are printed at boot
+pub fn logs(level: u32) -> bool {
+    command_line().number("loglevel").unwrap_or(LOG_INFO) >= level
+}
+
+pub const COMMANDS: &[Command] = &[Command {
+    name: "cmdline",
+    usage: "[NAME]",
+    help: "kernel command line and the effective parameters, * marks those it set",
+    subcommands: &[],
+    run: cmdline,
+}];
+
+struct Show(Value, Kind);
+
+impl fmt::Display for Show {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.0, self.1) {
+            (Value::Flag(flag), _) => write!(f, "{}", if flag { "on" } else { "off" }),
+            (Value::Number(number), _) => write!(f, "{number}"),
+            (Value::Choice(index), Kind::Choice(choices)) => write!(f, "{}", choices[index]),
+            (Value::Choice(index), _) => write!(f, "#{index}"),
+            (Value::Text(text), _) => write!(f, "\"{text}\""),
+        }
+    }
+}
+
+fn cmdline(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let line = command_line();
+    match args {
+        [] => {
+            let _ = writeln!(shell, "{}", line.line());
+            for (param, value, set) in line.params() {
+                let mark = if set { '*' } else { ' ' };
+                let _ = writeln!(
+                    shell,
+                    "{mark} {:<10} {}",
+                    param.name,
+                    Show(value, param.kind)
+                );
+            }
+            for error in line.errors() {
+                let _ = writeln!(shell, "error: {error}");
+            }
+        }
+        [name] => {
+            let (param, value, _) = line
+                .params()
+                .find(|(param, _, _)| param.name == *name)
+                .ok_or(CommandError::Invalid("no such parameter"))?;
+            let _ = writeln!(shell, "{} = {}", param.name, Show(value, param.kind));
+            let _ = writeln!(shell, "{}", param.help);
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PARAMS: &[Param] = &[
+        Param {
+            name: "verbose",
+            help: "",
+            kind: Kind::Flag,
+            default: Value::Flag(false),
+            check: None,
+        },
+        Param {
+            name: "label",
+            help: "",
+            kind: Kind::Text,
+            default: Value::Text("none"),
+            check: None,
+        },
+    ];
+
+    #[test]
+    fn mini_uart() {
+        use crate::aux::peripherals::MiniUart;
+        use crate::mock::RegisterFile;
+        const AUX_MU_BAUD: usize = 0x68;
+
+        let file = RegisterFile::new(0x6c);
+        let uart = unsafe { &mut *file.block::<MiniUart>(0x40) };
+        let mut line = CommandLine::new();
+        line.register(PARAMS).unwrap();
+
+        line.parse("baud=9600");
+        uart.set_baudrate(mini_uart_baud(&line));
+        assert_eq!(file.peek(AUX_MU_BAUD), 3254);
+        // the rate is for the PL011 the shell moved to
+        line.parse("shell=uart0 baud=9600");
+        uart.set_baudrate(mini_uart_baud(&line));
+        assert_eq!(file.peek(AUX_MU_BAUD), 270);
+        line.parse("");
+        uart.set_baudrate(mini_uart_baud(&line));
+        assert_eq!(file.peek(AUX_MU_BAUD), 270);
+    }
+
+    #[test]
+    fn parse() {
+        let mut line = CommandLine::new();
+        line.register(PARAMS).unwrap();
+        line.parse(
+            "coherent_pool=1M 8250.nr_uarts=1 console=ttyS0,115200 \
+             loglevel=4 shell=uart0 baud=0x1c200 ktest=\"fat::\" baud=9600",
+        );
+        assert_eq!(line.errors().count(), 0);
+        assert_eq!(line.number("loglevel"), Some(4));
+        assert_eq!(line.choice("shell"), Some("uart0"));
+        assert_eq!(line.number("baud"), Some(9600));
+        assert_eq!(line.text("ktest"), Some("fat::"));
+        assert!(line.is_set("baud"));
+        assert_eq!(line.get("console"), None);
+
+        // registered later, the line applies to them as well
+        line.register(TEST_PARAMS).unwrap();
+        assert_eq!(line.flag("verbose"), Some(false));
+        line.parse("verbose label=\"two words\" loglevel=2");
+        assert_eq!(line.flag("verbose"), Some(true));
+        assert_eq!(line.text("label"), Some("two words"));
+        assert_eq!(line.choice("shell"), Some("mini"));
+        assert!(!line.is_set("shell"));
+        assert_eq!(line.params().filter(|(_, _, set)| *set).count(), 3);
+
+        assert_eq!(
+            line.register(TEST_PARAMS),
+            Err(CmdlineError::Duplicate("verbose"))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut line = CommandLine::new();
+        line.register(PARAMS).unwrap();
+        line.register(TEST_PARAMS).unwrap();
+        line.parse("loglevel=9 shell=uart9 baud=1000 baud loglevel=x verbose=maybe label=\"open");
+        let errors: Vec<String> = line.errors().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "missing closing quote",
+                "loglevel=9: outside 0..7",
+                "shell=uart9: expected one of mini uart0 uart2 uart3 uart4 uart5",
+                "baud=1000: not a rate of the mini UART",
+                "baud: needs a value",
+                "loglevel=x: not a number",
+                "verbose=maybe: expected on or off",
+            ]
+        );
+        // rejected values keep the defaults, the open quote takes the rest of the line
+        assert_eq!(line.number("loglevel"), Some(7));
+        assert_eq!(line.number("baud"), Some(115_200));
+        assert_eq!(line.text("label"), Some("open"));
+
+        line.parse("loglevel=8 ".repeat(10).leak());
+        assert_eq!(line.errors().count(), MAX_ERRORS);
+        assert_eq!(line.error_count(), 10);
+    }
+}
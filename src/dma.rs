@@ -0,0 +1,1095 @@
+//! BCM2711 DMA controller: legacy channels 0-6, lite channels 7-10 and DMA4 channels
+//! 11-14. The engines fetch control blocks and data through the VideoCore bus, so every
+//! address handed to them has to be translated, see `bus_address`. The engines bypass the
+//! CPU caches.
+use crate::registers::*;
+
+register_bitfields! {u32,
+    pub DMA_CS [
+        ACTIVE OFFSET(0) NUMBITS(1) [],
+        /// write 1 to clear
+        END OFFSET(1) NUMBITS(1) [],
+        /// write 1 to clear
+        INT OFFSET(2) NUMBITS(1) [],
+        DREQ OFFSET(3) NUMBITS(1) [],
+        PAUSED OFFSET(4) NUMBITS(1) [],
+        DREQ_STOPS_DMA OFFSET(5) NUMBITS(1) [],
+        WAITING_FOR_OUTSTANDING_WRITES OFFSET(6) NUMBITS(1) [],
+        ERROR OFFSET(8) NUMBITS(1) [],
+        PRIORITY OFFSET(16) NUMBITS(4) [],
+        PANIC_PRIORITY OFFSET(20) NUMBITS(4) [],
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+        DISDEBUG OFFSET(29) NUMBITS(1) [],
+        ABORT OFFSET(30) NUMBITS(1) [],
+        RESET OFFSET(31) NUMBITS(1) [],
+    ],
+    pub DMA_TI [
+        INTEN OFFSET(0) NUMBITS(1) [],
+        /// 2D mode, legacy channels only
+        TDMODE OFFSET(1) NUMBITS(1) [],
+        WAIT_RESP OFFSET(3) NUMBITS(1) [],
+        DEST_INC OFFSET(4) NUMBITS(1) [],
+        DEST_WIDTH OFFSET(5) NUMBITS(1) [
+            Bits32 = 0,
+            Bits128 = 1,
+        ],
+        DEST_DREQ OFFSET(6) NUMBITS(1) [],
+        DEST_IGNORE OFFSET(7) NUMBITS(1) [],
+        SRC_INC OFFSET(8) NUMBITS(1) [],
+        SRC_WIDTH OFFSET(9) NUMBITS(1) [
+            Bits32 = 0,
+            Bits128 = 1,
+        ],
+        SRC_DREQ OFFSET(10) NUMBITS(1) [],
+        SRC_IGNORE OFFSET(11) NUMBITS(1) [],
+        BURST_LENGTH OFFSET(12) NUMBITS(4) [],
+        /// peripheral whose DREQ paces the transfer
+        PERMAP OFFSET(16) NUMBITS(5) [],
+        WAITS OFFSET(21) NUMBITS(5) [],
+        NO_WIDE_BURSTS OFFSET(26) NUMBITS(1) [],
+    ],
+    pub DMA_TXFR_LEN [
+        XLENGTH OFFSET(0) NUMBITS(16) [],
+        /// 2D mode only
+        YLENGTH OFFSET(16) NUMBITS(14) [],
+    ],
+    pub DMA_DEBUG [
+        /// write 1 to clear
+        READ_LAST_NOT_SET_ERROR OFFSET(0) NUMBITS(1) [],
+        /// write 1 to clear
+        FIFO_ERROR OFFSET(1) NUMBITS(1) [],
+        /// write 1 to clear
+        READ_ERROR OFFSET(2) NUMBITS(1) [],
+        OUTSTANDING_WRITES OFFSET(4) NUMBITS(4) [],
+        DMA_ID OFFSET(8) NUMBITS(8) [],
+        DMA_STATE OFFSET(16) NUMBITS(9) [],
+        VERSION OFFSET(25) NUMBITS(3) [],
+        LITE OFFSET(28) NUMBITS(1) [],
+    ],
+    pub DMA4_CS [
+        ACTIVE OFFSET(0) NUMBITS(1) [],
+        /// write 1 to clear
+        END OFFSET(1) NUMBITS(1) [],
+        /// write 1 to clear
+        INT OFFSET(2) NUMBITS(1) [],
+        DREQ OFFSET(3) NUMBITS(1) [],
+        RD_PAUSED OFFSET(4) NUMBITS(1) [],
+        WR_PAUSED OFFSET(5) NUMBITS(1) [],
+        DREQ_STOPS_DMA OFFSET(6) NUMBITS(1) [],
+        WAITING_FOR_OUTSTANDING_WRITES OFFSET(7) NUMBITS(1) [],
+        ERROR OFFSET(10) NUMBITS(1) [],
+        QOS OFFSET(16) NUMBITS(4) [],
+        PANIC_QOS OFFSET(20) NUMBITS(4) [],
+        DMA_BUSY OFFSET(24) NUMBITS(1) [],
+        OUTSTANDING_TRANSACTIONS OFFSET(25) NUMBITS(1) [],
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+        DISDEBUG OFFSET(29) NUMBITS(1) [],
+        ABORT OFFSET(30) NUMBITS(1) [],
+        HALT OFFSET(31) NUMBITS(1) [],
+    ],
+    pub DMA4_TI [
+        INTEN OFFSET(0) NUMBITS(1) [],
+        TDMODE OFFSET(1) NUMBITS(1) [],
+        WAIT_RESP OFFSET(2) NUMBITS(1) [],
+        WAIT_RD_RESP OFFSET(3) NUMBITS(1) [],
+        PERMAP OFFSET(9) NUMBITS(5) [],
+        S_DREQ OFFSET(14) NUMBITS(1) [],
+        D_DREQ OFFSET(15) NUMBITS(1) [],
+        S_WAITS OFFSET(16) NUMBITS(8) [],
+        D_WAITS OFFSET(24) NUMBITS(8) [],
+    ],
+    /// source and destination information share one layout
+    pub DMA4_XI [
+        /// bits 32-39 of the bus address
+        ADDR OFFSET(0) NUMBITS(8) [],
+        BURST_LENGTH OFFSET(8) NUMBITS(4) [],
+        INC OFFSET(12) NUMBITS(1) [],
+        SIZE OFFSET(13) NUMBITS(2) [
+            Bits32 = 0,
+            Bits64 = 1,
+            Bits128 = 2,
+            Bits256 = 3,
+        ],
+        IGNORE OFFSET(15) NUMBITS(1) [],
+        STRIDE OFFSET(16) NUMBITS(16) [],
+    ],
+    pub DMA4_DEBUG [
+        /// write 1 to clear
+        WRITE_ERROR OFFSET(0) NUMBITS(1) [],
+        /// write 1 to clear
+        FIFO_ERROR OFFSET(1) NUMBITS(1) [],
+        /// write 1 to clear
+        READ_ERROR OFFSET(2) NUMBITS(1) [],
+        /// write 1 to clear
+        READ_CB_ERROR OFFSET(3) NUMBITS(1) [],
+        RESET OFFSET(23) NUMBITS(1) [],
+    ],
+}
+
+/// peripheral DREQ lines, `DMA_TI::PERMAP` values
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DREQ {
+    Always = 0,
+    PWM1 = 1,
+    PCMTx = 2,
+    PCMRx = 3,
+    SMI = 4,
+    PWM0 = 5,
+    SPI0Tx = 6,
+    SPI0Rx = 7,
+    BSCSlaveTx = 8,
+    BSCSlaveRx = 9,
+    EMMC = 11,
+    UART0Tx = 12,
+    SDHost = 13,
+    UART0Rx = 14,
+    DSI1 = 15,
+    SPI1Tx = 16,
+    HDMI = 17,
+    SPI1Rx = 18,
+    /// shared with UART3 TX
+    SPI4Tx = 19,
+    /// shared with UART3 RX
+    SPI4Rx = 20,
+    /// shared with UART5 TX
+    SPI5Tx = 21,
+    /// shared with UART5 RX
+    SPI5Rx = 22,
+    SPI6Tx = 23,
+    SPI6Rx = 24,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DMAError {
+    /// engine stopped on a read error, FIFO error or an AXI read without last flag
+    Transfer,
+    /// DMA4 engine failed to fetch a control block
+    ControlBlock,
+    /// length beyond what the channel can move in one control block
+    InvalidLength,
+    /// buffer or control block outside the RAM a legacy or lite engine reaches
+    Unreachable,
+}
+
+/// end of the RAM the legacy and lite engines reach, their addresses have 30 bits
+pub const BUS_RAM_END: usize = 0x4000_0000;
+
+/// Bus address of memory as seen by the legacy and lite engines (uncached alias). `None`
+/// when the memory is beyond the first 1GB of RAM they reach, like the stack of a Pi 4.
+pub fn bus_address<T: ?Sized>(ptr: *const T) -> Option<u32> {
+    const SDRAM_UNCACHED: u32 = 0xc000_0000;
+    let address = ptr as *const u8 as usize;
+    (address < BUS_RAM_END).then_some(address as u32 | SDRAM_UNCACHED)
+}
+
+fn reachable<T: ?Sized>(ptr: *const T) -> Result<u32, DMAError> {
+    bus_address(ptr).ok_or(DMAError::Unreachable)
+}
+
+/// Run `check` on every block chained to `head` by `next`, `head` included. Chains end at a
+/// zero link or loop, a loop is detected by a second cursor going twice as fast, which has
+/// been around the loop once the two meet.
+fn check_chain<'a, B>(
+    head: &'a B,
+    next: impl Fn(&'a B) -> Result<Option<&'a B>, DMAError>,
+    check: impl Fn(&B) -> Result<(), DMAError>,
+) -> Result<(), DMAError> {
+    check(head)?;
+    let (mut slow, mut fast) = (head, head);
+    loop {
+        for _ in 0..2 {
+            match next(fast)? {
+                Some(block) => fast = block,
+                None => return Ok(()),
+            }
+            check(fast)?;
+        }
+        slow = next(slow)?.unwrap_or(slow);
+        if core::ptr::eq(slow, fast) {
+            return Ok(());
+        }
+    }
+}
+
+const ARM_PERIPHERALS: usize = crate::board::PERIPHERAL_BASE;
+const BUS_PERIPHERALS: usize = 0x7e00_0000;
+
+/// ARM physical address of a peripheral register as seen by the legacy and lite engines
+pub fn peripheral_bus_address<T: ?Sized>(ptr: *const T) -> u32 {
+    (ptr as *const u8 as usize)
+        .wrapping_sub(ARM_PERIPHERALS)
+        .wrapping_add(BUS_PERIPHERALS) as u32
+}
+
+/// ARM physical address of a legacy bus address, memory or peripheral
+pub fn arm_address(bus: u32) -> usize {
+    match bus & 0xff00_0000 {
+        0x7e00_0000 => bus as usize - BUS_PERIPHERALS + ARM_PERIPHERALS,
+        _ => (bus & 0x3fff_ffff) as usize,
+    }
+}
+
+/// 40-bit address of memory as seen by the DMA4 engines, which reach all RAM
+pub fn dma4_address<T: ?Sized>(ptr: *const T) -> u64 {
+    ptr as *const u8 as usize as u64
+}
+
+/// 40-bit address of a peripheral register as seen by the DMA4 engines
+pub fn dma4_peripheral_address<T: ?Sized>(ptr: *const T) -> u64 {
+    const DMA4_PERIPHERALS: u64 = 0x4_7e00_0000;
+    (ptr as *const u8 as usize as u64)
+        .wrapping_sub(ARM_PERIPHERALS as u64)
+        .wrapping_add(DMA4_PERIPHERALS)
+}
+
+/// transfer description read by a legacy or lite engine, must be 32 byte aligned
+#[repr(C, align(32))]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ControlBlock {
+    pub ti: u32,
+    pub source_ad: u32,
+    pub dest_ad: u32,
+    pub txfr_len: u32,
+    pub stride: u32,
+    pub nextconbk: u32,
+    reserved: [u32; 2],
+}
+
+impl ControlBlock {
+    pub const fn new(
+        ti: FieldValue<u32, DMA_TI::Register>,
+        source: u32,
+        dest: u32,
+        len: u32,
+    ) -> Self {
+        ControlBlock {
+            ti: ti.value,
+            source_ad: source,
+            dest_ad: dest,
+            txfr_len: len,
+            stride: 0,
+            nextconbk: 0,
+            reserved: [0; 2],
+        }
+    }
+
+    /// memory to memory copy
+    pub fn copy(source: &[u8], dest: &mut [u8]) -> Result<Self, DMAError> {
+        let len = source.len().min(dest.len()) as u32;
+        Ok(ControlBlock::new(
+            DMA_TI::SRC_INC::SET + DMA_TI::DEST_INC::SET + DMA_TI::WAIT_RESP::SET,
+            reachable(source.as_ptr())?,
+            reachable(dest.as_ptr())?,
+            len,
+        ))
+    }
+
+    /// fill `dest` with the word `pattern` points to, `dest` length must be a multiple of 4
+    pub fn fill(pattern: &u32, dest: &mut [u8]) -> Result<Self, DMAError> {
+        Ok(ControlBlock::new(
+            DMA_TI::DEST_INC::SET + DMA_TI::WAIT_RESP::SET,
+            reachable(pattern)?,
+            reachable(dest.as_ptr())?,
+            dest.len() as u32,
+        ))
+    }
+
+    /// memory to peripheral register `dest`, paced by `dreq`
+    pub fn to_peripheral<T>(source: &[u8], dest: *const T, dreq: DREQ) -> Result<Self, DMAError> {
+        Ok(ControlBlock::new(
+            DMA_TI::SRC_INC::SET
+                + DMA_TI::DEST_DREQ::SET
+                + DMA_TI::WAIT_RESP::SET
+                + DMA_TI::PERMAP.val(dreq as u32),
+            reachable(source.as_ptr())?,
+            peripheral_bus_address(dest),
+            source.len() as u32,
+        ))
+    }
+
+    /// peripheral register `source` to memory, paced by `dreq`, `None` discards the data
+    pub fn from_peripheral<T>(
+        source: *const T,
+        dest: Option<&mut [u8]>,
+        len: u32,
+        dreq: DREQ,
+    ) -> Result<Self, DMAError> {
+        let (dest, ti) = match dest {
+            Some(dest) => (reachable(dest.as_ptr())?, DMA_TI::DEST_INC::SET),
+            None => (0, DMA_TI::DEST_IGNORE::SET),
+        };
+        Ok(ControlBlock::new(
+            ti + DMA_TI::SRC_DREQ::SET + DMA_TI::PERMAP.val(dreq as u32),
+            peripheral_bus_address(source),
+            dest,
+            len,
+        ))
+    }
+
+    /// 2D transfer of `rows` rows of `row_len` bytes, strides are added after each row
+    pub fn with_2d(
+        mut self,
+        row_len: u16,
+        rows: u16,
+        source_stride: i16,
+        dest_stride: i16,
+    ) -> Self {
+        self.ti |= DMA_TI::TDMODE::SET.value;
+        self.txfr_len = (DMA_TXFR_LEN::XLENGTH.val(row_len as u32)
+            + DMA_TXFR_LEN::YLENGTH.val(rows.saturating_sub(1) as u32))
+        .value;
+        self.stride = (source_stride as u16 as u32) | ((dest_stride as u16 as u32) << 16);
+        self
+    }
+
+    /// raise the channel interrupt once this block is done
+    pub fn with_interrupt(mut self) -> Self {
+        self.ti |= DMA_TI::INTEN::SET.value;
+        self
+    }
+
+    /// continue with `next` once this block is done
+    pub fn chain(&mut self, next: &ControlBlock) -> Result<(), DMAError> {
+        self.nextconbk = reachable(next)?;
+        Ok(())
+    }
+
+    /// link `blocks` in order into a scatter-gather list ending with the last one
+    pub fn chain_all(blocks: &mut [ControlBlock]) -> Result<(), DMAError> {
+        for i in 0..blocks.len() {
+            blocks[i].nextconbk = match blocks.get(i + 1) {
+                Some(next) => reachable(next)?,
+                None => 0,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// transfer description read by a DMA4 engine, must be 32 byte aligned
+#[repr(C, align(32))]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DMA4ControlBlock {
+    pub ti: u32,
+    pub src: u32,
+    pub srci: u32,
+    pub dest: u32,
+    pub desti: u32,
+    pub len: u32,
+    /// bus address shifted right by 5
+    pub next_cb: u32,
+    reserved: u32,
+}
+
+impl DMA4ControlBlock {
+    pub fn new(
+        ti: FieldValue<u32, DMA4_TI::Register>,
+        source: u64,
+        source_info: FieldValue<u32, DMA4_XI::Register>,
+        dest: u64,
+        dest_info: FieldValue<u32, DMA4_XI::Register>,
+        len: u32,
+    ) -> Self {
+        DMA4ControlBlock {
+            ti: ti.value,
+            src: source as u32,
+            srci: (source_info + DMA4_XI::ADDR.val((source >> 32) as u32)).value,
+            dest: dest as u32,
+            desti: (dest_info + DMA4_XI::ADDR.val((dest >> 32) as u32)).value,
+            len,
+            next_cb: 0,
+            reserved: 0,
+        }
+    }
+
+    /// memory to memory copy
+    pub fn copy(source: &[u8], dest: &mut [u8]) -> Self {
+        DMA4ControlBlock::new(
+            DMA4_TI::WAIT_RESP::SET,
+            dma4_address(source.as_ptr()),
+            DMA4_XI::INC::SET,
+            dma4_address(dest.as_ptr()),
+            DMA4_XI::INC::SET,
+            source.len().min(dest.len()) as u32,
+        )
+    }
+
+    /// fill `dest` with the word `pattern` points to, `dest` length must be a multiple of 4
+    pub fn fill(pattern: &u32, dest: &mut [u8]) -> Self {
+        DMA4ControlBlock::new(
+            DMA4_TI::WAIT_RESP::SET,
+            dma4_address(pattern),
+            DMA4_XI::INC::CLEAR,
+            dma4_address(dest.as_ptr()),
+            DMA4_XI::INC::SET,
+            dest.len() as u32,
+        )
+    }
+
+    /// memory to peripheral register `dest`, paced by `dreq`
+    pub fn to_peripheral<T>(source: &[u8], dest: *const T, dreq: DREQ) -> Self {
+        DMA4ControlBlock::new(
+            DMA4_TI::D_DREQ::SET + DMA4_TI::WAIT_RESP::SET + DMA4_TI::PERMAP.val(dreq as u32),
+            dma4_address(source.as_ptr()),
+            DMA4_XI::INC::SET,
+            dma4_peripheral_address(dest),
+            DMA4_XI::INC::CLEAR,
+            source.len() as u32,
+        )
+    }
+
+    /// peripheral register `source` to memory, paced by `dreq`
+    pub fn from_peripheral<T>(source: *const T, dest: &mut [u8], dreq: DREQ) -> Self {
+        DMA4ControlBlock::new(
+            DMA4_TI::S_DREQ::SET + DMA4_TI::PERMAP.val(dreq as u32),
+            dma4_peripheral_address(source),
+            DMA4_XI::INC::CLEAR,
+            dma4_address(dest.as_ptr()),
+            DMA4_XI::INC::SET,
+            dest.len() as u32,
+        )
+    }
+
+    /// raise the channel interrupt once this block is done
+    pub fn with_interrupt(mut self) -> Self {
+        self.ti |= DMA4_TI::INTEN::SET.value;
+        self
+    }
+
+    /// continue with `next` once this block is done
+    pub fn chain(&mut self, next: &DMA4ControlBlock) {
+        self.next_cb = (dma4_address(next) >> 5) as u32;
+    }
+
+    /// link `blocks` in order into a scatter-gather list ending with the last one
+    pub fn chain_all(blocks: &mut [DMA4ControlBlock]) {
+        for i in 0..blocks.len() {
+            blocks[i].next_cb = match blocks.get(i + 1) {
+                Some(next) => (dma4_address(next) >> 5) as u32,
+                None => 0,
+            };
+        }
+    }
+}
+
+/// register block of a legacy or lite channel
+#[repr(C)]
+pub struct DMAChannelRegisters {
+    cs: ReadWrite<u32, DMA_CS::Register>, /* 0x00 CS Control and Status */
+    conblk_ad: ReadWrite<u32>,            /* 0x04 CONBLK_AD Control Block Address */
+    ti: ReadOnly<u32, DMA_TI::Register>,  /* 0x08 TI Transfer Information */
+    source_ad: ReadOnly<u32>,             /* 0x0c SOURCE_AD Source Address */
+    dest_ad: ReadOnly<u32>,               /* 0x10 DEST_AD Destination Address */
+    txfr_len: ReadOnly<u32, DMA_TXFR_LEN::Register>, /* 0x14 TXFR_LEN Transfer Length */
+    stride: ReadOnly<u32>,                /* 0x18 STRIDE 2D Stride */
+    nextconbk: ReadWrite<u32>,            /* 0x1c NEXTCONBK Next Control Block Address */
+    debug: ReadWrite<u32, DMA_DEBUG::Register>, /* 0x20 DEBUG Debug */
+}
+
+register_layout! {
+    DMAChannelRegisters @ 0x00 .. 0x24 {
+        0x00 => cs,
+        0x04 => conblk_ad,
+        0x08 => ti,
+        0x0c => source_ad,
+        0x10 => dest_ad,
+        0x14 => txfr_len,
+        0x18 => stride,
+        0x1c => nextconbk,
+        0x20 => debug,
+    }
+}
+
+#[repr(C)]
+pub struct DMA4ChannelRegisters {
+    cs: ReadWrite<u32, DMA4_CS::Register>, /* 0x00 CS Control and Status */
+    cb: ReadWrite<u32>,                    /* 0x04 CB Control Block Address >> 5 */
+    padding0: u32,                         /* 0x08 */
+    debug: ReadWrite<u32, DMA4_DEBUG::Register>, /* 0x0c DEBUG Debug */
+    ti: ReadOnly<u32, DMA4_TI::Register>,  /* 0x10 TI Transfer Information */
+    src: ReadOnly<u32>,                    /* 0x14 SRC Source Address */
+    srci: ReadOnly<u32, DMA4_XI::Register>, /* 0x18 SRCI Source Information */
+    dest: ReadOnly<u32>,                   /* 0x1c DEST Destination Address */
+    desti: ReadOnly<u32, DMA4_XI::Register>, /* 0x20 DESTI Destination Information */
+    len: ReadOnly<u32>,                    /* 0x24 LEN Transfer Length */
+    next_cb: ReadWrite<u32>,               /* 0x28 NEXT_CB Next Control Block Address >> 5 */
+    debug2: ReadOnly<u32>,                 /* 0x2c DEBUG2 More Debug */
+}
+
+register_layout! {
+    DMA4ChannelRegisters @ 0x00 .. 0x30 {
+        0x00 => cs,
+        0x04 => cb,
+        0x08 => padding0,
+        0x0c => debug,
+        0x10 => ti,
+        0x14 => src,
+        0x18 => srci,
+        0x1c => dest,
+        0x20 => desti,
+        0x24 => len,
+        0x28 => next_cb,
+        0x2c => debug2,
+    }
+}
+
+/// interrupt status and enables shared by all channels
+#[repr(C)]
+pub struct DMAGlobalRegisters {
+    int_status: ReadOnly<u32>, /* 0xfe0 INT_STATUS Interrupt Status of each channel */
+    padding0: [u32; 3],        /* 0xfe4 */
+    enable: ReadWrite<u32>,    /* 0xff0 ENABLE Global Enable bits for each channel */
+}
+
+register_layout! {
+    DMAGlobalRegisters @ 0xfe0 .. 0xff4 {
+        0xfe0 => int_status,
+        0xfe4 => padding0,
+        0xff0 => enable,
+    }
+}
+
+const BASE: usize = crate::board::peripheral(0x7000);
+const CHANNEL_STRIDE: usize = 0x100;
+
+/// channel numbers by engine type
+pub const LEGACY_CHANNELS: core::ops::Range<usize> = 0..7;
+pub const LITE_CHANNELS: core::ops::Range<usize> = 7..11;
+pub const DMA4_CHANNELS: core::ops::Range<usize> = 11..15;
+
+impl DMAChannelRegisters {
+    /// longest transfer of a legacy channel
+    pub const MAX_LEN: usize = 0x3fff_ffff;
+    /// longest transfer of a lite channel
+    pub const LITE_MAX_LEN: usize = 0xffff;
+
+    /// legacy channel 0-6 or lite channel 7-10
+    pub const fn channel(channel: usize) -> *mut DMAChannelRegisters {
+        Self::at(BASE + channel * CHANNEL_STRIDE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut DMAChannelRegisters {
+        base as *mut DMAChannelRegisters
+    }
+
+    pub fn reset(&mut self) {
+        self.cs.write(DMA_CS::RESET::SET);
+    }
+
+    pub fn is_lite(&self) -> bool {
+        self.debug.is_set(DMA_DEBUG::LITE)
+    }
+
+    /// Start executing `block` and the blocks chained to it. They have to stay in place
+    /// until the transfer finishes.
+    pub fn start(&mut self, block: &ControlBlock) -> Result<(), DMAError> {
+        let max = match self.is_lite() {
+            true => Self::LITE_MAX_LEN,
+            false => Self::MAX_LEN,
+        };
+        let address = reachable(block)?;
+        // links made by `chain` are in the uncached alias of memory, anything else is not
+        // a control block
+        let next = |block: &ControlBlock| match block.nextconbk {
+            0 => Ok(None),
+            link if link & 0xc000_0000 == 0xc000_0000 => Ok(Some(unsafe {
+                &*(arm_address(link) as *const ControlBlock)
+            })),
+            _ => Err(DMAError::Unreachable),
+        };
+        check_chain(block, next, |block| match block.txfr_len as usize > max {
+            true => Err(DMAError::InvalidLength),
+            false => Ok(()),
+        })?;
+
+        self.cs.write(DMA_CS::END::SET + DMA_CS::INT::SET);
+        self.debug.write(
+            DMA_DEBUG::READ_LAST_NOT_SET_ERROR::SET
+                + DMA_DEBUG::FIFO_ERROR::SET
+                + DMA_DEBUG::READ_ERROR::SET,
+        );
+        self.conblk_ad.set(address);
+        self.cs.write(
+            DMA_CS::ACTIVE::SET
+                + DMA_CS::WAIT_FOR_OUTSTANDING_WRITES::SET
+                + DMA_CS::PRIORITY.val(8)
+                + DMA_CS::PANIC_PRIORITY.val(15),
+        );
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.cs.is_set(DMA_CS::ACTIVE)
+    }
+
+    pub fn error(&self) -> Result<(), DMAError> {
+        match self.cs.is_set(DMA_CS::ERROR) {
+            true => Err(DMAError::Transfer),
+            false => Ok(()),
+        }
+    }
+
+    /// CS fields a write has to repeat to leave the channel as it is, END and INT are
+    /// left out as writing back a set one would acknowledge it
+    fn control(&self) -> FieldValue<u32, DMA_CS::Register> {
+        let cs = self.cs.get();
+        let keep = |field: Field<u32, DMA_CS::Register>| field.val(field.read(cs));
+        keep(DMA_CS::ACTIVE)
+            + keep(DMA_CS::PRIORITY)
+            + keep(DMA_CS::PANIC_PRIORITY)
+            + keep(DMA_CS::WAIT_FOR_OUTSTANDING_WRITES)
+            + keep(DMA_CS::DISDEBUG)
+    }
+
+    /// stop the running transfer after the current control block
+    pub fn abort(&mut self) {
+        self.cs.write(self.control() + DMA_CS::ABORT::SET);
+    }
+
+    /// block until the last chained control block is done
+    pub fn wait(&self) -> Result<(), DMAError> {
+        while self.is_active() {
+            self.error()?;
+        }
+        self.error()
+    }
+
+    /// run `block` to completion
+    pub fn run(&mut self, block: &ControlBlock) -> Result<(), DMAError> {
+        self.start(block)?;
+        self.wait()
+    }
+
+    /// acknowledge the channel interrupt, returns true when it was raised
+    pub fn handle_interrupt(&mut self) -> bool {
+        let raised = self.cs.is_set(DMA_CS::INT);
+        if raised {
+            self.cs.write(self.control() + DMA_CS::INT::SET);
+        }
+        raised
+    }
+}
+
+impl DMA4ChannelRegisters {
+    pub const MAX_LEN: usize = 0x3fff_ffff;
+
+    /// DMA4 channel 11-14
+    pub const fn channel(channel: usize) -> *mut DMA4ChannelRegisters {
+        Self::at(BASE + channel * CHANNEL_STRIDE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut DMA4ChannelRegisters {
+        base as *mut DMA4ChannelRegisters
+    }
+
+    pub fn reset(&mut self) {
+        self.debug.write(DMA4_DEBUG::RESET::SET);
+    }
+
+    /// Start executing `block` and the blocks chained to it. They have to stay in place
+    /// until the transfer finishes.
+    pub fn start(&mut self, block: &DMA4ControlBlock) -> Result<(), DMAError> {
+        let next = |block: &DMA4ControlBlock| match block.next_cb {
+            0 => Ok(None),
+            link => Ok(Some(unsafe {
+                &*(((link as u64) << 5) as usize as *const DMA4ControlBlock)
+            })),
+        };
+        check_chain(block, next, |block| {
+            match block.len as usize > Self::MAX_LEN {
+                true => Err(DMAError::InvalidLength),
+                false => Ok(()),
+            }
+        })?;
+
+        self.cs.write(DMA4_CS::END::SET + DMA4_CS::INT::SET);
+        self.debug.write(
+            DMA4_DEBUG::WRITE_ERROR::SET
+                + DMA4_DEBUG::FIFO_ERROR::SET
+                + DMA4_DEBUG::READ_ERROR::SET
+                + DMA4_DEBUG::READ_CB_ERROR::SET,
+        );
+        self.cb.set((dma4_address(block) >> 5) as u32);
+        self.cs.write(
+            DMA4_CS::ACTIVE::SET
+                + DMA4_CS::WAIT_FOR_OUTSTANDING_WRITES::SET
+                + DMA4_CS::QOS.val(8)
+                + DMA4_CS::PANIC_QOS.val(15),
+        );
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.cs.is_set(DMA4_CS::ACTIVE)
+    }
+
+    pub fn error(&self) -> Result<(), DMAError> {
+        if !self.cs.is_set(DMA4_CS::ERROR) {
+            return Ok(());
+        }
+        match self.debug.is_set(DMA4_DEBUG::READ_CB_ERROR) {
+            true => Err(DMAError::ControlBlock),
+            false => Err(DMAError::Transfer),
+        }
+    }
+
+    /// CS fields a write has to repeat to leave the channel as it is, END and INT are
+    /// left out as writing back a set one would acknowledge it
+    fn control(&self) -> FieldValue<u32, DMA4_CS::Register> {
+        let cs = self.cs.get();
+        let keep = |field: Field<u32, DMA4_CS::Register>| field.val(field.read(cs));
+        keep(DMA4_CS::ACTIVE)
+            + keep(DMA4_CS::QOS)
+            + keep(DMA4_CS::PANIC_QOS)
+            + keep(DMA4_CS::WAIT_FOR_OUTSTANDING_WRITES)
+            + keep(DMA4_CS::DISDEBUG)
+    }
+
+    /// stop the running transfer after the current control block
+    pub fn abort(&mut self) {
+        self.cs.write(self.control() + DMA4_CS::ABORT::SET);
+    }
+
+    /// block until the last chained control block is done
+    pub fn wait(&self) -> Result<(), DMAError> {
+        while self.is_active() {
+            self.error()?;
+        }
+        self.error()
+    }
+
+    /// run `block` to completion
+    pub fn run(&mut self, block: &DMA4ControlBlock) -> Result<(), DMAError> {
+        self.start(block)?;
+        self.wait()
+    }
+
+    /// acknowledge the channel interrupt, returns true when it was raised
+    pub fn handle_interrupt(&mut self) -> bool {
+        let raised = self.cs.is_set(DMA4_CS::INT);
+        if raised {
+            self.cs.write(self.control() + DMA4_CS::INT::SET);
+        }
+        raised
+    }
+}
+
+impl DMAGlobalRegisters {
+    pub const fn new() -> *mut DMAGlobalRegisters {
+        Self::at(BASE + 0xfe0)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut DMAGlobalRegisters {
+        base as *mut DMAGlobalRegisters
+    }
+
+    /// channels with a pending interrupt, one bit per channel
+    pub fn pending(&self) -> u32 {
+        self.int_status.get() & 0xffff
+    }
+
+    pub fn enable_channel(&mut self, channel: usize) {
+        self.enable.set(self.enable.get() | (1 << channel));
+    }
+
+    pub fn disable_channel(&mut self, channel: usize) {
+        self.enable.set(self.enable.get() & !(1 << channel));
+    }
+}
+
+pub static mut DMA: DMA = DMA::new();
+pub struct DMA {
+    /// legacy channels 0-6 followed by lite channels 7-10
+    channels: [Option<*mut DMAChannelRegisters>; 11],
+    dma4_channels: [Option<*mut DMA4ChannelRegisters>; 4],
+    global: Option<*mut DMAGlobalRegisters>,
+}
+
+impl DMA {
+    const fn new() -> DMA {
+        let mut channels = [None; 11];
+        let mut i = 0;
+        while i < channels.len() {
+            channels[i] = Some(DMAChannelRegisters::channel(i));
+            i += 1;
+        }
+        let mut dma4_channels = [None; 4];
+        let mut i = 0;
+        while i < dma4_channels.len() {
+            dma4_channels[i] = Some(DMA4ChannelRegisters::channel(DMA4_CHANNELS.start + i));
+            i += 1;
+        }
+        DMA {
+            channels,
+            dma4_channels,
+            global: Some(DMAGlobalRegisters::new()),
+        }
+    }
+
+    /// legacy channel 0-6 or lite channel 7-10
+    pub fn take_channel(&mut self, channel: usize) -> *mut DMAChannelRegisters {
+        let p = self.channels[channel].take();
+        p.unwrap()
+    }
+
+    pub fn return_channel(&mut self, channel: usize, registers: *mut DMAChannelRegisters) {
+        self.channels[channel].replace(registers);
+    }
+
+    /// DMA4 channel 11-14
+    pub fn take_dma4_channel(&mut self, channel: usize) -> *mut DMA4ChannelRegisters {
+        let p = self.dma4_channels[channel - DMA4_CHANNELS.start].take();
+        p.unwrap()
+    }
+
+    pub fn return_dma4_channel(&mut self, channel: usize, registers: *mut DMA4ChannelRegisters) {
+        self.dma4_channels[channel - DMA4_CHANNELS.start].replace(registers);
+    }
+
+    pub fn take_global(&mut self) -> *mut DMAGlobalRegisters {
+        let p = self.global.take();
+        p.unwrap()
+    }
+
+    pub fn return_global(&mut self, global: *mut DMAGlobalRegisters) {
+        self.global.replace(global);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile, low_memory};
+
+    const CS: usize = 0x00;
+    const CONBLK_AD: usize = 0x04;
+    const DEBUG: usize = 0x20;
+    const DMA4_CB: usize = 0x04;
+    const DMA4_DEBUG: usize = 0x0c;
+
+    /// transfers finish as soon as they start, or fail when `error` is set in the
+    /// upper half of the CS storage
+    pub(crate) fn channel(error: u32) -> RegisterFile {
+        let file = RegisterFile::new(0x30);
+        file.on(
+            CS,
+            Behavior::Custom(Box::new(move |storage, access| {
+                if let Access::Write(value) = access {
+                    let status = storage.get(CS) & !(value & 0b110);
+                    let status = match value & 1 {
+                        1 if error != 0 => status | error | 1,
+                        1 => status | 0b110,
+                        _ => status,
+                    };
+                    storage.set(CS, status);
+                }
+                storage.get(CS)
+            })),
+        );
+        file
+    }
+
+    #[test]
+    fn control_blocks() {
+        let source = low_memory(1u8, 64);
+        let dest = low_memory(0u8, 64);
+
+        let copy = ControlBlock::copy(source, dest).unwrap().with_interrupt();
+        assert_eq!(copy.ti, (1 << 8) | (1 << 4) | (1 << 3) | 1);
+        assert_eq!(copy.source_ad, bus_address(source.as_ptr()).unwrap());
+        assert_eq!(copy.txfr_len, 64);
+
+        let pattern = &low_memory(0xdead_beef, 1)[0];
+        let fill = ControlBlock::fill(pattern, dest).unwrap();
+        assert_eq!(fill.ti, (1 << 4) | (1 << 3));
+
+        let fifo = 0xfe20_4004 as *const u32;
+        let tx = ControlBlock::to_peripheral(source, fifo, DREQ::SPI0Tx).unwrap();
+        assert_eq!(tx.dest_ad, 0x7e20_4004);
+        assert_eq!(tx.ti, (6 << 16) | (1 << 8) | (1 << 6) | (1 << 3));
+        let rx = ControlBlock::from_peripheral(fifo, None, 16, DREQ::SPI0Rx).unwrap();
+        assert_eq!(rx.ti, (7 << 16) | (1 << 10) | (1 << 7));
+
+        let rows = ControlBlock::copy(source, dest)
+            .unwrap()
+            .with_2d(8, 4, 8, -8);
+        assert_eq!(rows.ti & 0b10, 0b10);
+        assert_eq!(rows.txfr_len, (3 << 16) | 8);
+        assert_eq!(rows.stride, 0xfff8_0008);
+
+        let blocks = low_memory(ControlBlock::default(), 3);
+        blocks.copy_from_slice(&[copy, fill, rows]);
+        assert_eq!(ControlBlock::chain_all(blocks), Ok(()));
+        assert_eq!(blocks[0].nextconbk, bus_address(&blocks[1]).unwrap());
+        assert_eq!(blocks[1].nextconbk, bus_address(&blocks[2]).unwrap());
+        assert_eq!(blocks[2].nextconbk, 0);
+        assert_eq!(bus_address(&blocks[0]).unwrap() & 0x1f, 0);
+
+        // the host stack is as far out of reach as the one of a Pi 4
+        let mut far = [0u8; 64];
+        assert_eq!(
+            ControlBlock::copy(source, &mut far).err(),
+            Some(DMAError::Unreachable)
+        );
+        let mut far = [copy, fill];
+        assert_eq!(
+            ControlBlock::chain_all(&mut far),
+            Err(DMAError::Unreachable)
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            peripheral_bus_address(0xfe21_5040 as *const u32),
+            0x7e21_5040
+        );
+        assert_eq!(arm_address(0x7e21_5040), 0xfe21_5040);
+        assert_eq!(bus_address(0x0008_0000 as *const u8), Some(0xc008_0000));
+        assert_eq!(bus_address(0x3fff_fffc as *const u8), Some(0xffff_fffc));
+        assert_eq!(bus_address(0x4000_0000 as *const u8), None);
+        assert_eq!(arm_address(0xc008_0000), 0x0008_0000);
+        assert_eq!(
+            dma4_peripheral_address(0xfe20_4004 as *const u32),
+            0x4_7e20_4004
+        );
+
+        let block = DMA4ControlBlock::new(
+            DMA4_TI::WAIT_RESP::SET,
+            0x1_2345_6780,
+            DMA4_XI::INC::SET,
+            0x4_7e20_4004,
+            DMA4_XI::INC::CLEAR,
+            32,
+        );
+        assert_eq!((block.src, block.srci), (0x2345_6780, (1 << 12) | 0x01));
+        assert_eq!((block.dest, block.desti), (0x7e20_4004, 0x04));
+    }
+
+    #[test]
+    fn legacy_transfer() {
+        let file = channel(0);
+        let dma = unsafe { &mut *file.block::<DMAChannelRegisters>(0) };
+        let source = low_memory(0u8, 16);
+        let dest = low_memory(0u8, 16);
+        let block = ControlBlock::copy(source, dest).unwrap().with_interrupt();
+        let block = &low_memory(block, 1)[0];
+
+        assert_eq!(dma.run(block), Ok(()));
+        assert_eq!(file.peek(CONBLK_AD), bus_address(block).unwrap());
+        assert!(dma.handle_interrupt());
+        assert!(!dma.handle_interrupt());
+
+        // a block on the stack never reaches the engine
+        let far = *block;
+        assert_eq!(dma.start(&far), Err(DMAError::Unreachable));
+
+        let file = channel(1 << 8);
+        let dma = unsafe { &mut *file.block::<DMAChannelRegisters>(0) };
+        assert_eq!(dma.run(block), Err(DMAError::Transfer));
+        // the channel stays stuck on the failed block
+        assert!(dma.is_active());
+    }
+
+    #[test]
+    fn lite_length() {
+        let file = channel(0);
+        let dma = unsafe { &mut *file.block::<DMAChannelRegisters>(0) };
+        file.poke(DEBUG, 1 << 28);
+
+        let block = ControlBlock::new(DMA_TI::SRC_INC::SET, 0, 0, 0x1_0000);
+        let block = &low_memory(block, 1)[0];
+        assert!(dma.is_lite());
+        assert_eq!(dma.start(block), Err(DMAError::InvalidLength));
+        file.poke(DEBUG, 0);
+        assert_eq!(dma.start(block), Ok(()));
+
+        // the second block of a chain is too long for a lite channel
+        let blocks = low_memory(ControlBlock::new(DMA_TI::SRC_INC::SET, 0, 0, 16), 3);
+        blocks[1].txfr_len = 0x1_0000;
+        ControlBlock::chain_all(&mut blocks[..2]).unwrap();
+        file.poke(DEBUG, 1 << 28);
+        assert_eq!(dma.start(&blocks[0]), Err(DMAError::InvalidLength));
+        file.poke(DEBUG, 0);
+        assert_eq!(dma.start(&blocks[0]), Ok(()));
+
+        // rings are walked once
+        file.poke(DEBUG, 1 << 28);
+        let (head, tail) = blocks.split_at_mut(2);
+        head[1].txfr_len = 16;
+        head[1].chain(&tail[0]).unwrap();
+        let ring = &tail[0] as *const ControlBlock;
+        tail[0].chain(unsafe { &*ring }).unwrap();
+        assert_eq!(dma.start(&blocks[0]), Ok(()));
+        blocks[2].txfr_len = 0x1_0000;
+        file.poke(DEBUG, 1 << 28);
+        assert_eq!(dma.start(&blocks[0]), Err(DMAError::InvalidLength));
+    }
+
+    #[test]
+    fn dma4_transfer() {
+        let file = channel(0);
+        let dma = unsafe { &mut *file.block::<DMA4ChannelRegisters>(0) };
+        let source = [0u8; 16];
+        let mut dest = [0u8; 16];
+        // links only hold 37 bits of address, the host stack is further out
+        let blocks = low_memory(DMA4ControlBlock::default(), 2);
+        blocks.copy_from_slice(&[
+            DMA4ControlBlock::copy(&source, &mut dest),
+            DMA4ControlBlock::fill(&0, &mut dest).with_interrupt(),
+        ]);
+        DMA4ControlBlock::chain_all(blocks);
+        assert_eq!(blocks[0].next_cb, (dma4_address(&blocks[1]) >> 5) as u32);
+
+        assert_eq!(dma.run(&blocks[0]), Ok(()));
+        assert_eq!(file.peek(DMA4_CB), (dma4_address(&blocks[0]) >> 5) as u32);
+        assert!(dma.handle_interrupt());
+
+        let file = channel(1 << 10);
+        let dma = unsafe { &mut *file.block::<DMA4ChannelRegisters>(0) };
+        file.on(DMA4_DEBUG, Behavior::ReadOnly)
+            .poke(DMA4_DEBUG, 1 << 3);
+        assert_eq!(dma.run(&blocks[0]), Err(DMAError::ControlBlock));
+
+        blocks[1].len = DMA4ChannelRegisters::MAX_LEN as u32 + 1;
+        assert_eq!(dma.start(&blocks[0]), Err(DMAError::InvalidLength));
+    }
+
+    #[test]
+    fn status_writes() {
+        // priorities, ACTIVE and both write-1-to-clear flags set
+        let running = (15 << 20) | (8 << 16) | 0b111;
+        let file = RegisterFile::new(0x30);
+        let dma = unsafe { &mut *file.block::<DMAChannelRegisters>(0) };
+        file.poke(CS, running);
+        assert!(dma.handle_interrupt());
+        assert_eq!(file.peek(CS), (15 << 20) | (8 << 16) | 0b101);
+        file.poke(CS, running);
+        dma.abort();
+        assert_eq!(file.peek(CS), (1 << 30) | (15 << 20) | (8 << 16) | 1);
+
+        let dma = unsafe { &mut *file.block::<DMA4ChannelRegisters>(0) };
+        file.poke(CS, running);
+        assert!(dma.handle_interrupt());
+        assert_eq!(file.peek(CS), (15 << 20) | (8 << 16) | 0b101);
+        file.poke(CS, running);
+        dma.abort();
+        assert_eq!(file.peek(CS), (1 << 30) | (15 << 20) | (8 << 16) | 1);
+    }
+
+    #[test]
+    fn global_interrupts() {
+        let file = RegisterFile::new(0x20);
+        let global = unsafe { &mut *file.block::<DMAGlobalRegisters>(0) };
+        global.enable_channel(11);
+        global.enable_channel(2);
+        global.disable_channel(11);
+        assert_eq!(file.peek(0x10), 1 << 2);
+
+        file.poke(0x00, (1 << 2) | (1 << 20));
+        assert_eq!(global.pending(), 1 << 2);
+    }
+}
@@ -0,0 +1,959 @@
+//! BCM2711 EMMC2 controller (SDHCI) and SD memory cards attached to it
+use crate::block::{BlockDevice, BlockError};
+use crate::board::EMMC_CLOCK as BASE_CLOCK;
+use crate::registers::*;
+use crate::timer::{self, Timeout};
+
+const IDENTIFICATION_CLOCK: u32 = 400_000;
+const TRANSFER_CLOCK: u32 = 25_000_000;
+const BLOCK_SIZE: usize = 512;
+
+const COMMAND_TIMEOUT_US: u64 = 100_000;
+const DATA_TIMEOUT_US: u64 = 500_000;
+const RESET_TIMEOUT_US: u64 = 100_000;
+/// ACMD41 keeps reporting busy while the card powers up
+const POWER_UP_TIMEOUT_US: u64 = 1_000_000;
+/// attempts of a block transfer before giving up
+const RETRIES: usize = 3;
+
+register_bitfields! {u32,
+    pub EMMC_BLKSIZECNT [
+        BLKSIZE OFFSET(0) NUMBITS(10) [],
+        BLKCNT OFFSET(16) NUMBITS(16) [],
+    ],
+    pub EMMC_CMDTM [
+        TM_BLKCNT_EN OFFSET(1) NUMBITS(1) [],
+        TM_AUTO_CMD_EN OFFSET(2) NUMBITS(2) [
+            None = 0,
+            CMD12 = 1,
+            CMD23 = 2,
+        ],
+        /// card to host
+        TM_DAT_DIR OFFSET(4) NUMBITS(1) [],
+        TM_MULTI_BLOCK OFFSET(5) NUMBITS(1) [],
+        CMD_RSPNS_TYPE OFFSET(16) NUMBITS(2) [
+            None = 0,
+            Bits136 = 1,
+            Bits48 = 2,
+            Bits48Busy = 3,
+        ],
+        CMD_CRCCHK_EN OFFSET(19) NUMBITS(1) [],
+        CMD_IXCHK_EN OFFSET(20) NUMBITS(1) [],
+        CMD_ISDATA OFFSET(21) NUMBITS(1) [],
+        CMD_TYPE OFFSET(22) NUMBITS(2) [
+            Normal = 0,
+            Suspend = 1,
+            Resume = 2,
+            Abort = 3,
+        ],
+        CMD_INDEX OFFSET(24) NUMBITS(6) [],
+    ],
+    pub EMMC_STATUS [
+        CMD_INHIBIT OFFSET(0) NUMBITS(1) [],
+        DAT_INHIBIT OFFSET(1) NUMBITS(1) [],
+        DAT_ACTIVE OFFSET(2) NUMBITS(1) [],
+        WRITE_TRANSFER OFFSET(8) NUMBITS(1) [],
+        READ_TRANSFER OFFSET(9) NUMBITS(1) [],
+        CARD_INSERTED OFFSET(16) NUMBITS(1) [],
+        DAT_LEVEL0 OFFSET(20) NUMBITS(4) [],
+        CMD_LEVEL OFFSET(24) NUMBITS(1) [],
+    ],
+    pub EMMC_CONTROL0 [
+        /// 4-bit data bus
+        HCTL_DWIDTH OFFSET(1) NUMBITS(1) [],
+        HCTL_HS_EN OFFSET(2) NUMBITS(1) [],
+        HCTL_8BIT OFFSET(5) NUMBITS(1) [],
+        /// SD bus power
+        POWER OFFSET(8) NUMBITS(1) [],
+        VOLTAGE OFFSET(9) NUMBITS(3) [
+            V1_8 = 5,
+            V3_0 = 6,
+            V3_3 = 7,
+        ],
+    ],
+    pub EMMC_CONTROL1 [
+        CLK_INTLEN OFFSET(0) NUMBITS(1) [],
+        CLK_STABLE OFFSET(1) NUMBITS(1) [],
+        CLK_EN OFFSET(2) NUMBITS(1) [],
+        CLK_GENSEL OFFSET(5) NUMBITS(1) [],
+        /// bits 9:8 of the 10-bit divider
+        CLK_FREQ_MS2 OFFSET(6) NUMBITS(2) [],
+        /// bits 7:0 of the 10-bit divider
+        CLK_FREQ8 OFFSET(8) NUMBITS(8) [],
+        /// data timeout is base clock * 2^(DATA_TOUNIT + 13)
+        DATA_TOUNIT OFFSET(16) NUMBITS(4) [],
+        SRST_HC OFFSET(24) NUMBITS(1) [],
+        SRST_CMD OFFSET(25) NUMBITS(1) [],
+        SRST_DATA OFFSET(26) NUMBITS(1) [],
+    ],
+    /// also the layout of IRPT_MASK and IRPT_EN
+    pub EMMC_INTERRUPT [
+        CMD_DONE OFFSET(0) NUMBITS(1) [],
+        DATA_DONE OFFSET(1) NUMBITS(1) [],
+        BLOCK_GAP OFFSET(2) NUMBITS(1) [],
+        WRITE_RDY OFFSET(4) NUMBITS(1) [],
+        READ_RDY OFFSET(5) NUMBITS(1) [],
+        CARD OFFSET(8) NUMBITS(1) [],
+        RETUNE OFFSET(12) NUMBITS(1) [],
+        BOOTACK OFFSET(13) NUMBITS(1) [],
+        ENDBOOT OFFSET(14) NUMBITS(1) [],
+        ERR OFFSET(15) NUMBITS(1) [],
+        CTO_ERR OFFSET(16) NUMBITS(1) [],
+        CCRC_ERR OFFSET(17) NUMBITS(1) [],
+        CEND_ERR OFFSET(18) NUMBITS(1) [],
+        CBAD_ERR OFFSET(19) NUMBITS(1) [],
+        DTO_ERR OFFSET(20) NUMBITS(1) [],
+        DCRC_ERR OFFSET(21) NUMBITS(1) [],
+        DEND_ERR OFFSET(22) NUMBITS(1) [],
+        ACMD_ERR OFFSET(24) NUMBITS(1) [],
+        /// every error bit
+        ERRORS OFFSET(15) NUMBITS(17) [],
+    ],
+    pub EMMC_SLOTISR_VER [
+        SLOT_STATUS OFFSET(0) NUMBITS(8) [],
+        SDVERSION OFFSET(16) NUMBITS(8) [],
+        VENDOR OFFSET(24) NUMBITS(8) [],
+    ],
+}
+
+#[repr(C)]
+pub struct EMMCRegisters {
+    arg2: ReadWrite<u32>, /* 0x00 ARG2 ACMD23 Argument */
+    blksizecnt: ReadWrite<u32, EMMC_BLKSIZECNT::Register>, /* 0x04 BLKSIZECNT Block Size and Count */
+    arg1: ReadWrite<u32>,                                  /* 0x08 ARG1 Argument */
+    cmdtm: ReadWrite<u32, EMMC_CMDTM::Register>, /* 0x0c CMDTM Command and Transfer Mode */
+    resp: [ReadOnly<u32>; 4],                    /* 0x10 RESP0-3 Response bits 31:0 - 127:96 */
+    data: ReadWrite<u32>,                        /* 0x20 DATA Data */
+    status: ReadOnly<u32, EMMC_STATUS::Register>, /* 0x24 STATUS Status */
+    control0: ReadWrite<u32, EMMC_CONTROL0::Register>, /* 0x28 CONTROL0 Host Configuration bits */
+    control1: ReadWrite<u32, EMMC_CONTROL1::Register>, /* 0x2c CONTROL1 Host Configuration bits */
+    interrupt: ReadWrite<u32, EMMC_INTERRUPT::Register>, /* 0x30 INTERRUPT Interrupt Flags */
+    irpt_mask: ReadWrite<u32, EMMC_INTERRUPT::Register>, /* 0x34 IRPT_MASK Interrupt Flag Enable */
+    irpt_en: ReadWrite<u32, EMMC_INTERRUPT::Register>, /* 0x38 IRPT_EN Interrupt Generation Enable */
+    control2: ReadWrite<u32>,                          /* 0x3c CONTROL2 Host Configuration bits */
+    padding0: [u32; 0x2f],                             /* 0x40 */
+    slotisr_ver: ReadOnly<u32, EMMC_SLOTISR_VER::Register>, /* 0xfc SLOTISR_VER Slot Interrupt Status and Version */
+}
+
+register_layout! {
+    EMMCRegisters @ 0x00 .. 0x100 {
+        0x00 => arg2,
+        0x04 => blksizecnt,
+        0x08 => arg1,
+        0x0c => cmdtm,
+        0x10 => resp,
+        0x20 => data,
+        0x24 => status,
+        0x28 => control0,
+        0x2c => control1,
+        0x30 => interrupt,
+        0x34 => irpt_mask,
+        0x38 => irpt_en,
+        0x3c => control2,
+        0x40 => padding0,
+        0xfc => slotisr_ver,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SDError {
+    /// no response or no data in time
+    Timeout,
+    /// CRC, end bit or index error on the command or data lines
+    Crc,
+    /// other error interrupt, raw INTERRUPT bits
+    Controller(u32),
+    /// card did not finish powering up or does not support 3.3V
+    Unusable,
+    /// controller did not leave reset or stabilise its clock
+    Reset,
+}
+
+impl SDError {
+    fn from_interrupt(interrupt: u32) -> SDError {
+        let set = |field: Field<u32, EMMC_INTERRUPT::Register>| field.is_set(interrupt);
+        if set(EMMC_INTERRUPT::CTO_ERR) || set(EMMC_INTERRUPT::DTO_ERR) {
+            SDError::Timeout
+        } else if set(EMMC_INTERRUPT::CCRC_ERR)
+            || set(EMMC_INTERRUPT::CEND_ERR)
+            || set(EMMC_INTERRUPT::CBAD_ERR)
+            || set(EMMC_INTERRUPT::DCRC_ERR)
+            || set(EMMC_INTERRUPT::DEND_ERR)
+        {
+            SDError::Crc
+        } else {
+            SDError::Controller(interrupt)
+        }
+    }
+}
+
+impl From<SDError> for BlockError {
+    fn from(error: SDError) -> Self {
+        BlockError::Device(match error {
+            SDError::Timeout => 1,
+            SDError::Crc => 2,
+            SDError::Controller(bits) => bits,
+            SDError::Unusable => 3,
+            SDError::Reset => 4,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Response {
+    None,
+    /// R2
+    Long,
+    /// R1, R3, R6, R7
+    Short,
+    /// R1b
+    Busy,
+}
+
+/// SD commands used by the driver, `APP` ones are prefixed by CMD55
+#[derive(Clone, Copy)]
+struct Command {
+    index: u32,
+    response: Response,
+    app: bool,
+}
+
+impl Command {
+    const fn new(index: u32, response: Response) -> Command {
+        Command {
+            index,
+            response,
+            app: false,
+        }
+    }
+
+    const fn app(index: u32, response: Response) -> Command {
+        Command {
+            index,
+            response,
+            app: true,
+        }
+    }
+}
+
+const GO_IDLE_STATE: Command = Command::new(0, Response::None);
+const ALL_SEND_CID: Command = Command::new(2, Response::Long);
+const SEND_RELATIVE_ADDR: Command = Command::new(3, Response::Short);
+const SELECT_CARD: Command = Command::new(7, Response::Busy);
+const SEND_IF_COND: Command = Command::new(8, Response::Short);
+const SEND_CSD: Command = Command::new(9, Response::Long);
+const SET_BLOCKLEN: Command = Command::new(16, Response::Short);
+const READ_SINGLE_BLOCK: Command = Command::new(17, Response::Short);
+const READ_MULTIPLE_BLOCK: Command = Command::new(18, Response::Short);
+const WRITE_BLOCK: Command = Command::new(24, Response::Short);
+const WRITE_MULTIPLE_BLOCK: Command = Command::new(25, Response::Short);
+const APP_CMD: Command = Command::new(55, Response::Short);
+const SET_BUS_WIDTH: Command = Command::app(6, Response::Short);
+/// R3 carries no CRC
+const SD_SEND_OP_COND: Command = Command::app(41, Response::Short);
+
+/// SEND_IF_COND argument, 2.7-3.6V and a check pattern echoed back
+const IF_COND: u32 = 0x1aa;
+/// ACMD41 argument, 3.2-3.4V window and high capacity support
+const OP_COND: u32 = 0x4030_0000;
+const OCR_POWERED_UP: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+
+/// direction of a data transfer
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Data {
+    Read,
+    Write,
+}
+
+impl EMMCRegisters {
+    const BASE: usize = crate::board::peripheral(crate::board::EMMC_OFFSET);
+
+    pub const fn new() -> *mut EMMCRegisters {
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut EMMCRegisters {
+        base as *mut EMMCRegisters
+    }
+
+    /// reset the controller, power the bus at 3.3V and run the identification clock
+    fn reset(&mut self) -> Result<(), SDError> {
+        self.control1.write(EMMC_CONTROL1::SRST_HC::SET);
+        self.wait_reset(EMMC_CONTROL1::SRST_HC)?;
+
+        self.control0
+            .write(EMMC_CONTROL0::POWER::SET + EMMC_CONTROL0::VOLTAGE::V3_3);
+        self.control2.set(0);
+        self.set_clock(IDENTIFICATION_CLOCK)?;
+
+        // flags are polled, nothing is signalled to the interrupt controller
+        self.irpt_en.set(0);
+        self.irpt_mask.set(u32::MAX);
+        self.interrupt.set(u32::MAX);
+        Ok(())
+    }
+
+    /// run the card clock as close to `hz` as the 10-bit divider allows without going over,
+    /// returns the achieved frequency
+    pub fn set_clock(&mut self, hz: u32) -> Result<u32, SDError> {
+        let timeout = Timeout::after_us(COMMAND_TIMEOUT_US);
+        while self.status.is_set(EMMC_STATUS::CMD_INHIBIT)
+            || self.status.is_set(EMMC_STATUS::DAT_INHIBIT)
+        {
+            if timeout.expired() {
+                return Err(SDError::Timeout);
+            }
+        }
+
+        // output is base / (2 * divider), 0 passes the base clock through
+        let divider = BASE_CLOCK.div_ceil(2 * hz.max(1)).min(0x3ff);
+        let achieved = match divider {
+            0 => BASE_CLOCK,
+            divider => BASE_CLOCK / (2 * divider),
+        };
+
+        self.control1.modify(EMMC_CONTROL1::CLK_EN::CLEAR);
+        self.control1.modify(
+            EMMC_CONTROL1::CLK_FREQ8.val(divider & 0xff)
+                + EMMC_CONTROL1::CLK_FREQ_MS2.val(divider >> 8)
+                + EMMC_CONTROL1::DATA_TOUNIT.val(0xe)
+                + EMMC_CONTROL1::CLK_INTLEN::SET,
+        );
+        let timeout = Timeout::after_us(RESET_TIMEOUT_US);
+        while !self.control1.is_set(EMMC_CONTROL1::CLK_STABLE) {
+            if timeout.expired() {
+                return Err(SDError::Reset);
+            }
+        }
+        self.control1.modify(EMMC_CONTROL1::CLK_EN::SET);
+        timer::delay_us(10);
+        Ok(achieved)
+    }
+
+    fn wait_reset(&self, field: Field<u32, EMMC_CONTROL1::Register>) -> Result<(), SDError> {
+        let timeout = Timeout::after_us(RESET_TIMEOUT_US);
+        while self.control1.is_set(field) {
+            if timeout.expired() {
+                return Err(SDError::Reset);
+            }
+        }
+        Ok(())
+    }
+
+    /// reset the command and data state machines after an error
+    fn recover(&mut self) -> Result<(), SDError> {
+        self.control1
+            .modify(EMMC_CONTROL1::SRST_CMD::SET + EMMC_CONTROL1::SRST_DATA::SET);
+        let result = self
+            .wait_reset(EMMC_CONTROL1::SRST_CMD)
+            .and_then(|_| self.wait_reset(EMMC_CONTROL1::SRST_DATA));
+        self.interrupt.set(u32::MAX);
+        result
+    }
+
+    /// wait for `flag` in INTERRUPT and acknowledge it, failing on any error flag
+    fn wait_interrupt(
+        &mut self,
+        flag: Field<u32, EMMC_INTERRUPT::Register>,
+        timeout_us: u64,
+    ) -> Result<(), SDError> {
+        let timeout = Timeout::after_us(timeout_us);
+        loop {
+            let interrupt = self.interrupt.get();
+            if EMMC_INTERRUPT::ERRORS.is_set(interrupt) {
+                self.interrupt.set(interrupt);
+                return Err(SDError::from_interrupt(interrupt));
+            }
+            if flag.is_set(interrupt) {
+                self.interrupt.set(1 << flag.shift);
+                return Ok(());
+            }
+            if timeout.expired() {
+                return Err(SDError::Timeout);
+            }
+        }
+    }
+
+    fn command(&mut self, command: Command, argument: u32) -> Result<u32, SDError> {
+        self.command_data(command, argument, None, 0)
+    }
+
+    /// issue `command`, returns RESP0. Data commands transfer `blocks` blocks in the given
+    /// direction and only return once the command phase is done.
+    fn command_data(
+        &mut self,
+        command: Command,
+        argument: u32,
+        data: Option<Data>,
+        blocks: u32,
+    ) -> Result<u32, SDError> {
+        let timeout = Timeout::after_us(COMMAND_TIMEOUT_US);
+        let busy = matches!(command.response, Response::Busy) || data.is_some();
+        while self.status.is_set(EMMC_STATUS::CMD_INHIBIT)
+            || (busy && self.status.is_set(EMMC_STATUS::DAT_INHIBIT))
+        {
+            if timeout.expired() {
+                return Err(SDError::Timeout);
+            }
+        }
+
+        let response = match command.response {
+            Response::None => EMMC_CMDTM::CMD_RSPNS_TYPE::None,
+            Response::Long => EMMC_CMDTM::CMD_RSPNS_TYPE::Bits136 + EMMC_CMDTM::CMD_CRCCHK_EN::SET,
+            Response::Short => {
+                EMMC_CMDTM::CMD_RSPNS_TYPE::Bits48
+                    + EMMC_CMDTM::CMD_CRCCHK_EN.val((command.index != 41) as u32)
+                    + EMMC_CMDTM::CMD_IXCHK_EN.val((command.index != 41) as u32)
+            }
+            Response::Busy => {
+                EMMC_CMDTM::CMD_RSPNS_TYPE::Bits48Busy
+                    + EMMC_CMDTM::CMD_CRCCHK_EN::SET
+                    + EMMC_CMDTM::CMD_IXCHK_EN::SET
+            }
+        };
+        let transfer = match data {
+            None => EMMC_CMDTM::CMD_ISDATA::CLEAR,
+            Some(direction) => {
+                self.blksizecnt.write(
+                    EMMC_BLKSIZECNT::BLKSIZE.val(BLOCK_SIZE as u32)
+                        + EMMC_BLKSIZECNT::BLKCNT.val(blocks),
+                );
+                EMMC_CMDTM::CMD_ISDATA::SET
+                    + EMMC_CMDTM::TM_DAT_DIR.val((direction == Data::Read) as u32)
+                    + EMMC_CMDTM::TM_BLKCNT_EN.val((blocks > 1) as u32)
+                    + EMMC_CMDTM::TM_MULTI_BLOCK.val((blocks > 1) as u32)
+                    + match blocks > 1 {
+                        true => EMMC_CMDTM::TM_AUTO_CMD_EN::CMD12,
+                        false => EMMC_CMDTM::TM_AUTO_CMD_EN::None,
+                    }
+            }
+        };
+
+        self.arg1.set(argument);
+        self.cmdtm
+            .write(EMMC_CMDTM::CMD_INDEX.val(command.index) + response + transfer);
+        self.wait_interrupt(EMMC_INTERRUPT::CMD_DONE, COMMAND_TIMEOUT_US)?;
+        // a busy response is done once the card releases DAT0
+        if matches!(command.response, Response::Busy) {
+            self.wait_interrupt(EMMC_INTERRUPT::DATA_DONE, DATA_TIMEOUT_US)?;
+        }
+        Ok(self.resp[0].get())
+    }
+
+    fn app_command(&mut self, command: Command, argument: u32, rca: u32) -> Result<u32, SDError> {
+        self.command(APP_CMD, rca << 16)?;
+        self.command(command, argument)
+    }
+
+    /// the 120 response bits of an R2 response, CRC stripped
+    fn long_response(&self) -> [u32; 4] {
+        core::array::from_fn(|i| self.resp[i].get())
+    }
+}
+
+/// identified SD memory card in transfer state
+pub struct SDCard<'a> {
+    emmc: &'a mut EMMCRegisters,
+    rca: u32,
+    high_capacity: bool,
+    blocks: u64,
+}
+
+impl<'a> SDCard<'a> {
+    /// Identify the card in the slot and switch it to a 4-bit bus at 25 MHz.
+    pub fn init(emmc: &'a mut EMMCRegisters) -> Result<SDCard<'a>, SDError> {
+        emmc.reset()?;
+        emmc.command(GO_IDLE_STATE, 0)?;
+
+        // version 1 cards do not know CMD8
+        let version2 = match emmc.command(SEND_IF_COND, IF_COND) {
+            Ok(echo) if echo & 0xfff == IF_COND => true,
+            Ok(_) => return Err(SDError::Unusable),
+            Err(SDError::Timeout) => {
+                emmc.recover()?;
+                false
+            }
+            Err(error) => return Err(error),
+        };
+
+        let argument = match version2 {
+            true => OP_COND,
+            false => OP_COND & !OCR_HIGH_CAPACITY,
+        };
+        let timeout = Timeout::after_us(POWER_UP_TIMEOUT_US);
+        let ocr = loop {
+            let ocr = emmc.app_command(SD_SEND_OP_COND, argument, 0)?;
+            if ocr & OCR_POWERED_UP != 0 {
+                break ocr;
+            }
+            if timeout.expired() {
+                return Err(SDError::Unusable);
+            }
+            timer::delay_us(1_000);
+        };
+
+        emmc.command(ALL_SEND_CID, 0)?;
+        let rca = emmc.command(SEND_RELATIVE_ADDR, 0)? >> 16;
+        emmc.command(SEND_CSD, rca << 16)?;
+        let blocks = csd_blocks(emmc.long_response());
+
+        emmc.command(SELECT_CARD, rca << 16)?;
+        emmc.set_clock(TRANSFER_CLOCK)?;
+        // every SD memory card supports a 4-bit bus
+        emmc.app_command(SET_BUS_WIDTH, 2, rca)?;
+        emmc.control0.modify(EMMC_CONTROL0::HCTL_DWIDTH::SET);
+
+        let high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+        if !high_capacity {
+            emmc.command(SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        Ok(SDCard {
+            emmc,
+            rca,
+            high_capacity,
+            blocks,
+        })
+    }
+
+    pub fn is_high_capacity(&self) -> bool {
+        self.high_capacity
+    }
+
+    pub fn rca(&self) -> u32 {
+        self.rca
+    }
+
+    /// switch the card clock, returns the achieved frequency
+    pub fn set_clock(&mut self, hz: u32) -> Result<u32, SDError> {
+        self.emmc.set_clock(hz)
+    }
+
+    /// block address in the unit the card expects, bytes for standard capacity cards
+    fn address(&self, lba: u64) -> u32 {
+        match self.high_capacity {
+            true => lba as u32,
+            false => (lba * BLOCK_SIZE as u64) as u32,
+        }
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), SDError> {
+        let blocks = (buf.len() / BLOCK_SIZE) as u32;
+        let command = match blocks {
+            1 => READ_SINGLE_BLOCK,
+            _ => READ_MULTIPLE_BLOCK,
+        };
+        let address = self.address(lba);
+        self.emmc
+            .command_data(command, address, Some(Data::Read), blocks)?;
+        for block in buf.chunks_exact_mut(BLOCK_SIZE) {
+            self.emmc
+                .wait_interrupt(EMMC_INTERRUPT::READ_RDY, DATA_TIMEOUT_US)?;
+            for word in block.chunks_exact_mut(4) {
+                word.copy_from_slice(&self.emmc.data.get().to_le_bytes());
+            }
+        }
+        self.emmc
+            .wait_interrupt(EMMC_INTERRUPT::DATA_DONE, DATA_TIMEOUT_US)
+    }
+
+    fn write(&mut self, lba: u64, buf: &[u8]) -> Result<(), SDError> {
+        let blocks = (buf.len() / BLOCK_SIZE) as u32;
+        let command = match blocks {
+            1 => WRITE_BLOCK,
+            _ => WRITE_MULTIPLE_BLOCK,
+        };
+        let address = self.address(lba);
+        self.emmc
+            .command_data(command, address, Some(Data::Write), blocks)?;
+        for block in buf.chunks_exact(BLOCK_SIZE) {
+            self.emmc
+                .wait_interrupt(EMMC_INTERRUPT::WRITE_RDY, DATA_TIMEOUT_US)?;
+            for word in block.chunks_exact(4) {
+                self.emmc
+                    .data
+                    .set(u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+            }
+        }
+        self.emmc
+            .wait_interrupt(EMMC_INTERRUPT::DATA_DONE, DATA_TIMEOUT_US)
+    }
+
+    /// run `transfer` until it succeeds, resetting the controller state between attempts
+    fn retry(
+        &mut self,
+        mut transfer: impl FnMut(&mut Self) -> Result<(), SDError>,
+    ) -> Result<(), BlockError> {
+        let mut result = Ok(());
+        for _ in 0..RETRIES {
+            result = transfer(self);
+            match result {
+                Ok(()) => return Ok(()),
+                Err(_) => self.emmc.recover()?,
+            }
+        }
+        result.map_err(BlockError::from)
+    }
+}
+
+/// card capacity in 512 byte blocks from the CSD register
+fn csd_blocks(resp: [u32; 4]) -> u64 {
+    // the controller drops the CRC byte, so CSD bit n is response bit n - 8
+    let bits = |high: u32, low: u32| -> u64 {
+        let value = ((resp[3] as u128) << 96)
+            | ((resp[2] as u128) << 64)
+            | ((resp[1] as u128) << 32)
+            | resp[0] as u128;
+        ((value >> (low - 8)) & ((1 << (high - low + 1)) - 1)) as u64
+    };
+    match bits(127, 126) {
+        // CSD version 1: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of 2^READ_BL_LEN bytes
+        0 => {
+            let bytes = (bits(73, 62) + 1) << (bits(49, 47) + 2) << bits(83, 80);
+            bytes / BLOCK_SIZE as u64
+        }
+        // CSD version 2: (C_SIZE + 1) * 512KB
+        _ => (bits(69, 48) + 1) * 1024,
+    }
+}
+
+impl BlockDevice for SDCard<'_> {
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        // BLKCNT is 16 bits wide
+        for (i, chunk) in buf.chunks_mut(BLOCK_SIZE * 0xffff).enumerate() {
+            let lba = lba + (i * 0xffff) as u64;
+            self.retry(|card| card.read(lba, chunk))?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        for (i, chunk) in buf.chunks(BLOCK_SIZE * 0xffff).enumerate() {
+            let lba = lba + (i * 0xffff) as u64;
+            self.retry(|card| card.write(lba, chunk))?;
+        }
+        Ok(())
+    }
+}
+
+pub static mut EMMC: EMMC = EMMC::new();
+pub struct EMMC {
+    emmc: Option<*mut EMMCRegisters>,
+}
+
+impl EMMC {
+    const fn new() -> EMMC {
+        EMMC {
+            emmc: Some(EMMCRegisters::new()),
+        }
+    }
+
+    pub fn take_emmc(&mut self) -> *mut EMMCRegisters {
+        let p = self.emmc.take();
+        p.unwrap()
+    }
+
+    pub fn return_emmc(&mut self, emmc: *mut EMMCRegisters) {
+        self.emmc.replace(emmc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile, Storage};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    const BLKSIZECNT: usize = 0x04;
+    const ARG1: usize = 0x08;
+    const CMDTM: usize = 0x0c;
+    const RESP0: usize = 0x10;
+    const DATA: usize = 0x20;
+    const CONTROL0: usize = 0x28;
+    const CONTROL1: usize = 0x2c;
+    const INTERRUPT: usize = 0x30;
+
+    const CMD_DONE: u32 = 1 << 0;
+    const DATA_DONE: u32 = 1 << 1;
+    const WRITE_RDY: u32 = 1 << 4;
+    const READ_RDY: u32 = 1 << 5;
+    const ERR: u32 = 1 << 15;
+    const CTO_ERR: u32 = 1 << 16;
+    const DCRC_ERR: u32 = 1 << 21;
+
+    const BLOCKS: usize = 2048;
+    const RCA: u32 = 0x1234;
+
+    /// SD card answering the commands the driver sends
+    struct Card {
+        memory: Vec<u8>,
+        high_capacity: bool,
+        app: bool,
+        /// ACMD41 calls reporting busy before power up completes
+        busy_polls: usize,
+        /// data commands to fail with a CRC error
+        data_errors: usize,
+        interrupt: u32,
+        rx: VecDeque<u32>,
+        /// byte address and data of a running write
+        write: Option<(usize, Vec<u8>, usize)>,
+        commands: Vec<(u32, u32)>,
+    }
+
+    impl Card {
+        fn command(&mut self, storage: &mut Storage, index: u32) {
+            let argument = storage.get(ARG1);
+            let blocks = (storage.get(BLKSIZECNT) >> 16) as usize;
+            let app = core::mem::take(&mut self.app);
+            self.commands.push((index, argument));
+            let mut response = 0;
+            let mut flags = CMD_DONE;
+            match (app, index) {
+                (_, 55) => {
+                    self.app = true;
+                    response = 0x120;
+                }
+                (false, 8) if !self.high_capacity => flags = ERR | CTO_ERR,
+                (false, 8) => response = argument,
+                (true, 41) => {
+                    response = 0x00ff_8000;
+                    match self.busy_polls {
+                        0 => response |= (1 << 31) | ((self.high_capacity as u32) << 30),
+                        _ => self.busy_polls -= 1,
+                    }
+                }
+                (false, 3) => response = RCA << 16,
+                (false, 9) => {
+                    // CSD version 2, C_SIZE counts 512KB units
+                    storage.set(RESP0 + 4, ((BLOCKS / 1024 - 1) as u32) << 8);
+                    storage.set(RESP0 + 12, 1 << 22);
+                }
+                (false, 7) => flags |= DATA_DONE,
+                (false, 17 | 18 | 24 | 25) if self.data_errors > 0 => {
+                    self.data_errors -= 1;
+                    flags |= ERR | DCRC_ERR;
+                }
+                (false, 17 | 18) => {
+                    let start = self.byte_address(argument);
+                    for word in self.memory[start..start + blocks * BLOCK_SIZE].chunks(4) {
+                        self.rx
+                            .push_back(u32::from_le_bytes(word.try_into().unwrap()));
+                    }
+                }
+                (false, 24 | 25) => {
+                    let start = self.byte_address(argument);
+                    self.write = Some((start, Vec::new(), blocks * BLOCK_SIZE));
+                }
+                _ => {}
+            }
+            storage.set(RESP0, response);
+            self.interrupt |= flags;
+        }
+
+        fn byte_address(&self, argument: u32) -> usize {
+            match self.high_capacity {
+                true => argument as usize * BLOCK_SIZE,
+                false => argument as usize,
+            }
+        }
+
+        fn data(&mut self, access: Access) -> u32 {
+            match access {
+                Access::Read => {
+                    let word = self.rx.pop_front().unwrap_or(0);
+                    if self.rx.is_empty() {
+                        self.interrupt |= DATA_DONE;
+                    }
+                    word
+                }
+                Access::Write(word) => {
+                    let (start, mut data, len) = self.write.take().unwrap();
+                    data.extend_from_slice(&word.to_le_bytes());
+                    match data.len() == len {
+                        true => {
+                            self.memory[start..start + len].copy_from_slice(&data);
+                            self.interrupt |= DATA_DONE;
+                        }
+                        false => self.write = Some((start, data, len)),
+                    }
+                    0
+                }
+            }
+        }
+
+        /// data ready flags follow the block boundaries of the running transfer
+        fn ready(&self) -> u32 {
+            let read = !self.rx.is_empty() && self.rx.len() % (BLOCK_SIZE / 4) == 0;
+            let write = matches!(&self.write, Some((_, data, _)) if data.len() % BLOCK_SIZE == 0);
+            ((read as u32) * READ_RDY) | ((write as u32) * WRITE_RDY)
+        }
+    }
+
+    fn card(high_capacity: bool) -> (RegisterFile, Rc<RefCell<Card>>) {
+        let file = RegisterFile::new(0x100);
+        let card = Rc::new(RefCell::new(Card {
+            memory: (0..BLOCKS * BLOCK_SIZE)
+                .map(|i| (i / BLOCK_SIZE) as u8)
+                .collect(),
+            high_capacity,
+            app: false,
+            busy_polls: 2,
+            data_errors: 0,
+            interrupt: 0,
+            rx: VecDeque::new(),
+            write: None,
+            commands: Vec::new(),
+        }));
+
+        let c = card.clone();
+        file.on(
+            CMDTM,
+            Behavior::Custom(Box::new(move |storage, access| {
+                if let Access::Write(value) = access {
+                    storage.set(CMDTM, value);
+                    c.borrow_mut().command(storage, value >> 24);
+                }
+                storage.get(CMDTM)
+            })),
+        );
+        let c = card.clone();
+        file.on(
+            INTERRUPT,
+            Behavior::Custom(Box::new(move |_, access| {
+                let mut card = c.borrow_mut();
+                if let Access::Write(value) = access {
+                    card.interrupt &= !value;
+                }
+                card.interrupt | card.ready()
+            })),
+        );
+        let c = card.clone();
+        file.on(
+            DATA,
+            Behavior::Custom(Box::new(move |_, access| c.borrow_mut().data(access))),
+        );
+        // resets finish and the clock is stable right away
+        file.on(
+            CONTROL1,
+            Behavior::Custom(Box::new(|storage, access| {
+                if let Access::Write(value) = access {
+                    storage.set(CONTROL1, value & !(0b111 << 24));
+                }
+                storage.get(CONTROL1) | 0b10
+            })),
+        );
+        (file, card)
+    }
+
+    #[test]
+    fn identification() {
+        let (file, card) = card(true);
+        let emmc = unsafe { &mut *file.block::<EMMCRegisters>(0) };
+        let sd = SDCard::init(emmc).unwrap();
+
+        assert!(sd.is_high_capacity());
+        assert_eq!(sd.rca(), RCA);
+        assert_eq!(sd.block_count(), BLOCKS as u64);
+        // 4-bit bus, powered at 3.3V
+        assert_eq!(file.peek(CONTROL0), (7 << 9) | (1 << 8) | (1 << 1));
+        // 100 MHz / (2 * 2)
+        assert_eq!((file.peek(CONTROL1) >> 8) & 0xff, 2);
+
+        let commands: Vec<u32> = card.borrow().commands.iter().map(|c| c.0).collect();
+        assert_eq!(commands, [0, 8, 55, 41, 55, 41, 55, 41, 2, 3, 9, 7, 55, 6]);
+        assert_eq!(card.borrow().commands[12], (55, RCA << 16));
+    }
+
+    #[test]
+    fn read_write() {
+        let (file, card) = card(true);
+        let emmc = unsafe { &mut *file.block::<EMMCRegisters>(0) };
+        let mut sd = SDCard::init(emmc).unwrap();
+
+        let mut buf = vec![0u8; 3 * BLOCK_SIZE];
+        sd.read_blocks(5, &mut buf).unwrap();
+        assert!(buf[..BLOCK_SIZE].iter().all(|b| *b == 5));
+        assert!(buf[2 * BLOCK_SIZE..].iter().all(|b| *b == 7));
+        assert_eq!(card.borrow().commands.last(), Some(&(18, 5)));
+
+        buf.fill(0xa5);
+        sd.write_blocks(100, &buf[..BLOCK_SIZE]).unwrap();
+        assert_eq!(card.borrow().commands.last(), Some(&(24, 100)));
+        sd.write_blocks(200, &buf).unwrap();
+        assert_eq!(card.borrow().commands.last(), Some(&(25, 200)));
+
+        let mut check = vec![0u8; BLOCK_SIZE];
+        sd.read_blocks(202, &mut check).unwrap();
+        assert!(check.iter().all(|b| *b == 0xa5));
+        assert_eq!(card.borrow().commands.last(), Some(&(17, 202)));
+
+        assert_eq!(
+            sd.read_blocks(BLOCKS as u64 - 1, &mut buf),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            sd.read_blocks(0, &mut buf[..100]),
+            Err(BlockError::InvalidBuffer)
+        );
+    }
+
+    #[test]
+    fn error_recovery() {
+        let (file, card) = card(true);
+        let emmc = unsafe { &mut *file.block::<EMMCRegisters>(0) };
+        let mut sd = SDCard::init(emmc).unwrap();
+        let mut buf = vec![0u8; BLOCK_SIZE];
+
+        card.borrow_mut().data_errors = 1;
+        sd.read_blocks(9, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 9));
+
+        card.borrow_mut().data_errors = RETRIES;
+        assert_eq!(
+            sd.read_blocks(9, &mut buf),
+            Err(BlockError::from(SDError::Crc))
+        );
+    }
+
+    #[test]
+    fn standard_capacity() {
+        let (file, card) = card(false);
+        let emmc = unsafe { &mut *file.block::<EMMCRegisters>(0) };
+        let mut sd = SDCard::init(emmc).unwrap();
+        assert!(!sd.is_high_capacity());
+
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        sd.read_blocks(3, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 3));
+        // byte addressing, block length set explicitly
+        let commands = card.borrow().commands.clone();
+        assert_eq!(commands.last(), Some(&(17, 3 * 512)));
+        assert!(commands.contains(&(16, 512)));
+        assert!(commands.contains(&(41, 0x0030_0000)));
+    }
+
+    #[test]
+    fn csd_capacity() {
+        // version 1: READ_BL_LEN 10, C_SIZE 4095, C_SIZE_MULT 7 is 2GB
+        let c_size = 4095u32;
+        let resp = [
+            0,
+            ((c_size & 0x3ff) << 22) | (7 << 7),
+            (10 << 8) | (c_size >> 10),
+            0,
+        ];
+        assert_eq!(csd_blocks(resp), 4096 * 512 * 1024 / 512);
+        // version 2: C_SIZE 15159 is about 8GB
+        assert_eq!(csd_blocks([0, 15159 << 8, 0, 1 << 22]), 15160 * 1024);
+    }
+}
@@ -0,0 +1,326 @@
+//! Exception vectors of the exception level the kernel runs at.
+//!
+//! Every exception saves a [`TrapFrame`] on the stack and ends up in [`handle`]. IRQs are
+//! dispatched by the GIC, everything else goes to the hook set with [`set_handler`], the
+//! GDB stub, or panics. Changes to the frame are in effect on return, except for `sp`.
+use crate::gic::{self, GICCPUInterface};
+
+/// registers saved on entry, `elr` is the return address
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrapFrame {
+    pub x: [u64; 31],
+    /// stack pointer before the exception, read only
+    pub sp: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub esr: u64,
+    pub far: u64,
+}
+
+// the vectors below hard code the layout
+const _: () = assert!(core::mem::size_of::<TrapFrame>() == 288);
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Kind {
+    Synchronous,
+    IRQ,
+    FIQ,
+    SError,
+}
+
+/// synchronous exception class from ESR
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Cause {
+    /// BRK instruction with its immediate
+    Breakpoint(u16),
+    /// software step completed
+    Step,
+    HardwareBreakpoint,
+    Watchpoint,
+    InstructionAbort,
+    DataAbort,
+    /// misaligned PC or SP
+    Alignment,
+    /// SVC, HVC or SMC with its immediate
+    Call(u16),
+    Undefined,
+    Other(u8),
+}
+
+impl Cause {
+    pub fn from_esr(esr: u64) -> Cause {
+        let iss = esr as u16;
+        match (esr >> 26) & 0x3f {
+            0x00 => Cause::Undefined,
+            0x15..=0x17 => Cause::Call(iss),
+            0x20 | 0x21 => Cause::InstructionAbort,
+            0x22 | 0x26 => Cause::Alignment,
+            0x24 | 0x25 => Cause::DataAbort,
+            0x30 | 0x31 => Cause::HardwareBreakpoint,
+            0x32 | 0x33 => Cause::Step,
+            0x34 | 0x35 => Cause::Watchpoint,
+            0x3c => Cause::Breakpoint(iss),
+            class => Cause::Other(class as u8),
+        }
+    }
+}
+
+static mut HANDLER: Option<fn(&mut TrapFrame, Kind)> = None;
+
+/// set while [`probe_read`] or [`probe_write`] access memory that may not exist
+static mut PROBING: bool = false;
+static mut FAULTED: bool = false;
+
+/// hook for all exceptions but IRQs
+pub fn set_handler(handler: Option<fn(&mut TrapFrame, Kind)>) {
+    unsafe { HANDLER = handler };
+}
+
+pub fn handle(frame: &mut TrapFrame, kind: Kind) {
+    if kind == Kind::IRQ {
+        let cpu = unsafe { &mut *GICCPUInterface::new() };
+        return gic::dispatch(cpu, frame);
+    }
+    let cause = Cause::from_esr(frame.esr);
+    // a faulting probe skips its load or store
+    if kind == Kind::Synchronous && cause == Cause::DataAbort && unsafe { PROBING } {
+        unsafe { FAULTED = true };
+        frame.elr += 4;
+        return;
+    }
+    match unsafe { HANDLER } {
+        Some(handler) => handler(frame, kind),
+        None => panic!(
+            "unhandled {kind:?} exception, {cause:?} at {:#x}, far {:#x}",
+            frame.elr, frame.far
+        ),
+    }
+}
+
+/// byte at `address`, `None` if reading it faults
+pub fn probe_read(address: usize) -> Option<u8> {
+    unsafe {
+        PROBING = true;
+        FAULTED = false;
+    }
+    #[cfg(target_arch = "aarch64")]
+    let value = {
+        let value: u32;
+        unsafe {
+            core::arch::asm!("ldrb {:w}, [{}]", out(reg) value, in(reg) address, options(nostack))
+        };
+        value as u8
+    };
+    #[cfg(not(target_arch = "aarch64"))]
+    let value = unsafe { core::ptr::read_volatile(address as *const u8) };
+    unsafe {
+        PROBING = false;
+        (!FAULTED).then_some(value)
+    }
+}
+
+/// false if writing `value` to `address` faults
+pub fn probe_write(address: usize, value: u8) -> bool {
+    unsafe {
+        PROBING = true;
+        FAULTED = false;
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("strb {:w}, [{}]", in(reg) value as u32, in(reg) address, options(nostack))
+    };
+    #[cfg(not(target_arch = "aarch64"))]
+    unsafe {
+        core::ptr::write_volatile(address as *mut u8, value)
+    };
+    unsafe {
+        PROBING = false;
+        !FAULTED
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    ".section .text.exceptions, \"ax\"",
+    ".balign 0x800",
+    ".global exception_vectors",
+    "exception_vectors:",
+    // current EL with SP0, current EL with SPx, lower EL AArch64, lower EL AArch32,
+    // each synchronous, IRQ, FIQ and SError
+    ".irp vector, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15",
+    ".balign 0x80",
+    "  sub sp, sp, #288",
+    "  stp x0, x1, [sp, #0]",
+    "  mov x0, #\\vector",
+    "  b exception_entry",
+    ".endr",
+    "exception_entry:",
+    "  stp x2, x3, [sp, #16]",
+    "  stp x4, x5, [sp, #32]",
+    "  stp x6, x7, [sp, #48]",
+    "  stp x8, x9, [sp, #64]",
+    "  stp x10, x11, [sp, #80]",
+    "  stp x12, x13, [sp, #96]",
+    "  stp x14, x15, [sp, #112]",
+    "  stp x16, x17, [sp, #128]",
+    "  stp x18, x19, [sp, #144]",
+    "  stp x20, x21, [sp, #160]",
+    "  stp x22, x23, [sp, #176]",
+    "  stp x24, x25, [sp, #192]",
+    "  stp x26, x27, [sp, #208]",
+    "  stp x28, x29, [sp, #224]",
+    "  add x1, sp, #288",
+    "  stp x30, x1, [sp, #240]",
+    "  mrs x2, CurrentEL",
+    "  cmp x2, #8",
+    "  b.eq 2f",
+    "  b.hi 3f",
+    "  mrs x3, elr_el1",
+    "  mrs x4, spsr_el1",
+    "  mrs x5, esr_el1",
+    "  mrs x6, far_el1",
+    "  b 4f",
+    "2:",
+    "  mrs x3, elr_el2",
+    "  mrs x4, spsr_el2",
+    "  mrs x5, esr_el2",
+    "  mrs x6, far_el2",
+    "  b 4f",
+    "3:",
+    "  mrs x3, elr_el3",
+    "  mrs x4, spsr_el3",
+    "  mrs x5, esr_el3",
+    "  mrs x6, far_el3",
+    "4:",
+    "  stp x3, x4, [sp, #256]",
+    "  stp x5, x6, [sp, #272]",
+    "  mov x1, x0",
+    "  mov x0, sp",
+    "  bl exception_handler",
+    "  ldp x3, x4, [sp, #256]",
+    "  mrs x2, CurrentEL",
+    "  cmp x2, #8",
+    "  b.eq 5f",
+    "  b.hi 6f",
+    "  msr elr_el1, x3",
+    "  msr spsr_el1, x4",
+    "  b 7f",
+    "5:",
+    "  msr elr_el2, x3",
+    "  msr spsr_el2, x4",
+    "  b 7f",
+    "6:",
+    "  msr elr_el3, x3",
+    "  msr spsr_el3, x4",
+    "7:",
+    "  ldp x0, x1, [sp, #0]",
+    "  ldp x2, x3, [sp, #16]",
+    "  ldp x4, x5, [sp, #32]",
+    "  ldp x6, x7, [sp, #48]",
+    "  ldp x8, x9, [sp, #64]",
+    "  ldp x10, x11, [sp, #80]",
+    "  ldp x12, x13, [sp, #96]",
+    "  ldp x14, x15, [sp, #112]",
+    "  ldp x16, x17, [sp, #128]",
+    "  ldp x18, x19, [sp, #144]",
+    "  ldp x20, x21, [sp, #160]",
+    "  ldp x22, x23, [sp, #176]",
+    "  ldp x24, x25, [sp, #192]",
+    "  ldp x26, x27, [sp, #208]",
+    "  ldp x28, x29, [sp, #224]",
+    "  ldr x30, [sp, #240]",
+    "  add sp, sp, #288",
+    "  eret",
+);
+
+#[cfg(target_arch = "aarch64")]
+#[unsafe(no_mangle)]
+extern "C" fn exception_handler(frame: &mut TrapFrame, vector: u64) {
+    let kind = match vector % 4 {
+        0 => Kind::Synchronous,
+        1 => Kind::IRQ,
+        2 => Kind::FIQ,
+        _ => Kind::SError,
+    };
+    handle(frame, kind);
+}
+
+pub fn current_el() -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let el: u64;
+        unsafe { core::arch::asm!("mrs {}, CurrentEL", out(reg) el, options(nomem, nostack)) };
+        (el >> 2) & 0b11
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        1
+    }
+}
+
+/// point VBAR of the current EL at the vectors, at EL2 interrupts are routed there
+#[cfg(target_arch = "aarch64")]
+pub fn install() {
+    use core::arch::asm;
+    unsafe extern "C" {
+        static exception_vectors: u8;
+    }
+    let vectors = &raw const exception_vectors as u64;
+    unsafe {
+        match current_el() {
+            1 => asm!("msr vbar_el1, {}", in(reg) vectors),
+            2 => {
+                const HCR_EL2_FMO_IMO_AMO: u64 = 0b111 << 3;
+                asm!(
+                    "msr vbar_el2, {0}",
+                    "mrs {1}, hcr_el2",
+                    "orr {1}, {1}, {2}",
+                    "msr hcr_el2, {1}",
+                    in(reg) vectors,
+                    out(reg) _,
+                    in(reg) HCR_EL2_FMO_IMO_AMO,
+                )
+            }
+            _ => asm!("msr vbar_el3, {}", in(reg) vectors),
+        }
+        asm!("isb");
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn enable_irqs() {
+    unsafe { core::arch::asm!("msr daifclr, #2", options(nomem, nostack)) };
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn disable_irqs() {
+    unsafe { core::arch::asm!("msr daifset, #2", options(nomem, nostack)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn causes() {
+        assert_eq!(
+            Cause::from_esr(0xf200_0000 | 0x3e8),
+            Cause::Breakpoint(1000)
+        );
+        assert_eq!(Cause::from_esr(0xce00_0022), Cause::Step);
+        assert_eq!(Cause::from_esr(0x9600_0010), Cause::DataAbort);
+        assert_eq!(Cause::from_esr(0x5600_0000), Cause::Call(0));
+        assert_eq!(Cause::from_esr(0x0200_0000), Cause::Undefined);
+        assert_eq!(Cause::from_esr(0x1fe0_0000), Cause::Other(7));
+    }
+
+    #[test]
+    fn probes() {
+        let mut byte = 0x5au8;
+        let address = &raw mut byte as usize;
+        assert_eq!(probe_read(address), Some(0x5a));
+        assert!(probe_write(address, 0xa5));
+        assert_eq!(byte, 0xa5);
+    }
+}
@@ -0,0 +1,1949 @@
+//! FAT12, FAT16 and FAT32 filesystems on a [`BlockDevice`].
+//!
+//! Paths are `/` separated and matched case-insensitively against long and short names.
+//! [`File`] and [`Dir`] are plain handles, every operation goes through the [`FileSystem`] so
+//! several of them can be open at once. Sectors of the FAT, directories and file data pass
+//! through a small write-back cache, [`FileSystem::flush`] writes it out along with the
+//! mirrored copies of the FAT.
+
+use crate::block::{BlockDevice, BlockError};
+use core::fmt;
+
+const SECTOR_SIZE: usize = 512;
+const ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / ENTRY_SIZE) as u32;
+/// sectors kept in the cache
+const CACHE_SLOTS: usize = 8;
+/// UTF-16 code units of a long name
+const MAX_NAME: usize = 255;
+/// UTF-16 code units per long name entry
+const LFN_CHARS: usize = 13;
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const LFN_LAST: u8 = 0x40;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LFN: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// first name byte of a free entry, 0 also ends the directory
+const DELETED: u8 = 0xe5;
+/// short name base and extension stored upper case but shown lower case
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+/// 1980-01-01, there is no real time clock to stamp entries with
+const DATE: u16 = (1 << 5) | 1;
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+/// FSInfo free cluster count when unknown
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FatError {
+    Block(BlockError),
+    /// no valid FAT boot sector
+    InvalidFilesystem,
+    /// sectors other than 512 bytes
+    UnsupportedSectorSize,
+    /// cluster chain points outside the volume or ends before the file does
+    Corrupt,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// fixed FAT12/16 root directory has no free entries left
+    DirectoryFull,
+    DiskFull,
+    InvalidName,
+    /// files end at 4 GiB - 1
+    FileTooLarge,
+}
+
+impl From<BlockError> for FatError {
+    fn from(error: BlockError) -> Self {
+        FatError::Block(error)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// entries from this value on end a cluster chain
+    fn end_of_chain(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xff8,
+            FatType::Fat16 => 0xfff8,
+            FatType::Fat32 => 0x0fff_fff8,
+        }
+    }
+
+    /// end of chain marker written to the FAT
+    fn end(&self) -> u32 {
+        self.end_of_chain() | 0x7
+    }
+}
+
+/// file or directory name as UTF-16
+#[derive(Clone, Copy)]
+pub struct Name {
+    units: [u16; MAX_NAME],
+    len: usize,
+}
+
+impl Name {
+    fn new() -> Name {
+        Name {
+            units: [0; MAX_NAME],
+            len: 0,
+        }
+    }
+
+    fn from_short(short: &[u8; 11], nt: u8) -> Name {
+        let mut name = Name::new();
+        let mut push = |bytes: &[u8], lower: bool| {
+            for (i, byte) in bytes.iter().enumerate() {
+                let byte = match (i, *byte) {
+                    // 0xe5 is a valid first character, stored as 0x05
+                    (0, 0x05) if name.len == 0 => DELETED,
+                    (_, b) if lower => b.to_ascii_lowercase(),
+                    (_, b) => b,
+                };
+                name.units[name.len] = byte as u16;
+                name.len += 1;
+            }
+        };
+        let base = trim_spaces(&short[..8]);
+        let ext = trim_spaces(&short[8..]);
+        push(base, nt & NT_LOWER_BASE != 0);
+        if !ext.is_empty() {
+            push(b".", false);
+            push(ext, nt & NT_LOWER_EXT != 0);
+        }
+        name
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        char::decode_utf16(self.units[..self.len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    /// compare ignoring ASCII case
+    pub fn matches(&self, name: &str) -> bool {
+        let mut units = name.encode_utf16();
+        self.units[..self.len]
+            .iter()
+            .all(|a| units.next().is_some_and(|b| fold(*a) == fold(b)))
+            && units.next().is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chars().try_for_each(|c| fmt::Write::write_char(f, c))
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{self}\"")
+    }
+}
+
+/// directory entry position, sector and byte offset in it
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    sector: u32,
+    offset: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntry {
+    name: Name,
+    short_name: [u8; 11],
+    nt: u8,
+    attributes: u8,
+    cluster: u32,
+    size: u32,
+    location: Location,
+    /// first cluster of the directory holding the entry, 0 for the FAT12/16 root
+    parent: u32,
+    /// index of the first long name entry, or of the short entry without a long name
+    first_slot: u32,
+    slot: u32,
+}
+
+impl DirEntry {
+    /// long name, or the short one when there is none
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// 8.3 name
+    pub fn short_name(&self) -> Name {
+        Name::from_short(&self.short_name, self.nt)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name.matches(name) || self.short_name().matches(name)
+    }
+
+    fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+}
+
+/// position in a directory for [`FileSystem::read_dir`]
+#[derive(Clone, Copy, Debug)]
+pub struct Dir {
+    /// first cluster, 0 for the FAT12/16 root
+    first: u32,
+    cluster: u32,
+    cluster_index: u32,
+    index: u32,
+}
+
+impl Dir {
+    /// start over from the first entry
+    pub fn rewind(&mut self) {
+        self.index = 0;
+    }
+}
+
+/// open file, position and size
+#[derive(Clone, Copy, Debug)]
+pub struct File {
+    entry: Location,
+    first: u32,
+    size: u32,
+    position: u32,
+    /// cluster holding `position` and its index in the chain, 0 when not looked up yet
+    cluster: u32,
+    cluster_index: u32,
+}
+
+impl File {
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// move to `position`, at most the end of the file
+    pub fn seek(&mut self, position: u32) {
+        self.position = position.min(self.size);
+    }
+}
+
+struct Slot {
+    sector: u32,
+    valid: bool,
+    dirty: bool,
+    used: u32,
+    data: [u8; SECTOR_SIZE],
+}
+
+/// least recently used write-back sector cache
+struct Cache {
+    slots: [Slot; CACHE_SLOTS],
+    clock: u32,
+    /// writes to the first FAT go to every copy
+    fat_start: u32,
+    fat_size: u32,
+    fats: u32,
+}
+
+impl Cache {
+    fn new(fat_start: u32, fat_size: u32, fats: u32) -> Cache {
+        Cache {
+            slots: core::array::from_fn(|_| Slot {
+                sector: 0,
+                valid: false,
+                dirty: false,
+                used: 0,
+                data: [0; SECTOR_SIZE],
+            }),
+            clock: 0,
+            fat_start,
+            fat_size,
+            fats,
+        }
+    }
+
+    fn slot<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u32,
+        load: bool,
+    ) -> Result<usize, FatError> {
+        self.clock = self.clock.wrapping_add(1);
+        let slot = match self
+            .slots
+            .iter()
+            .position(|s| s.valid && s.sector == sector)
+        {
+            Some(slot) => slot,
+            None => {
+                let slot = (0..CACHE_SLOTS)
+                    .min_by_key(|i| (self.slots[*i].valid, self.slots[*i].used))
+                    .unwrap();
+                self.write_back(device, slot)?;
+                let s = &mut self.slots[slot];
+                s.valid = false;
+                match load {
+                    true => device.read_blocks(sector as u64, &mut s.data)?,
+                    false => s.data.fill(0),
+                }
+                s.sector = sector;
+                s.valid = true;
+                slot
+            }
+        };
+        self.slots[slot].used = self.clock;
+        Ok(slot)
+    }
+
+    fn read<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u32,
+    ) -> Result<&[u8; SECTOR_SIZE], FatError> {
+        let slot = self.slot(device, sector, true)?;
+        Ok(&self.slots[slot].data)
+    }
+
+    fn write<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u32,
+    ) -> Result<&mut [u8; SECTOR_SIZE], FatError> {
+        let slot = self.slot(device, sector, true)?;
+        self.slots[slot].dirty = true;
+        Ok(&mut self.slots[slot].data)
+    }
+
+    /// sector about to be overwritten entirely, not read from the device
+    fn overwrite<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u32,
+    ) -> Result<&mut [u8; SECTOR_SIZE], FatError> {
+        let slot = self.slot(device, sector, false)?;
+        let s = &mut self.slots[slot];
+        s.dirty = true;
+        s.data.fill(0);
+        Ok(&mut s.data)
+    }
+
+    fn write_back<D: BlockDevice>(&mut self, device: &mut D, slot: usize) -> Result<(), FatError> {
+        let s = &mut self.slots[slot];
+        if !(s.valid && s.dirty) {
+            return Ok(());
+        }
+        device.write_blocks(s.sector as u64, &s.data)?;
+        if (self.fat_start..self.fat_start + self.fat_size).contains(&s.sector) {
+            for copy in 1..self.fats {
+                device.write_blocks((s.sector + copy * self.fat_size) as u64, &s.data)?;
+            }
+        }
+        s.dirty = false;
+        Ok(())
+    }
+
+    fn flush<D: BlockDevice>(&mut self, device: &mut D) -> Result<(), FatError> {
+        (0..CACHE_SLOTS).try_for_each(|slot| self.write_back(device, slot))
+    }
+}
+
+/// long name collected from the entries in front of a short entry
+struct LongName {
+    units: [u16; 20 * LFN_CHARS],
+    checksum: u8,
+    /// sequence number expected next, 0 once complete
+    next: u8,
+    valid: bool,
+    first: u32,
+}
+
+impl LongName {
+    fn new() -> LongName {
+        LongName {
+            units: [0xffff; 20 * LFN_CHARS],
+            checksum: 0,
+            next: 0,
+            valid: false,
+            first: 0,
+        }
+    }
+
+    fn push(&mut self, raw: &[u8; ENTRY_SIZE], index: u32) {
+        let sequence = raw[0] & 0x1f;
+        if raw[0] & LFN_LAST != 0 {
+            self.valid = (1..=20).contains(&sequence);
+            self.next = sequence;
+            self.checksum = raw[13];
+            self.first = index;
+        }
+        // entries are stored last part first
+        if !self.valid || sequence != self.next || raw[13] != self.checksum {
+            self.valid = false;
+            return;
+        }
+        let start = (sequence as usize - 1) * LFN_CHARS;
+        for (i, offset) in LFN_OFFSETS.iter().enumerate() {
+            self.units[start + i] = u16::from_le_bytes([raw[*offset], raw[offset + 1]]);
+        }
+        self.next -= 1;
+    }
+
+    fn name(&self, short: &[u8; 11]) -> Option<Name> {
+        if !self.valid || self.next != 0 || self.checksum != checksum(short) {
+            return None;
+        }
+        let len = self
+            .units
+            .iter()
+            .position(|u| *u == 0 || *u == 0xffff)
+            .unwrap_or(self.units.len());
+        if len == 0 || len > MAX_NAME {
+            return None;
+        }
+        let mut name = Name::new();
+        name.units[..len].copy_from_slice(&self.units[..len]);
+        name.len = len;
+        Some(name)
+    }
+}
+
+pub struct FileSystem<D: BlockDevice> {
+    device: D,
+    cache: Cache,
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    /// fixed root directory of FAT12/16
+    root_start: u32,
+    root_entries: u32,
+    /// first cluster of the FAT32 root directory, 0 otherwise
+    root_cluster: u32,
+    data_start: u32,
+    /// clusters are numbered from 2 to `clusters + 1`
+    clusters: u32,
+    fsinfo: Option<u32>,
+    /// where the search for a free cluster starts
+    next_free: u32,
+    /// clusters changed since the FSInfo sector was written
+    allocated: bool,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// mount the FAT volume starting at block 0 of `device`
+    pub fn mount(mut device: D) -> Result<FileSystem<D>, FatError> {
+        if device.block_size() != SECTOR_SIZE {
+            return Err(FatError::UnsupportedSectorSize);
+        }
+        let mut boot = [0u8; SECTOR_SIZE];
+        device.read_blocks(0, &mut boot)?;
+        if boot[510..512] != [0x55, 0xaa] {
+            return Err(FatError::InvalidFilesystem);
+        }
+        if le_u16(&boot, 11) as usize != SECTOR_SIZE {
+            return Err(FatError::UnsupportedSectorSize);
+        }
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved = le_u16(&boot, 14) as u32;
+        let fats = boot[16] as u32;
+        let root_entries = le_u16(&boot, 17) as u32;
+        let total = match le_u16(&boot, 19) {
+            0 => le_u32(&boot, 32),
+            total => total as u32,
+        };
+        let fat_size = match le_u16(&boot, 22) {
+            0 => le_u32(&boot, 36),
+            size => size as u32,
+        };
+        if !sectors_per_cluster.is_power_of_two() || reserved == 0 || fats == 0 || fat_size == 0 {
+            return Err(FatError::InvalidFilesystem);
+        }
+
+        let root_sectors = (root_entries * ENTRY_SIZE as u32).div_ceil(SECTOR_SIZE as u32);
+        let root_start = reserved + fats * fat_size;
+        let data_start = root_start + root_sectors;
+        if total <= data_start || total as u64 > device.block_count() {
+            return Err(FatError::InvalidFilesystem);
+        }
+        // the cluster count alone decides the FAT type
+        let clusters = (total - data_start) / sectors_per_cluster;
+        let fat_type = match clusters {
+            ..4085 => FatType::Fat12,
+            4085..65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        let bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        if (fat_size as u64 * SECTOR_SIZE as u64 * 8) / bits < clusters as u64 + 2
+            || (fat_type == FatType::Fat32) != (root_entries == 0)
+        {
+            return Err(FatError::InvalidFilesystem);
+        }
+
+        let (root_cluster, fsinfo) = match fat_type {
+            FatType::Fat32 => (
+                le_u32(&boot, 44),
+                match le_u16(&boot, 48) {
+                    0 | 0xffff => None,
+                    sector => Some(sector as u32),
+                },
+            ),
+            _ => (0, None),
+        };
+        let mut fs = FileSystem {
+            device,
+            cache: Cache::new(reserved, fat_size, fats),
+            fat_type,
+            sectors_per_cluster,
+            fat_start: reserved,
+            root_start,
+            root_entries,
+            root_cluster,
+            data_start,
+            clusters,
+            fsinfo,
+            next_free: 2,
+            allocated: false,
+        };
+        if fat_type == FatType::Fat32 && !fs.is_cluster(root_cluster) {
+            return Err(FatError::InvalidFilesystem);
+        }
+        if let Some(sector) = fsinfo {
+            let info = fs.cache.read(&mut fs.device, sector)?;
+            let next = le_u32(info, 492);
+            if le_u32(info, 0) != FSINFO_LEAD || le_u32(info, 484) != FSINFO_STRUCT {
+                fs.fsinfo = None;
+            } else if fs.is_cluster(next) {
+                fs.next_free = next;
+            }
+        }
+        Ok(fs)
+    }
+
+    /// flush the cache and hand back the device
+    pub fn unmount(mut self) -> Result<D, FatError> {
+        self.flush()?;
+        Ok(self.device)
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// bytes per cluster
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    pub fn free_clusters(&mut self) -> Result<u32, FatError> {
+        let mut free = 0;
+        for cluster in 2..self.clusters + 2 {
+            if self.fat_entry(cluster)? == 0 {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+
+    /// write cached sectors and FSInfo back to the device
+    pub fn flush(&mut self) -> Result<(), FatError> {
+        if let (Some(sector), true) = (self.fsinfo, self.allocated) {
+            let next = self.next_free;
+            let info = self.cache.write(&mut self.device, sector)?;
+            info[488..492].copy_from_slice(&FSINFO_UNKNOWN.to_le_bytes());
+            info[492..496].copy_from_slice(&next.to_le_bytes());
+            self.allocated = false;
+        }
+        self.cache.flush(&mut self.device)
+    }
+
+    pub fn root_dir(&self) -> Dir {
+        self.dir(0)
+    }
+
+    pub fn open_dir(&mut self, path: &str) -> Result<Dir, FatError> {
+        match self.resolve(path)? {
+            None => Ok(self.root_dir()),
+            Some(entry) if entry.is_dir() => Ok(self.dir(entry.cluster)),
+            Some(_) => Err(FatError::NotADirectory),
+        }
+    }
+
+    /// next entry of `dir` apart from `.` and `..`
+    pub fn read_dir(&mut self, dir: &mut Dir) -> Result<Option<DirEntry>, FatError> {
+        while let Some(entry) = self.next_entry(dir)? {
+            if !entry.is_dot() {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// entry `path` names, the root directory has none
+    pub fn metadata(&mut self, path: &str) -> Result<DirEntry, FatError> {
+        self.resolve(path)?.ok_or(FatError::IsADirectory)
+    }
+
+    pub fn open(&mut self, path: &str) -> Result<File, FatError> {
+        match self.resolve(path)? {
+            Some(entry) if !entry.is_dir() => Ok(File {
+                entry: entry.location,
+                first: entry.cluster,
+                size: entry.size,
+                position: 0,
+                cluster: 0,
+                cluster_index: 0,
+            }),
+            _ => Err(FatError::IsADirectory),
+        }
+    }
+
+    /// open `path` emptied, creating it when missing
+    pub fn create(&mut self, path: &str) -> Result<File, FatError> {
+        match self.open(path) {
+            Ok(mut file) => {
+                self.truncate(&mut file, 0)?;
+                Ok(file)
+            }
+            Err(FatError::NotFound) => {
+                let (parent, name) = split(path);
+                let parent = self.parent_cluster(parent)?;
+                let entry = self.create_entry(parent, name, ATTR_ARCHIVE, 0)?;
+                self.open_entry(entry)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<(), FatError> {
+        let (parent, name) = split(path);
+        let parent = self.parent_cluster(parent)?;
+        let cluster = self.allocate(None)?;
+        self.zero_cluster(cluster)?;
+        if let Err(error) = self.create_entry(parent, name, ATTR_DIRECTORY, cluster) {
+            self.free_chain(cluster)?;
+            return Err(error);
+        }
+        let sector = self.cluster_sector(cluster);
+        let dots = self.cache.write(&mut self.device, sector)?;
+        // `..` links to the root with cluster 0, also on FAT32
+        let parent = if parent == self.root_cluster {
+            0
+        } else {
+            parent
+        };
+        dots[..ENTRY_SIZE].copy_from_slice(&short_entry(
+            b".          ",
+            0,
+            ATTR_DIRECTORY,
+            cluster,
+        ));
+        dots[ENTRY_SIZE..2 * ENTRY_SIZE].copy_from_slice(&short_entry(
+            b"..         ",
+            0,
+            ATTR_DIRECTORY,
+            parent,
+        ));
+        Ok(())
+    }
+
+    /// delete a file or an empty directory
+    pub fn remove(&mut self, path: &str) -> Result<(), FatError> {
+        let entry = self.resolve(path)?.ok_or(FatError::InvalidName)?;
+        if entry.is_dot() {
+            return Err(FatError::InvalidName);
+        }
+        if entry.is_dir() && self.read_dir(&mut self.dir(entry.cluster))?.is_some() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+        let mut dir = self.dir(entry.parent);
+        for index in entry.first_slot..=entry.slot {
+            let location = self
+                .dir_slot(&mut dir, index, false)?
+                .ok_or(FatError::Corrupt)?;
+            self.cache.write(&mut self.device, location.sector)?[location.offset] = DELETED;
+        }
+        if entry.cluster != 0 {
+            self.free_chain(entry.cluster)?;
+        }
+        Ok(())
+    }
+
+    /// read from the current position, returns the bytes read, 0 at the end of the file
+    pub fn read(&mut self, file: &mut File, buf: &mut [u8]) -> Result<usize, FatError> {
+        let len = buf.len().min((file.size - file.position) as usize);
+        let mut done = 0;
+        while done < len {
+            let (sector, offset) = self.file_sector(file, false)?;
+            let n = (len - done).min(SECTOR_SIZE - offset);
+            let data = self.cache.read(&mut self.device, sector)?;
+            buf[done..done + n].copy_from_slice(&data[offset..offset + n]);
+            done += n;
+            file.position += n as u32;
+        }
+        Ok(done)
+    }
+
+    /// write at the current position, growing the file as needed
+    pub fn write(&mut self, file: &mut File, buf: &[u8]) -> Result<(), FatError> {
+        if file.position as u64 + buf.len() as u64 > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge);
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let (sector, offset) = match self.file_sector(file, true) {
+                Ok(position) => position,
+                Err(error) => {
+                    self.update_entry(file)?;
+                    return Err(error);
+                }
+            };
+            let n = (buf.len() - done).min(SECTOR_SIZE - offset);
+            let data = match n {
+                SECTOR_SIZE => self.cache.overwrite(&mut self.device, sector)?,
+                _ => self.cache.write(&mut self.device, sector)?,
+            };
+            data[offset..offset + n].copy_from_slice(&buf[done..done + n]);
+            done += n;
+            file.position += n as u32;
+            file.size = file.size.max(file.position);
+        }
+        self.update_entry(file)
+    }
+
+    /// write at the end of the file
+    pub fn append(&mut self, file: &mut File, buf: &[u8]) -> Result<(), FatError> {
+        file.seek(file.size);
+        self.write(file, buf)
+    }
+
+    /// cut the file to `len` bytes, or zero fill it up to `len`
+    pub fn truncate(&mut self, file: &mut File, len: u32) -> Result<(), FatError> {
+        if len > file.size {
+            let position = file.position;
+            file.seek(file.size);
+            while file.size < len {
+                let n = ((len - file.size) as usize).min(SECTOR_SIZE);
+                self.write(file, &[0; SECTOR_SIZE][..n])?;
+            }
+            file.seek(position);
+            return Ok(());
+        }
+
+        let keep = len.div_ceil(self.cluster_size() as u32);
+        if keep == 0 {
+            if file.first != 0 {
+                self.free_chain(file.first)?;
+            }
+            file.first = 0;
+        } else if let Some(last) = self.file_cluster(file, keep - 1, false)?
+            && let Some(next) = self.next_cluster(last)?
+        {
+            let end = self.fat_type.end();
+            self.set_fat_entry(last, end)?;
+            self.free_chain(next)?;
+        }
+        file.size = len;
+        file.position = file.position.min(len);
+        file.cluster = 0;
+        self.update_entry(file)
+    }
+
+    fn dir(&self, cluster: u32) -> Dir {
+        Dir {
+            first: match cluster {
+                0 => self.root_cluster,
+                cluster => cluster,
+            },
+            cluster: 0,
+            cluster_index: 0,
+            index: 0,
+        }
+    }
+
+    /// entry `path` leads to, None for the root directory
+    fn resolve(&mut self, path: &str) -> Result<Option<DirEntry>, FatError> {
+        let mut current: Option<DirEntry> = None;
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            let mut dir = match current {
+                None => self.root_dir(),
+                Some(entry) if entry.is_dir() => self.dir(entry.cluster),
+                Some(_) => return Err(FatError::NotADirectory),
+            };
+            let entry = self.find(&mut dir, component)?.ok_or(FatError::NotFound)?;
+            // `..` of a directory in the root
+            current = match entry.is_dot() && entry.cluster == 0 {
+                true => None,
+                false => Some(entry),
+            };
+        }
+        Ok(current)
+    }
+
+    /// first cluster of the directory `path`, 0 for the root
+    fn parent_cluster(&mut self, path: &str) -> Result<u32, FatError> {
+        Ok(self.open_dir(path)?.first)
+    }
+
+    fn find(&mut self, dir: &mut Dir, name: &str) -> Result<Option<DirEntry>, FatError> {
+        while let Some(entry) = self.next_entry(dir)? {
+            if entry.matches(name) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    /// next entry with its long name, skipping free entries and volume labels
+    fn next_entry(&mut self, dir: &mut Dir) -> Result<Option<DirEntry>, FatError> {
+        let mut long = LongName::new();
+        loop {
+            let index = dir.index;
+            let Some(location) = self.dir_slot(dir, index, false)? else {
+                return Ok(None);
+            };
+            let raw: [u8; ENTRY_SIZE] = self.cache.read(&mut self.device, location.sector)?
+                [location.offset..location.offset + ENTRY_SIZE]
+                .try_into()
+                .unwrap();
+            match raw[0] {
+                0 => return Ok(None),
+                DELETED => long.valid = false,
+                _ if raw[11] & 0x3f == ATTR_LFN => long.push(&raw, index),
+                _ if raw[11] & ATTR_VOLUME_ID != 0 => long.valid = false,
+                _ => {
+                    dir.index += 1;
+                    let short_name: [u8; 11] = raw[..11].try_into().unwrap();
+                    let (name, first_slot) = match long.name(&short_name) {
+                        Some(name) => (name, long.first),
+                        None => (Name::from_short(&short_name, raw[12]), index),
+                    };
+                    return Ok(Some(DirEntry {
+                        name,
+                        short_name,
+                        nt: raw[12],
+                        attributes: raw[11],
+                        cluster: ((le_u16(&raw, 20) as u32) << 16) | le_u16(&raw, 26) as u32,
+                        size: le_u32(&raw, 28),
+                        location,
+                        parent: dir.first,
+                        first_slot,
+                        slot: index,
+                    }));
+                }
+            }
+            dir.index += 1;
+        }
+    }
+
+    /// location of entry `index`, growing cluster directories by a cluster with `extend`
+    fn dir_slot(
+        &mut self,
+        dir: &mut Dir,
+        index: u32,
+        extend: bool,
+    ) -> Result<Option<Location>, FatError> {
+        if dir.first == 0 {
+            return Ok((index < self.root_entries).then(|| Location {
+                sector: self.root_start + index / ENTRIES_PER_SECTOR,
+                offset: (index % ENTRIES_PER_SECTOR) as usize * ENTRY_SIZE,
+            }));
+        }
+        let per_cluster = self.sectors_per_cluster * ENTRIES_PER_SECTOR;
+        let target = index / per_cluster;
+        if dir.cluster == 0 || target < dir.cluster_index {
+            dir.cluster = dir.first;
+            dir.cluster_index = 0;
+        }
+        while dir.cluster_index < target {
+            dir.cluster = match self.next_cluster(dir.cluster)? {
+                Some(next) => next,
+                None if extend => {
+                    let cluster = self.allocate(Some(dir.cluster))?;
+                    self.zero_cluster(cluster)?;
+                    cluster
+                }
+                None => return Ok(None),
+            };
+            dir.cluster_index += 1;
+        }
+        let within = index % per_cluster;
+        Ok(Some(Location {
+            sector: self.cluster_sector(dir.cluster) + within / ENTRIES_PER_SECTOR,
+            offset: (within % ENTRIES_PER_SECTOR) as usize * ENTRY_SIZE,
+        }))
+    }
+
+    /// add an entry for `name` to the directory at `parent`, with long name entries when
+    /// it is no valid 8.3 name
+    fn create_entry(
+        &mut self,
+        parent: u32,
+        name: &str,
+        attributes: u8,
+        cluster: u32,
+    ) -> Result<DirEntry, FatError> {
+        if !valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        if self.find(&mut self.dir(parent), name)?.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+        let (short, nt, long) = match short_name(name) {
+            Some((short, nt)) => (short, nt, false),
+            None => (self.numbered_short_name(parent, name)?, 0, true),
+        };
+        let mut units = [0u16; MAX_NAME];
+        let mut len = 0usize;
+        for (unit, c) in units.iter_mut().zip(name.encode_utf16()) {
+            *unit = c;
+            len += 1;
+        }
+        let slots = match long {
+            true => len.div_ceil(LFN_CHARS) as u32 + 1,
+            false => 1,
+        };
+
+        // first run of free entries long enough
+        let mut dir = self.dir(parent);
+        let (mut start, mut run, mut index) = (0, 0, 0);
+        while run < slots {
+            let location = self
+                .dir_slot(&mut dir, index, true)?
+                .ok_or(FatError::DirectoryFull)?;
+            match self.cache.read(&mut self.device, location.sector)?[location.offset] {
+                0 | DELETED => {
+                    if run == 0 {
+                        start = index;
+                    }
+                    run += 1;
+                }
+                _ => run = 0,
+            }
+            index += 1;
+        }
+
+        let sum = checksum(&short);
+        for slot in 0..slots - 1 {
+            let sequence = (slots - 1 - slot) as u8;
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = sequence | if slot == 0 { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LFN;
+            raw[13] = sum;
+            for (i, offset) in LFN_OFFSETS.iter().enumerate() {
+                let at = (sequence as usize - 1) * LFN_CHARS + i;
+                let unit = match at.cmp(&len) {
+                    core::cmp::Ordering::Less => units[at],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                raw[*offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            self.write_entry(&mut dir, start + slot, &raw)?;
+        }
+        let raw = short_entry(&short, nt, attributes, cluster);
+        let location = self.write_entry(&mut dir, start + slots - 1, &raw)?;
+        let mut name = Name::new();
+        name.units = units;
+        name.len = len;
+        Ok(DirEntry {
+            name,
+            short_name: short,
+            nt,
+            attributes,
+            cluster,
+            size: 0,
+            location,
+            parent: dir.first,
+            first_slot: start,
+            slot: start + slots - 1,
+        })
+    }
+
+    fn write_entry(
+        &mut self,
+        dir: &mut Dir,
+        index: u32,
+        raw: &[u8; ENTRY_SIZE],
+    ) -> Result<Location, FatError> {
+        let location = self.dir_slot(dir, index, false)?.ok_or(FatError::Corrupt)?;
+        self.cache.write(&mut self.device, location.sector)?
+            [location.offset..location.offset + ENTRY_SIZE]
+            .copy_from_slice(raw);
+        Ok(location)
+    }
+
+    /// `BASIS~N.EXT` not yet used in the directory
+    fn numbered_short_name(&mut self, parent: u32, name: &str) -> Result<[u8; 11], FatError> {
+        let (base, ext) = match name.rsplit_once('.') {
+            Some((base, ext)) if !base.trim_matches('.').is_empty() => (base, ext),
+            _ => (name, ""),
+        };
+        let mut basis = [b' '; 11];
+        let mut base_len = 0;
+        for c in base.chars().filter(|c| *c != ' ' && *c != '.').take(8) {
+            basis[base_len] = short_char(c);
+            base_len += 1;
+        }
+        for (i, c) in ext.chars().filter(|c| *c != ' ').take(3).enumerate() {
+            basis[8 + i] = short_char(c);
+        }
+        if base_len == 0 {
+            basis[0] = b'_';
+            base_len = 1;
+        }
+
+        for n in 1..1_000_000u32 {
+            let mut digits = [0u8; 8];
+            let mut tail = 0;
+            let mut value = n;
+            while value > 0 {
+                digits[7 - tail] = b'0' + (value % 10) as u8;
+                value /= 10;
+                tail += 1;
+            }
+            let keep = base_len.min(7 - tail);
+            let mut short = basis;
+            short[keep] = b'~';
+            short[keep + 1..keep + 1 + tail].copy_from_slice(&digits[8 - tail..]);
+            short[keep + 1 + tail..8].fill(b' ');
+
+            let mut dir = self.dir(parent);
+            let mut taken = false;
+            while let Some(entry) = self.next_entry(&mut dir)? {
+                taken |= entry.short_name == short;
+            }
+            if !taken {
+                return Ok(short);
+            }
+        }
+        Err(FatError::DirectoryFull)
+    }
+
+    fn open_entry(&mut self, entry: DirEntry) -> Result<File, FatError> {
+        Ok(File {
+            entry: entry.location,
+            first: entry.cluster,
+            size: entry.size,
+            position: 0,
+            cluster: 0,
+            cluster_index: 0,
+        })
+    }
+
+    /// store size and first cluster of `file` in its directory entry
+    fn update_entry(&mut self, file: &File) -> Result<(), FatError> {
+        let Location { sector, offset } = file.entry;
+        let raw = &mut self.cache.write(&mut self.device, sector)?[offset..offset + ENTRY_SIZE];
+        raw[20..22].copy_from_slice(&((file.first >> 16) as u16).to_le_bytes());
+        raw[24..26].copy_from_slice(&DATE.to_le_bytes());
+        raw[26..28].copy_from_slice(&(file.first as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&file.size.to_le_bytes());
+        Ok(())
+    }
+
+    /// sector and offset of the file position
+    fn file_sector(&mut self, file: &mut File, allocate: bool) -> Result<(u32, usize), FatError> {
+        let cluster_size = self.cluster_size() as u32;
+        let cluster = self
+            .file_cluster(file, file.position / cluster_size, allocate)?
+            .ok_or(FatError::Corrupt)?;
+        let within = (file.position % cluster_size) as usize;
+        Ok((
+            self.cluster_sector(cluster) + (within / SECTOR_SIZE) as u32,
+            within % SECTOR_SIZE,
+        ))
+    }
+
+    /// cluster `index` of the file chain, appended to the chain with `allocate`
+    fn file_cluster(
+        &mut self,
+        file: &mut File,
+        index: u32,
+        allocate: bool,
+    ) -> Result<Option<u32>, FatError> {
+        if file.first == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            file.first = self.allocate(None)?;
+            file.cluster = 0;
+        }
+        if file.cluster == 0 || index < file.cluster_index {
+            file.cluster = file.first;
+            file.cluster_index = 0;
+        }
+        while file.cluster_index < index {
+            file.cluster = match self.next_cluster(file.cluster)? {
+                Some(next) => next,
+                None if allocate => self.allocate(Some(file.cluster))?,
+                None => return Ok(None),
+            };
+            file.cluster_index += 1;
+        }
+        Ok(Some(file.cluster))
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.clusters + 2).contains(&cluster)
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FatError> {
+        let first = self.cluster_sector(cluster);
+        for sector in first..first + self.sectors_per_cluster {
+            self.cache.overwrite(&mut self.device, sector)?;
+        }
+        Ok(())
+    }
+
+    /// cluster following `cluster`, None at the end of the chain
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FatError> {
+        if !self.is_cluster(cluster) {
+            return Err(FatError::Corrupt);
+        }
+        match self.fat_entry(cluster)? {
+            next if next >= self.fat_type.end_of_chain() => Ok(None),
+            next if self.is_cluster(next) => Ok(Some(next)),
+            _ => Err(FatError::Corrupt),
+        }
+    }
+
+    /// take a free cluster, linked after `previous`
+    fn allocate(&mut self, previous: Option<u32>) -> Result<u32, FatError> {
+        let start = self.next_free;
+        for i in 0..self.clusters {
+            let cluster = 2 + (start - 2 + i) % self.clusters;
+            if self.fat_entry(cluster)? != 0 {
+                continue;
+            }
+            let end = self.fat_type.end();
+            self.set_fat_entry(cluster, end)?;
+            if let Some(previous) = previous {
+                self.set_fat_entry(previous, cluster)?;
+            }
+            self.next_free = cluster;
+            self.allocated = true;
+            return Ok(cluster);
+        }
+        Err(FatError::DiskFull)
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), FatError> {
+        let mut cluster = first;
+        for _ in 0..self.clusters {
+            let next = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, 0)?;
+            self.allocated = true;
+            match next {
+                Some(next) => cluster = next,
+                None => return Ok(()),
+            }
+        }
+        Err(FatError::Corrupt)
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FatError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster + cluster / 2;
+                let pair =
+                    u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]) as u32;
+                Ok(match cluster & 1 {
+                    1 => pair >> 4,
+                    _ => pair & 0xfff,
+                })
+            }
+            FatType::Fat16 => {
+                let (sector, offset) = self.fat_position(cluster * 2);
+                Ok(le_u16(self.cache.read(&mut self.device, sector)?, offset) as u32)
+            }
+            FatType::Fat32 => {
+                let (sector, offset) = self.fat_position(cluster * 4);
+                Ok(le_u32(self.cache.read(&mut self.device, sector)?, offset) & 0x0fff_ffff)
+            }
+        }
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FatError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                // entries share the middle byte of each 3 byte pair
+                let offset = cluster + cluster / 2;
+                let pair = u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]);
+                let pair = match cluster & 1 {
+                    1 => (pair & 0x000f) | ((value as u16) << 4),
+                    _ => (pair & 0xf000) | (value as u16 & 0xfff),
+                };
+                let [low, high] = pair.to_le_bytes();
+                self.set_fat_byte(offset, low)?;
+                self.set_fat_byte(offset + 1, high)
+            }
+            FatType::Fat16 => {
+                let (sector, offset) = self.fat_position(cluster * 2);
+                self.cache.write(&mut self.device, sector)?[offset..offset + 2]
+                    .copy_from_slice(&(value as u16).to_le_bytes());
+                Ok(())
+            }
+            FatType::Fat32 => {
+                let (sector, offset) = self.fat_position(cluster * 4);
+                let data = self.cache.write(&mut self.device, sector)?;
+                // the top 4 bits are reserved and kept
+                let value = (le_u32(data, offset) & 0xf000_0000) | (value & 0x0fff_ffff);
+                data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                Ok(())
+            }
+        }
+    }
+
+    fn fat_position(&self, offset: u32) -> (u32, usize) {
+        (
+            self.fat_start + offset / SECTOR_SIZE as u32,
+            offset as usize % SECTOR_SIZE,
+        )
+    }
+
+    fn fat_byte(&mut self, offset: u32) -> Result<u8, FatError> {
+        let (sector, offset) = self.fat_position(offset);
+        Ok(self.cache.read(&mut self.device, sector)?[offset])
+    }
+
+    fn set_fat_byte(&mut self, offset: u32, value: u8) -> Result<(), FatError> {
+        let (sector, offset) = self.fat_position(offset);
+        self.cache.write(&mut self.device, sector)?[offset] = value;
+        Ok(())
+    }
+}
+
+fn short_entry(name: &[u8; 11], nt: u8, attributes: u8, cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut raw = [0u8; ENTRY_SIZE];
+    raw[..11].copy_from_slice(name);
+    if raw[0] == DELETED {
+        raw[0] = 0x05;
+    }
+    raw[11] = attributes;
+    raw[12] = nt;
+    for date in [16, 18, 24] {
+        raw[date..date + 2].copy_from_slice(&DATE.to_le_bytes());
+    }
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw
+}
+
+/// checksum of a short name stored in its long name entries
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// 8.3 name and case flags when `name` needs no long name
+fn short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.') {
+        return None;
+    }
+    let valid = |c: u8| c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&c);
+    // a part can be shown lower case only when it has no upper case letters
+    let lower = |part: &str| match (
+        part.bytes().any(|c| c.is_ascii_lowercase()),
+        part.bytes().any(|c| c.is_ascii_uppercase()),
+    ) {
+        (true, true) => None,
+        (lower, _) => Some(lower),
+    };
+    if !base.bytes().chain(ext.bytes()).all(valid) {
+        return None;
+    }
+    let nt = (lower(base)? as u8 * NT_LOWER_BASE) | (lower(ext)? as u8 * NT_LOWER_EXT);
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    short.make_ascii_uppercase();
+    Some((short, nt))
+}
+
+fn short_char(c: char) -> u8 {
+    match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase() as u8,
+        '!' | '#' | '$' | '%' | '&' | '\'' | '(' | ')' | '-' | '@' | '^' | '_' | '`' | '{'
+        | '}' | '~' => c as u8,
+        _ => b'_',
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    name.encode_utf16().count() <= MAX_NAME
+        && !matches!(name, "" | "." | "..")
+        && !name.ends_with(['.', ' '])
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+/// parent directory and last component of `path`
+fn split(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn trim_spaces(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+    &bytes[..len]
+}
+
+fn fold(unit: u16) -> u16 {
+    match unit {
+        0x61..=0x7a => unit - 0x20,
+        _ => unit,
+    }
+}
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::block::RamDisk;
+    use crate::utils::crc32;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// lay out an empty volume like mkfs.fat does, 2 FATs and 1 sector clusters
+    pub(crate) fn mkfs(sectors: u32, root_entries: u16) -> Vec<u8> {
+        let mut disk = vec![0u8; sectors as usize * SECTOR_SIZE];
+        let fat32 = root_entries == 0;
+        let reserved = if fat32 { 32 } else { 1 };
+        let root_sectors = root_entries as u32 * 32 / 512;
+        let mut fat_size = 1;
+        let clusters = loop {
+            let clusters = sectors - reserved - 2 * fat_size - root_sectors;
+            let bits = match clusters {
+                ..4085 => 12,
+                4085..65525 => 16,
+                _ => 32,
+            };
+            let needed = ((clusters + 2) * bits).div_ceil(8 * 512);
+            if needed <= fat_size {
+                break clusters;
+            }
+            fat_size = needed;
+        };
+
+        let boot = &mut disk[..512];
+        boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        boot[3..11].copy_from_slice(b"mkfs.fat");
+        boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&(reserved as u16).to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&root_entries.to_le_bytes());
+        boot[21] = 0xf8;
+        if fat32 {
+            boot[32..36].copy_from_slice(&sectors.to_le_bytes());
+            boot[36..40].copy_from_slice(&fat_size.to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+            boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+            boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+        } else {
+            boot[19..21].copy_from_slice(&(sectors as u16).to_le_bytes());
+            boot[22..24].copy_from_slice(&(fat_size as u16).to_le_bytes());
+        }
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let media: &[u8] = match clusters {
+            ..4085 => &[0xf8, 0xff, 0xff],
+            4085..65525 => &[0xf8, 0xff, 0xff, 0xff],
+            // the FAT32 root directory takes cluster 2
+            _ => &[
+                0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+            ],
+        };
+        for copy in 0..2 {
+            let start = (reserved + copy * fat_size) as usize * 512;
+            disk[start..start + media.len()].copy_from_slice(media);
+        }
+        if fat32 {
+            let info = &mut disk[512..1024];
+            info[..4].copy_from_slice(&FSINFO_LEAD.to_le_bytes());
+            info[484..488].copy_from_slice(&FSINFO_STRUCT.to_le_bytes());
+            info[488..492].copy_from_slice(&(clusters - 1).to_le_bytes());
+            info[492..496].copy_from_slice(&3u32.to_le_bytes());
+            info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+        }
+        disk
+    }
+
+    fn fat12() -> Vec<u8> {
+        mkfs(2048, 64)
+    }
+
+    fn fat16() -> Vec<u8> {
+        mkfs(8192, 512)
+    }
+
+    fn fat32() -> Vec<u8> {
+        mkfs(70_000, 0)
+    }
+
+    fn names<D: BlockDevice>(fs: &mut FileSystem<D>, path: &str) -> Vec<String> {
+        let mut dir = fs.open_dir(path).unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = fs.read_dir(&mut dir).unwrap() {
+            names.push(entry.name().to_string());
+        }
+        names
+    }
+
+    fn read_all<D: BlockDevice>(fs: &mut FileSystem<D>, path: &str) -> Vec<u8> {
+        let mut file = fs.open(path).unwrap();
+        let mut data = vec![0u8; file.size() as usize + 10];
+        let len = fs.read(&mut file, &mut data).unwrap();
+        data.truncate(len);
+        data
+    }
+
+    #[test]
+    fn fat_types() {
+        for (mut disk, fat_type) in [
+            (fat12(), FatType::Fat12),
+            (fat16(), FatType::Fat16),
+            (fat32(), FatType::Fat32),
+        ] {
+            let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+            assert_eq!(fs.fat_type(), fat_type);
+            assert!(names(&mut fs, "/").is_empty());
+        }
+
+        let mut disk = fat16();
+        disk[510] = 0;
+        assert_eq!(
+            FileSystem::mount(RamDisk::new(&mut disk, 512)).err(),
+            Some(FatError::InvalidFilesystem)
+        );
+    }
+
+    /// bits of a deflate stream, least significant first
+    struct Bits<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl Bits<'_> {
+        fn bits(&mut self, count: u32) -> usize {
+            (0..count).fold(0, |value, bit| {
+                let byte = self.data[self.position / 8];
+                let value = value | ((byte >> (self.position % 8)) as usize & 1) << bit;
+                self.position += 1;
+                value
+            })
+        }
+
+        fn byte_aligned(&mut self) -> &[u8] {
+            self.position = self.position.next_multiple_of(8);
+            &self.data[self.position / 8..]
+        }
+    }
+
+    /// canonical Huffman code, the number of codes of each length and the symbols in code order
+    struct Huffman {
+        counts: [usize; 16],
+        symbols: Vec<usize>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[usize]) -> Huffman {
+            let mut counts = [0; 16];
+            lengths.iter().for_each(|len| counts[*len] += 1);
+            counts[0] = 0;
+            let mut symbols: Vec<usize> = (0..lengths.len()).filter(|s| lengths[*s] > 0).collect();
+            symbols.sort_by_key(|s| lengths[*s]);
+            Huffman { counts, symbols }
+        }
+
+        fn decode(&self, bits: &mut Bits) -> usize {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for count in &self.counts[1..] {
+                code |= bits.bits(1);
+                if code < first + count {
+                    return self.symbols[index + code - first];
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            panic!("bad Huffman code");
+        }
+    }
+
+    /// RFC 1951 deflate stream at the start of `data`
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        const LENGTH_BASE: [usize; 29] = [
+            3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+            115, 131, 163, 195, 227, 258,
+        ];
+        const LENGTH_EXTRA: [u32; 29] = [
+            0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+        ];
+        const DISTANCE_BASE: [usize; 30] = [
+            1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025,
+            1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+        ];
+        const DISTANCE_EXTRA: [u32; 30] = [
+            0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12,
+            12, 13, 13,
+        ];
+        const ORDER: [usize; 19] = [
+            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+        ];
+
+        let mut bits = Bits { data, position: 0 };
+        let mut out = Vec::new();
+        loop {
+            let last = bits.bits(1) == 1;
+            let (literals, distances) = match bits.bits(2) {
+                0 => {
+                    let block = bits.byte_aligned();
+                    let len = u16::from_le_bytes([block[0], block[1]]) as usize;
+                    out.extend_from_slice(&block[4..4 + len]);
+                    bits.position += (4 + len) * 8;
+                    match last {
+                        true => return out,
+                        false => continue,
+                    }
+                }
+                1 => {
+                    let lengths: Vec<usize> = (0..288)
+                        .map(|s| match s {
+                            ..144 => 8,
+                            ..256 => 9,
+                            ..280 => 7,
+                            _ => 8,
+                        })
+                        .collect();
+                    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+                }
+                2 => {
+                    let literals = bits.bits(5) + 257;
+                    let distances = bits.bits(5) + 1;
+                    let mut code_lengths = [0; 19];
+                    for symbol in &ORDER[..bits.bits(4) + 4] {
+                        code_lengths[*symbol] = bits.bits(3);
+                    }
+                    let codes = Huffman::new(&code_lengths);
+                    let mut lengths = Vec::new();
+                    while lengths.len() < literals + distances {
+                        let (value, repeat) = match codes.decode(&mut bits) {
+                            16 => (*lengths.last().unwrap(), 3 + bits.bits(2)),
+                            17 => (0, 3 + bits.bits(3)),
+                            18 => (0, 11 + bits.bits(7)),
+                            len => (len, 1),
+                        };
+                        lengths.extend(std::iter::repeat_n(value, repeat));
+                    }
+                    (
+                        Huffman::new(&lengths[..literals]),
+                        Huffman::new(&lengths[literals..]),
+                    )
+                }
+                kind => panic!("block type {kind}"),
+            };
+            loop {
+                let symbol = literals.decode(&mut bits);
+                if symbol < 256 {
+                    out.push(symbol as u8);
+                    continue;
+                }
+                if symbol == 256 {
+                    break;
+                }
+                let len = LENGTH_BASE[symbol - 257] + bits.bits(LENGTH_EXTRA[symbol - 257]);
+                let distance = distances.decode(&mut bits);
+                let distance = DISTANCE_BASE[distance] + bits.bits(DISTANCE_EXTRA[distance]);
+                let start = out.len() - distance;
+                (start..start + len).for_each(|i| out.push(out[i]));
+            }
+            if last {
+                return out;
+            }
+        }
+    }
+
+    /// RFC 1952 gzip member, checked against its CRC-32 and size
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        assert_eq!(data[..3], [0x1f, 0x8b, 8], "not gzip");
+        let flags = data[3];
+        let mut start = 10;
+        if flags & 4 != 0 {
+            start += 2 + u16::from_le_bytes([data[start], data[start + 1]]) as usize;
+        }
+        // file name and comment
+        for flag in [8, 16] {
+            if flags & flag != 0 {
+                start += data[start..].iter().position(|b| *b == 0).unwrap() + 1;
+            }
+        }
+        if flags & 2 != 0 {
+            start += 2;
+        }
+        let out = inflate(&data[start..]);
+        let trailer = &data[data.len() - 8..];
+        assert_eq!(trailer[..4], crc32::checksum(&out).to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn gunzip_blocks() {
+        // printf 'hello, world\n' | gzip -9n, fixed codes
+        let fixed = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0xd7, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0xe1, 0x02, 0x00, 0x53, 0x74, 0x24,
+            0xf4, 0x0d, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(gunzip(&fixed), b"hello, world\n");
+        // the first lines of NUMBERS.TXT, dynamic codes
+        let dynamic = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x15, 0xcb, 0xb1, 0x01,
+            0x00, 0x20, 0x08, 0xc4, 0xc0, 0x9e, 0x69, 0xfe, 0x11, 0x15, 0xf7, 0x5f, 0x4c, 0x52,
+            0xe4, 0xba, 0x48, 0x52, 0x4c, 0x86, 0x84, 0x05, 0x05, 0x1b, 0x0e, 0x5c, 0x68, 0x78,
+            0x83, 0x39, 0xcc, 0x61, 0x0e, 0x73, 0xb8, 0xe2, 0x03, 0x35, 0x8e, 0x8b, 0x40, 0x4b,
+            0x00, 0x00, 0x00,
+        ];
+        let numbers: Vec<u8> = (0..15)
+            .flat_map(|i| format!("{i:04}\n").into_bytes())
+            .collect();
+        assert_eq!(gunzip(&dynamic), numbers);
+        // incompressible, stored
+        let stored = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x01, 0x25, 0x00, 0xda,
+            0xff, 0x00, 0x07, 0x0e, 0x15, 0x1c, 0x23, 0x2a, 0x31, 0x38, 0x3f, 0x46, 0x4d, 0x54,
+            0x5b, 0x62, 0x69, 0x70, 0x77, 0x7e, 0x85, 0x8c, 0x93, 0x9a, 0xa1, 0xa8, 0xaf, 0xb6,
+            0xbd, 0xc4, 0xcb, 0xd2, 0xd9, 0xe0, 0xe7, 0xee, 0xf5, 0xfc, 0x70, 0x5a, 0xa9, 0x1e,
+            0x25, 0x00, 0x00, 0x00,
+        ];
+        let bytes: Vec<u8> = (0..=255).step_by(7).collect();
+        assert_eq!(gunzip(&stored), bytes);
+    }
+
+    /// `name`.img.gz of src/testdata, made by tools/fat-images.sh
+    fn image(name: &str) -> Vec<u8> {
+        let path = format!("{}/src/testdata/{name}.img.gz", env!("CARGO_MANIFEST_DIR"));
+        let data = std::fs::read(&path).unwrap_or_else(|error| panic!("{path}: {error}"));
+        gunzip(&data)
+    }
+
+    #[test]
+    #[ignore = "needs the images of tools/fat-images.sh in src/testdata"]
+    fn mkfs_fat_images() {
+        let numbers: Vec<u8> = (0..4000)
+            .flat_map(|i| format!("{i:04}\n").into_bytes())
+            .collect();
+        for (name, fat_type) in [
+            ("fat12", FatType::Fat12),
+            ("fat16", FatType::Fat16),
+            ("fat32", FatType::Fat32),
+        ] {
+            let mut disk = image(name);
+            let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+            assert_eq!(fs.fat_type(), fat_type, "{name}");
+            let mut found = names(&mut fs, "/");
+            found.sort();
+            assert_eq!(
+                found,
+                ["BOOT", "HELLO.TXT", "Long File Name.txt", "NUMBERS.TXT"],
+                "{name}"
+            );
+            assert_eq!(read_all(&mut fs, "hello.txt"), b"hello, world\n");
+            assert_eq!(read_all(&mut fs, "long file name.TXT"), b"long file name\n");
+            assert_eq!(read_all(&mut fs, "NUMBERS.TXT"), numbers, "{name}");
+            assert_eq!(read_all(&mut fs, "boot/config.txt"), b"arm_64bit=1\n");
+
+            // changes land where mkfs.fat and mtools left room for them
+            let mut file = fs.create("boot/cmdline.txt").unwrap();
+            fs.write(&mut file, b"loglevel=4").unwrap();
+            fs.remove("HELLO.TXT").unwrap();
+            fs.unmount().unwrap();
+            let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+            assert_eq!(read_all(&mut fs, "BOOT/CMDLINE.TXT"), b"loglevel=4");
+            assert_eq!(fs.open("hello.txt").err(), Some(FatError::NotFound));
+            assert_eq!(read_all(&mut fs, "NUMBERS.TXT"), numbers, "{name}");
+        }
+    }
+
+    #[test]
+    fn read_write() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 + i / 512) as u8).collect();
+        for mut disk in [fat12(), fat16(), fat32()] {
+            let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+            let mut file = fs.create("/hello.txt").unwrap();
+            fs.write(&mut file, b"hello world").unwrap();
+            fs.create_dir("logs").unwrap();
+            let mut log = fs.create("logs/Boot Log.txt").unwrap();
+            fs.write(&mut log, &data[..1000]).unwrap();
+            for chunk in data[1000..].chunks(777) {
+                fs.append(&mut log, chunk).unwrap();
+            }
+            assert_eq!(log.size(), 20_000);
+            fs.unmount().unwrap();
+
+            let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+            assert_eq!(names(&mut fs, "/"), ["hello.txt", "logs"]);
+            assert_eq!(names(&mut fs, "/LOGS/"), ["Boot Log.txt"]);
+            assert_eq!(read_all(&mut fs, "HELLO.TXT"), b"hello world");
+            assert_eq!(read_all(&mut fs, "logs/../logs/./boot log.txt"), data);
+
+            // overwrite inside the file, reading from the middle
+            let mut log = fs.open("logs/Boot Log.txt").unwrap();
+            log.seek(10_000);
+            fs.write(&mut log, &[0xaa; 600]).unwrap();
+            log.seek(9_999);
+            let mut buf = [0u8; 602];
+            assert_eq!(fs.read(&mut log, &mut buf).unwrap(), 602);
+            assert_eq!(buf[0], data[9_999]);
+            assert!(buf[1..601].iter().all(|b| *b == 0xaa));
+            assert_eq!(buf[601], data[10_600]);
+            assert_eq!(log.size(), 20_000);
+
+            assert_eq!(fs.open("logs").err(), Some(FatError::IsADirectory));
+            assert_eq!(fs.open("missing").err(), Some(FatError::NotFound));
+            assert_eq!(fs.open("hello.txt/x").err(), Some(FatError::NotADirectory));
+            assert_eq!(fs.create_dir("logs").err(), Some(FatError::AlreadyExists));
+        }
+    }
+
+    #[test]
+    fn long_names() {
+        let mut disk = fat16();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        for name in [
+            "Long File Name 1.txt",
+            "Long File Name 2.txt",
+            "UPPER.TXT",
+            "lower.txt",
+            "Grüße.txt",
+            ".config",
+        ] {
+            fs.create(name).unwrap();
+        }
+
+        let entry = fs.metadata("long file name 2.TXT").unwrap();
+        assert_eq!(entry.name().to_string(), "Long File Name 2.txt");
+        assert_eq!(entry.short_name().to_string(), "LONGFI~2.TXT");
+        assert!(fs.metadata("LONGFI~1.TXT").is_ok());
+
+        // valid 8.3 names take a single entry
+        let upper = fs.metadata("upper.txt").unwrap();
+        assert_eq!(upper.first_slot, upper.slot);
+        let lower = fs.metadata("LOWER.TXT").unwrap();
+        assert_eq!(lower.first_slot, lower.slot);
+        assert_eq!(lower.name().to_string(), "lower.txt");
+
+        assert_eq!(
+            fs.metadata("grüße.txt").unwrap().short_name().to_string(),
+            "GR__E~1.TXT"
+        );
+        assert_eq!(
+            fs.metadata(".config").unwrap().short_name().to_string(),
+            "CONFIG~1"
+        );
+
+        let long: String = "x".repeat(255);
+        fs.create(&long).unwrap();
+        assert_eq!(fs.metadata(&long).unwrap().name().len(), 255);
+        assert_eq!(
+            fs.create(&"x".repeat(256)).err(),
+            Some(FatError::InvalidName)
+        );
+        assert_eq!(fs.create("a:b").err(), Some(FatError::InvalidName));
+
+        fs.unmount().unwrap();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        assert_eq!(names(&mut fs, "").len(), 7);
+        assert!(names(&mut fs, "").contains(&"Grüße.txt".to_string()));
+    }
+
+    #[test]
+    fn truncate_and_remove() {
+        let mut disk = fat12();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        let free = fs.free_clusters().unwrap();
+
+        let mut file = fs.create("data.bin").unwrap();
+        fs.write(&mut file, &[0x55; 5000]).unwrap();
+        assert_eq!(fs.free_clusters().unwrap(), free - 10);
+
+        fs.truncate(&mut file, 1000).unwrap();
+        assert_eq!(fs.free_clusters().unwrap(), free - 2);
+        fs.truncate(&mut file, 1500).unwrap();
+        let data = read_all(&mut fs, "data.bin");
+        assert_eq!(data.len(), 1500);
+        assert!(data[..1000].iter().all(|b| *b == 0x55));
+        assert!(data[1000..].iter().all(|b| *b == 0));
+
+        // create truncates existing files
+        let mut file = fs.create("data.bin").unwrap();
+        assert_eq!(file.size(), 0);
+        assert_eq!(fs.free_clusters().unwrap(), free);
+        fs.append(&mut file, &[1; 100]).unwrap();
+        fs.remove("data.bin").unwrap();
+        assert_eq!(fs.free_clusters().unwrap(), free);
+        assert_eq!(fs.metadata("data.bin").err(), Some(FatError::NotFound));
+
+        fs.create_dir("dir").unwrap();
+        fs.create("dir/A Long Name").unwrap();
+        assert_eq!(fs.remove("dir").err(), Some(FatError::DirectoryNotEmpty));
+        fs.remove("dir/a long name").unwrap();
+        fs.remove("dir").unwrap();
+        assert_eq!(fs.free_clusters().unwrap(), free);
+        assert!(names(&mut fs, "/").is_empty());
+    }
+
+    #[test]
+    fn directory_size() {
+        // subdirectories grow by a cluster
+        let mut disk = fat32();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        fs.create_dir("many").unwrap();
+        for i in 0..40 {
+            fs.create(&format!("many/file number {i}")).unwrap();
+        }
+        fs.unmount().unwrap();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        let found = names(&mut fs, "many");
+        assert_eq!(found.len(), 40);
+        assert_eq!(found[39], "file number 39");
+
+        // the FAT12/16 root is fixed
+        let mut disk = fat12();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        for i in 0..64 {
+            fs.create(&format!("F{i}")).unwrap();
+        }
+        assert_eq!(fs.create("F64").err(), Some(FatError::DirectoryFull));
+        fs.remove("F10").unwrap();
+        fs.create("F64").unwrap();
+    }
+
+    struct Counting<'a> {
+        disk: RamDisk<'a>,
+        reads: Rc<Cell<usize>>,
+    }
+
+    impl BlockDevice for Counting<'_> {
+        fn block_count(&self) -> u64 {
+            self.disk.block_count()
+        }
+
+        fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+            self.reads.set(self.reads.get() + 1);
+            self.disk.read_blocks(lba, buf)
+        }
+
+        fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+            self.disk.write_blocks(lba, buf)
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let mut disk = fat16();
+        let reads = Rc::new(Cell::new(0));
+        let device = Counting {
+            disk: RamDisk::new(&mut disk, 512),
+            reads: reads.clone(),
+        };
+        let mut fs = FileSystem::mount(device).unwrap();
+        let mut file = fs.create("small.txt").unwrap();
+        fs.write(&mut file, &[7; 1000]).unwrap();
+
+        let before = reads.get();
+        assert_eq!(read_all(&mut fs, "small.txt").len(), 1000);
+        assert_eq!(read_all(&mut fs, "small.txt").len(), 1000);
+        assert_eq!(reads.get(), before);
+        fs.unmount().unwrap();
+
+        // both FAT copies are written
+        let fat_size = le_u16(&disk, 22) as usize * 512;
+        let first = &disk[512..512 + fat_size];
+        assert_eq!(
+            &first[..10],
+            &[0xf8, 0xff, 0xff, 0xff, 0x03, 0, 0xff, 0xff, 0, 0]
+        );
+        assert_eq!(first, &disk[512 + fat_size..512 + 2 * fat_size]);
+    }
+
+    #[test]
+    fn fsinfo() {
+        let mut disk = fat32();
+        let mut fs = FileSystem::mount(RamDisk::new(&mut disk, 512)).unwrap();
+        assert_eq!(fs.next_free, 3);
+        fs.create_dir("a").unwrap();
+        fs.unmount().unwrap();
+        assert_eq!(le_u32(&disk, 512 + 488), FSINFO_UNKNOWN);
+        assert_eq!(le_u32(&disk, 512 + 492), 3);
+    }
+
+    #[test]
+    fn short_names() {
+        assert_eq!(short_name("KERNEL8.IMG"), Some((*b"KERNEL8 IMG", 0)));
+        assert_eq!(
+            short_name("config.txt"),
+            Some((*b"CONFIG  TXT", NT_LOWER_BASE | NT_LOWER_EXT))
+        );
+        assert_eq!(short_name("README"), Some((*b"README     ", 0)));
+        assert_eq!(short_name("Config.txt"), None);
+        assert_eq!(short_name("toolongname.txt"), None);
+        assert_eq!(short_name("a.b.c"), None);
+        assert_eq!(short_name("a b"), None);
+    }
+}
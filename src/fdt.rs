@@ -0,0 +1,1115 @@
+//! Flattened device tree (FDT) parser for the blob the firmware passes in x0.
+//!
+//! [`Fdt::new`] checks the header and walks the whole structure block once, so the
+//! iterators afterwards simply end where a malformed blob would have been a problem. Nodes
+//! are found by path or alias, `compatible` string or phandle. [`Node::reg`] decodes `reg`
+//! with the `#address-cells` and `#size-cells` of the parent, [`Node::region`] also
+//! translates the address through the `ranges` of the buses above, which is how the
+//! 0x7e000000 bus addresses of `/soc` become ARM physical addresses.
+//!
+//! Nothing is allocated, lookups of parents and phandles rescan the structure block.
+use crate::shell::{Command, CommandError, Shell};
+use core::fmt::Write;
+
+const MAGIC: u32 = 0xd00d_feed;
+/// format written by the firmware and dtc, the header layout fixed since version 16
+const VERSION: u32 = 17;
+const HEADER_LEN: usize = 40;
+/// nodes nested deeper than this are rejected
+const MAX_DEPTH: usize = 16;
+/// `#interrupt-cells` of the controllers that can be decoded
+pub const MAX_INTERRUPT_CELLS: usize = 4;
+/// defaults of a node without `#address-cells` or `#size-cells`
+const ADDRESS_CELLS: u32 = 2;
+const SIZE_CELLS: u32 = 1;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FdtError {
+    /// no blob, x0 was zero at boot
+    Missing,
+    BadMagic,
+    /// written for a newer format than this parser knows
+    BadVersion,
+    /// header offsets or sizes outside the blob
+    Truncated,
+    /// unknown token, unbalanced nodes or a name outside its block
+    BadStructure,
+    /// nodes nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn be64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// NUL terminated string at the start of `bytes`
+fn c_str(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|b| *b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// `cells` big endian cells from the start of `bytes` as one number and the rest of
+/// `bytes`, more than two cells keep the low 64 bits
+fn take_cells(bytes: &[u8], cells: u32) -> Option<(u64, &[u8])> {
+    let len = cells as usize * 4;
+    if bytes.len() < len {
+        return None;
+    }
+    let value = bytes[..len].chunks(4).fold(0u64, |value, cell| {
+        value.wrapping_shl(32) | u32::from_be_bytes(cell.try_into().unwrap()) as u64
+    });
+    Some((value, &bytes[len..]))
+}
+
+enum Token<'a> {
+    Begin(&'a str),
+    End,
+    Property(&'a str, &'a [u8]),
+    Nop,
+    Finish,
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structure: &'a [u8],
+    strings: &'a [u8],
+    reservations: &'a [u8],
+    boot_cpu: u32,
+}
+
+impl<'a> Fdt<'a> {
+    /// check the header and the structure block of `blob`
+    pub fn new(blob: &'a [u8]) -> Result<Fdt<'a>, FdtError> {
+        let field = |index: usize| be32(blob, index * 4).ok_or(FdtError::Truncated);
+        if field(0)? != MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+        if blob.len() < HEADER_LEN {
+            return Err(FdtError::Truncated);
+        }
+        let (total, structure, strings, reservations) = (
+            field(1)? as usize,
+            field(2)? as usize,
+            field(3)? as usize,
+            field(4)? as usize,
+        );
+        if field(6)? > VERSION {
+            return Err(FdtError::BadVersion);
+        }
+        let (strings_len, structure_len) = (field(8)? as usize, field(9)? as usize);
+        let blob = blob.get(..total).ok_or(FdtError::Truncated)?;
+        let block = |offset: usize, len: usize| {
+            let end = offset.checked_add(len).ok_or(FdtError::Truncated)?;
+            blob.get(offset..end).ok_or(FdtError::Truncated)
+        };
+        let fdt = Fdt {
+            blob,
+            structure: block(structure, structure_len)?,
+            strings: block(strings, strings_len)?,
+            reservations: blob.get(reservations..).ok_or(FdtError::Truncated)?,
+            boot_cpu: field(7)?,
+        };
+        fdt.check()?;
+        Ok(fdt)
+    }
+
+    /// every token readable, one root node, nodes balanced and properties inside nodes,
+    /// the memory reservations terminated
+    fn check(&self) -> Result<(), FdtError> {
+        let mut offset = 0;
+        let mut depth = 0;
+        let mut roots = 0;
+        loop {
+            let (token, next) = self.token(offset).ok_or(FdtError::BadStructure)?;
+            match token {
+                Token::Begin(_) if depth == MAX_DEPTH => return Err(FdtError::TooDeep),
+                Token::Begin(_) => {
+                    roots += (depth == 0) as usize;
+                    depth += 1;
+                }
+                Token::End if depth == 0 => return Err(FdtError::BadStructure),
+                Token::End => depth -= 1,
+                Token::Property(..) if depth == 0 => return Err(FdtError::BadStructure),
+                Token::Property(..) | Token::Nop => {}
+                Token::Finish if depth == 0 && roots == 1 => break,
+                Token::Finish => return Err(FdtError::BadStructure),
+            }
+            offset = next;
+        }
+        let mut entries = self.reservations.chunks(16);
+        match entries.any(|entry| entry.len() == 16 && entry.iter().all(|b| *b == 0)) {
+            true => Ok(()),
+            false => Err(FdtError::Truncated),
+        }
+    }
+
+    /// offset of the root node's FDT_BEGIN_NODE, NOPs may come first
+    fn root_offset(&self) -> usize {
+        let mut offset = 0;
+        while let Some((Token::Nop, next)) = self.token(offset) {
+            offset = next;
+        }
+        offset
+    }
+
+    fn token(&self, offset: usize) -> Option<(Token<'a>, usize)> {
+        let structure = self.structure;
+        match be32(structure, offset)? {
+            FDT_BEGIN_NODE => {
+                let name = c_str(structure.get(offset + 4..)?)?;
+                Some((Token::Begin(name), align4(offset + 4 + name.len() + 1)))
+            }
+            FDT_END_NODE => Some((Token::End, offset + 4)),
+            FDT_PROP => {
+                let len = be32(structure, offset + 4)? as usize;
+                let name = c_str(self.strings.get(be32(structure, offset + 8)? as usize..)?)?;
+                let value = structure.get(offset + 12..(offset + 12).checked_add(len)?)?;
+                Some((Token::Property(name, value), align4(offset + 12 + len)))
+            }
+            FDT_NOP => Some((Token::Nop, offset + 4)),
+            FDT_END => Some((Token::Finish, offset + 4)),
+            _ => None,
+        }
+    }
+
+    fn node_at(&self, offset: usize) -> Option<Node<'a>> {
+        match self.token(offset)? {
+            (Token::Begin(name), body) => Some(Node {
+                fdt: *self,
+                offset,
+                name,
+                body,
+            }),
+            _ => None,
+        }
+    }
+
+    /// size of the blob as given by its header
+    pub fn total_size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// physical ID of the CPU the firmware booted on
+    pub fn boot_cpu(&self) -> u32 {
+        self.boot_cpu
+    }
+
+    pub fn root(&self) -> Node<'a> {
+        // checked by new
+        self.node_at(self.root_offset()).unwrap()
+    }
+
+    /// all nodes, depth first
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+        }
+    }
+
+    /// Node at an absolute `path`, or a path starting with an alias of `/aliases`. Path
+    /// components may leave out the unit address.
+    pub fn find(&self, path: &str) -> Option<Node<'a>> {
+        let (mut node, rest) = match path.strip_prefix('/') {
+            Some(rest) => (self.root(), rest),
+            None => {
+                let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+                let target = self.root().child("aliases")?.property(alias)?.str()?;
+                // aliases name absolute paths, anything else could lead back to an alias
+                if !target.starts_with('/') {
+                    return None;
+                }
+                (self.find(target)?, rest)
+            }
+        };
+        for name in rest.split('/').filter(|name| !name.is_empty()) {
+            node = node.child(name)?;
+        }
+        Some(node)
+    }
+
+    /// first node listing `compatible` in its `compatible` property
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// kernel command line from `/chosen`
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.find("/chosen")?.property("bootargs")?.str()
+    }
+
+    /// RAM described by the `memory` nodes
+    pub fn memory(&self) -> impl Iterator<Item = Region> + 'a {
+        self.nodes()
+            .filter(|node| node.property("device_type").and_then(|p| p.str()) == Some("memory"))
+            .flat_map(|node| node.reg().into_iter().flatten())
+    }
+
+    /// memory reservation block, regions the kernel must not touch
+    pub fn reservations(&self) -> impl Iterator<Item = Region> + 'a {
+        self.reservations
+            .chunks_exact(16)
+            .map(|entry| Region {
+                address: be64(entry, 0).unwrap(),
+                size: be64(entry, 8).unwrap(),
+            })
+            .take_while(|region| *region != Region::default())
+    }
+
+    /// the node containing the node at `offset`
+    fn parent_of(&self, offset: usize) -> Option<Node<'a>> {
+        let mut stack = [0; MAX_DEPTH];
+        let mut depth: usize = 0;
+        let mut cursor = 0;
+        loop {
+            let (token, next) = self.token(cursor)?;
+            match token {
+                Token::Begin(_) if cursor == offset => {
+                    return depth
+                        .checked_sub(1)
+                        .and_then(|top| self.node_at(stack[top]));
+                }
+                Token::Begin(_) => {
+                    stack[depth] = cursor;
+                    depth += 1;
+                }
+                Token::End => depth -= 1,
+                Token::Finish => return None,
+                _ => {}
+            }
+            cursor = next;
+        }
+    }
+
+    /// offset just past the FDT_END_NODE of the node whose body starts at `body`
+    fn skip_node(&self, body: usize) -> Option<usize> {
+        let mut depth = 1;
+        let mut offset = body;
+        while depth > 0 {
+            let (token, next) = self.token(offset)?;
+            match token {
+                Token::Begin(_) => depth += 1,
+                Token::End => depth -= 1,
+                Token::Finish => return None,
+                _ => {}
+            }
+            offset = next;
+        }
+        Some(offset)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Region {
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    /// offset of the FDT_BEGIN_NODE token
+    offset: usize,
+    name: &'a str,
+    /// offset of the first token after the name
+    body: usize,
+}
+
+impl<'a> Node<'a> {
+    /// full name, `serial@7e201000`, empty for the root
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// name without the unit address
+    pub fn base_name(&self) -> &'a str {
+        self.name
+            .split_once('@')
+            .map_or(self.name, |(base, _)| base)
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.body,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn children(&self) -> Children<'a> {
+        Children {
+            fdt: self.fdt,
+            offset: Some(self.body),
+        }
+    }
+
+    /// child called `name`, with or without its unit address
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        let exact = name.contains('@');
+        self.children().find(|child| match exact {
+            true => child.name == name,
+            false => child.base_name() == name,
+        })
+    }
+
+    pub fn parent(&self) -> Option<Node<'a>> {
+        self.fdt.parent_of(self.offset)
+    }
+
+    /// `#address-cells` of the children's `reg`
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|p| p.u32())
+            .unwrap_or(ADDRESS_CELLS)
+    }
+
+    /// `#size-cells` of the children's `reg`
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|p| p.u32())
+            .unwrap_or(SIZE_CELLS)
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible")
+            .is_some_and(|p| p.strings().any(|s| s == compatible))
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|p| p.u32())
+    }
+
+    /// `reg` as the node's parent bus sees it
+    pub fn reg(&self) -> Option<Reg<'a>> {
+        let parent = self.parent()?;
+        Some(Reg {
+            cells: self.property("reg")?.value,
+            address_cells: parent.address_cells(),
+            size_cells: parent.size_cells(),
+        })
+    }
+
+    /// entry `index` of `reg` with the address translated to a CPU physical address,
+    /// `None` when a bus on the way has no `ranges` covering it
+    pub fn region(&self, index: usize) -> Option<Region> {
+        let region = self.reg()?.nth(index)?;
+        let mut address = region.address;
+        let mut bus = self.parent()?;
+        while let Some(parent) = bus.parent() {
+            let ranges = bus.property("ranges")?;
+            // an empty property maps one to one
+            if !ranges.value.is_empty() {
+                address = translate(
+                    ranges.value,
+                    address,
+                    (
+                        bus.address_cells(),
+                        parent.address_cells(),
+                        bus.size_cells(),
+                    ),
+                )?;
+            }
+            bus = parent;
+        }
+        Some(Region {
+            address,
+            size: region.size,
+        })
+    }
+
+    /// controller receiving the node's interrupts, `interrupt-parent` here or above
+    pub fn interrupt_parent(&self) -> Option<Node<'a>> {
+        let mut node = *self;
+        loop {
+            if let Some(phandle) = node.property("interrupt-parent").and_then(|p| p.u32()) {
+                return self.fdt.find_phandle(phandle);
+            }
+            node = node.parent()?;
+        }
+    }
+
+    /// `interrupts` split by the `#interrupt-cells` of the interrupt parent
+    pub fn interrupts(&self) -> Option<Interrupts<'a>> {
+        let cells = self
+            .interrupt_parent()?
+            .property("#interrupt-cells")?
+            .u32()? as usize;
+        if cells == 0 || cells > MAX_INTERRUPT_CELLS {
+            return None;
+        }
+        Some(Interrupts {
+            cells: self.property("interrupts")?.value,
+            width: cells,
+        })
+    }
+}
+
+/// map `address` through one bus' `ranges`, cells of the child address, the parent address
+/// and the size
+fn translate(ranges: &[u8], address: u64, (child, parent, size): (u32, u32, u32)) -> Option<u64> {
+    // entries without cells would never use up `ranges`
+    if child == 0 && parent == 0 && size == 0 {
+        return None;
+    }
+    let mut rest = ranges;
+    while !rest.is_empty() {
+        let (child_address, next) = take_cells(rest, child)?;
+        let (parent_address, next) = take_cells(next, parent)?;
+        let (len, next) = take_cells(next, size)?;
+        if address >= child_address && address - child_address < len {
+            return parent_address.checked_add(address - child_address);
+        }
+        rest = next;
+    }
+    None
+}
+
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub fn u32(&self) -> Option<u32> {
+        (self.value.len() == 4).then(|| be32(self.value, 0).unwrap())
+    }
+
+    pub fn u64(&self) -> Option<u64> {
+        (self.value.len() == 8).then(|| be64(self.value, 0).unwrap())
+    }
+
+    /// single NUL terminated string
+    pub fn str(&self) -> Option<&'a str> {
+        let (last, text) = self.value.split_last()?;
+        if *last != 0 || text.contains(&0) {
+            return None;
+        }
+        core::str::from_utf8(text).ok()
+    }
+
+    /// string list, like `compatible`
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> + 'a {
+        let value = self.value.strip_suffix(&[0]).unwrap_or(&[]);
+        value
+            .split(|b| *b == 0)
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+
+    /// big endian cells, a trailing partial cell is dropped
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+    }
+}
+
+impl core::fmt::Display for Property<'_> {
+    /// strings quoted, multiples of 4 bytes as cells, anything else as bytes
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let printable = |s: &str| !s.is_empty() && s.bytes().all(|b| (0x20..0x7f).contains(&b));
+        if self.value.is_empty() {
+            return write!(f, "{}", self.name);
+        }
+        write!(f, "{} = ", self.name)?;
+        if self.value.ends_with(&[0]) && self.strings().all(printable) {
+            for (i, s) in self.strings().enumerate() {
+                let separator = if i == 0 { "" } else { ", " };
+                write!(f, "{separator}\"{s}\"")?;
+            }
+        } else if self.value.len().is_multiple_of(4) {
+            write!(f, "<")?;
+            for (i, cell) in self.cells().enumerate() {
+                let separator = if i == 0 { "" } else { " " };
+                write!(f, "{separator}{cell:#x}")?;
+            }
+            write!(f, ">")?;
+        } else {
+            write!(f, "[")?;
+            for (i, byte) in self.value.iter().enumerate() {
+                let separator = if i == 0 { "" } else { " " };
+                write!(f, "{separator}{byte:02x}")?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            let (token, next) = self.fdt.token(self.offset)?;
+            let offset = self.offset;
+            self.offset = next;
+            match token {
+                Token::Begin(_) => return self.fdt.node_at(offset),
+                Token::Finish => return None,
+                _ => {}
+            }
+        }
+    }
+}
+
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        loop {
+            let (token, next) = self.fdt.token(self.offset)?;
+            match token {
+                Token::Property(name, value) => {
+                    self.offset = next;
+                    return Some(Property { name, value });
+                }
+                Token::Nop => self.offset = next,
+                // properties come before the children
+                _ => return None,
+            }
+        }
+    }
+}
+
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    /// `None` once the parent's FDT_END_NODE was reached
+    offset: Option<usize>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            let offset = self.offset?;
+            let (token, next) = self.fdt.token(offset)?;
+            match token {
+                Token::Begin(_) => {
+                    self.offset = self.fdt.skip_node(next);
+                    return self.fdt.node_at(offset);
+                }
+                Token::Property(..) | Token::Nop => self.offset = Some(next),
+                Token::End | Token::Finish => self.offset = None,
+            }
+        }
+    }
+}
+
+/// `reg` entries
+pub struct Reg<'a> {
+    cells: &'a [u8],
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl Iterator for Reg<'_> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Region> {
+        // entries without cells would never use up `reg`
+        if self.address_cells == 0 && self.size_cells == 0 {
+            return None;
+        }
+        let (address, rest) = take_cells(self.cells, self.address_cells)?;
+        let (size, rest) = take_cells(rest, self.size_cells)?;
+        self.cells = rest;
+        Some(Region { address, size })
+    }
+}
+
+/// interrupt specifier, its meaning is up to the controller
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Interrupt {
+    cells: [u32; MAX_INTERRUPT_CELLS],
+    len: usize,
+}
+
+impl Interrupt {
+    pub fn cells(&self) -> &[u32] {
+        &self.cells[..self.len]
+    }
+
+    /// GIC interrupt ID of a three cell GIC specifier
+    pub fn gic_irq(&self) -> Option<u32> {
+        const SPI: u32 = 0;
+        const PPI: u32 = 1;
+        match self.cells() {
+            [SPI, irq, _] => Some(32 + irq),
+            [PPI, irq, _] => Some(16 + irq),
+            _ => None,
+        }
+    }
+}
+
+pub struct Interrupts<'a> {
+    cells: &'a [u8],
+    width: usize,
+}
+
+impl Iterator for Interrupts<'_> {
+    type Item = Interrupt;
+
+    fn next(&mut self) -> Option<Interrupt> {
+        let len = self.width * 4;
+        let specifier = self.cells.get(..len)?;
+        self.cells = &self.cells[len..];
+        let mut cells = [0; MAX_INTERRUPT_CELLS];
+        for (cell, bytes) in cells.iter_mut().zip(specifier.chunks(4)) {
+            *cell = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        Some(Interrupt {
+            cells,
+            len: self.width,
+        })
+    }
+}
+
+static mut DEVICE_TREE: Option<Fdt<'static>> = None;
+
+/// Check the blob at `address` and keep it for [`device_tree`].
+///
+/// # Safety
+/// `address` is zero or the blob passed by the firmware, which is left alone from then on
+pub unsafe fn load(address: usize) -> Result<Fdt<'static>, FdtError> {
+    if address == 0 {
+        return Err(FdtError::Missing);
+    }
+    let header = unsafe { core::slice::from_raw_parts(address as *const u8, HEADER_LEN) };
+    if be32(header, 0) != Some(MAGIC) {
+        return Err(FdtError::BadMagic);
+    }
+    let total = be32(header, 4).unwrap() as usize;
+    let blob = unsafe { core::slice::from_raw_parts(address as *const u8, total) };
+    let fdt = Fdt::new(blob)?;
+    unsafe { DEVICE_TREE = Some(fdt) };
+    Ok(fdt)
+}
+
+/// blob accepted by [`load`]
+pub fn device_tree() -> Option<Fdt<'static>> {
+    unsafe { DEVICE_TREE }
+}
+
+/// where the blob passed at boot is, zero without one
+pub fn address() -> usize {
+    device_tree().map_or(0, |fdt| fdt.blob.as_ptr() as usize)
+}
+
+pub const COMMANDS: &[Command] = &[Command {
+    name: "dt",
+    usage: "[PATH | memory]",
+    help: "device tree node with its properties and children, paths may start with an alias",
+    subcommands: &["memory"],
+    run: dt,
+}];
+
+fn dt(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let fdt = device_tree().ok_or(CommandError::Invalid("no device tree was passed at boot"))?;
+    let path = match args {
+        [] => "/",
+        ["memory"] => {
+            for region in fdt.memory() {
+                let _ = writeln!(
+                    shell,
+                    "memory   {:#010x} {:#x}",
+                    region.address, region.size
+                );
+            }
+            for region in fdt.reservations() {
+                let _ = writeln!(
+                    shell,
+                    "reserved {:#010x} {:#x}",
+                    region.address, region.size
+                );
+            }
+            return Ok(());
+        }
+        [path] => path,
+        _ => return Err(CommandError::Usage),
+    };
+    let node = fdt
+        .find(path)
+        .ok_or(CommandError::Invalid("no such node"))?;
+    for property in node.properties() {
+        let _ = writeln!(shell, "{property}");
+    }
+    for child in node.children() {
+        let _ = writeln!(shell, "{}/", child.name());
+    }
+    if let Some(region) = node.region(0) {
+        let _ = writeln!(
+            shell,
+            "# at {:#x}, {:#x} bytes",
+            region.address, region.size
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// blob written token by token
+    pub(crate) struct Builder {
+        structure: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        pub fn new() -> Builder {
+            Builder {
+                structure: Vec::new(),
+                strings: Vec::new(),
+            }
+        }
+
+        fn word(&mut self, word: u32) {
+            self.structure.extend_from_slice(&word.to_be_bytes());
+        }
+
+        fn pad(&mut self) {
+            self.structure.resize(align4(self.structure.len()), 0);
+        }
+
+        pub fn begin(&mut self, name: &str) -> &mut Builder {
+            self.word(FDT_BEGIN_NODE);
+            self.structure.extend_from_slice(name.as_bytes());
+            self.structure.push(0);
+            self.pad();
+            self
+        }
+
+        pub fn end(&mut self) -> &mut Builder {
+            self.word(FDT_END_NODE);
+            self
+        }
+
+        pub fn property(&mut self, name: &str, value: &[u8]) -> &mut Builder {
+            let offset = self.strings.len();
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.word(FDT_PROP);
+            self.word(value.len() as u32);
+            self.word(offset as u32);
+            self.structure.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        pub fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Builder {
+            let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.property(name, &value)
+        }
+
+        pub fn string(&mut self, name: &str, value: &str) -> &mut Builder {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.push(0);
+            self.property(name, &bytes)
+        }
+
+        pub fn nop(&mut self) -> &mut Builder {
+            self.word(FDT_NOP);
+            self
+        }
+
+        pub fn finish(&mut self, reservations: &[(u64, u64)]) -> Vec<u8> {
+            self.word(FDT_END);
+            let mut rsvmap = Vec::new();
+            for (address, size) in reservations.iter().chain(&[(0, 0)]) {
+                rsvmap.extend_from_slice(&address.to_be_bytes());
+                rsvmap.extend_from_slice(&size.to_be_bytes());
+            }
+            let reservations = HEADER_LEN.next_multiple_of(8);
+            let structure = reservations + rsvmap.len();
+            let strings = structure + self.structure.len();
+            let total = strings + self.strings.len();
+            let header = [
+                MAGIC,
+                total as u32,
+                structure as u32,
+                strings as u32,
+                reservations as u32,
+                VERSION,
+                16,
+                0,
+                self.strings.len() as u32,
+                self.structure.len() as u32,
+            ];
+            let mut blob: Vec<u8> = header.iter().flat_map(|w| w.to_be_bytes()).collect();
+            blob.resize(reservations, 0);
+            blob.extend_from_slice(&rsvmap);
+            blob.extend_from_slice(&self.structure);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// the parts of the Pi 4 tree the kernel looks at
+    pub(crate) fn raspi4b() -> Vec<u8> {
+        Builder::new()
+            .begin("")
+            .cells("#address-cells", &[2])
+            .cells("#size-cells", &[1])
+            .cells("interrupt-parent", &[1])
+            .begin("aliases")
+            .string("serial0", "/soc/serial@7e215040")
+            .string("uart0", "/soc/serial@7e201000")
+            .end()
+            .begin("chosen")
+            .string("bootargs", "console=ttyS0,115200 loglevel=4")
+            .end()
+            .begin("memory@0")
+            .string("device_type", "memory")
+            .cells("reg", &[0, 0, 0x3b40_0000, 0, 0x4000_0000, 0x4000_0000])
+            .end()
+            .begin("soc")
+            .property("compatible", b"simple-bus\0")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[1])
+            .cells("ranges", &[0x7e00_0000, 0, 0xfe00_0000, 0x0180_0000])
+            .nop()
+            .begin("serial@7e201000")
+            .property("compatible", b"arm,pl011\0arm,primecell\0")
+            .cells("reg", &[0x7e20_1000, 0x200])
+            .cells("interrupts", &[0, 121, 4])
+            .end()
+            .begin("serial@7e215040")
+            .property("compatible", b"brcm,bcm2835-aux-uart\0")
+            .cells("reg", &[0x7e21_5040, 0x40])
+            .cells("interrupts", &[0, 93, 4])
+            .end()
+            .end()
+            .begin("interrupt-controller@40041000")
+            .property("compatible", b"arm,gic-400\0")
+            .cells("#interrupt-cells", &[3])
+            .property("interrupt-controller", &[])
+            .cells("reg", &[0, 0xff84_1000, 0x1000, 0, 0xff84_2000, 0x2000])
+            .cells("phandle", &[1])
+            .end()
+            .end()
+            .finish(&[(0, 0x1000)])
+    }
+
+    #[test]
+    fn header() {
+        let blob = raspi4b();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(fdt.total_size(), blob.len());
+        assert_eq!(fdt.boot_cpu(), 0);
+
+        let mut bad = blob.clone();
+        bad[0] = 0;
+        assert_eq!(Fdt::new(&bad).err(), Some(FdtError::BadMagic));
+        let mut bad = blob.clone();
+        bad[27] = 18;
+        assert_eq!(Fdt::new(&bad).err(), Some(FdtError::BadVersion));
+        assert_eq!(
+            Fdt::new(&blob[..blob.len() - 1]).err(),
+            Some(FdtError::Truncated)
+        );
+        assert_eq!(Fdt::new(&blob[..20]).err(), Some(FdtError::Truncated));
+
+        let unbalanced = Builder::new().begin("").begin("a").end().finish(&[]);
+        assert_eq!(Fdt::new(&unbalanced).err(), Some(FdtError::BadStructure));
+        let two_roots = Builder::new().begin("").end().begin("").end().finish(&[]);
+        assert_eq!(Fdt::new(&two_roots).err(), Some(FdtError::BadStructure));
+        let mut deep = Builder::new();
+        (0..=MAX_DEPTH).for_each(|_| {
+            deep.begin("n");
+        });
+        (0..=MAX_DEPTH).for_each(|_| {
+            deep.end();
+        });
+        assert_eq!(Fdt::new(&deep.finish(&[])).err(), Some(FdtError::TooDeep));
+    }
+
+    #[test]
+    fn walk() {
+        let blob = raspi4b();
+        let fdt = Fdt::new(&blob).unwrap();
+        let names: Vec<&str> = fdt.nodes().map(|node| node.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "",
+                "aliases",
+                "chosen",
+                "memory@0",
+                "soc",
+                "serial@7e201000",
+                "serial@7e215040",
+                "interrupt-controller@40041000"
+            ]
+        );
+        let root: Vec<&str> = fdt.root().children().map(|n| n.base_name()).collect();
+        assert_eq!(
+            root,
+            ["aliases", "chosen", "memory", "soc", "interrupt-controller"]
+        );
+
+        let soc = fdt.find("/soc").unwrap();
+        let properties: Vec<&str> = soc.properties().map(|p| p.name).collect();
+        assert_eq!(
+            properties,
+            ["compatible", "#address-cells", "#size-cells", "ranges"]
+        );
+        assert_eq!(soc.address_cells(), 1);
+        assert_eq!(fdt.root().address_cells(), 2);
+
+        let uart = fdt.find("/soc/serial@7e201000").unwrap();
+        assert_eq!(uart.parent().unwrap().name(), "soc");
+        assert!(fdt.root().parent().is_none());
+        assert!(uart.is_compatible("arm,primecell"));
+        assert_eq!(fdt.find("uart0").unwrap().name(), uart.name());
+        assert_eq!(fdt.find("serial0").unwrap().name(), "serial@7e215040");
+        assert_eq!(
+            fdt.find_compatible("brcm,bcm2835-aux-uart").unwrap().name(),
+            "serial@7e215040"
+        );
+        assert!(fdt.find("/soc/serial").is_some());
+        assert!(fdt.find("/soc/spi").is_none());
+        assert!(fdt.find("nothing").is_none());
+
+        let looped = Builder::new()
+            .begin("")
+            .begin("aliases")
+            .string("self", "self")
+            .string("ping", "pong")
+            .string("pong", "ping/child")
+            .end()
+            .end()
+            .finish(&[]);
+        let looped = Fdt::new(&looped).unwrap();
+        assert!(looped.find("self").is_none());
+        assert!(looped.find("ping").is_none());
+        assert!(looped.find("pong/child").is_none());
+        assert_eq!(
+            fdt.find_phandle(1).unwrap().base_name(),
+            "interrupt-controller"
+        );
+
+        let compatible = uart.property("compatible").unwrap();
+        assert_eq!(
+            compatible.strings().collect::<Vec<_>>(),
+            ["arm,pl011", "arm,primecell"]
+        );
+        assert_eq!(compatible.str(), None);
+        assert_eq!(
+            format!("{compatible}"),
+            "compatible = \"arm,pl011\", \"arm,primecell\""
+        );
+        assert_eq!(
+            format!("{}", uart.property("reg").unwrap()),
+            "reg = <0x7e201000 0x200>"
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        let blob = raspi4b();
+        let fdt = Fdt::new(&blob).unwrap();
+        let uart = fdt.find("uart0").unwrap();
+        assert_eq!(
+            uart.reg().unwrap().next(),
+            Some(Region {
+                address: 0x7e20_1000,
+                size: 0x200
+            })
+        );
+        assert_eq!(
+            uart.region(0),
+            Some(Region {
+                address: 0xfe20_1000,
+                size: 0x200
+            })
+        );
+        assert_eq!(uart.region(1), None);
+        let gic = fdt.find_phandle(1).unwrap();
+        assert_eq!(gic.region(1).unwrap().address, 0xff84_2000);
+
+        let odd = Builder::new()
+            .begin("")
+            .cells("#address-cells", &[0])
+            .cells("#size-cells", &[0])
+            .begin("empty")
+            .cells("reg", &[1])
+            .end()
+            .end()
+            .finish(&[]);
+        let odd = Fdt::new(&odd).unwrap();
+        // no cells at all
+        assert_eq!(odd.find("/empty").unwrap().reg().unwrap().next(), None);
+        assert_eq!(translate(&[0; 4], 0, (0, 0, 0)), None);
+        // past the end of the parent's address space
+        assert_eq!(translate(&[0xff; 16], 0x1_0000_0000, (1, 2, 1)), None);
+
+        let memory: Vec<Region> = fdt.memory().collect();
+        assert_eq!(
+            memory,
+            [
+                Region {
+                    address: 0,
+                    size: 0x3b40_0000
+                },
+                Region {
+                    address: 0x4000_0000,
+                    size: 0x4000_0000
+                }
+            ]
+        );
+        let reserved: Vec<Region> = fdt.reservations().collect();
+        assert_eq!(
+            reserved,
+            [Region {
+                address: 0,
+                size: 0x1000
+            }]
+        );
+        assert_eq!(fdt.bootargs(), Some("console=ttyS0,115200 loglevel=4"));
+
+        let interrupts: Vec<Interrupt> = uart.interrupts().unwrap().collect();
+        assert_eq!(interrupts.len(), 1);
+        assert_eq!(interrupts[0].cells(), [0, 121, 4]);
+        assert_eq!(interrupts[0].gic_irq(), Some(153));
+        assert!(fdt.find("/chosen").unwrap().interrupts().is_none());
+    }
+}
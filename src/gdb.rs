@@ -0,0 +1,801 @@
+//! GDB remote serial protocol stub.
+//!
+//! The stub takes over every exception the kernel does not handle itself: breakpoints, single
+//! steps and crashes stop the kernel and hand the [`TrapFrame`] to GDB until it resumes.
+//! Software breakpoints patch a `BRK` into memory, single steps use the software step of the
+//! debug architecture, Ctrl-C arrives as a UART interrupt. Memory is accessed with
+//! [`exception::probe_read`] and [`exception::probe_write`], a bad address is an error reply
+//! and not another exception.
+use crate::exception::{self, Cause, Kind, TrapFrame};
+use crate::serial::Serial;
+
+pub const SIGINT: u8 = 2;
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+pub const SIGBUS: u8 = 7;
+pub const SIGSEGV: u8 = 11;
+
+/// largest packet accepted and sent, announced in `qSupported`
+const PACKET_SIZE: usize = 4096;
+const BREAKPOINTS: usize = 32;
+/// `brk #0`
+const BRK: u32 = 0xd420_0000;
+const INTERRUPT: u8 = 0x03;
+
+/// SPSR software step and IRQ mask bits
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_I: u64 = 1 << 7;
+
+/// register numbers of the `p` and `P` packets
+const SP: usize = 31;
+const PC: usize = 32;
+const CPSR: usize = 33;
+
+const TARGET_XML: &str = concat!(
+    "<?xml version=\"1.0\"?>",
+    "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+    "<target><architecture>aarch64</architecture>",
+    "<feature name=\"org.gnu.gdb.aarch64.core\">",
+    "<reg name=\"x0\" bitsize=\"64\"/><reg name=\"x1\" bitsize=\"64\"/>",
+    "<reg name=\"x2\" bitsize=\"64\"/><reg name=\"x3\" bitsize=\"64\"/>",
+    "<reg name=\"x4\" bitsize=\"64\"/><reg name=\"x5\" bitsize=\"64\"/>",
+    "<reg name=\"x6\" bitsize=\"64\"/><reg name=\"x7\" bitsize=\"64\"/>",
+    "<reg name=\"x8\" bitsize=\"64\"/><reg name=\"x9\" bitsize=\"64\"/>",
+    "<reg name=\"x10\" bitsize=\"64\"/><reg name=\"x11\" bitsize=\"64\"/>",
+    "<reg name=\"x12\" bitsize=\"64\"/><reg name=\"x13\" bitsize=\"64\"/>",
+    "<reg name=\"x14\" bitsize=\"64\"/><reg name=\"x15\" bitsize=\"64\"/>",
+    "<reg name=\"x16\" bitsize=\"64\"/><reg name=\"x17\" bitsize=\"64\"/>",
+    "<reg name=\"x18\" bitsize=\"64\"/><reg name=\"x19\" bitsize=\"64\"/>",
+    "<reg name=\"x20\" bitsize=\"64\"/><reg name=\"x21\" bitsize=\"64\"/>",
+    "<reg name=\"x22\" bitsize=\"64\"/><reg name=\"x23\" bitsize=\"64\"/>",
+    "<reg name=\"x24\" bitsize=\"64\"/><reg name=\"x25\" bitsize=\"64\"/>",
+    "<reg name=\"x26\" bitsize=\"64\"/><reg name=\"x27\" bitsize=\"64\"/>",
+    "<reg name=\"x28\" bitsize=\"64\"/><reg name=\"x29\" bitsize=\"64\"/>",
+    "<reg name=\"x30\" bitsize=\"64\"/>",
+    "<reg name=\"sp\" bitsize=\"64\" type=\"data_ptr\"/>",
+    "<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\"/>",
+    "<reg name=\"cpsr\" bitsize=\"32\"/>",
+    "</feature></target>",
+);
+
+/// how the stopped kernel continues
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Resume {
+    Continue,
+    /// stop again after one instruction
+    Step,
+}
+
+pub struct GdbStub<'a> {
+    serial: &'a mut dyn Serial,
+    /// address and the instruction replaced by `BRK`
+    breakpoints: [Option<(usize, u32)>; BREAKPOINTS],
+    packet: [u8; PACKET_SIZE],
+    reply: [u8; PACKET_SIZE],
+    reply_len: usize,
+    /// acknowledgements turned off by `QStartNoAckMode`
+    no_ack: bool,
+    /// IRQ mask of the stepped code, IRQs are masked during a step
+    stepping: Option<u64>,
+    /// reason of the current stop
+    signal: u8,
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(serial: &'a mut dyn Serial) -> GdbStub<'a> {
+        GdbStub {
+            serial,
+            breakpoints: [None; BREAKPOINTS],
+            packet: [0; PACKET_SIZE],
+            reply: [0; PACKET_SIZE],
+            reply_len: 0,
+            no_ack: false,
+            stepping: None,
+            signal: SIGTRAP,
+        }
+    }
+
+    pub fn serial(&mut self) -> &mut dyn Serial {
+        &mut *self.serial
+    }
+
+    pub fn is_breakpoint(&self, address: usize) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| matches!(breakpoint, Some((at, _)) if *at == address))
+    }
+
+    /// report the stop to GDB and serve it until it resumes
+    pub fn handle(&mut self, frame: &mut TrapFrame, signal: u8) -> Resume {
+        // a finished step gives the IRQ mask back
+        if let Some(mask) = self.stepping.take() {
+            frame.spsr = (frame.spsr & !(SPSR_SS | SPSR_I)) | mask;
+        }
+        self.signal = signal;
+        self.reply_len = 0;
+        self.push_stop();
+        self.send();
+        loop {
+            let len = self.receive();
+            self.reply_len = 0;
+            if let Some(resume) = self.command(frame, len) {
+                return self.resume(frame, resume);
+            }
+            self.send();
+            // the reply to it is the last one acknowledged
+            if &self.packet[..len] == b"QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+    }
+
+    fn resume(&mut self, frame: &mut TrapFrame, resume: Resume) -> Resume {
+        match resume {
+            Resume::Step => {
+                self.stepping = Some(frame.spsr & SPSR_I);
+                frame.spsr |= SPSR_SS | SPSR_I;
+            }
+            Resume::Continue => frame.spsr &= !SPSR_SS,
+        }
+        #[cfg(target_arch = "aarch64")]
+        set_single_step(resume == Resume::Step);
+        resume
+    }
+
+    /// payload of the next packet with a valid checksum in `packet`
+    fn receive(&mut self) -> usize {
+        loop {
+            while self.serial.read_byte_blocking() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            loop {
+                let byte = self.serial.read_byte_blocking();
+                if byte == b'#' {
+                    break;
+                }
+                if len < PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                }
+                sum = sum.wrapping_add(byte);
+            }
+            let high = self.serial.read_byte_blocking();
+            let low = self.serial.read_byte_blocking();
+            if self.no_ack {
+                return len;
+            }
+            match (hex_digit(high), hex_digit(low)) {
+                (Some(high), Some(low)) if high << 4 | low == sum && len < PACKET_SIZE => {
+                    self.serial.write_byte(b'+');
+                    return len;
+                }
+                _ => self.serial.write_byte(b'-'),
+            }
+        }
+    }
+
+    /// send `reply`, repeated until GDB acknowledges it
+    fn send(&mut self) {
+        let reply = &self.reply[..self.reply_len];
+        let sum = reply.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        loop {
+            self.serial.write_byte(b'$');
+            self.serial.write_bytes(reply);
+            self.serial.write_byte(b'#');
+            self.serial
+                .write_bytes(&[HEX[sum as usize >> 4], HEX[sum as usize & 0xf]]);
+            self.serial.flush();
+            if self.no_ack {
+                return;
+            }
+            loop {
+                match self.serial.read_byte_blocking() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn push_stop(&mut self) {
+        self.push(b"S");
+        self.push_hex(&[self.signal]);
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(PACKET_SIZE - self.reply_len);
+        self.reply[self.reply_len..self.reply_len + len].copy_from_slice(&bytes[..len]);
+        self.reply_len += len;
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(&[HEX[*byte as usize >> 4], HEX[*byte as usize & 0xf]]);
+        }
+    }
+
+    /// `len` bytes of `packet` handled, a resume ends the stop
+    fn command(&mut self, frame: &mut TrapFrame, len: usize) -> Option<Resume> {
+        let packet = self.packet;
+        let packet = &packet[..len];
+        let (&kind, args) = packet.split_first()?;
+        match kind {
+            b'?' => self.push_stop(),
+            b'g' => {
+                for register in 0..=CPSR {
+                    self.push_register(frame, register);
+                }
+            }
+            b'G' => match write_registers(frame, args) {
+                true => self.push(b"OK"),
+                false => self.push(b"E01"),
+            },
+            b'p' => match parse_hex(args) {
+                Some(register) if register as usize <= CPSR => {
+                    self.push_register(frame, register as usize)
+                }
+                _ => self.push(b"E01"),
+            },
+            b'P' => {
+                let written = split(args, b'=').and_then(|(register, value)| {
+                    let register = parse_hex(register)? as usize;
+                    let width = register_width(register)?;
+                    let value = parse_le(value, width)?;
+                    set_register(frame, register, value);
+                    Some(())
+                });
+                match written {
+                    Some(()) => self.push(b"OK"),
+                    None => self.push(b"E01"),
+                }
+            }
+            b'm' => match split(args, b',').and_then(|(a, l)| Some((parse_hex(a)?, parse_hex(l)?)))
+            {
+                Some((address, len)) => self.read_memory(address as usize, len as usize),
+                None => self.push(b"E01"),
+            },
+            b'M' => {
+                let written = split(args, b',')
+                    .and_then(|(address, rest)| Some((parse_hex(address)?, split(rest, b':')?)))
+                    .map(|(address, (_, data))| write_memory(address as usize, data));
+                match written {
+                    Some(true) => self.push(b"OK"),
+                    Some(false) => self.push(b"E14"),
+                    None => self.push(b"E01"),
+                }
+            }
+            b'c' | b's' => {
+                if let Some(address) = parse_hex(args) {
+                    frame.elr = address;
+                }
+                return Some(match kind {
+                    b'c' => Resume::Continue,
+                    _ => Resume::Step,
+                });
+            }
+            // hardware breakpoints and watchpoints are not supported
+            b'Z' | b'z' => {
+                if let Some(address) = parse_breakpoint(args) {
+                    let done = match kind {
+                        b'Z' => self.insert_breakpoint(address),
+                        _ => self.remove_breakpoint(address),
+                    };
+                    match done {
+                        true => self.push(b"OK"),
+                        false => self.push(b"E14"),
+                    }
+                }
+            }
+            b'D' | b'k' => {
+                self.remove_breakpoints();
+                if kind == b'D' {
+                    self.push(b"OK");
+                    self.send();
+                }
+                return Some(Resume::Continue);
+            }
+            b'H' | b'T' => self.push(b"OK"),
+            b'q' | b'Q' => self.query(packet),
+            _ => {}
+        }
+        None
+    }
+
+    fn query(&mut self, packet: &[u8]) {
+        const FEATURES: &[u8] = b"qXfer:features:read:target.xml:";
+        if packet.starts_with(b"qSupported") {
+            self.push(b"PacketSize=");
+            self.push_hex(&(PACKET_SIZE as u16).to_be_bytes());
+            self.push(b";qXfer:features:read+;QStartNoAckMode+");
+        } else if packet == b"QStartNoAckMode" {
+            self.push(b"OK");
+        } else if packet == b"qAttached" {
+            self.push(b"1");
+        } else if packet == b"qC" {
+            self.push(b"QC1");
+        } else if packet == b"qfThreadInfo" {
+            self.push(b"m1");
+        } else if packet == b"qsThreadInfo" {
+            self.push(b"l");
+        } else if let Some(range) = packet.strip_prefix(FEATURES) {
+            match split(range, b',').and_then(|(o, l)| Some((parse_hex(o)?, parse_hex(l)?))) {
+                Some((offset, len)) => {
+                    let document = TARGET_XML.as_bytes();
+                    let start = (offset as usize).min(document.len());
+                    let end = start
+                        + (len as usize)
+                            .min(document.len() - start)
+                            .min(PACKET_SIZE - 1);
+                    self.push(if end == document.len() { b"l" } else { b"m" });
+                    self.push(&document[start..end]);
+                }
+                None => self.push(b"E01"),
+            }
+        }
+    }
+
+    fn push_register(&mut self, frame: &TrapFrame, register: usize) {
+        let value = get_register(frame, register);
+        match register {
+            CPSR => self.push_hex(&(value as u32).to_le_bytes()),
+            _ => self.push_hex(&value.to_le_bytes()),
+        }
+    }
+
+    fn read_memory(&mut self, address: usize, len: usize) {
+        let len = len.min((PACKET_SIZE - 1) / 2);
+        let start = self.reply_len;
+        for offset in 0..len {
+            match exception::probe_read(address.wrapping_add(offset)) {
+                Some(byte) => self.push_hex(&[byte]),
+                None if offset == 0 => {
+                    self.reply_len = start;
+                    return self.push(b"E14");
+                }
+                // the part that could be read
+                None => return,
+            }
+        }
+    }
+
+    fn insert_breakpoint(&mut self, address: usize) -> bool {
+        if self.is_breakpoint(address) {
+            return true;
+        }
+        let Some(slot) = self.breakpoints.iter().position(Option::is_none) else {
+            return false;
+        };
+        let Some(instruction) = read_u32(address) else {
+            return false;
+        };
+        if !write_memory_raw(address, &BRK.to_le_bytes()) {
+            return false;
+        }
+        self.breakpoints[slot] = Some((address, instruction));
+        true
+    }
+
+    fn remove_breakpoint(&mut self, address: usize) -> bool {
+        for breakpoint in self.breakpoints.iter_mut() {
+            if let Some((at, instruction)) = *breakpoint
+                && at == address
+            {
+                *breakpoint = None;
+                return write_memory_raw(address, &instruction.to_le_bytes());
+            }
+        }
+        // GDB removes what it never managed to insert
+        true
+    }
+
+    fn remove_breakpoints(&mut self) {
+        for slot in 0..BREAKPOINTS {
+            if let Some((address, _)) = self.breakpoints[slot] {
+                self.remove_breakpoint(address);
+            }
+        }
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// big endian number as in addresses and lengths
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        Some(value << 4 | hex_digit(*digit)? as u64)
+    })
+}
+
+/// `width` bytes of hex pairs in target order
+fn parse_le(digits: &[u8], width: usize) -> Option<u64> {
+    if digits.len() != width * 2 {
+        return None;
+    }
+    let mut value = 0u64;
+    for (i, pair) in digits.chunks(2).enumerate() {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        value |= (byte as u64) << (i * 8);
+    }
+    Some(value)
+}
+
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = bytes.iter().position(|byte| *byte == separator)?;
+    Some((&bytes[..at], &bytes[at + 1..]))
+}
+
+/// address of a `0,address,kind` software breakpoint
+fn parse_breakpoint(args: &[u8]) -> Option<usize> {
+    let (kind, rest) = split(args, b',')?;
+    if kind != b"0" {
+        return None;
+    }
+    let address = split(rest, b',').map_or(rest, |(address, _)| address);
+    Some(parse_hex(address)? as usize)
+}
+
+fn register_width(register: usize) -> Option<usize> {
+    match register {
+        0..=PC => Some(8),
+        CPSR => Some(4),
+        _ => None,
+    }
+}
+
+fn get_register(frame: &TrapFrame, register: usize) -> u64 {
+    match register {
+        0..SP => frame.x[register],
+        SP => frame.sp,
+        PC => frame.elr,
+        _ => frame.spsr,
+    }
+}
+
+/// the stack pointer is not restored from the frame, writes to it are dropped
+fn set_register(frame: &mut TrapFrame, register: usize, value: u64) {
+    match register {
+        0..SP => frame.x[register] = value,
+        SP => {}
+        PC => frame.elr = value,
+        _ => frame.spsr = (frame.spsr & !0xffff_ffff) | value,
+    }
+}
+
+fn write_registers(frame: &mut TrapFrame, mut digits: &[u8]) -> bool {
+    for register in 0..=CPSR {
+        let width = register_width(register).unwrap() * 2;
+        let Some(value) = digits
+            .get(..width)
+            .and_then(|value| parse_le(value, width / 2))
+        else {
+            return false;
+        };
+        set_register(frame, register, value);
+        digits = &digits[width..];
+    }
+    true
+}
+
+fn read_u32(address: usize) -> Option<u32> {
+    let mut bytes = [0; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = exception::probe_read(address + i)?;
+    }
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn write_memory(address: usize, digits: &[u8]) -> bool {
+    if !digits.len().is_multiple_of(2) {
+        return false;
+    }
+    for (i, pair) in digits.chunks(2).enumerate() {
+        let (Some(high), Some(low)) = (hex_digit(pair[0]), hex_digit(pair[1])) else {
+            return false;
+        };
+        if !exception::probe_write(address + i, high << 4 | low) {
+            return false;
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    sync_instructions(address, digits.len() / 2);
+    true
+}
+
+fn write_memory_raw(address: usize, bytes: &[u8]) -> bool {
+    for (i, byte) in bytes.iter().enumerate() {
+        if !exception::probe_write(address + i, *byte) {
+            return false;
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    sync_instructions(address, bytes.len());
+    true
+}
+
+/// signal GDB is told about for an exception
+pub fn signal(frame: &mut TrapFrame, kind: Kind, stub: &GdbStub) -> u8 {
+    match kind {
+        Kind::Synchronous => match Cause::from_esr(frame.esr) {
+            Cause::Breakpoint(_) => {
+                // a BRK compiled into the kernel is stepped over
+                if !stub.is_breakpoint(frame.elr as usize) {
+                    frame.elr += 4;
+                }
+                SIGTRAP
+            }
+            Cause::Step | Cause::HardwareBreakpoint | Cause::Watchpoint => SIGTRAP,
+            Cause::InstructionAbort | Cause::DataAbort => SIGSEGV,
+            Cause::Alignment => SIGBUS,
+            Cause::Undefined | Cause::Call(_) | Cause::Other(_) => SIGILL,
+        },
+        Kind::FIQ => SIGINT,
+        Kind::IRQ | Kind::SError => SIGBUS,
+    }
+}
+
+static mut STUB: Option<GdbStub<'static>> = None;
+
+fn trap(frame: &mut TrapFrame, kind: Kind) {
+    let stub = &raw mut STUB;
+    let Some(stub) = (unsafe { &mut *stub }).as_mut() else {
+        return;
+    };
+    let signal = signal(frame, kind, stub);
+    stub.handle(frame, signal);
+}
+
+/// receive interrupt of the stub's UART, Ctrl-C stops the kernel
+fn interrupt(frame: &mut TrapFrame) {
+    let stub = &raw mut STUB;
+    let Some(stub) = (unsafe { &mut *stub }).as_mut() else {
+        return;
+    };
+    let mut stop = false;
+    // draining the FIFO clears the interrupt
+    while let Some(byte) = stub.serial().read_byte() {
+        stop |= byte == INTERRUPT;
+    }
+    if stop {
+        stub.handle(frame, SIGINT);
+    }
+}
+
+/// run the stub on `instance` at 115200 baud and wait for GDB to attach
+#[cfg(target_arch = "aarch64")]
+pub fn start(instance: crate::pl011::UARTInstance) {
+    use crate::board::{self, UART_CLOCK};
+    use crate::gic::{self, GICCPUInterface, GICDistributor};
+    use crate::gpio::GPIO;
+    use crate::pl011::PL011;
+
+    let uart = unsafe { &mut *PL011::new(instance) };
+    uart.init(UART_CLOCK, 115_200);
+    let gpio_ = &raw mut GPIO;
+    let gpio = unsafe { &mut *(*gpio_).take_gpio() };
+    instance.route(gpio);
+    unsafe { (*gpio_).return_gpio(gpio) };
+
+    unsafe { STUB = Some(GdbStub::new(uart)) };
+    enable_debug();
+    exception::set_handler(Some(trap));
+
+    // without a GIC there is no Ctrl-C, GDB only gets control back at breakpoints
+    if board::GIC.is_some() {
+        let distributor = unsafe { &mut *GICDistributor::new() };
+        let cpu = unsafe { &mut *GICCPUInterface::new() };
+        distributor.init();
+        cpu.init();
+        gic::register(instance.irq(), interrupt);
+        distributor.enable(instance.irq(), 0);
+        unsafe { (*PL011::new(instance)).enable_receive_interrupt() };
+        exception::enable_irqs();
+    }
+
+    unsafe { core::arch::asm!("brk #0") };
+}
+
+/// debug exceptions taken at the kernel's exception level
+#[cfg(target_arch = "aarch64")]
+fn enable_debug() {
+    use core::arch::asm;
+    const MDSCR_EL1_KDE: u64 = 1 << 13;
+    const MDCR_EL2_TDE: u64 = 1 << 8;
+    unsafe {
+        asm!("msr oslar_el1, xzr");
+        asm!(
+            "mrs {0}, mdscr_el1",
+            "orr {0}, {0}, {1}",
+            "msr mdscr_el1, {0}",
+            out(reg) _,
+            in(reg) MDSCR_EL1_KDE,
+        );
+        if exception::current_el() == 2 {
+            asm!(
+                "mrs {0}, mdcr_el2",
+                "orr {0}, {0}, {1}",
+                "msr mdcr_el2, {0}",
+                out(reg) _,
+                in(reg) MDCR_EL2_TDE,
+            );
+        }
+        asm!("isb", "msr daifclr, #8");
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn set_single_step(enable: bool) {
+    const MDSCR_EL1_SS: u64 = 1;
+    unsafe {
+        core::arch::asm!(
+            "mrs {0}, mdscr_el1",
+            "bic {0}, {0}, {1}",
+            "orr {0}, {0}, {2}",
+            "msr mdscr_el1, {0}",
+            "isb",
+            out(reg) _,
+            in(reg) MDSCR_EL1_SS,
+            in(reg) if enable { MDSCR_EL1_SS } else { 0 },
+        )
+    };
+}
+
+/// make instructions written as data visible to instruction fetches
+#[cfg(target_arch = "aarch64")]
+fn sync_instructions(address: usize, len: usize) {
+    const LINE: usize = 64;
+    unsafe {
+        for line in (address & !(LINE - 1)..address + len).step_by(LINE) {
+            core::arch::asm!("dc cvau, {0}", "dsb ish", "ic ivau, {0}", in(reg) line);
+        }
+        core::arch::asm!("dsb ish", "isb");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockSerial;
+
+    fn packet(data: &str) -> Vec<u8> {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        format!("${data}#{sum:02x}").into_bytes()
+    }
+
+    /// GDB's side: acknowledges the stop reply, then sends `packets` each acknowledged
+    fn session(packets: &[&str]) -> MockSerial {
+        let mut rx = vec![b'+'];
+        for data in packets {
+            rx.extend(packet(data));
+            rx.push(b'+');
+        }
+        MockSerial::new(&rx)
+    }
+
+    /// payloads of the packets the stub sent, acknowledgements dropped
+    fn replies(tx: &[u8]) -> Vec<String> {
+        let text = String::from_utf8_lossy(tx);
+        text.split('$')
+            .skip(1)
+            .map(|packet| packet.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn registers() {
+        let mut serial = session(&[
+            "qSupported:multiprocess+",
+            "g",
+            "p20",
+            "P1=2a00000000000000",
+            "c",
+        ]);
+        let mut frame = TrapFrame::default();
+        frame.x[0] = 0x1122334455667788;
+        frame.elr = 0x80000;
+        frame.spsr = 0x3c5;
+        let mut stub = GdbStub::new(&mut serial);
+
+        assert_eq!(stub.handle(&mut frame, SIGTRAP), Resume::Continue);
+        assert_eq!(frame.x[1], 42);
+        let replies = replies(&serial.tx);
+        assert_eq!(replies[0], "S05");
+        assert_eq!(
+            replies[1],
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+"
+        );
+        assert_eq!(replies[2].len(), 33 * 16 + 8);
+        assert!(replies[2].starts_with("8877665544332211"));
+        assert!(replies[2].ends_with("0000080000000000c5030000"));
+        assert_eq!(replies[3], "0000080000000000");
+        assert_eq!(replies[4], "OK");
+    }
+
+    #[test]
+    fn memory_and_breakpoints() {
+        let mut memory = [0x11u8, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+        let address = &raw mut memory as usize;
+        let read = format!("m{address:x},4");
+        let write = format!("M{:x},2:a5a6", address + 4);
+        let insert = format!("Z0,{address:x},4");
+        let remove = format!("z0,{address:x},4");
+        let mut serial = session(&[&read, &write, &insert, &remove, "Z1,0,4", "s"]);
+        let mut frame = TrapFrame::default();
+        let mut stub = GdbStub::new(&mut serial);
+
+        assert_eq!(stub.handle(&mut frame, SIGTRAP), Resume::Step);
+        assert_eq!(frame.spsr, SPSR_SS | SPSR_I);
+        assert_eq!(memory, [0x11, 0x22, 0x33, 0x44, 0xa5, 0xa6, 0x77, 0x88]);
+        let replies = replies(&serial.tx);
+        assert_eq!(&replies[1..], ["11223344", "OK", "OK", "OK", ""]);
+    }
+
+    #[test]
+    fn breakpoint_stop() {
+        let mut code = [0u32; 2];
+        let address = &raw mut code as usize;
+        let insert = format!("Z0,{:x},4", address + 4);
+        let mut serial = session(&[&insert, "c"]);
+        // the second stop
+        serial.rx.push_back(b'+');
+        serial.rx.extend(packet("D"));
+        serial.rx.push_back(b'+');
+        let mut stub = GdbStub::new(&mut serial);
+        let mut frame = TrapFrame::default();
+        stub.handle(&mut frame, SIGTRAP);
+        assert_eq!(code[1], BRK);
+
+        // the patched instruction stays where it is, others are stepped over
+        frame.esr = 0xf200_0000;
+        frame.elr = address as u64 + 4;
+        assert_eq!(signal(&mut frame, Kind::Synchronous, &stub), SIGTRAP);
+        assert_eq!(frame.elr, address as u64 + 4);
+        frame.elr = address as u64;
+        signal(&mut frame, Kind::Synchronous, &stub);
+        assert_eq!(frame.elr, address as u64 + 4);
+        frame.esr = 0x9600_0000;
+        assert_eq!(signal(&mut frame, Kind::Synchronous, &stub), SIGSEGV);
+
+        // detaching restores the instruction
+        assert_eq!(stub.handle(&mut frame, SIGTRAP), Resume::Continue);
+        assert_eq!(code[1], 0);
+    }
+
+    #[test]
+    fn protocol() {
+        // a corrupted packet is rejected and sent again, a rejected reply repeated
+        let mut rx = vec![b'+'];
+        rx.extend(b"$?#00");
+        rx.extend(packet("?"));
+        rx.extend(b"-+");
+        rx.extend(packet("QStartNoAckMode"));
+        rx.push(b'+');
+        rx.extend(packet("qXfer:features:read:target.xml:0,5"));
+        rx.extend(packet("qfThreadInfo"));
+        rx.extend(packet("vMustReplyEmpty"));
+        rx.extend(packet("c"));
+        let mut serial = MockSerial::new(&rx);
+        let mut frame = TrapFrame::default();
+        frame.spsr = SPSR_SS | SPSR_I;
+        let mut stub = GdbStub::new(&mut serial);
+        stub.stepping = Some(0);
+
+        assert_eq!(stub.handle(&mut frame, SIGINT), Resume::Continue);
+        assert_eq!(frame.spsr, 0);
+        let text = String::from_utf8_lossy(&serial.tx).into_owned();
+        assert!(text.starts_with("$S02#b5-+$S02#b5$S02#b5+$OK#9a"));
+        assert_eq!(&replies(&serial.tx)[4..], ["m<?xml", "m1", ""]);
+    }
+}
@@ -0,0 +1,292 @@
+//! GIC-400 interrupt controller, the distributor and the CPU interface of the ARM cores.
+//!
+//! Interrupt groups are left as the firmware set them up. Handlers are registered per
+//! interrupt ID and called by [`dispatch`] from the IRQ exception vector.
+//!
+//! The Pi 3 has no GIC, see [`board::GIC`].
+use crate::board;
+use crate::exception::TrapFrame;
+use crate::registers::*;
+
+/// interrupt IDs, SGIs and PPIs are below 32
+const MAX_IRQS: usize = 256;
+const SPURIOUS: u32 = 1023;
+
+register_bitfields! {u32,
+    pub GICD_CTLR [
+        ENABLE_GRP0 OFFSET(0) NUMBITS(1) [],
+        ENABLE_GRP1 OFFSET(1) NUMBITS(1) [],
+    ],
+    /// 32 interrupts per register
+    pub GICD_BITS [
+        BIT0 OFFSET(0) NUMBITS(1) [],
+        ALL OFFSET(0) NUMBITS(32) [],
+    ],
+    /// 4 interrupts per register
+    pub GICD_BYTES [
+        BYTE0 OFFSET(0) NUMBITS(8) [],
+    ],
+    /// 16 interrupts per register
+    pub GICD_ICFGR [
+        EDGE0 OFFSET(1) NUMBITS(1) [],
+    ],
+    pub GICC_CTLR [
+        ENABLE_GRP0 OFFSET(0) NUMBITS(1) [],
+        ENABLE_GRP1 OFFSET(1) NUMBITS(1) [],
+    ],
+    pub GICC_PMR [
+        PRIORITY OFFSET(0) NUMBITS(8) [],
+    ],
+    /// also the layout of EOIR
+    pub GICC_IAR [
+        INTERRUPT_ID OFFSET(0) NUMBITS(10) [],
+        CPU_ID OFFSET(10) NUMBITS(3) [],
+    ],
+}
+
+#[repr(C)]
+pub struct GICDistributor {
+    ctlr: ReadWrite<u32, GICD_CTLR::Register>, /* 0x000 GICD_CTLR Distributor Control */
+    typer: ReadOnly<u32>,                      /* 0x004 GICD_TYPER Interrupt Controller Type */
+    iidr: ReadOnly<u32>, /* 0x008 GICD_IIDR Distributor Implementer Identification */
+    padding0: [u8; 0x74], /* 0x00c padding */
+    igroupr: [ReadWrite<u32, GICD_BITS::Register>; 32], /* 0x080 GICD_IGROUPRn Interrupt Group */
+    isenabler: [ReadWrite<u32, GICD_BITS::Register>; 32], /* 0x100 GICD_ISENABLERn Interrupt Set-Enable */
+    icenabler: [ReadWrite<u32, GICD_BITS::Register>; 32], /* 0x180 GICD_ICENABLERn Interrupt Clear-Enable */
+    padding1: [u8; 0x200],                                /* 0x200 padding */
+    ipriorityr: [ReadWrite<u32, GICD_BYTES::Register>; 255], /* 0x400 GICD_IPRIORITYRn Interrupt Priority */
+    padding2: [u8; 0x4],                                     /* 0x7fc padding */
+    itargetsr: [ReadWrite<u32, GICD_BYTES::Register>; 255], /* 0x800 GICD_ITARGETSRn Interrupt Processor Targets */
+    padding3: [u8; 0x4],                                    /* 0xbfc padding */
+    icfgr: [ReadWrite<u32, GICD_ICFGR::Register>; 64], /* 0xc00 GICD_ICFGRn Interrupt Configuration */
+}
+
+register_layout! {
+    GICDistributor @ 0x000 .. 0xd00 {
+        0x000 => ctlr,
+        0x004 => typer,
+        0x008 => iidr,
+        0x00c => padding0,
+        0x080 => igroupr,
+        0x100 => isenabler,
+        0x180 => icenabler,
+        0x200 => padding1,
+        0x400 => ipriorityr,
+        0x7fc => padding2,
+        0x800 => itargetsr,
+        0xbfc => padding3,
+        0xc00 => icfgr,
+    }
+}
+
+#[repr(C)]
+pub struct GICCPUInterface {
+    ctlr: ReadWrite<u32, GICC_CTLR::Register>, /* 0x00 GICC_CTLR CPU Interface Control */
+    pmr: ReadWrite<u32, GICC_PMR::Register>,   /* 0x04 GICC_PMR Interrupt Priority Mask */
+    bpr: ReadWrite<u32>,                       /* 0x08 GICC_BPR Binary Point */
+    iar: ReadOnly<u32, GICC_IAR::Register>,    /* 0x0c GICC_IAR Interrupt Acknowledge */
+    eoir: WriteOnly<u32, GICC_IAR::Register>,  /* 0x10 GICC_EOIR End of Interrupt */
+    rpr: ReadOnly<u32>,                        /* 0x14 GICC_RPR Running Priority */
+    hppir: ReadOnly<u32>, /* 0x18 GICC_HPPIR Highest Priority Pending Interrupt */
+}
+
+register_layout! {
+    GICCPUInterface @ 0x00 .. 0x1c {
+        0x00 => ctlr,
+        0x04 => pmr,
+        0x08 => bpr,
+        0x0c => iar,
+        0x10 => eoir,
+        0x14 => rpr,
+        0x18 => hppir,
+    }
+}
+
+impl GICDistributor {
+    const BASE: usize = match board::GIC {
+        Some((distributor, _)) => distributor,
+        None => 0,
+    };
+
+    pub const fn new() -> *mut Self {
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut Self {
+        base as *mut Self
+    }
+
+    /// shared peripheral interrupts disabled, distribution on
+    pub fn init(&mut self) {
+        self.ctlr.set(0);
+        for icenabler in &self.icenabler[1..] {
+            icenabler.write(GICD_BITS::ALL.val(u32::MAX));
+        }
+        self.ctlr
+            .write(GICD_CTLR::ENABLE_GRP0.val(1) + GICD_CTLR::ENABLE_GRP1.val(1));
+    }
+
+    /// level triggered `irq` with the middle priority, delivered to `cpu`
+    pub fn enable(&mut self, irq: u32, cpu: u32) {
+        let (reg, bit) = (irq as usize / 32, irq as usize % 32);
+        let (bytes, byte) = (irq as usize / 4, (irq as usize % 4) * 8);
+        self.ipriorityr[bytes].modify(GICD_BYTES::BYTE0.offset(byte).val(0xa0));
+        if irq >= 32 {
+            self.itargetsr[bytes].modify(GICD_BYTES::BYTE0.offset(byte).val(1 << cpu));
+            let (cfg, field) = (irq as usize / 16, (irq as usize % 16) * 2);
+            self.icfgr[cfg].modify(GICD_ICFGR::EDGE0.offset(field).val(0));
+        }
+        self.isenabler[reg].write(GICD_BITS::BIT0.offset(bit).val(1));
+    }
+
+    pub fn disable(&mut self, irq: u32) {
+        let (reg, bit) = (irq as usize / 32, irq as usize % 32);
+        self.icenabler[reg].write(GICD_BITS::BIT0.offset(bit).val(1));
+    }
+
+    pub fn is_enabled(&self, irq: u32) -> bool {
+        let (reg, bit) = (irq as usize / 32, irq as usize % 32);
+        self.isenabler[reg].is_set(GICD_BITS::BIT0.offset(bit))
+    }
+}
+
+impl GICCPUInterface {
+    const BASE: usize = match board::GIC {
+        Some((_, cpu)) => cpu,
+        None => 0,
+    };
+
+    pub const fn new() -> *mut Self {
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut Self {
+        base as *mut Self
+    }
+
+    /// every priority passes
+    pub fn init(&mut self) {
+        self.pmr.write(GICC_PMR::PRIORITY.val(0xff));
+        self.ctlr
+            .write(GICC_CTLR::ENABLE_GRP0.val(1) + GICC_CTLR::ENABLE_GRP1.val(1));
+    }
+
+    /// raw IAR value of the highest priority pending interrupt, `None` if it was spurious
+    pub fn acknowledge(&mut self) -> Option<u32> {
+        let iar = self.iar.get();
+        match GICC_IAR::INTERRUPT_ID.read(iar) {
+            SPURIOUS => None,
+            _ => Some(iar),
+        }
+    }
+
+    /// `iar` as returned by [`GICCPUInterface::acknowledge`]
+    pub fn end(&mut self, iar: u32) {
+        self.eoir.set(iar);
+    }
+}
+
+static mut HANDLERS: [Option<fn(&mut TrapFrame)>; MAX_IRQS] = [None; MAX_IRQS];
+
+/// call `handler` for `irq`, it has to clear the interrupt at its source
+pub fn register(irq: u32, handler: fn(&mut TrapFrame)) {
+    let handlers = &raw mut HANDLERS;
+    unsafe { (*handlers)[irq as usize] = Some(handler) };
+}
+
+pub fn unregister(irq: u32) {
+    let handlers = &raw mut HANDLERS;
+    unsafe { (*handlers)[irq as usize] = None };
+}
+
+/// handle pending interrupts, interrupts without a handler are only acknowledged
+pub fn dispatch(cpu: &mut GICCPUInterface, frame: &mut TrapFrame) {
+    while let Some(iar) = cpu.acknowledge() {
+        let irq = GICC_IAR::INTERRUPT_ID.read(iar) as usize;
+        let handlers = &raw const HANDLERS;
+        let handler = unsafe { (*handlers).get(irq).copied().flatten() };
+        if let Some(handler) = handler {
+            handler(frame);
+        }
+        cpu.end(iar);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const GICD_ISENABLER: usize = 0x100;
+    const GICD_ICENABLER: usize = 0x180;
+    const GICD_IPRIORITYR: usize = 0x400;
+    const GICD_ITARGETSR: usize = 0x800;
+    const GICD_ICFGR: usize = 0xc00;
+    const GICC_IAR: usize = 0x0c;
+    const GICC_EOIR: usize = 0x10;
+
+    #[test]
+    fn distributor() {
+        let file = RegisterFile::new(core::mem::size_of::<GICDistributor>());
+        for i in 0..32 {
+            // set-enable registers read back the enabled interrupts
+            let isenabler = GICD_ISENABLER + i * 4;
+            file.on(
+                isenabler,
+                Behavior::Custom(Box::new(move |storage, access| match access {
+                    Access::Read => storage.get(isenabler),
+                    Access::Write(value) => {
+                        storage.set(isenabler, storage.get(isenabler) | value);
+                        0
+                    }
+                })),
+            )
+            .on(
+                GICD_ICENABLER + i * 4,
+                Behavior::ClearBits {
+                    target: GICD_ISENABLER + i * 4,
+                },
+            );
+        }
+        file.poke(GICD_ISENABLER + 4, 0xffff);
+        file.poke(GICD_ICFGR + 9 * 4, u32::MAX);
+        let gicd = unsafe { &mut *file.block::<GICDistributor>(0) };
+
+        gicd.init();
+        assert_eq!(file.peek(0), 0b11);
+        assert_eq!(file.peek(GICD_ISENABLER + 4), 0);
+
+        gicd.enable(153, 0);
+        assert!(gicd.is_enabled(153));
+        assert_eq!(file.peek(GICD_ISENABLER + 16), 1 << 25);
+        assert_eq!(file.peek(GICD_IPRIORITYR + 38 * 4), 0xa0 << 8);
+        assert_eq!(file.peek(GICD_ITARGETSR + 38 * 4), 1 << 8);
+        assert_eq!(file.peek(GICD_ICFGR + 9 * 4), !(1 << 19));
+        gicd.disable(153);
+        assert!(!gicd.is_enabled(153));
+    }
+
+    static mut HANDLED: [u32; 2] = [0; 2];
+
+    #[test]
+    fn dispatch_interrupts() {
+        let file = RegisterFile::new(core::mem::size_of::<GICCPUInterface>());
+        file.on(GICC_IAR, Behavior::Fifo)
+            .on(GICC_EOIR, Behavior::Fifo);
+        // two interrupts from CPU 1, then nothing is pending
+        file.push_rx(GICC_IAR, &[(1 << 10) | 200, 201, 1023]);
+        let gicc = unsafe { &mut *file.block::<GICCPUInterface>(0) };
+        gicc.init();
+        assert_eq!(file.peek(0x04), 0xff);
+
+        register(200, |_| unsafe { HANDLED[0] += 1 });
+        register(201, |_| unsafe { HANDLED[1] += 1 });
+        unregister(201);
+        let mut frame = TrapFrame::default();
+        dispatch(gicc, &mut frame);
+        assert_eq!(unsafe { HANDLED }, [1, 0]);
+        assert_eq!(file.take_tx(GICC_EOIR), [(1 << 10) | 200, 201]);
+    }
+}
@@ -1,7 +1,8 @@
-use crate::utils::bits::*;
-use core::ptr::read_volatile;
+use crate::board::{self, PullStyle};
+use crate::registers::*;
 
 #[derive(PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
 pub enum GPIOPin {
     PIN0,
     PIN1,
@@ -66,10 +67,9 @@ pub struct GPIO {
 
 impl GPIO {
     const fn new() -> GPIO {
-        let periph = GPIO {
+        GPIO {
             registers: Some(GPIORegisters::new()),
-        };
-        periph
+        }
     }
 
     pub fn take_gpio(&mut self) -> *mut GPIORegisters {
@@ -82,49 +82,112 @@ impl GPIO {
     }
 }
 
+register_bitfields! {u32,
+    /// 10 pins per register, `FSEL0` is the first pin of the bank
+    pub GPFSEL [
+        FSEL0 OFFSET(0) NUMBITS(3) [],
+    ],
+    /// 32 pins per register, write 1 to drive pin high
+    pub GPSET [
+        SET0 OFFSET(0) NUMBITS(1) [],
+    ],
+    /// 32 pins per register, write 1 to drive pin low
+    pub GPCLR [
+        CLR0 OFFSET(0) NUMBITS(1) [],
+    ],
+    /// 32 pins per register
+    pub GPLEV [
+        LEV0 OFFSET(0) NUMBITS(1) [],
+    ],
+    /// 32 pins per register, write 1 to clear event
+    pub GPEDS [
+        EDS0 OFFSET(0) NUMBITS(1) [],
+    ],
+    /// 32 pins per register, shared by all edge/level detect enable registers
+    pub GPDETECT [
+        EN0 OFFSET(0) NUMBITS(1) [],
+    ],
+    /// pull clocked into the pins by GPPUDCLK, BCM2835-BCM2837
+    pub GPPUD [
+        PUD OFFSET(0) NUMBITS(2) [
+            None = 0,
+            Down = 1,
+            Up = 2,
+        ],
+    ],
+    /// 32 pins per register, write 1 to clock GPPUD into the pin
+    pub GPPUDCLK [
+        CLK0 OFFSET(0) NUMBITS(1) [],
+    ],
+    /// 16 pins per register, BCM2711
+    pub GPIO_PUP_PDN_CNTRL [
+        PULL0 OFFSET(0) NUMBITS(2) [
+            None = 0,
+            Up = 1,
+            Down = 2,
+        ],
+    ],
+}
+
 #[repr(C)]
 pub struct GPIORegisters {
-    gpfsel0: u32,                 /* 0x00 GPFSEL0 GPIO Function Select 0 */
-    gpfsel1: u32,                 /* 0x04 GPFSEL1 GPIO Function Select 1 */
-    gpfsel2: u32,                 /* 0x08 GPFSEL2 GPIO Function Select 2 */
-    gpfsel3: u32,                 /* 0x0c GPFSEL3 GPIO Function Select 3 */
-    gpfsel4: u32,                 /* 0x10 GPFSEL4 GPIO Function Select 4 */
-    gpfsel5: u32,                 /* 0x14 GPFSEL5 GPIO Function Select 5 */
-    padding0: [u8; 0x4],          /* 0x18 padding */
-    gpset0: u32,                  /* 0x1c GPSET0 GPIO Pin Output Set 0 */
-    gpset1: u32,                  /* 0x20 GPSET1 GPIO Pin Output Set 1 */
-    padding1: [u8; 0x4],          /* 0x24 padding */
-    gpclr0: u32,                  /* 0x28 GPCLR0 GPIO Pin Output Clear 0 */
-    gpclr1: u32,                  /* 0x2c GPCLR1 GPIO Pin Output Clear 1 */
-    padding2: [u8; 0x4],          /* 0x30 padding */
-    gplev0: u32,                  /* 0x34 GPLEV0 GPIO Pin Level 0 */
-    gplev1: u32,                  /* 0x38 GPLEV1 GPIO Pin Level 1 */
-    padding3: [u8; 0x4],          /* 0x3c padding */
-    gpeds0: u32,                  /* 0x40 GPEDS0 GPIO Pin Event Detect Status 0 */
-    gpeds1: u32,                  /* 0x44 GPEDS1 GPIO Pin Event Detect Status 1 */
-    padding4: [u8; 0x4],          /* 0x48 padding */
-    gpren0: u32,                  /* 0x4c GPREN0 GPIO Pin Rising Edge Detect Enable 0 */
-    gpren1: u32,                  /* 0x50 GPREN1 GPIO Pin Rising Edge Detect Enable 1 */
-    padding5: [u8; 0x4],          /* 0x54 padding */
-    gpfen0: u32,                  /* 0x58 GPFEN0 GPIO Pin Falling Edge Detect Enable 0 */
-    gpfen1: u32,                  /* 0x5c GPFEN1 GPIO Pin Falling Edge Detect Enable 1 */
-    padding6: [u8; 0x4],          /* 0x60 padding */
-    gphen0: u32,                  /* 0x64 GPHEN0 GPIO Pin High Detect Enable 0 */
-    gphen1: u32,                  /* 0x68 GPHEN1 GPIO Pin High Detect Enable 1 */
-    padding7: [u8; 0x4],          /* 0x6c padding */
-    gplen0: u32,                  /* 0x70 GPLEN0 GPIO Pin Low Detect Enable 0 */
-    gplen1: u32,                  /* 0x74 GPLEN1 GPIO Pin Low Detect Enable 1 */
-    padding8: [u8; 0x4],          /* 0x78 padding */
-    gparen0: u32,                 /* 0x7c GPAREN0 GPIO Pin Async. Rising Edge Detect 0 */
-    gparen1: u32,                 /* 0x80 GPAREN1 GPIO Pin Async. Rising Edge Detect 1 */
-    padding9: [u8; 0x4],          /* 0x84 padding */
-    gpafen0: u32,                 /* 0x88 GPAFEN0 GPIO Pin Async. Falling Edge Detect 0 */
-    gpafen1: u32,                 /* 0x8c GPAFEN1 GPIO Pin Async. Falling Edge Detect 1 */
-    padding10: [u8; 0x54],        /* 0x90 padding */
-    gpio_pup_pdn_cntrl_reg0: u32, /* 0xe4 GPIO_PUP_PDN_CNTRL_REG0 GPIO Pull-up / Pull-down Register 0 */
-    gpio_pup_pdn_cntrl_reg1: u32, /* 0xe8 GPIO_PUP_PDN_CNTRL_REG1 GPIO Pull-up / Pull-down Register 1 */
-    gpio_pup_pdn_cntrl_reg2: u32, /* 0xec GPIO_PUP_PDN_CNTRL_REG2 GPIO Pull-up / Pull-down Register 2 */
-    gpio_pup_pdn_cntrl_reg3: u32, /* 0xf0 GPIO_PUP_PDN_CNTRL_REG3 GPIO Pull-up / Pull-down Register 3 */
+    gpfsel: [ReadWrite<u32, GPFSEL::Register>; 6], /* 0x00 GPFSEL0-5 GPIO Function Select 0-5 */
+    padding0: [u8; 0x4],                           /* 0x18 padding */
+    gpset: [WriteOnly<u32, GPSET::Register>; 2],   /* 0x1c GPSET0-1 GPIO Pin Output Set 0-1 */
+    padding1: [u8; 0x4],                           /* 0x24 padding */
+    gpclr: [WriteOnly<u32, GPCLR::Register>; 2],   /* 0x28 GPCLR0-1 GPIO Pin Output Clear 0-1 */
+    padding2: [u8; 0x4],                           /* 0x30 padding */
+    gplev: [ReadOnly<u32, GPLEV::Register>; 2],    /* 0x34 GPLEV0-1 GPIO Pin Level 0-1 */
+    padding3: [u8; 0x4],                           /* 0x3c padding */
+    gpeds: [ReadWrite<u32, GPEDS::Register>; 2], /* 0x40 GPEDS0-1 GPIO Pin Event Detect Status 0-1 */
+    padding4: [u8; 0x4],                         /* 0x48 padding */
+    gpren: [ReadWrite<u32, GPDETECT::Register>; 2], /* 0x4c GPREN0-1 GPIO Pin Rising Edge Detect Enable 0-1 */
+    padding5: [u8; 0x4],                            /* 0x54 padding */
+    gpfen: [ReadWrite<u32, GPDETECT::Register>; 2], /* 0x58 GPFEN0-1 GPIO Pin Falling Edge Detect Enable 0-1 */
+    padding6: [u8; 0x4],                            /* 0x60 padding */
+    gphen: [ReadWrite<u32, GPDETECT::Register>; 2], /* 0x64 GPHEN0-1 GPIO Pin High Detect Enable 0-1 */
+    padding7: [u8; 0x4],                            /* 0x6c padding */
+    gplen: [ReadWrite<u32, GPDETECT::Register>; 2], /* 0x70 GPLEN0-1 GPIO Pin Low Detect Enable 0-1 */
+    padding8: [u8; 0x4],                            /* 0x78 padding */
+    gparen: [ReadWrite<u32, GPDETECT::Register>; 2], /* 0x7c GPAREN0-1 GPIO Pin Async. Rising Edge Detect 0-1 */
+    padding9: [u8; 0x4],                             /* 0x84 padding */
+    gpafen: [ReadWrite<u32, GPDETECT::Register>; 2], /* 0x88 GPAFEN0-1 GPIO Pin Async. Falling Edge Detect 0-1 */
+    padding10: [u8; 0x4],                            /* 0x90 padding */
+    gppud: ReadWrite<u32, GPPUD::Register>, /* 0x94 GPPUD GPIO Pin Pull-up/down Enable (BCM2837) */
+    gppudclk: [ReadWrite<u32, GPPUDCLK::Register>; 2], /* 0x98 GPPUDCLK0-1 GPIO Pin Pull-up/down Enable Clock 0-1 (BCM2837) */
+    padding11: [u8; 0x44],                             /* 0xa0 padding */
+    gpio_pup_pdn_cntrl: [ReadWrite<u32, GPIO_PUP_PDN_CNTRL::Register>; 4], /* 0xe4 GPIO_PUP_PDN_CNTRL_REG0-3 GPIO Pull-up / Pull-down Register 0-3 */
+}
+
+register_layout! {
+    GPIORegisters @ 0x00 .. 0xf4 {
+        0x00 => gpfsel,
+        0x18 => padding0,
+        0x1c => gpset,
+        0x24 => padding1,
+        0x28 => gpclr,
+        0x30 => padding2,
+        0x34 => gplev,
+        0x3c => padding3,
+        0x40 => gpeds,
+        0x48 => padding4,
+        0x4c => gpren,
+        0x54 => padding5,
+        0x58 => gpfen,
+        0x60 => padding6,
+        0x64 => gphen,
+        0x6c => padding7,
+        0x70 => gplen,
+        0x78 => padding8,
+        0x7c => gparen,
+        0x84 => padding9,
+        0x88 => gpafen,
+        0x90 => padding10,
+        0x94 => gppud,
+        0x98 => gppudclk,
+        0xa0 => padding11,
+        0xe4 => gpio_pup_pdn_cntrl,
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -145,6 +208,66 @@ pub enum GPIOFunction {
     ALT5 = 2,
 }
 
+impl FieldEnum<u32> for GPIOFunction {
+    fn from_field(value: u32) -> Option<Self> {
+        GPIOFunction::try_from(value).ok()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GPIOPull {
+    None = 0,
+    Up = 1,
+    Down = 2,
+}
+
+impl GPIOPin {
+    const PINS_PER_FSEL: u32 = 10;
+    const PINS_PER_BANK: u32 = 32;
+    const PINS_PER_PULL: u32 = 16;
+
+    /// GPFSEL register index and field of this pin
+    fn function_field(self) -> (usize, Field<u32, GPFSEL::Register>) {
+        const BITS_PER_PIN: usize = 3;
+        let pin = self as u32;
+        (
+            (pin / Self::PINS_PER_FSEL) as usize,
+            GPFSEL::FSEL0.offset((pin % Self::PINS_PER_FSEL) as usize * BITS_PER_PIN),
+        )
+    }
+
+    /// GPIO_PUP_PDN_CNTRL register index and field of this pin
+    fn pull_field(self) -> (usize, Field<u32, GPIO_PUP_PDN_CNTRL::Register>) {
+        const BITS_PER_PIN: usize = 2;
+        let pin = self as u32;
+        (
+            (pin / Self::PINS_PER_PULL) as usize,
+            GPIO_PUP_PDN_CNTRL::PULL0.offset((pin % Self::PINS_PER_PULL) as usize * BITS_PER_PIN),
+        )
+    }
+
+    /// bank index and bit of this pin for registers holding one bit per pin
+    fn bank(self) -> (usize, usize) {
+        let pin = self as u32;
+        (
+            (pin / Self::PINS_PER_BANK) as usize,
+            (pin % Self::PINS_PER_BANK) as usize,
+        )
+    }
+}
+
+impl TryFrom<u32> for GPIOPin {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            // the pins are numbered without gaps
+            0..=53 => Ok(unsafe { core::mem::transmute::<u8, GPIOPin>(value as u8) }),
+            _ => Err(value),
+        }
+    }
+}
+
 impl TryFrom<u32> for GPIOFunction {
     type Error = u32;
 
@@ -164,93 +287,203 @@ impl TryFrom<u32> for GPIOFunction {
 }
 
 impl GPIORegisters {
-    const BASE: usize = 0xfe200000;
+    const BASE: usize = board::peripheral(0x20_0000);
 
     pub const fn new() -> *mut GPIORegisters {
-        Self::BASE as *mut GPIORegisters
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut GPIORegisters {
+        base as *mut GPIORegisters
     }
 
     pub fn pin_function_set(&mut self, pin: GPIOPin, function: GPIOFunction) {
-        let pin_ = pin as u32;
-
-        let reg = match pin_ {
-            0..=9 => &mut self.gpfsel0,
-            10..=19 => &mut self.gpfsel1,
-            20..=29 => &mut self.gpfsel2,
-            30..=39 => &mut self.gpfsel3,
-            40..=49 => &mut self.gpfsel4,
-            50..=53 => &mut self.gpfsel5,
-            _ => unreachable!(),
-        };
-
-        const BITS_PER_PIN: u32 = 3;
-        const BITS_PER_REGISTER: u32 = 30;
-        let shift = (pin_ * BITS_PER_PIN) % BITS_PER_REGISTER;
-        register_volatile_and(reg, !(0x7u32 << shift));
-        register_volatile_or(reg, (function as u32) << shift);
+        let (reg, field) = pin.function_field();
+        self.gpfsel[reg].modify(field.val(function as u32));
     }
 
     pub fn pin_function_get(&self, pin: GPIOPin) -> GPIOFunction {
-        let pin_ = pin as u32;
-
-        let reg = match pin_ {
-            0..=9 => &self.gpfsel0,
-            10..=19 => &self.gpfsel1,
-            20..=29 => &self.gpfsel2,
-            30..=39 => &self.gpfsel3,
-            40..=49 => &self.gpfsel4,
-            50..=53 => &self.gpfsel5,
-            _ => unreachable!(),
-        };
-
-        const BITS_PER_PIN: u32 = 3;
-        const BITS_PER_REGISTER: u32 = 30;
-        let pin_start_bit = (pin_ * BITS_PER_PIN) % BITS_PER_REGISTER;
-        let mask = ((pin_start_bit << BITS_PER_PIN) - 1) & !(pin_start_bit - 1);
-        let value = unsafe { read_volatile(reg) };
-        ((value & mask) >> pin_start_bit).try_into().unwrap()
+        let (reg, field) = pin.function_field();
+        // every 3 bit value is a valid function
+        self.gpfsel[reg].read_as_enum(field).unwrap()
     }
 
     pub fn pin_set(&mut self, pin: GPIOPin) {
-        let pin_ = pin as u32;
-
-        let reg = match pin_ {
-            0..=31 => &mut self.gpset0,
-            32..=53 => &mut self.gpset1,
-            _ => unreachable!(),
-        };
-
-        const BITS_PER_REGISTER: u32 = 32;
-        register_volatile_or(reg, 1u32 << (pin_ % BITS_PER_REGISTER));
+        let (bank, bit) = pin.bank();
+        self.gpset[bank].write(GPSET::SET0.offset(bit).val(1));
     }
 
     pub fn pin_clear(&mut self, pin: GPIOPin) {
-        let pin_ = pin as u32;
+        let (bank, bit) = pin.bank();
+        self.gpclr[bank].write(GPCLR::CLR0.offset(bit).val(1));
+    }
 
-        let reg = match pin_ {
-            0..=31 => &mut self.gpclr0,
-            32..=53 => &mut self.gpclr1,
-            _ => unreachable!(),
-        };
+    pub fn pin_pull_set(&mut self, pin: GPIOPin, pull: GPIOPull) {
+        self.pull_set(board::PULL_STYLE, pin, pull);
+    }
 
-        const BITS_PER_REGISTER: u32 = 32;
-        register_volatile_or(reg, 1u32 << (pin_ % BITS_PER_REGISTER));
+    /// `None` where the pull cannot be read back, see [`PullStyle::Clocked`]
+    pub fn pin_pull_get(&self, pin: GPIOPin) -> Option<GPIOPull> {
+        self.pull_get(board::PULL_STYLE, pin)
     }
 
-    pub fn pin_level(&self, pin: GPIOPin) -> GPIOPinLevel {
-        let pin_ = pin as u32;
+    fn pull_set(&mut self, style: PullStyle, pin: GPIOPin, pull: GPIOPull) {
+        match style {
+            PullStyle::Direct => {
+                let (reg, field) = pin.pull_field();
+                self.gpio_pup_pdn_cntrl[reg].modify(field.val(pull as u32));
+            }
+            PullStyle::Clocked => {
+                // the control signal needs 150 cycles of setup and hold time
+                const SETUP_CYCLES: usize = 150;
+                let (bank, bit) = pin.bank();
+                self.gppud.write(match pull {
+                    GPIOPull::None => GPPUD::PUD::None,
+                    GPIOPull::Up => GPPUD::PUD::Up,
+                    GPIOPull::Down => GPPUD::PUD::Down,
+                });
+                (0..SETUP_CYCLES).for_each(|_| core::hint::spin_loop());
+                self.gppudclk[bank].write(GPPUDCLK::CLK0.offset(bit).val(1));
+                (0..SETUP_CYCLES).for_each(|_| core::hint::spin_loop());
+                self.gppud.write(GPPUD::PUD::None);
+                self.gppudclk[bank].set(0);
+            }
+        }
+    }
 
-        let reg = match pin_ {
-            0..=31 => &self.gplev0,
-            32..=53 => &self.gplev1,
-            _ => unreachable!(),
-        };
+    fn pull_get(&self, style: PullStyle, pin: GPIOPin) -> Option<GPIOPull> {
+        if style == PullStyle::Clocked {
+            return None;
+        }
+        let (reg, field) = pin.pull_field();
+        match self.gpio_pup_pdn_cntrl[reg].read(field) {
+            1 => Some(GPIOPull::Up),
+            2 => Some(GPIOPull::Down),
+            _ => Some(GPIOPull::None),
+        }
+    }
 
-        const BITS_PER_REGISTER: u32 = 32;
-        let value = unsafe { read_volatile(reg) } & (pin_ % BITS_PER_REGISTER);
-        match value > 0 {
+    pub fn pin_level(&self, pin: GPIOPin) -> GPIOPinLevel {
+        let (bank, bit) = pin.bank();
+        match self.gplev[bank].is_set(GPLEV::LEV0.offset(bit)) {
             true => GPIOPinLevel::High,
             false => GPIOPinLevel::Low,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const GPFSEL1: usize = 0x04;
+    const GPSET0: usize = 0x1c;
+    const GPCLR0: usize = 0x28;
+    const GPLEV0: usize = 0x34;
+
+    /// set/clear registers drive the level registers like pins configured as outputs
+    fn gpio() -> RegisterFile {
+        let file = RegisterFile::new(core::mem::size_of::<GPIORegisters>());
+        for bank in [0, 4] {
+            file.on(
+                GPSET0 + bank,
+                Behavior::SetBits {
+                    target: GPLEV0 + bank,
+                },
+            )
+            .on(
+                GPCLR0 + bank,
+                Behavior::ClearBits {
+                    target: GPLEV0 + bank,
+                },
+            )
+            .on(GPLEV0 + bank, Behavior::ReadOnly);
+        }
+        file
+    }
+
+    #[test]
+    fn function_select() {
+        let file = gpio();
+        let gpio = unsafe { &mut *file.block::<GPIORegisters>(0) };
+
+        gpio.pin_function_set(GPIOPin::PIN14, GPIOFunction::ALT5);
+        gpio.pin_function_set(GPIOPin::PIN15, GPIOFunction::ALT5);
+        gpio.pin_function_set(GPIOPin::PIN19, GPIOFunction::ALT3);
+        assert_eq!(file.peek(GPFSEL1), (2 << 12) | (2 << 15) | (7 << 27));
+
+        gpio.pin_function_set(GPIOPin::PIN15, GPIOFunction::OUTPUT);
+        assert_eq!(file.peek(GPFSEL1), (2 << 12) | (1 << 15) | (7 << 27));
+        assert!(gpio.pin_function_get(GPIOPin::PIN14) == GPIOFunction::ALT5);
+        assert!(gpio.pin_function_get(GPIOPin::PIN15) == GPIOFunction::OUTPUT);
+        assert!(gpio.pin_function_get(GPIOPin::PIN19) == GPIOFunction::ALT3);
+        assert!(gpio.pin_function_get(GPIOPin::PIN53) == GPIOFunction::INPUT);
+    }
+
+    #[test]
+    fn output_level() {
+        let file = gpio();
+        let gpio = unsafe { &mut *file.block::<GPIORegisters>(0) };
+
+        gpio.pin_set(GPIOPin::PIN3);
+        gpio.pin_set(GPIOPin::PIN40);
+        assert_eq!(file.peek(GPLEV0), 1 << 3);
+        assert_eq!(file.peek(GPLEV0 + 4), 1 << 8);
+        assert!(gpio.pin_level(GPIOPin::PIN3) == GPIOPinLevel::High);
+        assert!(gpio.pin_level(GPIOPin::PIN2) == GPIOPinLevel::Low);
+        assert!(gpio.pin_level(GPIOPin::PIN40) == GPIOPinLevel::High);
+
+        gpio.pin_clear(GPIOPin::PIN3);
+        assert!(gpio.pin_level(GPIOPin::PIN3) == GPIOPinLevel::Low);
+        assert!(gpio.pin_level(GPIOPin::PIN40) == GPIOPinLevel::High);
+    }
+
+    #[test]
+    fn pull() {
+        const GPIO_PUP_PDN_CNTRL_REG0: usize = 0xe4;
+        let file = gpio();
+        let gpio = unsafe { &mut *file.block::<GPIORegisters>(0) };
+
+        gpio.pin_pull_set(GPIOPin::PIN2, GPIOPull::Up);
+        gpio.pin_pull_set(GPIOPin::PIN15, GPIOPull::Down);
+        gpio.pin_pull_set(GPIOPin::PIN17, GPIOPull::Down);
+        assert_eq!(file.peek(GPIO_PUP_PDN_CNTRL_REG0), (1 << 4) | (2 << 30));
+        assert_eq!(file.peek(GPIO_PUP_PDN_CNTRL_REG0 + 4), 2 << 2);
+        assert_eq!(gpio.pin_pull_get(GPIOPin::PIN17), Some(GPIOPull::Down));
+        gpio.pin_pull_set(GPIOPin::PIN2, GPIOPull::None);
+        assert_eq!(gpio.pin_pull_get(GPIOPin::PIN2), Some(GPIOPull::None));
+
+        assert!(GPIOPin::try_from(53) == Ok(GPIOPin::PIN53));
+        assert!(GPIOPin::try_from(54).is_err());
+    }
+
+    #[test]
+    fn clocked_pull() {
+        const GPPUD: usize = 0x94;
+        const GPPUDCLK1: usize = 0x9c;
+        let file = gpio();
+        // record the pull and the pins clocked together in GPFSEL0, unused here
+        file.on(
+            GPPUDCLK1,
+            Behavior::Custom(Box::new(|storage, access| {
+                if let Access::Write(pins) = access
+                    && pins != 0
+                {
+                    let pud = storage.get(GPPUD);
+                    storage.set(0, pud << 16 | pins);
+                }
+                0
+            })),
+        );
+        let gpio = unsafe { &mut *file.block::<GPIORegisters>(0) };
+
+        gpio.pull_set(PullStyle::Clocked, GPIOPin::PIN40, GPIOPull::Up);
+        assert_eq!(file.peek(0), 2 << 16 | 1 << 8);
+        gpio.pull_set(PullStyle::Clocked, GPIOPin::PIN33, GPIOPull::Down);
+        assert_eq!(file.peek(0), 1 << 16 | 1 << 1);
+        assert_eq!(file.peek(GPPUD), 0);
+        assert_eq!(gpio.pull_get(PullStyle::Clocked, GPIOPin::PIN33), None);
+    }
+}
@@ -0,0 +1,687 @@
+//! BCM2711 Broadcom Serial Controller (BSC) I2C masters
+use crate::board::{self, CORE_CLOCK};
+use crate::registers::*;
+use crate::timer::Timeout;
+
+const FIFO_DEPTH: usize = 16;
+
+register_bitfields! {u32,
+    pub BSC_C [
+        /// read transfer
+        READ OFFSET(0) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Fifo = 1,
+        ],
+        /// start transfer
+        ST OFFSET(7) NUMBITS(1) [],
+        INTD OFFSET(8) NUMBITS(1) [],
+        INTT OFFSET(9) NUMBITS(1) [],
+        INTR OFFSET(10) NUMBITS(1) [],
+        I2CEN OFFSET(15) NUMBITS(1) [],
+    ],
+    pub BSC_S [
+        /// transfer active
+        TA OFFSET(0) NUMBITS(1) [],
+        /// write 1 to clear
+        DONE OFFSET(1) NUMBITS(1) [],
+        TXW OFFSET(2) NUMBITS(1) [],
+        RXR OFFSET(3) NUMBITS(1) [],
+        /// FIFO can accept data
+        TXD OFFSET(4) NUMBITS(1) [],
+        /// FIFO contains data
+        RXD OFFSET(5) NUMBITS(1) [],
+        TXE OFFSET(6) NUMBITS(1) [],
+        RXF OFFSET(7) NUMBITS(1) [],
+        /// slave did not acknowledge, write 1 to clear
+        ERR OFFSET(8) NUMBITS(1) [],
+        /// slave held SCL low too long, write 1 to clear
+        CLKT OFFSET(9) NUMBITS(1) [],
+    ],
+    pub BSC_DLEN [
+        DLEN OFFSET(0) NUMBITS(16) [],
+    ],
+    pub BSC_A [
+        ADDR OFFSET(0) NUMBITS(7) [],
+    ],
+    pub BSC_DIV [
+        /// even divider of the core clock, 0 is 32768
+        CDIV OFFSET(0) NUMBITS(16) [],
+    ],
+    pub BSC_DEL [
+        /// rising edge delay in core clocks
+        REDL OFFSET(0) NUMBITS(16) [],
+        /// falling edge delay in core clocks
+        FEDL OFFSET(16) NUMBITS(16) [],
+    ],
+    pub BSC_CLKT [
+        /// clock stretch timeout in SCL cycles, 0 disables it
+        TOUT OFFSET(0) NUMBITS(16) [],
+    ],
+}
+
+#[repr(C)]
+pub struct I2CRegisters {
+    c: ReadWrite<u32, BSC_C::Register>,       /* 0x00 C Control */
+    s: ReadWrite<u32, BSC_S::Register>,       /* 0x04 S Status */
+    dlen: ReadWrite<u32, BSC_DLEN::Register>, /* 0x08 DLEN Data Length */
+    a: ReadWrite<u32, BSC_A::Register>,       /* 0x0c A Slave Address */
+    fifo: ReadWrite<u32>,                     /* 0x10 FIFO Data FIFO */
+    div: ReadWrite<u32, BSC_DIV::Register>,   /* 0x14 DIV Clock Divider */
+    del: ReadWrite<u32, BSC_DEL::Register>,   /* 0x18 DEL Data Delay */
+    clkt: ReadWrite<u32, BSC_CLKT::Register>, /* 0x1c CLKT Clock Stretch Timeout */
+}
+
+register_layout! {
+    I2CRegisters @ 0x00 .. 0x20 {
+        0x00 => c,
+        0x04 => s,
+        0x08 => dlen,
+        0x0c => a,
+        0x10 => fifo,
+        0x14 => div,
+        0x18 => del,
+        0x1c => clkt,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum I2CAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl I2CAddress {
+    /// address written to the A register and the byte sent ahead of the data
+    fn split(self) -> (u8, Option<u8>) {
+        match self {
+            I2CAddress::SevenBit(address) => (address & 0x7f, None),
+            I2CAddress::TenBit(address) => {
+                (0x78 | ((address >> 8) & 0b11) as u8, Some(address as u8))
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum I2CError {
+    /// no acknowledge for the address or a data byte
+    Nack,
+    /// slave stretched the clock past the configured timeout
+    ClockStretchTimeout,
+    /// transfer did not finish in time
+    Timeout,
+    /// transfer longer than the controller supports
+    InvalidLength,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct I2CConfig {
+    pub speed_hz: u32,
+    pub clock_stretch_timeout_us: u32,
+}
+
+impl I2CConfig {
+    pub const fn new() -> I2CConfig {
+        I2CConfig {
+            speed_hz: 100_000,
+            clock_stretch_timeout_us: 35_000,
+        }
+    }
+}
+
+impl Default for I2CConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum I2CInstance {
+    I2C0,
+    I2C1,
+    /// HDMI0 DDC bus
+    I2C2,
+    I2C3,
+    I2C4,
+    I2C5,
+    I2C6,
+}
+
+impl I2CInstance {
+    const ALL: [I2CInstance; 7] = [
+        I2CInstance::I2C0,
+        I2CInstance::I2C1,
+        I2CInstance::I2C2,
+        I2CInstance::I2C3,
+        I2CInstance::I2C4,
+        I2CInstance::I2C5,
+        I2CInstance::I2C6,
+    ];
+
+    pub const fn base(self) -> usize {
+        match self {
+            I2CInstance::I2C0 => board::peripheral(0x20_5000),
+            I2CInstance::I2C1 => board::peripheral(0x80_4000),
+            I2CInstance::I2C2 => board::peripheral(0x80_5000),
+            I2CInstance::I2C3 => board::peripheral(0x20_5600),
+            I2CInstance::I2C4 => board::peripheral(0x20_5800),
+            I2CInstance::I2C5 => board::peripheral(0x20_5a00),
+            I2CInstance::I2C6 => board::peripheral(0x20_5c00),
+        }
+    }
+}
+
+/// set of 7-bit addresses
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct I2CAddresses(u128);
+
+impl I2CAddresses {
+    pub fn insert(&mut self, address: u8) {
+        self.0 |= 1 << (address & 0x7f);
+    }
+
+    pub fn contains(&self, address: u8) -> bool {
+        self.0 & (1 << (address & 0x7f)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..128u8).filter(|address| self.contains(*address))
+    }
+}
+
+impl I2CRegisters {
+    /// bytes a single transfer can move, DLEN is 16 bits wide
+    pub const MAX_LEN: usize = 0xffff;
+
+    pub const fn new(instance: I2CInstance) -> *mut I2CRegisters {
+        Self::at(instance.base())
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut I2CRegisters {
+        base as *mut I2CRegisters
+    }
+
+    /// enable the controller, returns the achieved bus clock in Hz
+    pub fn configure(&mut self, config: &I2CConfig) -> u32 {
+        let divider = CORE_CLOCK.div_ceil(config.speed_hz.max(1));
+        let divider = (divider + (divider & 1)).clamp(2, 0xfffe);
+        let speed = CORE_CLOCK / divider;
+
+        self.div.write(BSC_DIV::CDIV.val(divider));
+        // sample and drive data well away from the SCL edges
+        self.del.write(
+            BSC_DEL::FEDL.val((divider / 16).max(1)) + BSC_DEL::REDL.val((divider / 4).max(1)),
+        );
+        let timeout = config.clock_stretch_timeout_us as u64 * speed as u64 / 1_000_000;
+        self.clkt
+            .write(BSC_CLKT::TOUT.val(timeout.min(0xffff) as u32));
+        self.c.write(BSC_C::I2CEN::SET + BSC_C::CLEAR::Fifo);
+        self.s
+            .write(BSC_S::DONE::SET + BSC_S::ERR::SET + BSC_S::CLKT::SET);
+        speed
+    }
+
+    /// bus clock in Hz
+    pub fn speed(&self) -> u32 {
+        match self.div.read(BSC_DIV::CDIV) {
+            0 => CORE_CLOCK / 0x8000,
+            divider => CORE_CLOCK / divider,
+        }
+    }
+
+    pub fn write(&mut self, address: I2CAddress, data: &[u8]) -> Result<(), I2CError> {
+        self.write_read(address, data, &mut [])
+    }
+
+    pub fn read(&mut self, address: I2CAddress, data: &mut [u8]) -> Result<(), I2CError> {
+        self.write_read(address, &[], data)
+    }
+
+    /// Write `write`, then read into `read` after a repeated start without releasing the
+    /// bus. When both are given, `write` has to fit the FIFO (16 bytes, 15 for 10-bit
+    /// addresses) so the read can be queued while the write is still in progress.
+    pub fn write_read(
+        &mut self,
+        address: I2CAddress,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2CError> {
+        let (slave, prefix) = address.split();
+        let write_len = write.len() + prefix.is_some() as usize;
+        // 10-bit reads always start by writing the second address byte
+        let write_phase = write_len > 0 || read.is_empty();
+        if write_len > Self::MAX_LEN
+            || read.len() > Self::MAX_LEN
+            || (write_phase && !read.is_empty() && write_len > FIFO_DEPTH)
+        {
+            return Err(I2CError::InvalidLength);
+        }
+
+        let timeout = Timeout::after_us(self.transfer_time_us(write_len + read.len()));
+        self.c.write(BSC_C::I2CEN::SET + BSC_C::CLEAR::Fifo);
+        self.s
+            .write(BSC_S::DONE::SET + BSC_S::ERR::SET + BSC_S::CLKT::SET);
+        self.a.write(BSC_A::ADDR.val(slave as u32));
+
+        let result = match write_phase {
+            true => self.write_phase(write_len, prefix, write, read.is_empty(), &timeout),
+            false => Ok(()),
+        };
+        let result = result.and_then(|_| match read.is_empty() {
+            true => Ok(()),
+            false => self.read_phase(read, &timeout),
+        });
+
+        if result.is_err() {
+            self.c.write(BSC_C::I2CEN::SET + BSC_C::CLEAR::Fifo);
+        }
+        self.s
+            .write(BSC_S::DONE::SET + BSC_S::ERR::SET + BSC_S::CLKT::SET);
+        result
+    }
+
+    /// addresses in 0x08-0x77 acknowledging a single byte read
+    pub fn scan(&mut self) -> I2CAddresses {
+        let mut found = I2CAddresses::default();
+        for address in 0x08..0x78 {
+            if self.read(I2CAddress::SevenBit(address), &mut [0]).is_ok() {
+                found.insert(address);
+            }
+        }
+        found
+    }
+
+    fn write_phase(
+        &mut self,
+        len: usize,
+        prefix: Option<u8>,
+        data: &[u8],
+        last: bool,
+        timeout: &Timeout,
+    ) -> Result<(), I2CError> {
+        let mut bytes = prefix.into_iter().chain(data.iter().copied());
+        self.dlen.write(BSC_DLEN::DLEN.val(len as u32));
+        // prefill the FIFO so the transfer does not stall right after the address
+        self.fill(&mut bytes);
+        self.c.write(BSC_C::I2CEN::SET + BSC_C::ST::SET);
+
+        loop {
+            let status = self.status(timeout)?;
+            self.fill(&mut bytes);
+            // a following read only has to wait for the write to get going
+            if BSC_S::DONE.is_set(status) || (!last && BSC_S::TA.is_set(status)) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_phase(&mut self, data: &mut [u8], timeout: &Timeout) -> Result<(), I2CError> {
+        self.s.write(BSC_S::DONE::SET);
+        self.dlen.write(BSC_DLEN::DLEN.val(data.len() as u32));
+        self.c
+            .write(BSC_C::I2CEN::SET + BSC_C::ST::SET + BSC_C::READ::SET);
+
+        let mut received = 0;
+        loop {
+            let status = self.status(timeout)?;
+            while received < data.len() && self.s.is_set(BSC_S::RXD) {
+                data[received] = self.fifo.get() as u8;
+                received += 1;
+            }
+            // the write phase may still be running, so DONE alone does not end the read
+            if received == data.len() && BSC_S::DONE.is_set(status) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn fill(&mut self, bytes: &mut impl Iterator<Item = u8>) {
+        while self.s.is_set(BSC_S::TXD) {
+            match bytes.next() {
+                Some(byte) => self.fifo.set(byte as u32),
+                None => break,
+            }
+        }
+    }
+
+    /// status register, failing on bus errors and when `timeout` expired
+    fn status(&self, timeout: &Timeout) -> Result<u32, I2CError> {
+        let status = self.s.get();
+        if BSC_S::ERR.is_set(status) {
+            Err(I2CError::Nack)
+        } else if BSC_S::CLKT.is_set(status) {
+            Err(I2CError::ClockStretchTimeout)
+        } else if timeout.expired() {
+            Err(I2CError::Timeout)
+        } else {
+            Ok(status)
+        }
+    }
+
+    /// twice the time `bytes` take on the bus plus the longest clock stretch
+    fn transfer_time_us(&self, bytes: usize) -> u64 {
+        let speed = self.speed() as u64;
+        let bits = (bytes as u64 + 2) * 9;
+        let stretch = self.clkt.read(BSC_CLKT::TOUT) as u64 * 1_000_000 / speed;
+        bits * 2_000_000 / speed + stretch + 1_000
+    }
+}
+
+pub static mut I2C: I2C = I2C::new();
+pub struct I2C {
+    registers: [Option<*mut I2CRegisters>; 7],
+}
+
+impl I2C {
+    const fn new() -> I2C {
+        let mut registers = [None; 7];
+        let mut i = 0;
+        while i < registers.len() {
+            registers[i] = Some(I2CRegisters::new(I2CInstance::ALL[i]));
+            i += 1;
+        }
+        I2C { registers }
+    }
+
+    fn index(instance: I2CInstance) -> usize {
+        I2CInstance::ALL
+            .iter()
+            .position(|i| *i == instance)
+            .unwrap()
+    }
+
+    pub fn take_i2c(&mut self, instance: I2CInstance) -> *mut I2CRegisters {
+        let p = self.registers[Self::index(instance)].take();
+        p.unwrap()
+    }
+
+    pub fn return_i2c(&mut self, instance: I2CInstance, i2c: *mut I2CRegisters) {
+        self.registers[Self::index(instance)].replace(i2c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    const C: usize = 0x00;
+    const S: usize = 0x04;
+    const DLEN: usize = 0x08;
+    const A: usize = 0x0c;
+    const FIFO: usize = 0x10;
+    const DIV: usize = 0x14;
+    const DEL: usize = 0x18;
+    const CLKT: usize = 0x1c;
+
+    /// bus with one register based slave, the first written byte selects the register
+    struct Bus {
+        slave: I2CAddress,
+        memory: [u8; 256],
+        pointer: u8,
+        /// 10-bit slave matched its second address byte
+        selected: bool,
+        /// bytes left in the running write transfer
+        writing: Option<usize>,
+        first: bool,
+        tx: VecDeque<u8>,
+        rx: VecDeque<u8>,
+        status: u32,
+        /// SCL held low on every transfer
+        stretch: bool,
+    }
+
+    impl Bus {
+        fn start(&mut self, address: u8, len: usize, read: bool) {
+            self.status &= !0b10;
+            if self.stretch {
+                self.status |= 1 << 9 | 0b10;
+                return;
+            }
+            let acked = match self.slave {
+                I2CAddress::SevenBit(slave) => slave == address,
+                I2CAddress::TenBit(slave) => {
+                    0x78 | (slave >> 8) as u8 == address && (!read || self.selected)
+                }
+            };
+            if !acked {
+                self.status |= 1 << 8 | 0b10;
+                return;
+            }
+            if read {
+                for _ in 0..len {
+                    self.rx.push_back(self.memory[self.pointer as usize]);
+                    self.pointer = self.pointer.wrapping_add(1);
+                }
+                self.status |= 0b10;
+            } else {
+                self.selected = false;
+                self.first = true;
+                self.writing = Some(len);
+                self.step();
+            }
+        }
+
+        fn step(&mut self) {
+            while let Some(len) = self.writing {
+                if len == 0 {
+                    self.writing = None;
+                    self.status |= 0b10;
+                    break;
+                }
+                let Some(byte) = self.tx.pop_front() else {
+                    break;
+                };
+                match (self.slave, self.selected) {
+                    (I2CAddress::TenBit(slave), false) if byte != slave as u8 => {
+                        self.writing = None;
+                        self.status |= 1 << 8 | 0b10;
+                        return;
+                    }
+                    (I2CAddress::TenBit(_), false) => self.selected = true,
+                    _ if self.first => {
+                        self.pointer = byte;
+                        self.first = false;
+                    }
+                    _ => {
+                        self.memory[self.pointer as usize] = byte;
+                        self.pointer = self.pointer.wrapping_add(1);
+                    }
+                }
+                self.writing = Some(len - 1);
+            }
+        }
+    }
+
+    fn bus(slave: I2CAddress) -> (RegisterFile, Rc<RefCell<Bus>>) {
+        let file = RegisterFile::new(0x20);
+        let bus = Rc::new(RefCell::new(Bus {
+            slave,
+            memory: core::array::from_fn(|i| i as u8),
+            pointer: 0,
+            selected: false,
+            writing: None,
+            first: false,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            status: 0,
+            stretch: false,
+        }));
+
+        let b = bus.clone();
+        file.on(
+            C,
+            Behavior::Custom(Box::new(move |storage, access| {
+                if let Access::Write(value) = access {
+                    let mut bus = b.borrow_mut();
+                    if value & (1 << 4) != 0 {
+                        bus.tx.clear();
+                        bus.rx.clear();
+                    }
+                    if value & (1 << 7) != 0 {
+                        let (address, len) = (storage.get(A) as u8, storage.get(DLEN) as usize);
+                        bus.start(address, len, value & 1 != 0);
+                    }
+                    storage.set(C, value & !((1 << 7) | (0b11 << 4)));
+                }
+                storage.get(C)
+            })),
+        );
+        let b = bus.clone();
+        file.on(
+            S,
+            Behavior::Custom(Box::new(move |_, access| {
+                let mut bus = b.borrow_mut();
+                match access {
+                    Access::Write(value) => bus.status &= !(value & 0x302),
+                    Access::Read => {}
+                }
+                let txd = (bus.tx.len() < FIFO_DEPTH) as u32;
+                let rxd = !bus.rx.is_empty() as u32;
+                let ta = bus.writing.is_some() as u32;
+                bus.status | ta | (txd << 4) | (rxd << 5)
+            })),
+        );
+        let b = bus.clone();
+        file.on(
+            FIFO,
+            Behavior::Custom(Box::new(move |_, access| {
+                let mut bus = b.borrow_mut();
+                match access {
+                    Access::Write(value) => {
+                        bus.tx.push_back(value as u8);
+                        bus.step();
+                        0
+                    }
+                    Access::Read => bus.rx.pop_front().unwrap_or(0) as u32,
+                }
+            })),
+        );
+        (file, bus)
+    }
+
+    #[test]
+    fn configure() {
+        let (file, _) = bus(I2CAddress::SevenBit(0x50));
+        let i2c = unsafe { &mut *file.block::<I2CRegisters>(0) };
+
+        assert_eq!(i2c.configure(&I2CConfig::new()), 100_000);
+        assert_eq!(file.peek(DIV), 2500);
+        assert_eq!(file.peek(DEL), (156 << 16) | 625);
+        assert_eq!(file.peek(CLKT), 3500);
+        assert_eq!(file.peek(C), 1 << 15);
+        assert_eq!(i2c.speed(), 100_000);
+
+        let config = I2CConfig {
+            speed_hz: 400_000,
+            clock_stretch_timeout_us: 1_000_000,
+        };
+        assert_eq!(i2c.configure(&config), 399_361);
+        assert_eq!(file.peek(CLKT), 0xffff);
+    }
+
+    #[test]
+    fn write_read() {
+        let (file, bus) = bus(I2CAddress::SevenBit(0x50));
+        let i2c = unsafe { &mut *file.block::<I2CRegisters>(0) };
+        i2c.configure(&I2CConfig::new());
+        let slave = I2CAddress::SevenBit(0x50);
+
+        assert_eq!(i2c.write(slave, &[0x10, 0xaa, 0xbb]), Ok(()));
+        assert_eq!(bus.borrow().memory[0x10..0x12], [0xaa, 0xbb]);
+
+        let mut data = [0; 4];
+        assert_eq!(i2c.write_read(slave, &[0x0f], &mut data), Ok(()));
+        assert_eq!(data, [0x0f, 0xaa, 0xbb, 0x12]);
+
+        // continues after the last byte read
+        assert_eq!(i2c.read(slave, &mut data[..2]), Ok(()));
+        assert_eq!(data[..2], [0x13, 0x14]);
+
+        // longer writes are fed while the transfer runs
+        let long: Vec<u8> = (0..40).collect();
+        assert_eq!(i2c.write(slave, &long), Ok(()));
+        assert_eq!(bus.borrow().memory[..39], long[1..]);
+    }
+
+    #[test]
+    fn ten_bit_address() {
+        let (file, bus) = bus(I2CAddress::TenBit(0x2a5));
+        let i2c = unsafe { &mut *file.block::<I2CRegisters>(0) };
+        i2c.configure(&I2CConfig::new());
+        let slave = I2CAddress::TenBit(0x2a5);
+
+        assert_eq!(i2c.write(slave, &[0x20, 1, 2]), Ok(()));
+        assert_eq!(file.peek(A), 0x7a);
+        assert_eq!(bus.borrow().memory[0x20..0x22], [1, 2]);
+
+        bus.borrow_mut().pointer = 0x20;
+        let mut data = [0; 2];
+        assert_eq!(i2c.read(slave, &mut data), Ok(()));
+        assert_eq!(data, [1, 2]);
+
+        assert_eq!(
+            i2c.read(I2CAddress::TenBit(0x1a5), &mut data),
+            Err(I2CError::Nack)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let (file, bus) = bus(I2CAddress::SevenBit(0x50));
+        let i2c = unsafe { &mut *file.block::<I2CRegisters>(0) };
+        i2c.configure(&I2CConfig::new());
+
+        assert_eq!(
+            i2c.write(I2CAddress::SevenBit(0x51), &[0]),
+            Err(I2CError::Nack)
+        );
+        // status is cleared for the next transfer
+        assert_eq!(i2c.write(I2CAddress::SevenBit(0x50), &[0]), Ok(()));
+
+        bus.borrow_mut().stretch = true;
+        assert_eq!(
+            i2c.read(I2CAddress::SevenBit(0x50), &mut [0]),
+            Err(I2CError::ClockStretchTimeout)
+        );
+        bus.borrow_mut().stretch = false;
+
+        assert_eq!(
+            i2c.write_read(I2CAddress::SevenBit(0x50), &[0; 17], &mut [0]),
+            Err(I2CError::InvalidLength)
+        );
+
+        // a slave that never finishes the read
+        file.on(FIFO, Behavior::Memory);
+        file.on(S, Behavior::Memory);
+        file.poke(S, 0);
+        assert_eq!(
+            i2c.read(I2CAddress::SevenBit(0x50), &mut [0]),
+            Err(I2CError::Timeout)
+        );
+    }
+
+    #[test]
+    fn scan() {
+        let (file, _) = bus(I2CAddress::SevenBit(0x3c));
+        let i2c = unsafe { &mut *file.block::<I2CRegisters>(0) };
+        i2c.configure(&I2CConfig::new());
+
+        let found = i2c.scan();
+        assert_eq!(found.len(), 1);
+        assert!(found.contains(0x3c));
+        assert_eq!(found.iter().collect::<Vec<_>>(), [0x3c]);
+    }
+}
@@ -0,0 +1,82 @@
+//! On-target test framework.
+//!
+//! Tests are registered with `kernel_test!` into the `.kernel_tests` linker section and
+//! executed by [`run`] when the kernel is built with the `qemu-test` feature. Results go
+//! to the serial port, the outcome is reported as the emulator exit status through
+//! semihosting. A failing test panics, the panic handler is expected to call [`fail`].
+use crate::semihosting;
+use crate::serial::{Serial, SerialWriter};
+use core::fmt::Write;
+
+pub struct KernelTest {
+    pub name: &'static str,
+    pub test: fn(),
+}
+
+// bounds of the `.kernel_tests` section, see script.ld
+unsafe extern "C" {
+    static __kernel_tests_start: u8;
+    static __kernel_tests_end: u8;
+}
+
+/// Registers a test function, the `#[test_case]` of this kernel.
+///
+/// ```ignore
+/// kernel_test! {
+///     fn pin_function_roundtrip() {
+///         assert!(...);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! kernel_test {
+    ($(fn $name: ident() $body: block)*) => {
+        $(
+            fn $name() $body
+
+            const _: () = {
+                #[used]
+                #[unsafe(link_section = ".kernel_tests")]
+                static TEST: $crate::ktest::KernelTest = $crate::ktest::KernelTest {
+                    name: concat!(module_path!(), "::", stringify!($name)),
+                    test: $name,
+                };
+            };
+        )*
+    };
+}
+
+pub fn tests() -> &'static [KernelTest] {
+    unsafe {
+        let start = &raw const __kernel_tests_start as *const KernelTest;
+        let end = &raw const __kernel_tests_end as *const KernelTest;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// run the registered tests whose name contains `filter` and exit the emulator
+pub fn run<S: Serial + ?Sized>(serial: &mut S, filter: &str) -> ! {
+    let tests = || tests().iter().filter(|test| test.name.contains(filter));
+    let (count, filtered) = (tests().count(), self::tests().len() - tests().count());
+    let mut out = SerialWriter(serial);
+    let _ = writeln!(out, "\nrunning {count} tests");
+    for test in tests() {
+        let _ = write!(out, "test {} ... ", test.name);
+        (test.test)();
+        let _ = writeln!(out, "ok");
+    }
+    let _ = writeln!(
+        out,
+        "\ntest result: ok. {count} passed; {filtered} filtered out"
+    );
+    out.0.flush();
+    semihosting::exit(0)
+}
+
+/// report the running test as failed and exit the emulator
+pub fn fail<S: Serial + ?Sized>(serial: &mut S, info: &core::panic::PanicInfo) -> ! {
+    let mut out = SerialWriter(serial);
+    let _ = writeln!(out, "FAILED\n\n{info}\n\ntest result: FAILED");
+    out.0.flush();
+    semihosting::exit(1)
+}
@@ -0,0 +1,40 @@
+//! Tests executed on the emulated board, see `raspi4b::ktest`.
+use raspi4b::aux::AUX_PERIPHERALS;
+use raspi4b::gpio::*;
+use raspi4b::kernel_test;
+
+kernel_test! {
+    fn gpio_function_select() {
+        let gpio_ = &raw mut GPIO;
+        let gpio = unsafe { &mut *(*gpio_).take_gpio() };
+
+        let previous = gpio.pin_function_get(GPIOPin::PIN21);
+        gpio.pin_function_set(GPIOPin::PIN21, GPIOFunction::ALT4);
+        assert!(gpio.pin_function_get(GPIOPin::PIN21) == GPIOFunction::ALT4);
+        assert!(gpio.pin_function_get(GPIOPin::PIN14) == GPIOFunction::ALT5);
+        gpio.pin_function_set(GPIOPin::PIN21, previous);
+
+        unsafe { (*gpio_).return_gpio(gpio) };
+    }
+
+    fn gpio_output_level() {
+        let gpio_ = &raw mut GPIO;
+        let gpio = unsafe { &mut *(*gpio_).take_gpio() };
+
+        gpio.pin_function_set(GPIOPin::PIN21, GPIOFunction::OUTPUT);
+        gpio.pin_set(GPIOPin::PIN21);
+        assert!(gpio.pin_level(GPIOPin::PIN21) == GPIOPinLevel::High);
+        gpio.pin_clear(GPIOPin::PIN21);
+        assert!(gpio.pin_level(GPIOPin::PIN21) == GPIOPinLevel::Low);
+        gpio.pin_function_set(GPIOPin::PIN21, GPIOFunction::INPUT);
+
+        unsafe { (*gpio_).return_gpio(gpio) };
+    }
+
+    fn aux_mini_uart_enabled() {
+        let aux = &raw mut AUX_PERIPHERALS;
+        let registers = unsafe { &mut *(*aux).take_aux_registers() };
+        assert!(!registers.irq_pending_spi());
+        unsafe { (*aux).return_aux_registers(registers) };
+    }
+}
@@ -0,0 +1,42 @@
+#![cfg_attr(not(test), no_std)]
+#![allow(dead_code)]
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod aux;
+pub mod block;
+pub mod board;
+pub mod chainload;
+pub mod clock;
+pub mod cmdline;
+pub mod dma;
+pub mod emmc;
+pub mod exception;
+pub mod fat;
+pub mod fdt;
+pub mod gdb;
+pub mod gic;
+pub mod gpio;
+pub mod i2c;
+#[cfg(feature = "qemu-test")]
+pub mod ktest;
+pub mod mailbox;
+#[cfg(test)]
+pub mod mock;
+pub mod partition;
+pub mod pl011;
+pub mod platform;
+pub mod pm;
+pub mod pwm;
+pub mod registers;
+pub mod rng;
+#[cfg(target_arch = "aarch64")]
+pub mod semihosting;
+pub mod serial;
+pub mod shell;
+pub mod spi;
+pub mod thermal;
+pub mod timer;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod utils;
+pub mod xmodem;
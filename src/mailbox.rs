@@ -0,0 +1,313 @@
+//! VideoCore mailbox, the property interface of the firmware.
+//!
+//! A request is a [`PropertyBuffer`] handed to the firmware by bus address on the property
+//! channel, the firmware overwrites it with the response. The MMU is off, so the buffer is
+//! not cached and needs no maintenance. The VideoCore only reaches the first 1GB of RAM,
+//! requests are copied to a static buffer there since the stack may be above.
+use crate::dma::bus_address;
+use crate::registers::*;
+use crate::timer::Timeout;
+use crate::utils::bariers::*;
+
+/// channel of the ARM to VideoCore property tags
+pub const CHANNEL_PROPERTY: u32 = 8;
+
+pub const TAG_GET_CLOCK_RATE: u32 = 0x0003_0002;
+pub const TAG_GET_VOLTAGE: u32 = 0x0003_0003;
+pub const TAG_GET_MAX_VOLTAGE: u32 = 0x0003_0005;
+pub const TAG_GET_TEMPERATURE: u32 = 0x0003_0006;
+pub const TAG_GET_MAX_TEMPERATURE: u32 = 0x0003_000a;
+pub const TAG_GET_THROTTLED: u32 = 0x0003_0046;
+pub const TAG_SET_CLOCK_RATE: u32 = 0x0003_8002;
+
+/// clock IDs of the clock rate tags
+pub const CLOCK_CORE: u32 = 4;
+
+const BUFFER_WORDS: usize = 32;
+/// size, code, tag, value size, tag code and the end tag
+const HEADER_WORDS: usize = 6;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const TAG_RESPONSE: u32 = 1 << 31;
+const CALL_TIMEOUT_US: u64 = 100_000;
+
+register_bitfields! {u32,
+    pub MBOX_STATUS [
+        EMPTY OFFSET(30) NUMBITS(1) [],
+        FULL OFFSET(31) NUMBITS(1) [],
+    ],
+    /// channel in the low bits, a 16 byte aligned bus address above
+    pub MBOX_MESSAGE [
+        CHANNEL OFFSET(0) NUMBITS(4) [],
+        DATA OFFSET(4) NUMBITS(28) [],
+    ],
+}
+
+/// mailbox 0 is read by the ARM, mailbox 1 written
+#[repr(C)]
+pub struct MailboxRegisters {
+    read: ReadOnly<u32, MBOX_MESSAGE::Register>, /* 0x00 MBOX0_READ Mailbox 0 Read */
+    padding0: [u8; 0xc],                         /* 0x04 padding */
+    peek: ReadOnly<u32>,                         /* 0x10 MBOX0_PEEK Mailbox 0 Peek */
+    sender: ReadOnly<u32>,                       /* 0x14 MBOX0_SENDER Mailbox 0 Sender */
+    status: ReadOnly<u32, MBOX_STATUS::Register>, /* 0x18 MBOX0_STATUS Mailbox 0 Status */
+    config: ReadWrite<u32>,                      /* 0x1c MBOX0_CONFIG Mailbox 0 Config */
+    write: WriteOnly<u32, MBOX_MESSAGE::Register>, /* 0x20 MBOX1_WRITE Mailbox 1 Write */
+    padding1: [u8; 0x14],                        /* 0x24 padding */
+    write_status: ReadOnly<u32, MBOX_STATUS::Register>, /* 0x38 MBOX1_STATUS Mailbox 1 Status */
+}
+
+register_layout! {
+    MailboxRegisters @ 0x00 .. 0x3c {
+        0x00 => read,
+        0x04 => padding0,
+        0x10 => peek,
+        0x14 => sender,
+        0x18 => status,
+        0x1c => config,
+        0x20 => write,
+        0x24 => padding1,
+        0x38 => write_status,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MailboxError {
+    /// no response from the firmware
+    Timeout,
+    /// the firmware could not parse the request
+    Failed,
+    /// the firmware does not know the tag
+    UnknownTag,
+    /// request or response larger than a [`PropertyBuffer`]
+    TooLarge,
+    /// buffer outside the RAM the VideoCore reaches
+    Unreachable,
+}
+
+/// property request with a single tag
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+pub struct PropertyBuffer {
+    pub words: [u32; BUFFER_WORDS],
+}
+
+impl PropertyBuffer {
+    /// `tag` with `request` as its values and room for `value_words` in the response
+    pub fn new(tag: u32, request: &[u32], value_words: usize) -> Result<Self, MailboxError> {
+        let values = request.len().max(value_words);
+        if HEADER_WORDS + values > BUFFER_WORDS {
+            return Err(MailboxError::TooLarge);
+        }
+        let mut words = [0; BUFFER_WORDS];
+        words[0] = ((HEADER_WORDS + values) * 4) as u32;
+        words[2] = tag;
+        words[3] = (values * 4) as u32;
+        words[5..5 + request.len()].copy_from_slice(request);
+        Ok(PropertyBuffer { words })
+    }
+
+    /// values of the tag the firmware answered with
+    pub fn response(&self) -> Result<&[u32], MailboxError> {
+        if self.words[1] != RESPONSE_SUCCESS {
+            return Err(MailboxError::Failed);
+        }
+        let status = self.words[4];
+        if status & TAG_RESPONSE == 0 {
+            return Err(MailboxError::UnknownTag);
+        }
+        let values = self.words[3] as usize / 4;
+        let len = ((status & !TAG_RESPONSE) as usize).div_ceil(4).min(values);
+        Ok(&self.words[5..5 + len])
+    }
+}
+
+/// something answering property requests
+pub trait Firmware {
+    /// hand `buffer` to the firmware and wait for it to be answered
+    fn call(&mut self, buffer: &mut PropertyBuffer) -> Result<(), MailboxError>;
+
+    /// Request `tag` with `request` as values, the response values are copied to `response`.
+    /// Returns the number of response words.
+    fn property(
+        &mut self,
+        tag: u32,
+        request: &[u32],
+        response: &mut [u32],
+    ) -> Result<usize, MailboxError> {
+        let mut buffer = PropertyBuffer::new(tag, request, response.len())?;
+        self.call(&mut buffer)?;
+        let values = buffer.response()?;
+        let len = values.len().min(response.len());
+        response[..len].copy_from_slice(&values[..len]);
+        Ok(len)
+    }
+}
+
+impl MailboxRegisters {
+    const BASE: usize = crate::board::peripheral(0xb880);
+
+    pub const fn new() -> *mut Self {
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut Self {
+        base as *mut Self
+    }
+}
+
+/// statics are linked with the kernel at the start of RAM, in reach of the VideoCore
+#[cfg(not(test))]
+static mut SHARED: PropertyBuffer = PropertyBuffer {
+    words: [0; BUFFER_WORDS],
+};
+
+fn with_shared<R>(f: impl FnOnce(&mut PropertyBuffer) -> R) -> R {
+    // tests run in parallel threads, each gets its own buffer below 1GB
+    #[cfg(test)]
+    {
+        std::thread_local! {
+            static SHARED: core::cell::RefCell<&'static mut PropertyBuffer> =
+                core::cell::RefCell::new(&mut crate::mock::low_memory(PropertyBuffer {
+                    words: [0; BUFFER_WORDS],
+                }, 1)[0]);
+        }
+        SHARED.with_borrow_mut(|shared| f(shared))
+    }
+    #[cfg(not(test))]
+    {
+        let shared = &raw mut SHARED;
+        f(unsafe { &mut *shared })
+    }
+}
+
+impl Firmware for MailboxRegisters {
+    /// `buffer` goes through the static buffer, wherever it is
+    fn call(&mut self, buffer: &mut PropertyBuffer) -> Result<(), MailboxError> {
+        with_shared(|shared| {
+            *shared = *buffer;
+            let result = self.send(shared);
+            *buffer = *shared;
+            result
+        })
+    }
+}
+
+impl MailboxRegisters {
+    /// hand `buffer` itself to the firmware
+    fn send(&mut self, buffer: &mut PropertyBuffer) -> Result<(), MailboxError> {
+        let address = bus_address(buffer).ok_or(MailboxError::Unreachable)?;
+        let message = address | CHANNEL_PROPERTY;
+        let timeout = Timeout::after_us(CALL_TIMEOUT_US);
+        while self.write_status.is_set(MBOX_STATUS::FULL) {
+            if timeout.expired() {
+                return Err(MailboxError::Timeout);
+            }
+        }
+        memory_write_barier();
+        self.write.set(message);
+        // responses for other channels are dropped
+        loop {
+            while self.status.is_set(MBOX_STATUS::EMPTY) {
+                if timeout.expired() {
+                    return Err(MailboxError::Timeout);
+                }
+            }
+            if self.read.get() == message {
+                break;
+            }
+        }
+        memory_read_barier();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const READ: usize = 0x00;
+    const STATUS: usize = 0x18;
+    const WRITE: usize = 0x20;
+
+    /// firmware answering `tag` with `values`
+    pub(crate) struct FakeFirmware {
+        pub answers: Vec<(u32, Vec<u32>)>,
+    }
+
+    impl Firmware for FakeFirmware {
+        fn call(&mut self, buffer: &mut PropertyBuffer) -> Result<(), MailboxError> {
+            let tag = buffer.words[2];
+            buffer.words[1] = RESPONSE_SUCCESS;
+            // unknown tags are left unanswered
+            if let Some((_, values)) = self.answers.iter().find(|(answer, _)| *answer == tag) {
+                buffer.words[4] = TAG_RESPONSE | (values.len() * 4) as u32;
+                buffer.words[5..5 + values.len()].copy_from_slice(values);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffer() {
+        let buffer = PropertyBuffer::new(TAG_GET_VOLTAGE, &[1], 2).unwrap();
+        assert_eq!(buffer.words[..8], [32, 0, 0x30003, 8, 0, 1, 0, 0]);
+        assert_eq!(buffer.response(), Err(MailboxError::Failed));
+        assert_eq!(
+            PropertyBuffer::new(TAG_GET_VOLTAGE, &[], 27).err(),
+            Some(MailboxError::TooLarge)
+        );
+        assert_eq!(core::mem::align_of::<PropertyBuffer>(), 16);
+    }
+
+    #[test]
+    fn call() {
+        // the firmware answers a message in the read FIFO
+        let file = RegisterFile::new(core::mem::size_of::<MailboxRegisters>());
+        file.on(READ, Behavior::Fifo).on(
+            STATUS,
+            Behavior::Custom(Box::new(|storage, access| match access {
+                Access::Read if storage.rx(READ).is_empty() => 1 << 30,
+                _ => 0,
+            })),
+        );
+        file.on(
+            WRITE,
+            Behavior::Custom(Box::new(|storage, access| {
+                if let Access::Write(message) = access {
+                    storage.tx(WRITE).push(message);
+                    storage.rx(READ).push_back(message - 8 + 1);
+                    storage.rx(READ).push_back(message);
+                }
+                0
+            })),
+        );
+        let mailbox = unsafe { &mut *file.block::<MailboxRegisters>(0) };
+
+        // the answer for another channel is skipped, nobody filled in the buffer
+        let mut response = [0; 2];
+        assert_eq!(
+            mailbox.property(TAG_GET_TEMPERATURE, &[0], &mut response),
+            Err(MailboxError::Failed)
+        );
+        let shared = with_shared(|shared| bus_address(shared).unwrap());
+        assert_eq!(file.take_tx(WRITE), [shared | CHANNEL_PROPERTY]);
+        // the stack of the host is out of reach like the one of a Pi 4
+        let mut far = PropertyBuffer::new(TAG_GET_TEMPERATURE, &[0], 2).unwrap();
+        assert_eq!(mailbox.send(&mut far), Err(MailboxError::Unreachable));
+
+        let mut firmware = FakeFirmware {
+            answers: vec![(TAG_GET_TEMPERATURE, vec![0, 48_000])],
+        };
+        assert_eq!(
+            firmware.property(TAG_GET_TEMPERATURE, &[0], &mut response),
+            Ok(2)
+        );
+        assert_eq!(response, [0, 48_000]);
+        assert_eq!(
+            firmware.property(TAG_GET_THROTTLED, &[], &mut response),
+            Err(MailboxError::UnknownTag)
+        );
+    }
+}
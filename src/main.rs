@@ -2,30 +2,83 @@
 #![no_main]
 #![allow(dead_code)]
 
-use core::arch::{asm, global_asm};
+#[cfg(not(feature = "qemu-test"))]
+use core::arch::asm;
+use core::arch::global_asm;
+use core::fmt::Write;
 use core::panic::PanicInfo;
 
-mod aux;
-mod gpio;
-mod utils;
-use crate::aux::AUX_PERIPHERALS;
-use crate::aux::peripherals::*;
+use raspi4b::aux::AUX_PERIPHERALS;
+use raspi4b::aux::peripherals::*;
 
-use crate::gpio::*;
-use crate::utils::bariers::*;
+use raspi4b::cmdline::{self, COMMAND_LINE, command_line};
+use raspi4b::fdt::{self, FdtError};
+use raspi4b::gpio::*;
+use raspi4b::mailbox::{CLOCK_CORE, Firmware, MailboxRegisters, TAG_SET_CLOCK_RATE};
+use raspi4b::pl011::{PL011, UARTInstance};
+use raspi4b::platform::{self, Feature};
+use raspi4b::serial::{Serial, SerialWriter};
+use raspi4b::utils::bariers::*;
 
+#[cfg(feature = "qemu-test")]
+mod ktests;
+
+#[cfg(feature = "qemu-test")]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // whoever panicked may hold the uart, it is not coming back
+    let uart = unsafe { &mut *MiniUart::new() };
+    raspi4b::ktest::fail(uart, info)
+}
+
+#[cfg(not(feature = "qemu-test"))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    let _ = info;
+    // whoever panicked may hold the uart, it is not coming back
+    let uart = unsafe { &mut *MiniUart::new() };
+    let mut out = SerialWriter(uart);
+    let _ = write!(out, "\r\npanic: {info}\r\n");
+    out.0.flush();
     unsafe {
         asm!("ldr x0, =0xdeadbeef", options(nostack));
     }
-    loop {}
+    // with a debugger attached the panic traps into the stub for inspection
+    #[cfg(feature = "gdb")]
+    loop {
+        unsafe { asm!("brk #0") };
+    }
+    #[cfg(not(feature = "gdb"))]
+    unsafe {
+        (*raspi4b::pm::PMRegisters::new()).reboot()
+    }
 }
 
 global_asm!(include_str!("./init.S"));
 
+/// the baud rate divisors assume the core clock does not scale with load
+fn init_core_clock() {
+    if !platform::available(Feature::CoreClock) {
+        return;
+    }
+    let mailbox = unsafe { &mut *MailboxRegisters::new() };
+    // clock, rate, skip setting turbo
+    let request = [CLOCK_CORE, raspi4b::board::CORE_CLOCK, 1];
+    let _ = mailbox.property(TAG_SET_CLOCK_RATE, &request, &mut [0; 2]);
+}
+
+/// parameters from the bootargs of the device tree, errors are reported once a UART is up
+fn init_command_line() {
+    let bootargs = fdt::device_tree().and_then(|fdt| fdt.bootargs());
+    let line = &raw mut COMMAND_LINE;
+    unsafe {
+        let _ = (*line).register(cmdline::PARAMS);
+        (*line).parse(bootargs.unwrap_or(""));
+    }
+}
+
 fn init_mini_uart() {
+    init_core_clock();
+
     let aux = &raw mut AUX_PERIPHERALS;
     let uart = unsafe { &mut *(*aux).take_mini_uart() };
     let gpio_ = &raw mut GPIO;
@@ -49,9 +102,13 @@ fn init_mini_uart() {
     uart.clear_transmit_fifo();
     uart.clear_receive_fifo();
 
-    // unsupported in qemu
-    // uart.set_baudrate(BaudRate::Baud476);
+    if platform::available(Feature::MiniUartBaud) {
+        uart.set_baudrate(cmdline::mini_uart_baud(command_line()));
+    }
     uart.set_8bit_mode();
+    if platform::available(Feature::MiniUartModem) {
+        uart.set_request_to_send(true);
+    }
 
     uart.transmitter_enable();
     uart.receiver_enable();
@@ -74,8 +131,57 @@ fn print(uart: &mut MiniUart, str: &[u8]) {
     while !uart.tranmitter_idle() {}
 }
 
+/// PL011 picked with `shell=`, set up at `baud=` on its header pins
+fn init_shell_uart(name: &str) -> Option<&'static mut PL011> {
+    let instance = match name {
+        "uart0" => UARTInstance::UART0,
+        "uart2" => UARTInstance::UART2,
+        "uart3" => UARTInstance::UART3,
+        "uart4" => UARTInstance::UART4,
+        "uart5" => UARTInstance::UART5,
+        _ => return None,
+    };
+    let uart = unsafe { &mut *PL011::new(instance) };
+    let baud = command_line().number("baud").unwrap_or(115_200);
+    uart.init(raspi4b::board::UART_CLOCK, baud);
+    let gpio_ = &raw mut GPIO;
+    let gpio = unsafe { &mut *(*gpio_).take_gpio() };
+    instance.route(gpio);
+    unsafe { (*gpio_).return_gpio(gpio) };
+    Some(uart)
+}
+
+/// boot problems, as far as `loglevel` lets them through
+fn report(serial: &mut dyn Serial, device_tree: Result<fdt::Fdt, FdtError>) {
+    let mut out = SerialWriter(serial);
+    if let Err(error) = device_tree
+        && error != FdtError::Missing
+        && cmdline::logs(cmdline::LOG_WARNING)
+    {
+        let _ = writeln!(out, "ignoring the device tree: {error:?}");
+    }
+    if !cmdline::logs(cmdline::LOG_ERROR) {
+        return;
+    }
+    let line = command_line();
+    for error in line.errors() {
+        let _ = writeln!(out, "command line: {error}");
+    }
+    let dropped = line.error_count() - line.errors().count();
+    if dropped > 0 {
+        let _ = writeln!(out, "command line: {dropped} more errors");
+    }
+}
+
 #[unsafe(no_mangle)]
-fn main() {
+#[cfg_attr(
+    any(feature = "qemu-test", feature = "chainload"),
+    allow(unreachable_code, unused_variables)
+)]
+extern "C" fn main(device_tree: usize) {
+    // the blob stays where the firmware put it
+    let loaded = unsafe { fdt::load(device_tree) };
+    init_command_line();
     memory_write_barier();
     let aux = &raw mut AUX_PERIPHERALS;
     let registers = unsafe { &mut *(*aux).take_aux_registers() };
@@ -83,19 +189,51 @@ fn main() {
     unsafe {
         (*aux).return_aux_registers(registers);
     }
+    init_mini_uart();
 
-    let aux = &raw mut AUX_PERIPHERALS;
-    let mini_uart = unsafe { &mut *(*aux).take_mini_uart() };
+    #[cfg(feature = "qemu-test")]
+    {
+        let mini_uart = unsafe { &mut *(*aux).take_mini_uart() };
+        raspi4b::ktest::run(mini_uart, command_line().text("ktest").unwrap_or(""));
+    }
 
-    let str = b"Hello, World!";
-    for i in 0..13 {
-        print(mini_uart, &str[0..i]);
-        print(mini_uart, b"\n");
+    #[cfg(feature = "chainload")]
+    {
+        let mini_uart = unsafe { &mut *(*aux).take_mini_uart() };
+        raspi4b::chainload::run(mini_uart, device_tree);
     }
 
-    loop {
-        while !mini_uart.receiver_symbol_avaliable() {}
-        let byte = mini_uart.receive();
-        putchar(mini_uart, byte as u8);
+    let aux = &raw mut AUX_PERIPHERALS;
+    let mini_uart = unsafe { &mut *(*aux).take_mini_uart() };
+
+    if cmdline::logs(cmdline::LOG_INFO) {
+        let str = b"Hello, World!";
+        for i in 0..13 {
+            print(mini_uart, &str[0..i]);
+            print(mini_uart, b"\n");
+        }
     }
+    report(mini_uart, loaded);
+
+    #[cfg(feature = "trace")]
+    raspi4b::trace::dump(mini_uart);
+
+    raspi4b::exception::install();
+    #[cfg(feature = "gdb")]
+    raspi4b::gdb::start(platform::GDB_UART);
+
+    let console: &mut dyn Serial = match command_line().choice("shell") {
+        Some("mini") | None => mini_uart,
+        Some(name) => {
+            let uart = init_shell_uart(name).unwrap();
+            report(uart, loaded);
+            uart
+        }
+    };
+
+    let mut shell = raspi4b::shell::Shell::new(console);
+    shell.register(raspi4b::thermal::COMMANDS);
+    shell.register(fdt::COMMANDS);
+    shell.register(cmdline::COMMANDS);
+    shell.run()
 }
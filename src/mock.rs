@@ -0,0 +1,293 @@
+//! Simulated register files for host tests.
+//!
+//! A [`RegisterFile`] owns a chunk of memory register blocks can be placed on. While it is
+//! alive, every register access falling into it is served from its own storage through the
+//! [`Behavior`] configured for the accessed word, so hardware side effects like
+//! write-1-to-set registers or FIFOs can be simulated. Files are per test thread.
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::Mutex;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Access {
+    Read,
+    Write(u32),
+}
+
+pub enum Behavior {
+    /// plain storage
+    Memory,
+    /// writes are ignored
+    ReadOnly,
+    /// reads as zero, written ones are set in the word at offset `target`
+    SetBits { target: usize },
+    /// reads as zero, written ones are cleared in the word at offset `target`
+    ClearBits { target: usize },
+    /// written ones clear bits of this word, e.g. event status registers
+    WriteOneToClear,
+    /// reads pop the receive queue (0 when empty), writes are appended to the transmit log
+    Fifo,
+    /// arbitrary side effect, returns the value seen by reads
+    Custom(Box<dyn FnMut(&mut Storage, Access) -> u32>),
+}
+
+/// word storage and FIFO queues of a register file, offsets are in bytes
+#[derive(Default)]
+pub struct Storage {
+    words: Vec<u32>,
+    rx: HashMap<usize, VecDeque<u32>>,
+    tx: HashMap<usize, Vec<u32>>,
+}
+
+impl Storage {
+    pub fn get(&self, offset: usize) -> u32 {
+        self.words[offset / 4]
+    }
+
+    pub fn set(&mut self, offset: usize, value: u32) {
+        self.words[offset / 4] = value;
+    }
+
+    pub fn rx(&mut self, offset: usize) -> &mut VecDeque<u32> {
+        self.rx.entry(offset).or_default()
+    }
+
+    pub fn tx(&mut self, offset: usize) -> &mut Vec<u32> {
+        self.tx.entry(offset).or_default()
+    }
+}
+
+struct State {
+    base: usize,
+    size: usize,
+    storage: Storage,
+    behaviors: HashMap<usize, Behavior>,
+}
+
+impl State {
+    fn access(&mut self, offset: usize, access: Access) -> u32 {
+        let storage = &mut self.storage;
+        let Some(behavior) = self.behaviors.get_mut(&offset) else {
+            if let Access::Write(value) = access {
+                storage.set(offset, value);
+            }
+            return storage.get(offset);
+        };
+
+        match (behavior, access) {
+            (Behavior::Memory, Access::Read) | (Behavior::ReadOnly, Access::Read) => {
+                storage.get(offset)
+            }
+            (Behavior::Memory, Access::Write(value)) => {
+                storage.set(offset, value);
+                value
+            }
+            (Behavior::ReadOnly, Access::Write(_)) => 0,
+            (Behavior::SetBits { .. }, Access::Read)
+            | (Behavior::ClearBits { .. }, Access::Read) => 0,
+            (Behavior::SetBits { target }, Access::Write(value)) => {
+                let old = storage.get(*target);
+                storage.set(*target, old | value);
+                0
+            }
+            (Behavior::ClearBits { target }, Access::Write(value)) => {
+                let old = storage.get(*target);
+                storage.set(*target, old & !value);
+                0
+            }
+            (Behavior::WriteOneToClear, Access::Read) => storage.get(offset),
+            (Behavior::WriteOneToClear, Access::Write(value)) => {
+                let old = storage.get(offset);
+                storage.set(offset, old & !value);
+                0
+            }
+            (Behavior::Fifo, Access::Read) => storage.rx(offset).pop_front().unwrap_or(0),
+            (Behavior::Fifo, Access::Write(value)) => {
+                storage.tx(offset).push(value);
+                0
+            }
+            (Behavior::Custom(f), access) => f(storage, access),
+        }
+    }
+}
+
+thread_local! {
+    static FILES: RefCell<Vec<Rc<RefCell<State>>>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct RegisterFile {
+    /// address space handed out to register blocks, never accessed directly
+    memory: Box<[u32]>,
+    state: Rc<RefCell<State>>,
+}
+
+impl RegisterFile {
+    /// `size` bytes of zeroed registers behaving as plain memory
+    pub fn new(size: usize) -> RegisterFile {
+        let words = size.div_ceil(4);
+        let memory = vec![0u32; words].into_boxed_slice();
+        let state = Rc::new(RefCell::new(State {
+            base: memory.as_ptr() as usize,
+            size: words * 4,
+            storage: Storage {
+                words: vec![0; words],
+                ..Default::default()
+            },
+            behaviors: HashMap::new(),
+        }));
+        FILES.with_borrow_mut(|files| files.push(state.clone()));
+        RegisterFile { memory, state }
+    }
+
+    pub fn base(&self) -> usize {
+        self.memory.as_ptr() as usize
+    }
+
+    /// register block of type `T` placed `offset` bytes into the file
+    pub fn block<T>(&self, offset: usize) -> *mut T {
+        assert!(offset + core::mem::size_of::<T>() <= self.state.borrow().size);
+        (self.base() + offset) as *mut T
+    }
+
+    pub fn on(&self, offset: usize, behavior: Behavior) -> &Self {
+        self.state.borrow_mut().behaviors.insert(offset, behavior);
+        self
+    }
+
+    /// read storage without side effects
+    pub fn peek(&self, offset: usize) -> u32 {
+        self.state.borrow().storage.get(offset)
+    }
+
+    /// write storage without side effects
+    pub fn poke(&self, offset: usize, value: u32) {
+        self.state.borrow_mut().storage.set(offset, value);
+    }
+
+    /// queue values to be read from the FIFO at `offset`
+    pub fn push_rx(&self, offset: usize, values: &[u32]) {
+        self.state
+            .borrow_mut()
+            .storage
+            .rx(offset)
+            .extend(values.iter().copied());
+    }
+
+    /// values written to the FIFO at `offset` so far
+    pub fn take_tx(&self, offset: usize) -> Vec<u32> {
+        core::mem::take(self.state.borrow_mut().storage.tx(offset))
+    }
+}
+
+impl Drop for RegisterFile {
+    fn drop(&mut self) {
+        FILES.with_borrow_mut(|files| files.retain(|f| !Rc::ptr_eq(f, &self.state)));
+    }
+}
+
+fn find(address: usize, size: usize) -> Option<(Rc<RefCell<State>>, usize)> {
+    FILES
+        .with_borrow(|files| {
+            files.iter().find_map(|file| {
+                let state = file.borrow();
+                (state.base..state.base + state.size)
+                    .contains(&address)
+                    .then(|| (file.clone(), address - state.base))
+            })
+        })
+        .inspect(|(_, offset)| {
+            assert_eq!(size, 4, "register file only simulates 32-bit registers");
+            assert_eq!(offset % 4, 0, "unaligned register access");
+        })
+}
+
+pub(crate) fn read(address: usize, size: usize) -> Option<u64> {
+    let (file, offset) = find(address, size)?;
+    let value = file.borrow_mut().access(offset, Access::Read);
+    Some(value as u64)
+}
+
+pub(crate) fn write(address: usize, size: usize, value: u64) -> bool {
+    let Some((file, offset)) = find(address, size) else {
+        return false;
+    };
+    file.borrow_mut()
+        .access(offset, Access::Write(value as u32));
+    true
+}
+
+/// where [`low_memory`] maps its arena, the host keeps its own mappings far above
+const LOW_MEMORY: usize = 0x2000_0000;
+const LOW_MEMORY_SIZE: usize = 0x100_0000;
+
+/// bytes of the arena handed out so far
+static LOW_MEMORY_USED: Mutex<Option<usize>> = Mutex::new(None);
+
+unsafe extern "C" {
+    fn mmap(address: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+}
+
+/// `count` copies of `value` in memory below 1GB, where the legacy DMA engines and the
+/// mailbox reach on the board. Host allocations are above, so buffers meant for them
+/// come from here. Never freed.
+pub fn low_memory<T: Copy>(value: T, count: usize) -> &'static mut [T] {
+    const PROT_READ_WRITE: i32 = 0x3;
+    const MAP_PRIVATE_ANONYMOUS: i32 = 0x22;
+    const MAP_FIXED_NOREPLACE: i32 = 0x10_0000;
+
+    let mut used = LOW_MEMORY_USED.lock().unwrap();
+    let offset = *used.get_or_insert_with(|| {
+        let flags = MAP_PRIVATE_ANONYMOUS | MAP_FIXED_NOREPLACE;
+        let base = unsafe {
+            mmap(
+                LOW_MEMORY as *mut u8,
+                LOW_MEMORY_SIZE,
+                PROT_READ_WRITE,
+                flags,
+                -1,
+                0,
+            )
+        };
+        assert_eq!(base as usize, LOW_MEMORY, "could not map memory below 1GB");
+        0
+    });
+    // aligned for control blocks
+    let start = offset.next_multiple_of(64);
+    let size = count * core::mem::size_of::<T>();
+    assert!(start + size <= LOW_MEMORY_SIZE, "low memory used up");
+    *used = Some(start + size);
+
+    let memory = (LOW_MEMORY + start) as *mut T;
+    let slice = unsafe { core::slice::from_raw_parts_mut(memory, count) };
+    slice.fill(value);
+    slice
+}
+
+/// serial port reading from a queue and recording what is written
+#[derive(Default)]
+pub struct MockSerial {
+    pub rx: VecDeque<u8>,
+    pub tx: Vec<u8>,
+}
+
+impl MockSerial {
+    pub fn new(rx: &[u8]) -> MockSerial {
+        MockSerial {
+            rx: rx.iter().copied().collect(),
+            tx: Vec::new(),
+        }
+    }
+}
+
+impl crate::serial::Serial for MockSerial {
+    fn write_byte(&mut self, byte: u8) {
+        self.tx.push(byte);
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        self.rx.pop_front()
+    }
+
+    fn flush(&mut self) {}
+}
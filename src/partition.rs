@@ -0,0 +1,694 @@
+//! MBR and GPT partition tables.
+//!
+//! [`PartitionTable::read`] finds the partitions of a [`BlockDevice`]. MBR extended partitions
+//! are followed, and GPT headers and entry arrays are checked against their CRC32, falling
+//! back to the backup copy at the end of the disk. Each [`Partition`] opens as a block device
+//! of its own that cannot reach outside its bounds.
+
+use crate::block::{BlockDevice, BlockError};
+use crate::utils::crc32::{self, Crc32};
+use core::fmt;
+
+/// partitions a table can hold, GPT disks usually use a handful of their 128 entries
+pub const MAX_PARTITIONS: usize = 16;
+/// largest block size the tables are read with
+const MAX_BLOCK_SIZE: usize = 4096;
+/// UTF-16 code units of a GPT partition name
+const LABEL_LEN: usize = 36;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_ENTRIES: usize = 446;
+const MBR_DISK_ID: usize = 440;
+const MBR_EMPTY: u8 = 0x00;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const MBR_FAT: [u8; 6] = [0x01, 0x04, 0x06, 0x0b, 0x0c, 0x0e];
+/// single partition covering a GPT disk
+const MBR_PROTECTIVE: u8 = 0xee;
+const MBR_BOOTABLE: u8 = 0x80;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_SIZE: usize = 92;
+const GPT_ENTRY_SIZE: usize = 128;
+/// entry arrays larger than this are treated as corrupt
+const GPT_MAX_ENTRIES: usize = 4096;
+/// attribute bit 2, legacy BIOS bootable
+const GPT_BOOTABLE: u64 = 1 << 2;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PartitionError {
+    Block(BlockError),
+    /// block 0 holds no MBR signature
+    NoTable,
+    /// GPT header is malformed
+    InvalidHeader,
+    /// GPT header or entry array does not match its CRC32
+    Checksum,
+    /// partition outside the disk or the usable area, or a looping extended partition chain
+    InvalidEntry,
+    /// more than `MAX_PARTITIONS` partitions
+    TooManyPartitions,
+    /// block size below 512 or above 4096 bytes
+    UnsupportedBlockSize,
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(error: BlockError) -> Self {
+        PartitionError::Block(error)
+    }
+}
+
+/// GUID in its on-disk byte order, the first three fields little-endian
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const EFI_SYSTEM: Guid = Guid::new(
+        0xc12a_7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    pub const BASIC_DATA: Guid = Guid::new(
+        0xebd0_a0a2,
+        0xb9e5,
+        0x4433,
+        [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    );
+    pub const LINUX_FILESYSTEM: Guid = Guid::new(
+        0x0fc6_3daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+
+    /// GUID written as `a-b-c-d[0..2]-d[2..8]`
+    pub const fn new(a: u32, b: u16, c: u16, d: [u8; 8]) -> Guid {
+        let a = a.to_le_bytes();
+        let b = b.to_le_bytes();
+        let c = c.to_le_bytes();
+        Guid([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5],
+            d[6], d[7],
+        ])
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]])
+        )?;
+        for (i, byte) in g[8..].iter().enumerate() {
+            if i == 2 {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Scheme {
+    /// with the 32-bit disk signature
+    Mbr(u32),
+    /// with the disk GUID
+    Gpt(Guid),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PartitionKind {
+    /// MBR partition type byte
+    Mbr(u8),
+    Gpt {
+        type_guid: Guid,
+        guid: Guid,
+        attributes: u64,
+    },
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Partition {
+    /// 1-4 for MBR primary partitions and 5 on for logical ones, the entry index + 1 on GPT
+    pub number: u32,
+    /// first block on the disk
+    pub start: u64,
+    pub blocks: u64,
+    pub kind: PartitionKind,
+    pub bootable: bool,
+    label: [u16; LABEL_LEN],
+}
+
+impl Partition {
+    /// GPT partition name, empty on MBR disks
+    pub fn label(&self) -> impl Iterator<Item = char> + '_ {
+        let len = self.label.iter().position(|c| *c == 0).unwrap_or(LABEL_LEN);
+        char::decode_utf16(self.label[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.label().eq(label.chars())
+    }
+
+    /// FAT partition type or a GPT EFI system / basic data partition
+    pub fn is_fat(&self) -> bool {
+        match self.kind {
+            PartitionKind::Mbr(kind) => MBR_FAT.contains(&kind),
+            PartitionKind::Gpt { type_guid, .. } => {
+                type_guid == Guid::EFI_SYSTEM || type_guid == Guid::BASIC_DATA
+            }
+        }
+    }
+
+    /// block device covering only this partition of `device`
+    pub fn open<D: BlockDevice>(&self, device: D) -> Result<PartitionDevice<D>, BlockError> {
+        PartitionDevice::new(device, self.start, self.blocks)
+    }
+}
+
+pub struct PartitionTable {
+    scheme: Scheme,
+    partitions: [Option<Partition>; MAX_PARTITIONS],
+}
+
+impl PartitionTable {
+    /// parse the partition table of `device`, GPT when the MBR is protective
+    pub fn read<D: BlockDevice + ?Sized>(device: &mut D) -> Result<PartitionTable, PartitionError> {
+        let size = device.block_size();
+        if !(512..=MAX_BLOCK_SIZE).contains(&size) {
+            return Err(PartitionError::UnsupportedBlockSize);
+        }
+        let mut buf = [0u8; MAX_BLOCK_SIZE];
+        let block = &mut buf[..size];
+
+        device.read_blocks(0, block)?;
+        if block[510..512] != MBR_SIGNATURE {
+            return Err(PartitionError::NoTable);
+        }
+        let entries = MbrEntry::parse(block);
+        if entries.iter().any(|e| e.kind == MBR_PROTECTIVE) {
+            return match Self::read_gpt(device, block, 1) {
+                Err(PartitionError::InvalidHeader | PartitionError::Checksum) => {
+                    Self::read_gpt(device, block, device.block_count() - 1)
+                }
+                table => table,
+            };
+        }
+
+        let mut table = PartitionTable::new(Scheme::Mbr(le_u32(block, MBR_DISK_ID)));
+        let mut extended = None;
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.is_empty() {
+                continue;
+            }
+            if MBR_EXTENDED.contains(&entry.kind) {
+                extended = Some(entry.start as u64);
+                continue;
+            }
+            table.push(device, entry.partition(i as u32 + 1, 0))?;
+        }
+        if let Some(start) = extended {
+            table.read_extended(device, block, start)?;
+        }
+        Ok(table)
+    }
+
+    /// follow the chain of extended boot records, each describing one logical partition
+    fn read_extended<D: BlockDevice + ?Sized>(
+        &mut self,
+        device: &mut D,
+        block: &mut [u8],
+        start: u64,
+    ) -> Result<(), PartitionError> {
+        let mut ebr = start;
+        for number in 5..5 + MAX_PARTITIONS as u32 {
+            device.read_blocks(ebr, block)?;
+            if block[510..512] != MBR_SIGNATURE {
+                return Err(PartitionError::InvalidEntry);
+            }
+            let [logical, next, ..] = MbrEntry::parse(block);
+            if !logical.is_empty() {
+                self.push(device, logical.partition(number, ebr))?;
+            }
+            if next.is_empty() || !MBR_EXTENDED.contains(&next.kind) {
+                return Ok(());
+            }
+            // links are relative to the start of the extended partition, and only go forward
+            let link = start + next.start as u64;
+            if link <= ebr {
+                return Err(PartitionError::InvalidEntry);
+            }
+            ebr = link;
+        }
+        Err(PartitionError::InvalidEntry)
+    }
+
+    /// parse the GPT whose header is at `lba`
+    fn read_gpt<D: BlockDevice + ?Sized>(
+        device: &mut D,
+        block: &mut [u8],
+        lba: u64,
+    ) -> Result<PartitionTable, PartitionError> {
+        device.read_blocks(lba, block)?;
+        let header_size = le_u32(block, 12) as usize;
+        if &block[..8] != GPT_SIGNATURE || !(GPT_HEADER_SIZE..=block.len()).contains(&header_size) {
+            return Err(PartitionError::InvalidHeader);
+        }
+        let crc = le_u32(block, 16);
+        block[16..20].fill(0);
+        if crc32::checksum(&block[..header_size]) != crc {
+            return Err(PartitionError::Checksum);
+        }
+
+        let first_usable = le_u64(block, 40);
+        let last_usable = le_u64(block, 48);
+        let mut disk = Guid::default();
+        disk.0.copy_from_slice(&block[56..72]);
+        let entries_lba = le_u64(block, 72);
+        let count = le_u32(block, 80) as usize;
+        let entry_size = le_u32(block, 84) as usize;
+        let entries_crc = le_u32(block, 88);
+        if le_u64(block, 24) != lba
+            || last_usable >= device.block_count()
+            || first_usable > last_usable
+            || count > GPT_MAX_ENTRIES
+            || entry_size < GPT_ENTRY_SIZE
+            || !block.len().is_multiple_of(entry_size)
+        {
+            return Err(PartitionError::InvalidHeader);
+        }
+
+        // entries are only looked at once the whole array matches its checksum, so a
+        // corrupted one sends `read` to the backup table
+        let per_block = block.len() / entry_size;
+        let mut crc = Crc32::new();
+        for index in (0..count).step_by(per_block) {
+            device.read_blocks(entries_lba + (index / per_block) as u64, block)?;
+            let entries = (count - index).min(per_block);
+            crc.update(&block[..entries * entry_size]);
+        }
+        if crc.finish() != entries_crc {
+            return Err(PartitionError::Checksum);
+        }
+
+        let mut table = PartitionTable::new(Scheme::Gpt(disk));
+        for index in (0..count).step_by(per_block) {
+            device.read_blocks(entries_lba + (index / per_block) as u64, block)?;
+            let entries = (count - index).min(per_block);
+            for (i, entry) in block.chunks(entry_size).take(entries).enumerate() {
+                let mut type_guid = Guid::default();
+                type_guid.0.copy_from_slice(&entry[..16]);
+                if type_guid.is_nil() {
+                    continue;
+                }
+                let mut guid = Guid::default();
+                guid.0.copy_from_slice(&entry[16..32]);
+                let first = le_u64(entry, 32);
+                let last = le_u64(entry, 40);
+                if first < first_usable || last > last_usable || first > last {
+                    return Err(PartitionError::InvalidEntry);
+                }
+                let attributes = le_u64(entry, 48);
+                let mut label = [0u16; LABEL_LEN];
+                for (c, bytes) in label.iter_mut().zip(entry[56..128].chunks(2)) {
+                    *c = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                table.push(
+                    device,
+                    Partition {
+                        number: (index + i) as u32 + 1,
+                        start: first,
+                        blocks: last - first + 1,
+                        kind: PartitionKind::Gpt {
+                            type_guid,
+                            guid,
+                            attributes,
+                        },
+                        bootable: attributes & GPT_BOOTABLE != 0,
+                        label,
+                    },
+                )?;
+            }
+        }
+        Ok(table)
+    }
+
+    fn new(scheme: Scheme) -> PartitionTable {
+        PartitionTable {
+            scheme,
+            partitions: [None; MAX_PARTITIONS],
+        }
+    }
+
+    fn push<D: BlockDevice + ?Sized>(
+        &mut self,
+        device: &D,
+        partition: Partition,
+    ) -> Result<(), PartitionError> {
+        match partition.start.checked_add(partition.blocks) {
+            Some(end) if end <= device.block_count() => {}
+            _ => return Err(PartitionError::InvalidEntry),
+        }
+        let slot = self
+            .partitions
+            .iter_mut()
+            .find(|p| p.is_none())
+            .ok_or(PartitionError::TooManyPartitions)?;
+        *slot = Some(partition);
+        Ok(())
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    /// partitions in table order
+    pub fn iter(&self) -> impl Iterator<Item = &Partition> {
+        self.partitions.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions[0].is_none()
+    }
+
+    /// partition by its number
+    pub fn get(&self, number: u32) -> Option<&Partition> {
+        self.iter().find(|p| p.number == number)
+    }
+
+    pub fn find(&self, predicate: impl Fn(&Partition) -> bool) -> Option<&Partition> {
+        self.iter().find(|p| predicate(p))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct MbrEntry {
+    status: u8,
+    kind: u8,
+    start: u32,
+    sectors: u32,
+}
+
+impl MbrEntry {
+    fn parse(block: &[u8]) -> [MbrEntry; 4] {
+        core::array::from_fn(|i| {
+            let entry = &block[MBR_ENTRIES + i * 16..][..16];
+            MbrEntry {
+                status: entry[0],
+                kind: entry[4],
+                start: le_u32(entry, 8),
+                sectors: le_u32(entry, 12),
+            }
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.kind == MBR_EMPTY || self.sectors == 0
+    }
+
+    /// partition with its start relative to the record at `base`
+    fn partition(&self, number: u32, base: u64) -> Partition {
+        Partition {
+            number,
+            start: base + self.start as u64,
+            blocks: self.sectors as u64,
+            kind: PartitionKind::Mbr(self.kind),
+            bootable: self.status & MBR_BOOTABLE != 0,
+            label: [0; LABEL_LEN],
+        }
+    }
+}
+
+/// blocks `start..start + blocks` of a device
+pub struct PartitionDevice<D> {
+    device: D,
+    start: u64,
+    blocks: u64,
+}
+
+impl<D: BlockDevice> PartitionDevice<D> {
+    pub fn new(device: D, start: u64, blocks: u64) -> Result<PartitionDevice<D>, BlockError> {
+        match start.checked_add(blocks) {
+            Some(end) if end <= device.block_count() => Ok(PartitionDevice {
+                device,
+                start,
+                blocks,
+            }),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for PartitionDevice<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.device.read_blocks(self.start + lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.device.write_blocks(self.start + lba, buf)
+    }
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::RamDisk;
+
+    const BLOCKS: usize = 256;
+
+    fn mbr_entry(block: &mut [u8], i: usize, status: u8, kind: u8, start: u32, sectors: u32) {
+        let entry = &mut block[MBR_ENTRIES + i * 16..][..16];
+        entry[0] = status;
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        block[510..512].copy_from_slice(&MBR_SIGNATURE);
+    }
+
+    fn block(disk: &mut [u8], lba: usize) -> &mut [u8] {
+        &mut disk[lba * 512..][..512]
+    }
+
+    /// GPT with its header at `lba` and entries at `entries`
+    fn gpt_header(disk: &mut [u8], lba: u64, backup: u64, entries: u64) {
+        let array = crc32::checksum(&disk[entries as usize * 512..][..128 * 128]);
+        let header = block(disk, lba as usize);
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[32..40].copy_from_slice(&backup.to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(BLOCKS as u64 - 34).to_le_bytes());
+        header[56..72].fill(0x42);
+        header[72..80].copy_from_slice(&entries.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&array.to_le_bytes());
+        let crc = crc32::checksum(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn gpt_entry(disk: &mut [u8], index: usize, kind: Guid, first: u64, last: u64, name: &str) {
+        for entries in [2, BLOCKS - 33] {
+            let entry = &mut disk[entries * 512 + index * 128..][..128];
+            entry[..16].copy_from_slice(&kind.0);
+            entry[16..32].fill(index as u8);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            entry[48..56].copy_from_slice(&GPT_BOOTABLE.to_le_bytes());
+            for (c, unit) in entry[56..].chunks_mut(2).zip(name.encode_utf16()) {
+                c.copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+    }
+
+    fn gpt_disk() -> Vec<u8> {
+        let mut disk = vec![0u8; BLOCKS * 512];
+        mbr_entry(
+            block(&mut disk, 0),
+            0,
+            0,
+            MBR_PROTECTIVE,
+            1,
+            BLOCKS as u32 - 1,
+        );
+        gpt_entry(&mut disk, 0, Guid::EFI_SYSTEM, 40, 79, "boot");
+        gpt_entry(&mut disk, 2, Guid::LINUX_FILESYSTEM, 100, 199, "root");
+        gpt_header(&mut disk, 1, BLOCKS as u64 - 1, 2);
+        gpt_header(&mut disk, BLOCKS as u64 - 1, 1, BLOCKS as u64 - 33);
+        disk
+    }
+
+    #[test]
+    fn mbr() {
+        let mut disk = vec![0u8; BLOCKS * 512];
+        let mbr = block(&mut disk, 0);
+        mbr[MBR_DISK_ID..MBR_DISK_ID + 4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        mbr_entry(mbr, 0, MBR_BOOTABLE, 0x0c, 8, 32);
+        mbr_entry(mbr, 1, 0, 0x0f, 64, 128);
+        // two logical partitions, the second record linked relative to the extended start
+        mbr_entry(block(&mut disk, 64), 0, 0, 0x83, 2, 20);
+        mbr_entry(block(&mut disk, 64), 1, 0, 0x05, 32, 16);
+        mbr_entry(block(&mut disk, 96), 0, 0, 0x83, 1, 10);
+
+        let mut device = RamDisk::new(&mut disk, 512);
+        let table = PartitionTable::read(&mut device).unwrap();
+        assert_eq!(table.scheme(), Scheme::Mbr(0x1234_5678));
+        let found: Vec<_> = table
+            .iter()
+            .map(|p| (p.number, p.start, p.blocks))
+            .collect();
+        assert_eq!(found, [(1, 8, 32), (5, 66, 20), (6, 97, 10)]);
+
+        let boot = table.find(|p| p.bootable).unwrap();
+        assert!(boot.is_fat());
+        assert_eq!(boot.label().count(), 0);
+        assert!(!table.get(5).unwrap().is_fat());
+
+        // extended partition linking to itself
+        mbr_entry(block(&mut disk, 96), 1, 0, 0x05, 32, 16);
+        let mut device = RamDisk::new(&mut disk, 512);
+        assert_eq!(
+            PartitionTable::read(&mut device).err(),
+            Some(PartitionError::InvalidEntry)
+        );
+
+        block(&mut disk, 0)[511] = 0;
+        let mut device = RamDisk::new(&mut disk, 512);
+        assert_eq!(
+            PartitionTable::read(&mut device).err(),
+            Some(PartitionError::NoTable)
+        );
+    }
+
+    #[test]
+    fn gpt() {
+        let mut disk = gpt_disk();
+        let mut device = RamDisk::new(&mut disk, 512);
+        let table = PartitionTable::read(&mut device).unwrap();
+        assert_eq!(table.scheme(), Scheme::Gpt(Guid([0x42; 16])));
+        assert_eq!(table.len(), 2);
+
+        let boot = table.get(1).unwrap();
+        assert_eq!((boot.start, boot.blocks), (40, 40));
+        assert!(boot.has_label("boot") && boot.is_fat() && boot.bootable);
+        let root = table.find(|p| p.has_label("root")).unwrap();
+        assert_eq!(root.number, 3);
+        match root.kind {
+            PartitionKind::Gpt {
+                type_guid, guid, ..
+            } => {
+                assert_eq!(type_guid, Guid::LINUX_FILESYSTEM);
+                assert_eq!(guid, Guid([2; 16]));
+            }
+            kind => panic!("{kind:?}"),
+        }
+    }
+
+    #[test]
+    fn gpt_checksums() {
+        // corrupt primary header, the backup is used
+        let mut disk = gpt_disk();
+        block(&mut disk, 1)[40] ^= 1;
+        let mut device = RamDisk::new(&mut disk, 512);
+        assert_eq!(PartitionTable::read(&mut device).unwrap().len(), 2);
+
+        // corrupt primary entries
+        let mut disk = gpt_disk();
+        block(&mut disk, 2)[200] ^= 1;
+        let mut device = RamDisk::new(&mut disk, 512);
+        assert_eq!(PartitionTable::read(&mut device).unwrap().len(), 2);
+
+        // primary entry out of the usable range
+        let mut disk = gpt_disk();
+        block(&mut disk, 2)[40..48].fill(0xff);
+        let mut device = RamDisk::new(&mut disk, 512);
+        let table = PartitionTable::read(&mut device).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(1).unwrap().blocks, 40);
+
+        // both entry arrays corrupt
+        block(&mut disk, 2)[40..48].copy_from_slice(&79u64.to_le_bytes());
+        block(&mut disk, 2)[200] ^= 1;
+        block(&mut disk, BLOCKS - 33)[200] ^= 1;
+        let mut device = RamDisk::new(&mut disk, 512);
+        assert_eq!(
+            PartitionTable::read(&mut device).err(),
+            Some(PartitionError::Checksum)
+        );
+    }
+
+    #[test]
+    fn guid_display() {
+        assert_eq!(
+            format!("{}", Guid::EFI_SYSTEM),
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+        );
+        assert_eq!(&Guid::EFI_SYSTEM.0[..4], &[0x28, 0x73, 0x2a, 0xc1]);
+    }
+
+    #[test]
+    fn partition_device() {
+        let mut disk = gpt_disk();
+        let mut device = RamDisk::new(&mut disk, 512);
+        let table = PartitionTable::read(&mut device).unwrap();
+        let mut boot = table.get(1).unwrap().open(&mut device).unwrap();
+        assert_eq!(boot.block_count(), 40);
+
+        boot.write_blocks(39, &[0xa5; 512]).unwrap();
+        assert_eq!(
+            boot.write_blocks(39, &[0xa5; 1024]),
+            Err(BlockError::OutOfRange)
+        );
+        assert_eq!(
+            boot.read_blocks(40, &mut [0; 512]),
+            Err(BlockError::OutOfRange)
+        );
+        assert!(block(&mut disk, 79).iter().all(|b| *b == 0xa5));
+        assert!(block(&mut disk, 80).iter().all(|b| *b == 0));
+
+        let mut device = RamDisk::new(&mut disk, 512);
+        assert!(PartitionDevice::new(&mut device, 200, 57).is_err());
+    }
+}
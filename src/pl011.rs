@@ -0,0 +1,264 @@
+//! BCM2711 PL011 UARTs, UART0 and UART2-5. The Pi 3 only has UART0.
+//!
+//! The mini UART keeps the console, these are free for other uses like the GDB stub.
+use crate::board;
+use crate::gpio::{GPIOFunction, GPIOPin, GPIORegisters};
+use crate::registers::*;
+use crate::serial::Serial;
+
+register_bitfields! {u32,
+    pub UART_DR [
+        DATA OFFSET(0) NUMBITS(8) [],
+        /// framing, parity, break and overrun errors of this byte
+        ERRORS OFFSET(8) NUMBITS(4) [],
+    ],
+    pub UART_FR [
+        BUSY OFFSET(3) NUMBITS(1) [],
+        RXFE OFFSET(4) NUMBITS(1) [],
+        TXFF OFFSET(5) NUMBITS(1) [],
+        RXFF OFFSET(6) NUMBITS(1) [],
+        TXFE OFFSET(7) NUMBITS(1) [],
+    ],
+    pub UART_IBRD [
+        IBRD OFFSET(0) NUMBITS(16) [],
+    ],
+    pub UART_FBRD [
+        /// fractional part of the divisor in 1/64
+        FBRD OFFSET(0) NUMBITS(6) [],
+    ],
+    pub UART_LCRH [
+        /// enable FIFOs
+        FEN OFFSET(4) NUMBITS(1) [],
+        WLEN OFFSET(5) NUMBITS(2) [
+            Bits5 = 0,
+            Bits6 = 1,
+            Bits7 = 2,
+            Bits8 = 3,
+        ],
+    ],
+    pub UART_CR [
+        UARTEN OFFSET(0) NUMBITS(1) [],
+        TXE OFFSET(8) NUMBITS(1) [],
+        RXE OFFSET(9) NUMBITS(1) [],
+    ],
+    /// shared by the mask, raw, masked status and clear registers
+    pub UART_INT [
+        /// receive FIFO reached its level
+        RX OFFSET(4) NUMBITS(1) [],
+        TX OFFSET(5) NUMBITS(1) [],
+        /// receive FIFO not empty and idle
+        RT OFFSET(6) NUMBITS(1) [],
+        ALL OFFSET(0) NUMBITS(11) [],
+    ],
+}
+
+#[repr(C)]
+pub struct PL011 {
+    dr: ReadWrite<u32, UART_DR::Register>,     /* 0x00 DR Data */
+    rsrecr: ReadWrite<u32>,                    /* 0x04 RSRECR Receive Status / Error Clear */
+    padding0: [u8; 0x10],                      /* 0x08 padding */
+    fr: ReadOnly<u32, UART_FR::Register>,      /* 0x18 FR Flag */
+    padding1: [u8; 0x4],                       /* 0x1c padding */
+    ilpr: ReadWrite<u32>,                      /* 0x20 ILPR unused */
+    ibrd: ReadWrite<u32, UART_IBRD::Register>, /* 0x24 IBRD Integer Baud Rate Divisor */
+    fbrd: ReadWrite<u32, UART_FBRD::Register>, /* 0x28 FBRD Fractional Baud Rate Divisor */
+    lcrh: ReadWrite<u32, UART_LCRH::Register>, /* 0x2c LCRH Line Control */
+    cr: ReadWrite<u32, UART_CR::Register>,     /* 0x30 CR Control */
+    ifls: ReadWrite<u32>,                      /* 0x34 IFLS Interrupt FIFO Level Select */
+    imsc: ReadWrite<u32, UART_INT::Register>,  /* 0x38 IMSC Interrupt Mask Set/Clear */
+    ris: ReadOnly<u32, UART_INT::Register>,    /* 0x3c RIS Raw Interrupt Status */
+    mis: ReadOnly<u32, UART_INT::Register>,    /* 0x40 MIS Masked Interrupt Status */
+    icr: WriteOnly<u32, UART_INT::Register>,   /* 0x44 ICR Interrupt Clear */
+}
+
+register_layout! {
+    PL011 @ 0x00 .. 0x48 {
+        0x00 => dr,
+        0x04 => rsrecr,
+        0x08 => padding0,
+        0x18 => fr,
+        0x1c => padding1,
+        0x20 => ilpr,
+        0x24 => ibrd,
+        0x28 => fbrd,
+        0x2c => lcrh,
+        0x30 => cr,
+        0x34 => ifls,
+        0x38 => imsc,
+        0x3c => ris,
+        0x40 => mis,
+        0x44 => icr,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum UARTInstance {
+    /// on GPIO 14/15 it collides with the mini UART, the emulator only has this one
+    UART0,
+    UART2,
+    UART3,
+    UART4,
+    UART5,
+}
+
+impl UARTInstance {
+    pub const fn base(self) -> usize {
+        match self {
+            UARTInstance::UART0 => board::peripheral(0x20_1000),
+            UARTInstance::UART2 => board::peripheral(0x20_1400),
+            UARTInstance::UART3 => board::peripheral(0x20_1600),
+            UARTInstance::UART4 => board::peripheral(0x20_1800),
+            UARTInstance::UART5 => board::peripheral(0x20_1a00),
+        }
+    }
+
+    /// interrupt number, all PL011s share VideoCore interrupt 57
+    pub const fn irq(self) -> u32 {
+        board::vc_irq(57)
+    }
+
+    /// TX and RX pins on the 40 pin header and their function
+    pub const fn pins(self) -> (GPIOPin, GPIOPin, GPIOFunction) {
+        match self {
+            UARTInstance::UART0 => (GPIOPin::PIN14, GPIOPin::PIN15, GPIOFunction::ALT0),
+            UARTInstance::UART2 => (GPIOPin::PIN0, GPIOPin::PIN1, GPIOFunction::ALT4),
+            UARTInstance::UART3 => (GPIOPin::PIN4, GPIOPin::PIN5, GPIOFunction::ALT4),
+            UARTInstance::UART4 => (GPIOPin::PIN8, GPIOPin::PIN9, GPIOFunction::ALT4),
+            UARTInstance::UART5 => (GPIOPin::PIN12, GPIOPin::PIN13, GPIOFunction::ALT4),
+        }
+    }
+
+    pub fn route(self, gpio: &mut GPIORegisters) {
+        let (tx, rx, function) = self.pins();
+        gpio.pin_function_set(tx, function);
+        gpio.pin_function_set(rx, function);
+    }
+}
+
+impl PL011 {
+    pub const fn new(instance: UARTInstance) -> *mut Self {
+        Self::at(instance.base())
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut Self {
+        base as *mut Self
+    }
+
+    /// 8N1 with FIFOs at `baud`, interrupts masked
+    pub fn init(&mut self, clock: u32, baud: u32) {
+        self.cr.set(0);
+        while self.fr.is_set(UART_FR::BUSY) {}
+        // disabling the FIFOs flushes them
+        self.lcrh.set(0);
+        self.imsc.set(0);
+        self.icr.write(UART_INT::ALL.val(0x7ff));
+        // divisor in 1/64, rounded
+        let divisor = ((clock as u64 * 4 + baud as u64 / 2) / baud as u64) as u32;
+        self.ibrd.write(UART_IBRD::IBRD.val(divisor >> 6));
+        self.fbrd.write(UART_FBRD::FBRD.val(divisor & 0x3f));
+        self.lcrh
+            .write(UART_LCRH::WLEN::Bits8 + UART_LCRH::FEN.val(1));
+        self.cr
+            .write(UART_CR::UARTEN.val(1) + UART_CR::TXE.val(1) + UART_CR::RXE.val(1));
+    }
+
+    pub fn baudrate(&self, clock: u32) -> u32 {
+        let divisor = (self.ibrd.read(UART_IBRD::IBRD) << 6) | self.fbrd.read(UART_FBRD::FBRD);
+        match divisor {
+            0 => 0,
+            _ => ((clock as u64 * 4) / divisor as u64) as u32,
+        }
+    }
+
+    /// interrupt whenever something was received
+    pub fn enable_receive_interrupt(&mut self) {
+        self.imsc.modify(UART_INT::RX.val(1) + UART_INT::RT.val(1));
+    }
+
+    pub fn disable_receive_interrupt(&mut self) {
+        self.imsc.modify(UART_INT::RX.val(0) + UART_INT::RT.val(0));
+    }
+
+    pub fn clear_interrupts(&mut self) {
+        self.icr.write(UART_INT::ALL.val(0x7ff));
+    }
+}
+
+impl Serial for PL011 {
+    fn write_byte(&mut self, byte: u8) {
+        while self.fr.is_set(UART_FR::TXFF) {}
+        self.dr.write(UART_DR::DATA.val(byte as u32));
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        match self.fr.is_set(UART_FR::RXFE) {
+            true => None,
+            false => Some(self.dr.read(UART_DR::DATA) as u8),
+        }
+    }
+
+    fn flush(&mut self) {
+        while self.fr.is_set(UART_FR::BUSY) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::UART_CLOCK;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const DR: usize = 0x00;
+    const FR: usize = 0x18;
+    const IBRD: usize = 0x24;
+    const FBRD: usize = 0x28;
+    const LCRH: usize = 0x2c;
+    const CR: usize = 0x30;
+    const IMSC: usize = 0x38;
+
+    /// data register backed by FIFOs, the flags report an empty receive FIFO
+    fn uart() -> RegisterFile {
+        let file = RegisterFile::new(core::mem::size_of::<PL011>());
+        file.on(DR, Behavior::Fifo).on(
+            FR,
+            Behavior::Custom(Box::new(|storage, access| match access {
+                Access::Read if storage.rx(DR).is_empty() => 1 << 4,
+                _ => 0,
+            })),
+        );
+        file
+    }
+
+    #[test]
+    fn configure() {
+        let file = uart();
+        let uart = unsafe { &mut *file.block::<PL011>(0) };
+
+        uart.init(UART_CLOCK, 115_200);
+        assert_eq!(file.peek(IBRD), 26);
+        assert_eq!(file.peek(FBRD), 3);
+        assert_eq!(file.peek(LCRH), 0x70);
+        assert_eq!(file.peek(CR), 0x301);
+        assert_eq!(uart.baudrate(UART_CLOCK), 115_176);
+
+        uart.enable_receive_interrupt();
+        assert_eq!(file.peek(IMSC), 0x50);
+        uart.disable_receive_interrupt();
+        assert_eq!(file.peek(IMSC), 0);
+    }
+
+    #[test]
+    fn transfer() {
+        let file = uart();
+        let uart = unsafe { &mut *file.block::<PL011>(0) };
+
+        uart.write_bytes(b"ok");
+        assert_eq!(file.take_tx(DR), [b'o' as u32, b'k' as u32]);
+        assert_eq!(uart.read_byte(), None);
+        // error flags above the data are dropped
+        file.push_rx(DR, &[0x441]);
+        assert_eq!(uart.read_byte(), Some(0x41));
+        assert_eq!(uart.read_byte(), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! Emulator or real board, chosen with the `qemu` cargo feature (`qemu-test` implies it).
+//!
+//! Every place the kernel behaves differently under QEMU is a [`Feature`] listed here, with
+//! what the emulator lacks and what the kernel does instead. Drivers and `main` ask
+//! [`available`] rather than checking the cargo feature themselves.
+use crate::board::{self, Board};
+use crate::pl011::UARTInstance;
+
+pub const EMULATED: bool = cfg!(feature = "qemu");
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Feature {
+    /// AUX_MU_BAUD is not emulated, the mini UART runs at whatever rate the host end uses.
+    /// Boot leaves the baud rate alone and the `uart baud` command refuses to change it.
+    MiniUartBaud,
+    /// AUX_MU_MCR, AUX_MU_MSR, the scratch register and AUX_MU_LCR beyond the data size
+    /// are not emulated. Boot asserts RTS only on hardware.
+    MiniUartModem,
+    /// The firmware is asked to fix the VPU core clock at [`board::CORE_CLOCK`], which the
+    /// mini UART, SPI and I2C dividers assume. The emulator's property interface answers
+    /// without changing anything.
+    CoreClock,
+    /// The emulator models the older BCM2835 generator at the RNG200 address.
+    /// [`crate::rng::Random`] notices at its first read and uses its software generator.
+    RNG200,
+}
+
+/// whether `feature` works on the platform the kernel was built for
+pub const fn available(feature: Feature) -> bool {
+    match feature {
+        Feature::MiniUartBaud | Feature::MiniUartModem | Feature::CoreClock | Feature::RNG200 => {
+            !EMULATED
+        }
+    }
+}
+
+/// UART of the GDB stub. The emulator only has UART0, which shares GPIO 14/15 with the
+/// mini UART, so on a Pi 4 the stub uses UART3 on GPIO 4/5. The Pi 3 only has UART0.
+pub const GDB_UART: UARTInstance = match (EMULATED, board::BOARD) {
+    (false, Board::Raspi4B) => UARTInstance::UART3,
+    _ => UARTInstance::UART0,
+};
@@ -0,0 +1,221 @@
+//! BCM2711 power management watchdog, resets and powers off the board
+use crate::registers::*;
+
+/// the watchdog counts down in 1/65536 s
+const TICKS_PER_SECOND: u64 = 65536;
+/// watchdog time of a reset requested right away
+const RESET_TICKS: u32 = 10;
+/// boot partition the firmware takes as "stay halted"
+const HALT_PARTITION: u8 = 63;
+
+register_bitfields! {u32,
+    pub PM_RSTC [
+        /// what the watchdog does when it runs out
+        WRCFG OFFSET(4) NUMBITS(2) [
+            Clear = 0,
+            FullReset = 2,
+        ],
+        /// writes without the password are ignored
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a,
+        ],
+    ],
+    pub PM_RSTS [
+        /// the last reset was a full reset by the watchdog
+        HADWRF OFFSET(5) NUMBITS(1) [],
+        /// the last reset was a power on reset
+        HADPOR OFFSET(12) NUMBITS(1) [],
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a,
+        ],
+    ],
+    pub PM_WDOG [
+        /// ticks left until the watchdog fires
+        TIME OFFSET(0) NUMBITS(20) [],
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5a,
+        ],
+    ],
+}
+
+#[repr(C)]
+pub struct PMRegisters {
+    padding0: [u8; 0x1c],                    /* 0x00 padding */
+    rstc: ReadWrite<u32, PM_RSTC::Register>, /* 0x1c RSTC Reset Control */
+    rsts: ReadWrite<u32, PM_RSTS::Register>, /* 0x20 RSTS Reset Status */
+    wdog: ReadWrite<u32, PM_WDOG::Register>, /* 0x24 WDOG Watchdog */
+}
+
+register_layout! {
+    PMRegisters @ 0x00 .. 0x28 {
+        0x00 => padding0,
+        0x1c => rstc,
+        0x20 => rsts,
+        0x24 => wdog,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PMError {
+    /// longer than the watchdog can count, about 16 s
+    InvalidTimeout,
+}
+
+/// cause of the last reset as recorded by the PM block
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BootReason {
+    PowerOn,
+    /// the watchdog ran out, [`PMRegisters::reboot`] included
+    Watchdog,
+    Other,
+}
+
+impl PMRegisters {
+    const BASE: usize = crate::board::peripheral(0x10_0000);
+
+    pub const fn new() -> *mut Self {
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut Self {
+        base as *mut Self
+    }
+
+    pub fn reboot(&mut self) -> ! {
+        self.reset(0);
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// reset into the halt partition, the firmware stops until power is cycled
+    pub fn power_off(&mut self) -> ! {
+        self.reset(HALT_PARTITION);
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// full reset in a few ticks, the firmware boots `partition`
+    fn reset(&mut self, partition: u8) {
+        // the partition number is spread over the even bits
+        let bits = (0..6)
+            .filter(|bit| partition & (1 << bit) != 0)
+            .fold(0, |bits, bit| bits | 1 << (bit * 2));
+        let rsts = self.rsts.get() & !0x555;
+        self.rsts.set(PM_RSTS::PASSWD::Password.modify(rsts | bits));
+        self.arm(RESET_TICKS);
+    }
+
+    fn arm(&mut self, ticks: u32) {
+        self.wdog
+            .write(PM_WDOG::TIME.val(ticks) + PM_WDOG::PASSWD::Password);
+        self.rstc
+            .modify(PM_RSTC::WRCFG::FullReset + PM_RSTC::PASSWD::Password);
+    }
+
+    /// Reset the board unless [`Watchdog::feed`] is called within `timeout_ms`.
+    pub fn watchdog(&mut self, timeout_ms: u32) -> Result<Watchdog<'_>, PMError> {
+        let ticks = timeout_ms as u64 * TICKS_PER_SECOND / 1000;
+        if ticks == 0 || ticks > PM_WDOG::TIME.mask as u64 {
+            return Err(PMError::InvalidTimeout);
+        }
+        self.arm(ticks as u32);
+        Ok(Watchdog {
+            pm: self,
+            ticks: ticks as u32,
+        })
+    }
+
+    pub fn watchdog_running(&self) -> bool {
+        self.rstc.matches_all(PM_RSTC::WRCFG::FullReset)
+    }
+
+    pub fn boot_reason(&self) -> BootReason {
+        if self.rsts.is_set(PM_RSTS::HADWRF) {
+            BootReason::Watchdog
+        } else if self.rsts.is_set(PM_RSTS::HADPOR) {
+            BootReason::PowerOn
+        } else {
+            BootReason::Other
+        }
+    }
+}
+
+/// armed watchdog, dropping it leaves it running
+pub struct Watchdog<'a> {
+    pm: &'a mut PMRegisters,
+    ticks: u32,
+}
+
+impl Watchdog<'_> {
+    /// start the timeout over
+    pub fn feed(&mut self) {
+        self.pm
+            .wdog
+            .write(PM_WDOG::TIME.val(self.ticks) + PM_WDOG::PASSWD::Password);
+    }
+
+    pub fn remaining_ms(&self) -> u32 {
+        (self.pm.wdog.read(PM_WDOG::TIME) as u64 * 1000 / TICKS_PER_SECOND) as u32
+    }
+
+    pub fn disarm(self) {
+        self.pm
+            .rstc
+            .modify(PM_RSTC::WRCFG::Clear + PM_RSTC::PASSWD::Password);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::RegisterFile;
+
+    const RSTC: usize = 0x1c;
+    const RSTS: usize = 0x20;
+    const WDOG: usize = 0x24;
+
+    #[test]
+    fn reset() {
+        let file = RegisterFile::new(core::mem::size_of::<PMRegisters>());
+        file.poke(RSTC, 0x102);
+        file.poke(RSTS, 0x1000);
+        let pm = unsafe { &mut *file.block::<PMRegisters>(0) };
+
+        assert_eq!(pm.boot_reason(), BootReason::PowerOn);
+        pm.reset(0);
+        assert_eq!(file.peek(RSTS), 0x5a00_1000);
+        assert_eq!(file.peek(WDOG), 0x5a00_000a);
+        assert_eq!(file.peek(RSTC), 0x5a00_0122);
+
+        pm.reset(HALT_PARTITION);
+        assert_eq!(file.peek(RSTS), 0x5a00_1555);
+        file.poke(RSTS, 0x1020);
+        assert_eq!(pm.boot_reason(), BootReason::Watchdog);
+        file.poke(RSTS, 0);
+        assert_eq!(pm.boot_reason(), BootReason::Other);
+    }
+
+    #[test]
+    fn watchdog() {
+        let file = RegisterFile::new(core::mem::size_of::<PMRegisters>());
+        let pm = unsafe { &mut *file.block::<PMRegisters>(0) };
+
+        assert_eq!(pm.watchdog(0).err(), Some(PMError::InvalidTimeout));
+        assert_eq!(pm.watchdog(16_000).err(), Some(PMError::InvalidTimeout));
+        let mut watchdog = pm.watchdog(1000).unwrap();
+        assert_eq!(file.peek(WDOG), 0x5a01_0000);
+        assert_eq!(file.peek(RSTC), 0x5a00_0020);
+
+        // counting down, then fed
+        file.poke(WDOG, 0x8000);
+        assert_eq!(watchdog.remaining_ms(), 500);
+        watchdog.feed();
+        assert_eq!(watchdog.remaining_ms(), 1000);
+        watchdog.disarm();
+        assert_eq!(file.peek(RSTC), 0x5a00_0000);
+        assert!(!pm.watchdog_running());
+    }
+}
@@ -0,0 +1,421 @@
+//! BCM2711 PWM0 and PWM1 controllers. Both run from the clock manager's PWM clock,
+//! which has to be started with `clock::ClockRegisters` before a channel is enabled.
+use crate::board;
+use crate::registers::*;
+
+register_bitfields! {u32,
+    /// fields of channel 1, channel 2 repeats them 8 bits higher
+    pub PWM_CTL [
+        PWEN1 OFFSET(0) NUMBITS(1) [],
+        MODE1 OFFSET(1) NUMBITS(1) [
+            PWM = 0,
+            Serializer = 1,
+        ],
+        /// repeat the last FIFO word when it runs empty
+        RPTL1 OFFSET(2) NUMBITS(1) [],
+        /// output level while no data is transmitted
+        SBIT1 OFFSET(3) NUMBITS(1) [],
+        POLA1 OFFSET(4) NUMBITS(1) [],
+        USEF1 OFFSET(5) NUMBITS(1) [],
+        /// shared by both channels, write only
+        CLRF OFFSET(6) NUMBITS(1) [],
+        MSEN1 OFFSET(7) NUMBITS(1) [],
+    ],
+    pub PWM_STA [
+        FULL1 OFFSET(0) NUMBITS(1) [],
+        EMPT1 OFFSET(1) NUMBITS(1) [],
+        /// write 1 to clear
+        WERR1 OFFSET(2) NUMBITS(1) [],
+        /// write 1 to clear
+        RERR1 OFFSET(3) NUMBITS(1) [],
+        GAPO1 OFFSET(4) NUMBITS(1) [],
+        GAPO2 OFFSET(5) NUMBITS(1) [],
+        /// write 1 to clear
+        BERR OFFSET(8) NUMBITS(1) [],
+        STA1 OFFSET(9) NUMBITS(1) [],
+        STA2 OFFSET(10) NUMBITS(1) [],
+    ],
+    pub PWM_DMAC [
+        DREQ OFFSET(0) NUMBITS(8) [],
+        PANIC OFFSET(8) NUMBITS(8) [],
+        ENAB OFFSET(31) NUMBITS(1) [],
+    ],
+}
+
+#[repr(C)]
+pub struct PWMRegisters {
+    ctl: ReadWrite<u32, PWM_CTL::Register>, /* 0x00 CTL PWM Control */
+    sta: ReadWrite<u32, PWM_STA::Register>, /* 0x04 STA PWM Status */
+    dmac: ReadWrite<u32, PWM_DMAC::Register>, /* 0x08 DMAC PWM DMA Configuration */
+    padding0: u32,                          /* 0x0c */
+    rng1: ReadWrite<u32>,                   /* 0x10 RNG1 PWM Channel 1 Range */
+    dat1: ReadWrite<u32>,                   /* 0x14 DAT1 PWM Channel 1 Data */
+    fif1: WriteOnly<u32>,                   /* 0x18 FIF1 PWM FIFO Input */
+    padding1: u32,                          /* 0x1c */
+    rng2: ReadWrite<u32>,                   /* 0x20 RNG2 PWM Channel 2 Range */
+    dat2: ReadWrite<u32>,                   /* 0x24 DAT2 PWM Channel 2 Data */
+}
+
+register_layout! {
+    PWMRegisters @ 0x00 .. 0x28 {
+        0x00 => ctl,
+        0x04 => sta,
+        0x08 => dmac,
+        0x0c => padding0,
+        0x10 => rng1,
+        0x14 => dat1,
+        0x18 => fif1,
+        0x1c => padding1,
+        0x20 => rng2,
+        0x24 => dat2,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PWMInstance {
+    PWM0,
+    PWM1,
+}
+
+impl PWMInstance {
+    pub const fn base(self) -> usize {
+        match self {
+            PWMInstance::PWM0 => board::peripheral(0x20_c000),
+            PWMInstance::PWM1 => board::peripheral(0x20_c800),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PWMChannel {
+    Channel1,
+    Channel2,
+}
+
+impl PWMChannel {
+    /// position of the channel's fields in CTL
+    const fn shift(self) -> usize {
+        match self {
+            PWMChannel::Channel1 => 0,
+            PWMChannel::Channel2 => 8,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PWMMode {
+    /// high for data clocks out of every range clocks
+    MarkSpace,
+    /// data high clocks spread as evenly as possible over the range
+    Balanced,
+    /// data (or FIFO words) shifted out MSB first, range bits per word
+    Serializer,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PWMChannelConfig {
+    pub mode: PWMMode,
+    /// period in PWM clocks, or bits per word in serializer mode
+    pub range: u32,
+    pub invert_polarity: bool,
+    /// take data from the FIFO instead of the data register
+    pub use_fifo: bool,
+    /// repeat the last FIFO word instead of going silent
+    pub repeat_last: bool,
+    /// output high between transmissions
+    pub silence_high: bool,
+}
+
+impl PWMChannelConfig {
+    pub const fn new() -> PWMChannelConfig {
+        PWMChannelConfig {
+            mode: PWMMode::MarkSpace,
+            range: 1024,
+            invert_polarity: false,
+            use_fifo: false,
+            repeat_last: false,
+            silence_high: false,
+        }
+    }
+}
+
+impl Default for PWMChannelConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PWMError {
+    /// FIFO written while full
+    FifoWrite,
+    /// FIFO read while empty
+    FifoRead,
+    /// bus error while writing registers
+    Bus,
+}
+
+impl PWMRegisters {
+    pub const FIFO_DEPTH: usize = 16;
+
+    pub const fn new(instance: PWMInstance) -> *mut PWMRegisters {
+        Self::at(instance.base())
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut PWMRegisters {
+        base as *mut PWMRegisters
+    }
+
+    /// set up a channel and leave it disabled
+    pub fn configure(&mut self, channel: PWMChannel, config: &PWMChannelConfig) {
+        let shift = channel.shift();
+        self.disable(channel);
+        self.set_range(channel, config.range);
+        if config.use_fifo {
+            self.clear_fifo();
+        }
+
+        let settings = PWM_CTL::MODE1
+            .offset(shift)
+            .val((config.mode == PWMMode::Serializer) as u32)
+            + PWM_CTL::MSEN1
+                .offset(shift)
+                .val((config.mode == PWMMode::MarkSpace) as u32)
+            + PWM_CTL::POLA1
+                .offset(shift)
+                .val(config.invert_polarity as u32)
+            + PWM_CTL::USEF1.offset(shift).val(config.use_fifo as u32)
+            + PWM_CTL::RPTL1.offset(shift).val(config.repeat_last as u32)
+            + PWM_CTL::SBIT1.offset(shift).val(config.silence_high as u32);
+        self.ctl.modify(settings);
+    }
+
+    pub fn enable(&mut self, channel: PWMChannel) {
+        self.ctl
+            .modify(PWM_CTL::PWEN1.offset(channel.shift()).val(1));
+    }
+
+    pub fn disable(&mut self, channel: PWMChannel) {
+        self.ctl
+            .modify(PWM_CTL::PWEN1.offset(channel.shift()).val(0));
+    }
+
+    pub fn is_enabled(&self, channel: PWMChannel) -> bool {
+        self.ctl.is_set(PWM_CTL::PWEN1.offset(channel.shift()))
+    }
+
+    pub fn set_range(&mut self, channel: PWMChannel, range: u32) {
+        match channel {
+            PWMChannel::Channel1 => self.rng1.set(range),
+            PWMChannel::Channel2 => self.rng2.set(range),
+        }
+    }
+
+    pub fn range(&self, channel: PWMChannel) -> u32 {
+        match channel {
+            PWMChannel::Channel1 => self.rng1.get(),
+            PWMChannel::Channel2 => self.rng2.get(),
+        }
+    }
+
+    pub fn set_data(&mut self, channel: PWMChannel, data: u32) {
+        match channel {
+            PWMChannel::Channel1 => self.dat1.set(data),
+            PWMChannel::Channel2 => self.dat2.set(data),
+        }
+    }
+
+    /// high time as a percentage of the range, returns the data value written
+    pub fn set_duty_percent(&mut self, channel: PWMChannel, percent: u32) -> u32 {
+        let data = (self.range(channel) as u64 * percent.min(100) as u64 / 100) as u32;
+        self.set_data(channel, data);
+        data
+    }
+
+    /// queue words until the FIFO is full, returns how many were queued
+    pub fn write_fifo(&mut self, words: &[u32]) -> usize {
+        let mut written = 0;
+        for word in words {
+            if self.sta.is_set(PWM_STA::FULL1) {
+                break;
+            }
+            self.fif1.set(*word);
+            written += 1;
+        }
+        written
+    }
+
+    pub fn clear_fifo(&mut self) {
+        self.ctl.modify(PWM_CTL::CLRF::SET);
+    }
+
+    pub fn fifo_empty(&self) -> bool {
+        self.sta.is_set(PWM_STA::EMPT1)
+    }
+
+    /// report and clear the first latched error
+    pub fn take_error(&mut self) -> Result<(), PWMError> {
+        let status = self.sta.get();
+        let error = if PWM_STA::WERR1.is_set(status) {
+            Some((PWMError::FifoWrite, PWM_STA::WERR1::SET))
+        } else if PWM_STA::RERR1.is_set(status) {
+            Some((PWMError::FifoRead, PWM_STA::RERR1::SET))
+        } else if PWM_STA::BERR.is_set(status) {
+            Some((PWMError::Bus, PWM_STA::BERR::SET))
+        } else {
+            None
+        };
+        match error {
+            Some((error, bit)) => {
+                self.sta.write(bit);
+                Err(error)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+pub static mut PWM: PWM = PWM::new();
+pub struct PWM {
+    pwm0: Option<*mut PWMRegisters>,
+    pwm1: Option<*mut PWMRegisters>,
+}
+
+impl PWM {
+    const fn new() -> PWM {
+        PWM {
+            pwm0: Some(PWMRegisters::new(PWMInstance::PWM0)),
+            pwm1: Some(PWMRegisters::new(PWMInstance::PWM1)),
+        }
+    }
+
+    fn slot(&mut self, instance: PWMInstance) -> &mut Option<*mut PWMRegisters> {
+        match instance {
+            PWMInstance::PWM0 => &mut self.pwm0,
+            PWMInstance::PWM1 => &mut self.pwm1,
+        }
+    }
+
+    pub fn take_pwm(&mut self, instance: PWMInstance) -> *mut PWMRegisters {
+        let p = self.slot(instance).take();
+        p.unwrap()
+    }
+
+    pub fn return_pwm(&mut self, instance: PWMInstance, pwm: *mut PWMRegisters) {
+        self.slot(instance).replace(pwm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const CTL: usize = 0x00;
+    const STA: usize = 0x04;
+    const RNG1: usize = 0x10;
+    const DAT1: usize = 0x14;
+    const FIF1: usize = 0x18;
+    const RNG2: usize = 0x20;
+    const DAT2: usize = 0x24;
+
+    fn pwm() -> RegisterFile {
+        let file = RegisterFile::new(0x28);
+        // CLRF reads as zero
+        file.on(
+            CTL,
+            Behavior::Custom(Box::new(|storage, access| {
+                if let Access::Write(value) = access {
+                    if value & (1 << 6) != 0 {
+                        storage.tx(FIF1).clear();
+                    }
+                    storage.set(CTL, value & !(1 << 6));
+                }
+                storage.get(CTL)
+            })),
+        );
+        file.on(FIF1, Behavior::Fifo);
+        file.on(
+            STA,
+            Behavior::Custom(Box::new(|storage, access| {
+                if let Access::Write(value) = access {
+                    let old = storage.get(STA);
+                    storage.set(STA, old & !value);
+                }
+                let level = storage.tx(FIF1).len();
+                let full = (level >= PWMRegisters::FIFO_DEPTH) as u32;
+                storage.get(STA) | full | (((level == 0) as u32) << 1)
+            })),
+        );
+        file
+    }
+
+    #[test]
+    fn configure() {
+        let file = pwm();
+        let pwm = unsafe { &mut *file.block::<PWMRegisters>(0) };
+
+        pwm.configure(PWMChannel::Channel1, &PWMChannelConfig::new());
+        pwm.enable(PWMChannel::Channel1);
+        assert_eq!(file.peek(CTL), (1 << 7) | 1);
+        assert_eq!(file.peek(RNG1), 1024);
+
+        let config = PWMChannelConfig {
+            mode: PWMMode::Balanced,
+            range: 200,
+            invert_polarity: true,
+            silence_high: true,
+            ..PWMChannelConfig::new()
+        };
+        pwm.configure(PWMChannel::Channel2, &config);
+        assert_eq!(file.peek(CTL), (1 << 12) | (1 << 11) | (1 << 7) | 1);
+        assert_eq!(file.peek(RNG2), 200);
+        assert!(!pwm.is_enabled(PWMChannel::Channel2));
+        pwm.enable(PWMChannel::Channel2);
+        assert!(pwm.is_enabled(PWMChannel::Channel2));
+
+        pwm.disable(PWMChannel::Channel1);
+        assert_eq!(file.peek(CTL) & 1, 0);
+    }
+
+    #[test]
+    fn duty_cycle() {
+        let file = pwm();
+        let pwm = unsafe { &mut *file.block::<PWMRegisters>(0) };
+        pwm.set_range(PWMChannel::Channel1, 1000);
+        pwm.set_range(PWMChannel::Channel2, 3);
+
+        assert_eq!(pwm.set_duty_percent(PWMChannel::Channel1, 25), 250);
+        assert_eq!(file.peek(DAT1), 250);
+        assert_eq!(pwm.set_duty_percent(PWMChannel::Channel1, 150), 1000);
+        assert_eq!(pwm.set_duty_percent(PWMChannel::Channel2, 50), 1);
+        assert_eq!(file.peek(DAT2), 1);
+    }
+
+    #[test]
+    fn fifo() {
+        let file = pwm();
+        let pwm = unsafe { &mut *file.block::<PWMRegisters>(0) };
+        let config = PWMChannelConfig {
+            mode: PWMMode::Serializer,
+            range: 32,
+            use_fifo: true,
+            ..PWMChannelConfig::new()
+        };
+        pwm.configure(PWMChannel::Channel1, &config);
+        assert_eq!(file.peek(CTL), (1 << 5) | (1 << 1));
+        assert!(pwm.fifo_empty());
+
+        let words: Vec<u32> = (0..20).collect();
+        assert_eq!(pwm.write_fifo(&words), PWMRegisters::FIFO_DEPTH);
+        assert!(!pwm.fifo_empty());
+        pwm.clear_fifo();
+        assert!(pwm.fifo_empty());
+
+        file.poke(STA, (1 << 2) | (1 << 8));
+        assert_eq!(pwm.take_error(), Err(PWMError::FifoWrite));
+        assert_eq!(pwm.take_error(), Err(PWMError::Bus));
+        assert_eq!(pwm.take_error(), Ok(()));
+    }
+}
@@ -0,0 +1,504 @@
+//! Typed volatile registers and named bitfields.
+//!
+//! Register blocks are `#[repr(C)]` structs made of [`ReadOnly`], [`WriteOnly`] and
+//! [`ReadWrite`] cells. Each cell is tagged with the register it represents, so only
+//! fields declared for that register with `register_bitfields!` can be used on it.
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Add, BitAnd, BitOr, Not, Shl, Shr};
+
+pub trait RegisterValue:
+    Copy
+    + Eq
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + Not<Output = Self>
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+{
+    const ZERO: Self;
+
+    fn into_u64(self) -> u64;
+    fn from_u64(value: u64) -> Self;
+}
+
+/// marker type for a register, generated by `register_bitfields!`
+pub trait RegisterName {}
+
+impl RegisterName for () {}
+
+/// conversion from a raw field value into an enumerated one
+pub trait FieldEnum<T: RegisterValue>: Sized {
+    fn from_field(value: T) -> Option<Self>;
+}
+
+/// `mask` bits wide field starting at bit `shift` of register `R`
+pub struct Field<T: RegisterValue, R: RegisterName> {
+    pub mask: T,
+    pub shift: usize,
+    register: PhantomData<R>,
+}
+
+impl<T: RegisterValue, R: RegisterName> Clone for Field<T, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: RegisterValue, R: RegisterName> Copy for Field<T, R> {}
+
+impl<T: RegisterValue, R: RegisterName> Field<T, R> {
+    pub const fn new(mask: T, shift: usize) -> Self {
+        Field {
+            mask,
+            shift,
+            register: PhantomData,
+        }
+    }
+
+    /// same field moved `bits` higher, for registers repeating a field per pin/channel
+    pub const fn offset(self, bits: usize) -> Self {
+        Field::new(self.mask, self.shift + bits)
+    }
+
+    /// extract field from raw register value
+    pub fn read(self, value: T) -> T {
+        (value >> self.shift) & self.mask
+    }
+
+    pub fn is_set(self, value: T) -> bool {
+        self.read(value) != T::ZERO
+    }
+
+    pub fn read_as_enum<E: FieldEnum<T>>(self, value: T) -> Option<E> {
+        E::from_field(self.read(value))
+    }
+}
+
+/// value of one or more fields of register `R`, already shifted into place
+pub struct FieldValue<T: RegisterValue, R: RegisterName> {
+    pub mask: T,
+    pub value: T,
+    register: PhantomData<R>,
+}
+
+impl<T: RegisterValue, R: RegisterName> Clone for FieldValue<T, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: RegisterValue, R: RegisterName> Copy for FieldValue<T, R> {}
+
+impl<T: RegisterValue, R: RegisterName> FieldValue<T, R> {
+    /// replace bits covered by this value in `original`
+    pub fn modify(self, original: T) -> T {
+        (original & !self.mask) | self.value
+    }
+
+    /// check that all fields in `value` are equal to this one
+    pub fn matches(self, value: T) -> bool {
+        (value & self.mask) == self.value
+    }
+}
+
+impl<T: RegisterValue, R: RegisterName> Add for FieldValue<T, R> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        FieldValue {
+            mask: self.mask | rhs.mask,
+            value: self.value | rhs.value,
+            register: PhantomData,
+        }
+    }
+}
+
+macro_rules! register_value {
+    ($($t: ident),*) => {
+        $(
+            impl RegisterValue for $t {
+                const ZERO: Self = 0;
+
+                fn into_u64(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_u64(value: u64) -> Self {
+                    value as $t
+                }
+            }
+
+            impl<R: RegisterName> Field<$t, R> {
+                pub const fn val(self, value: $t) -> FieldValue<$t, R> {
+                    FieldValue::<$t, R>::new(self.mask, self.shift, value)
+                }
+            }
+
+            impl<R: RegisterName> FieldValue<$t, R> {
+                pub const fn new(mask: $t, shift: usize, value: $t) -> Self {
+                    FieldValue {
+                        mask: mask << shift,
+                        value: (value & mask) << shift,
+                        register: PhantomData,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+register_value!(u8, u16, u32, u64);
+
+/// Every access made by register cells ends up here. Host tests redirect accesses that
+/// fall into a simulated register file, see `crate::mock`.
+pub mod mmio {
+    use super::RegisterValue;
+    use core::ptr::{read_volatile, write_volatile};
+
+    /// # Safety
+    /// `src` must be valid for volatile reads
+    #[inline]
+    pub unsafe fn read<T: RegisterValue>(src: *const T) -> T {
+        #[cfg(test)]
+        let value = match crate::mock::read(src as usize, core::mem::size_of::<T>()) {
+            Some(value) => T::from_u64(value),
+            None => unsafe { read_volatile(src) },
+        };
+        #[cfg(not(test))]
+        let value = unsafe { read_volatile(src) };
+        #[cfg(feature = "trace")]
+        crate::trace::record(
+            src as usize,
+            value.into_u64(),
+            crate::trace::Direction::Read,
+        );
+        value
+    }
+
+    /// # Safety
+    /// `dst` must be valid for volatile writes
+    #[inline]
+    pub unsafe fn write<T: RegisterValue>(dst: *mut T, value: T) {
+        #[cfg(feature = "trace")]
+        crate::trace::record(
+            dst as usize,
+            value.into_u64(),
+            crate::trace::Direction::Write,
+        );
+        #[cfg(test)]
+        if crate::mock::write(dst as usize, core::mem::size_of::<T>(), value.into_u64()) {
+            return;
+        }
+        unsafe { write_volatile(dst, value) }
+    }
+}
+
+macro_rules! register_common {
+    () => {
+        /// field of this register
+        pub fn read(&self, field: Field<T, R>) -> T {
+            field.read(self.get())
+        }
+
+        pub fn read_as_enum<E: FieldEnum<T>>(&self, field: Field<T, R>) -> Option<E> {
+            field.read_as_enum(self.get())
+        }
+
+        pub fn is_set(&self, field: Field<T, R>) -> bool {
+            field.is_set(self.get())
+        }
+
+        pub fn matches_all(&self, value: FieldValue<T, R>) -> bool {
+            value.matches(self.get())
+        }
+    };
+}
+
+#[repr(transparent)]
+pub struct ReadOnly<T: RegisterValue, R: RegisterName = ()> {
+    value: UnsafeCell<T>,
+    register: PhantomData<R>,
+}
+
+impl<T: RegisterValue, R: RegisterName> ReadOnly<T, R> {
+    #[inline]
+    pub fn get(&self) -> T {
+        unsafe { mmio::read(self.value.get()) }
+    }
+
+    register_common!();
+}
+
+#[repr(transparent)]
+pub struct WriteOnly<T: RegisterValue, R: RegisterName = ()> {
+    value: UnsafeCell<T>,
+    register: PhantomData<R>,
+}
+
+impl<T: RegisterValue, R: RegisterName> WriteOnly<T, R> {
+    #[inline]
+    pub fn set(&self, value: T) {
+        unsafe { mmio::write(self.value.get(), value) }
+    }
+
+    /// write fields, every bit not covered by `value` is written as zero
+    pub fn write(&self, value: FieldValue<T, R>) {
+        self.set(value.value);
+    }
+}
+
+#[repr(transparent)]
+pub struct ReadWrite<T: RegisterValue, R: RegisterName = ()> {
+    value: UnsafeCell<T>,
+    register: PhantomData<R>,
+}
+
+impl<T: RegisterValue, R: RegisterName> ReadWrite<T, R> {
+    #[inline]
+    pub fn get(&self) -> T {
+        unsafe { mmio::read(self.value.get()) }
+    }
+
+    #[inline]
+    pub fn set(&self, value: T) {
+        unsafe { mmio::write(self.value.get(), value) }
+    }
+
+    register_common!();
+
+    /// write fields, every bit not covered by `value` is written as zero
+    pub fn write(&self, value: FieldValue<T, R>) {
+        self.set(value.value);
+    }
+
+    /// read register, replace fields covered by `value` and write it back
+    pub fn modify(&self, value: FieldValue<T, R>) {
+        self.set(value.modify(self.get()));
+    }
+}
+
+/// Declares registers and their fields.
+///
+/// ```ignore
+/// register_bitfields! {u32,
+///     pub AUX_MU_LCR [
+///         DATA_SIZE OFFSET(0) NUMBITS(2) [
+///             SevenBit = 0,
+///             EightBit = 3,
+///         ],
+///         BREAK OFFSET(6) NUMBITS(1) [],
+///     ],
+/// }
+/// ```
+///
+/// expands into module `AUX_MU_LCR` with marker type `Register`, `Field` constants
+/// `DATA_SIZE`/`BREAK` and modules with the same names holding `FieldValue` constants
+/// for every enumerated value plus `SET`/`CLEAR`, and a `Value` enum to read them back.
+macro_rules! register_bitfields {
+    ($t: ident, $($(#[$attr: meta])* $vis: vis $reg: ident [ $($fields: tt)* ]),* $(,)?) => {
+        $(
+            #[allow(non_snake_case)]
+            $(#[$attr])*
+            $vis mod $reg {
+                pub struct Register;
+                impl $crate::registers::RegisterName for Register {}
+
+                $crate::registers::register_bitfields!(@fields $t, $($fields)*);
+            }
+        )*
+    };
+
+    (@fields $t: ident $(,)?) => {};
+
+    (@fields $t: ident, $(#[$attr: meta])* $field: ident OFFSET($shift: expr) NUMBITS($bits: expr)
+        [ $($variants: tt)* ] $(, $($rest: tt)*)?) => {
+        #[allow(non_upper_case_globals)]
+        $(#[$attr])*
+        pub const $field: $crate::registers::Field<$t, Register> =
+            $crate::registers::Field::<$t, Register>::new(<$t>::MAX >> (<$t>::BITS - $bits), $shift);
+
+        #[allow(non_snake_case, non_upper_case_globals, unused_imports)]
+        pub mod $field {
+            use super::Register;
+            use $crate::registers::{FieldEnum, FieldValue};
+
+            const MASK: $t = <$t>::MAX >> (<$t>::BITS - $bits);
+            pub const SET: FieldValue<$t, Register> = FieldValue::<$t, Register>::new(MASK, $shift, MASK);
+            pub const CLEAR: FieldValue<$t, Register> = FieldValue::<$t, Register>::new(MASK, $shift, 0);
+
+            $crate::registers::register_bitfields!(@variants $t, $shift, $($variants)*);
+        }
+
+        $crate::registers::register_bitfields!(@fields $t $(, $($rest)*)?);
+    };
+
+    (@variants $t: ident, $shift: expr, ) => {};
+
+    (@variants $t: ident, $shift: expr, $($(#[$attr: meta])* $variant: ident = $value: expr),+ $(,)?) => {
+        $(
+            $(#[$attr])*
+            pub const $variant: FieldValue<$t, Register> = FieldValue::<$t, Register>::new(MASK, $shift, $value);
+        )+
+
+        #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+        #[repr($t)]
+        pub enum Value {
+            $($variant = $value),+
+        }
+
+        impl FieldEnum<$t> for Value {
+            fn from_field(value: $t) -> Option<Self> {
+                match value {
+                    $(x if x == Value::$variant as $t => core::option::Option::Some(Value::$variant),)+
+                    _ => core::option::Option::None,
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use register_bitfields;
+
+/// field of a register block at its datasheet offset
+pub struct RegisterOffset {
+    pub name: &'static str,
+    pub offset: usize,
+}
+
+/// datasheet layout of a register block, generated by `register_layout!`
+pub trait RegisterLayout {
+    const NAME: &'static str;
+    /// datasheet offset of the first field
+    const ORIGIN: usize;
+    const SIZE: usize;
+    const FIELDS: &'static [RegisterOffset];
+
+    /// print `offset name` table of the block, one field per line
+    fn write_layout<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+        writeln!(w, "{} ({:#x} bytes)", Self::NAME, Self::SIZE)?;
+        for field in Self::FIELDS {
+            writeln!(w, "  {:#06x} {}", field.offset, field.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks at compile time that every field of a `#[repr(C)]` register block sits at
+/// its datasheet offset and that the block ends where the datasheet says it does.
+///
+/// ```ignore
+/// register_layout! {
+///     MiniUart @ 0x40 .. 0x48 {
+///         0x40 => io,
+///         0x44 => ier,
+///     }
+/// }
+/// ```
+///
+/// `0x40 .. 0x48` is the datasheet offset range covered by the block.
+macro_rules! register_layout {
+    ($block: ident @ $origin: literal .. $end: literal { $($offset: literal => $field: ident),* $(,)? }) => {
+        const _: () = {
+            $(
+                assert!(
+                    $origin + core::mem::offset_of!($block, $field) == $offset,
+                    concat!(stringify!($block), "::", stringify!($field), " is not at ", stringify!($offset))
+                );
+            )*
+            assert!(
+                $origin + core::mem::size_of::<$block>() == $end,
+                concat!(stringify!($block), " does not end at ", stringify!($end))
+            );
+        };
+
+        impl $crate::registers::RegisterLayout for $block {
+            const NAME: &'static str = stringify!($block);
+            const ORIGIN: usize = $origin;
+            const SIZE: usize = core::mem::size_of::<$block>();
+            const FIELDS: &'static [$crate::registers::RegisterOffset] = &[
+                $($crate::registers::RegisterOffset {
+                    name: stringify!($field),
+                    offset: $offset,
+                },)*
+            ];
+        }
+    };
+}
+
+pub(crate) use register_layout;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aux::AUXRegisters;
+    use crate::aux::peripherals::{AuxSpi, MiniUart};
+    use crate::clock::ClockRegisters;
+    use crate::dma::{DMA4ChannelRegisters, DMAChannelRegisters, DMAGlobalRegisters};
+    use crate::emmc::EMMCRegisters;
+    use crate::gic::{GICCPUInterface, GICDistributor};
+    use crate::gpio::GPIORegisters;
+    use crate::i2c::I2CRegisters;
+    use crate::mailbox::MailboxRegisters;
+    use crate::pl011::PL011;
+    use crate::pm::PMRegisters;
+    use crate::pwm::PWMRegisters;
+    use crate::rng::RNG200;
+    use crate::spi::SPIRegisters;
+
+    fn check<B: RegisterLayout>(table: &mut String) {
+        B::write_layout(table).unwrap();
+        let offsets = B::FIELDS.iter().map(|f| f.offset);
+        assert!(offsets.clone().zip(offsets.skip(1)).all(|(a, b)| a < b));
+        assert_eq!(B::FIELDS[0].offset, B::ORIGIN);
+    }
+
+    #[test]
+    fn layout_table() {
+        let mut table = String::new();
+        check::<AUXRegisters>(&mut table);
+        check::<MiniUart>(&mut table);
+        check::<AuxSpi>(&mut table);
+        check::<GPIORegisters>(&mut table);
+        check::<SPIRegisters>(&mut table);
+        check::<DMAChannelRegisters>(&mut table);
+        check::<DMA4ChannelRegisters>(&mut table);
+        check::<DMAGlobalRegisters>(&mut table);
+        check::<I2CRegisters>(&mut table);
+        check::<ClockRegisters>(&mut table);
+        check::<PWMRegisters>(&mut table);
+        check::<EMMCRegisters>(&mut table);
+        check::<PL011>(&mut table);
+        check::<GICDistributor>(&mut table);
+        check::<GICCPUInterface>(&mut table);
+        check::<PMRegisters>(&mut table);
+        check::<RNG200>(&mut table);
+        check::<MailboxRegisters>(&mut table);
+        println!("{table}");
+    }
+
+    register_bitfields! {u32,
+        TEST [
+            LOW OFFSET(0) NUMBITS(4) [],
+            MODE OFFSET(4) NUMBITS(2) [
+                A = 1,
+                B = 2,
+            ],
+            FULL OFFSET(0) NUMBITS(32) [],
+        ],
+    }
+
+    #[test]
+    fn field_values() {
+        assert_eq!(TEST::MODE.mask, 0x3);
+        assert_eq!(TEST::FULL.mask, u32::MAX);
+        assert_eq!(TEST::MODE::B.modify(0xffff_ffff), 0xffff_ffef);
+        assert_eq!((TEST::LOW.val(0x1f) + TEST::MODE::A).value, 0x1f);
+        assert_eq!(
+            TEST::MODE.read_as_enum::<TEST::MODE::Value>(0x20),
+            Some(TEST::MODE::Value::B)
+        );
+        assert_eq!(TEST::MODE.read_as_enum::<TEST::MODE::Value>(0x30), None);
+        assert!(TEST::LOW.offset(4).is_set(0x10));
+    }
+}
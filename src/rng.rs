@@ -0,0 +1,399 @@
+//! BCM2711 RNG200 hardware random number generator.
+//!
+//! [`RNG200`] hands out the raw FIFO words. [`Random`] is what callers want: it checks the
+//! output for a stuck generator and falls back to [`Xoshiro256`] seeded from the system
+//! counter when the hardware is missing or fails, as with emulators lacking the block.
+use crate::registers::*;
+use crate::timer::{self, Timeout};
+
+/// bits the generator throws away after enabling, the first ones are less random
+const WARMUP_BITS: u32 = 0x40000;
+/// the warm-up is over once bits are counted again
+const WARMUP_DONE_BITS: u32 = 16;
+const WARMUP_TIMEOUT_US: u64 = 1_000_000;
+const FIFO_TIMEOUT_US: u64 = 10_000;
+/// RBG clock divider, 1 MHz samples
+const SAMPLE_DIVIDER: u32 = 3;
+/// words read from the FIFO at once
+const BATCH: usize = 16;
+
+register_bitfields! {u32,
+    pub RNG_CTRL [
+        /// all ones to run the bit generators
+        RBGEN OFFSET(0) NUMBITS(13) [
+            Disabled = 0,
+            Enabled = 0x1fff,
+        ],
+        DIV OFFSET(13) NUMBITS(8) [],
+    ],
+    pub RNG_RESET [
+        RESET OFFSET(0) NUMBITS(1) [],
+    ],
+    /// shared by the status and enable registers
+    pub RNG_INT [
+        TOTAL_BITS_COUNT OFFSET(0) NUMBITS(1) [],
+        /// statistical self test failed
+        NIST_FAIL OFFSET(5) NUMBITS(1) [],
+        STARTUP_TRANSITIONS_MET OFFSET(17) NUMBITS(1) [],
+        /// too many failures, the generator stopped
+        MASTER_FAIL_LOCKOUT OFFSET(31) NUMBITS(1) [],
+    ],
+    pub RNG_FIFO_COUNT [
+        /// words waiting in the FIFO
+        COUNT OFFSET(0) NUMBITS(8) [],
+        THRESHOLD OFFSET(8) NUMBITS(8) [],
+    ],
+}
+
+#[repr(C)]
+pub struct RNG200 {
+    ctrl: ReadWrite<u32, RNG_CTRL::Register>, /* 0x00 RNG_CTRL Control */
+    rng_soft_reset: ReadWrite<u32, RNG_RESET::Register>, /* 0x04 RNG_SOFT_RESET RNG Soft Reset */
+    rbg_soft_reset: ReadWrite<u32, RNG_RESET::Register>, /* 0x08 RBG_SOFT_RESET RBG Soft Reset */
+    total_bit_count: ReadOnly<u32>,           /* 0x0c RNG_TOTAL_BIT_COUNT Total Bit Count */
+    total_bit_count_threshold: ReadWrite<u32>, /* 0x10 RNG_TOTAL_BIT_COUNT_THRESHOLD Warm-up Bits */
+    padding0: [u8; 0x4],                      /* 0x14 padding */
+    int_status: ReadWrite<u32, RNG_INT::Register>, /* 0x18 RNG_INT_STATUS Interrupt Status */
+    int_enable: ReadWrite<u32, RNG_INT::Register>, /* 0x1c RNG_INT_ENABLE Interrupt Enable */
+    fifo_data: ReadOnly<u32>,                 /* 0x20 RNG_FIFO_DATA FIFO Data */
+    fifo_count: ReadWrite<u32, RNG_FIFO_COUNT::Register>, /* 0x24 RNG_FIFO_COUNT FIFO Count */
+}
+
+register_layout! {
+    RNG200 @ 0x00 .. 0x28 {
+        0x00 => ctrl,
+        0x04 => rng_soft_reset,
+        0x08 => rbg_soft_reset,
+        0x0c => total_bit_count,
+        0x10 => total_bit_count_threshold,
+        0x14 => padding0,
+        0x18 => int_status,
+        0x1c => int_enable,
+        0x20 => fifo_data,
+        0x24 => fifo_count,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RNGError {
+    /// no bits counted after enabling, the block is missing or not clocked
+    WarmupTimeout,
+    /// the FIFO stayed empty
+    Timeout,
+    /// the self test failed or the generator locked itself out
+    Failed,
+    /// the same word twice in a row
+    Stuck,
+}
+
+impl RNG200 {
+    const BASE: usize = crate::board::peripheral(0x10_4000);
+
+    pub const fn new() -> *mut Self {
+        Self::at(Self::BASE)
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut Self {
+        base as *mut Self
+    }
+
+    /// reset and start the generator, the warm-up runs in the background
+    pub fn init(&mut self) {
+        self.ctrl.write(RNG_CTRL::RBGEN::Disabled);
+        self.rng_soft_reset.write(RNG_RESET::RESET.val(1));
+        self.rbg_soft_reset.write(RNG_RESET::RESET.val(1));
+        self.rng_soft_reset.write(RNG_RESET::RESET.val(0));
+        self.rbg_soft_reset.write(RNG_RESET::RESET.val(0));
+        self.int_enable.set(0);
+        self.int_status.set(u32::MAX);
+        self.total_bit_count_threshold.set(WARMUP_BITS);
+        self.fifo_count.write(RNG_FIFO_COUNT::THRESHOLD.val(2));
+        self.ctrl
+            .write(RNG_CTRL::RBGEN::Enabled + RNG_CTRL::DIV.val(SAMPLE_DIVIDER));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ctrl.matches_all(RNG_CTRL::RBGEN::Enabled)
+    }
+
+    pub fn warmed_up(&self) -> bool {
+        self.total_bit_count.get() > WARMUP_DONE_BITS
+    }
+
+    pub fn failed(&self) -> bool {
+        self.int_status.is_set(RNG_INT::NIST_FAIL)
+            || self.int_status.is_set(RNG_INT::MASTER_FAIL_LOCKOUT)
+    }
+
+    /// words waiting in the FIFO
+    pub fn available(&self) -> usize {
+        self.fifo_count.read(RNG_FIFO_COUNT::COUNT) as usize
+    }
+
+    /// Fill the start of `words` with what the FIFO holds, waiting for the warm-up and at
+    /// least one word. Returns the number of words read.
+    pub fn read_words(&mut self, words: &mut [u32]) -> Result<usize, RNGError> {
+        let timeout = Timeout::after_us(WARMUP_TIMEOUT_US);
+        while !self.warmed_up() {
+            if timeout.expired() {
+                return Err(RNGError::WarmupTimeout);
+            }
+        }
+        let timeout = Timeout::after_us(FIFO_TIMEOUT_US);
+        let available = loop {
+            if self.failed() {
+                return Err(RNGError::Failed);
+            }
+            match self.available() {
+                0 if timeout.expired() => return Err(RNGError::Timeout),
+                0 => {}
+                available => break available,
+            }
+        };
+        let count = available.min(words.len());
+        for word in &mut words[..count] {
+            *word = self.fifo_data.get();
+        }
+        Ok(count)
+    }
+}
+
+/// xoshiro256** by Blackman and Vigna, not for secrets
+#[derive(Clone, Debug)]
+pub struct Xoshiro256 {
+    state: [u64; 4],
+}
+
+impl Xoshiro256 {
+    /// state expanded from `seed` with SplitMix64, any seed works
+    pub fn new(mut seed: u64) -> Xoshiro256 {
+        let mut state = [0; 4];
+        for word in &mut state {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            *word = z ^ (z >> 31);
+        }
+        Xoshiro256 { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+/// where [`Random`] takes its numbers from
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Source {
+    Hardware,
+    /// the hardware is missing or failed with this error
+    Software(RNGError),
+}
+
+/// random numbers from the RNG200, from the software generator once it fails
+pub struct Random<'a> {
+    rng: Option<&'a mut RNG200>,
+    /// previous hardware word for the stuck output check
+    last: Option<u32>,
+    prng: Xoshiro256,
+    source: Source,
+}
+
+impl<'a> Random<'a> {
+    /// start `rng` if nobody did yet, the first read checks that it works
+    pub fn new(rng: &'a mut RNG200) -> Random<'a> {
+        if !rng.is_enabled() {
+            rng.init();
+        }
+        let mut random = Random {
+            rng: Some(rng),
+            last: None,
+            prng: Xoshiro256::new(timer::ticks()),
+            source: Source::Hardware,
+        };
+        let mut words = [0; 2];
+        random.fill_words(&mut words);
+        random
+    }
+
+    /// software generator only
+    pub fn software(seed: u64) -> Random<'static> {
+        Random {
+            rng: None,
+            last: None,
+            prng: Xoshiro256::new(seed),
+            source: Source::Software(RNGError::Timeout),
+        }
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut word = [0];
+        self.fill_words(&mut word);
+        word[0]
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut words = [0; 2];
+        self.fill_words(&mut words);
+        (words[1] as u64) << 32 | words[0] as u64
+    }
+
+    pub fn fill_bytes(&mut self, bytes: &mut [u8]) {
+        let mut words = [0u32; BATCH];
+        for chunk in bytes.chunks_mut(BATCH * 4) {
+            let words = &mut words[..chunk.len().div_ceil(4)];
+            self.fill_words(words);
+            for (bytes, word) in chunk.chunks_mut(4).zip(words.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+    }
+
+    fn fill_words(&mut self, words: &mut [u32]) {
+        if let Err(error) = self.fill_hardware(words) {
+            // whatever the hardware gave last still adds to the counter seed
+            let seed = timer::ticks() ^ ((self.last.unwrap_or(0) as u64) << 32);
+            self.prng = Xoshiro256::new(seed);
+            self.rng = None;
+            self.source = Source::Software(error);
+        }
+        if self.rng.is_none() {
+            for word in words {
+                *word = (self.prng.next_u64() >> 32) as u32;
+            }
+        }
+    }
+
+    fn fill_hardware(&mut self, words: &mut [u32]) -> Result<(), RNGError> {
+        let Some(rng) = self.rng.as_mut() else {
+            return Ok(());
+        };
+        let mut filled = 0;
+        while filled < words.len() {
+            filled += rng.read_words(&mut words[filled..])?;
+        }
+        for word in words.iter() {
+            if self.last == Some(*word) {
+                return Err(RNGError::Stuck);
+            }
+            self.last = Some(*word);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, Behavior, RegisterFile};
+
+    const CTRL: usize = 0x00;
+    const TOTAL_BIT_COUNT: usize = 0x0c;
+    const TOTAL_BIT_COUNT_THRESHOLD: usize = 0x10;
+    const INT_STATUS: usize = 0x18;
+    const FIFO_DATA: usize = 0x20;
+    const FIFO_COUNT: usize = 0x24;
+
+    /// FIFO count backed by the data queue, status bits cleared by writing them
+    fn rng() -> RegisterFile {
+        let file = RegisterFile::new(core::mem::size_of::<RNG200>());
+        file.on(INT_STATUS, Behavior::WriteOneToClear)
+            .on(FIFO_DATA, Behavior::Fifo)
+            .on(
+                FIFO_COUNT,
+                Behavior::Custom(Box::new(|storage, access| match access {
+                    Access::Read => storage.rx(FIFO_DATA).len() as u32,
+                    Access::Write(_) => 0,
+                })),
+            );
+        file
+    }
+
+    #[test]
+    fn hardware() {
+        let file = rng();
+        let rng = unsafe { &mut *file.block::<RNG200>(0) };
+        rng.init();
+        assert_eq!(file.peek(CTRL), 0x7fff);
+        assert_eq!(file.peek(TOTAL_BIT_COUNT_THRESHOLD), WARMUP_BITS);
+
+        // still warming up
+        let mut words = [0; 4];
+        assert_eq!(rng.read_words(&mut words), Err(RNGError::WarmupTimeout));
+        file.poke(TOTAL_BIT_COUNT, 0x100);
+        assert_eq!(rng.read_words(&mut words), Err(RNGError::Timeout));
+
+        // only what the FIFO holds
+        file.push_rx(FIFO_DATA, &[1, 2]);
+        assert_eq!(rng.read_words(&mut words), Ok(2));
+        assert_eq!(words[..2], [1, 2]);
+
+        file.push_rx(FIFO_DATA, &[3]);
+        file.poke(INT_STATUS, 1 << 31);
+        assert_eq!(rng.read_words(&mut words), Err(RNGError::Failed));
+    }
+
+    #[test]
+    fn random() {
+        let file = rng();
+        file.poke(TOTAL_BIT_COUNT, 0x100);
+        file.push_rx(
+            FIFO_DATA,
+            &[0x0403_0201, 0x0807_0605, 0x0c0b_0a09, 0x100f_0e0d],
+        );
+        let rng = unsafe { &mut *file.block::<RNG200>(0) };
+        let mut random = Random::new(rng);
+        assert_eq!(random.source(), Source::Hardware);
+
+        let mut bytes = [0; 7];
+        random.fill_bytes(&mut bytes);
+        assert_eq!(bytes, [9, 10, 11, 12, 13, 14, 15]);
+
+        // a repeated word switches to the software generator for good
+        file.push_rx(FIFO_DATA, &[0x55, 0x55]);
+        random.next_u64();
+        assert_eq!(random.source(), Source::Software(RNGError::Stuck));
+        file.push_rx(FIFO_DATA, &[0x66]);
+        assert_ne!(random.next_u32(), 0x66);
+    }
+
+    #[test]
+    fn missing_hardware() {
+        let file = RegisterFile::new(core::mem::size_of::<RNG200>());
+        let rng = unsafe { &mut *file.block::<RNG200>(0) };
+        let mut random = Random::new(rng);
+        assert_eq!(random.source(), Source::Software(RNGError::WarmupTimeout));
+        let (a, b) = (random.next_u64(), random.next_u64());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn xoshiro() {
+        let mut a = Xoshiro256::new(42);
+        let mut b = Xoshiro256::new(42);
+        let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first[0], first[1]);
+        assert_ne!(Xoshiro256::new(43).next_u64(), first[0]);
+
+        let mut software = Random::software(42);
+        let mut bytes = [0u8; 64];
+        software.fill_bytes(&mut bytes);
+        assert!(bytes.iter().any(|byte| *byte != 0));
+    }
+}
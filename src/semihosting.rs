@@ -0,0 +1,28 @@
+//! ARM semihosting calls, only serviced when running under an emulator or debugger
+//! with semihosting enabled (`qemu-system-aarch64 -semihosting`).
+use core::arch::asm;
+
+const SYS_EXIT: u64 = 0x18;
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// # Safety
+/// `parameter` must be what `operation` expects
+unsafe fn call(operation: u64, parameter: u64) -> u64 {
+    let result;
+    unsafe {
+        asm!("hlt #0xf000", inout("x0") operation => result, in("x1") parameter, options(nostack));
+    }
+    result
+}
+
+/// terminate the emulator, its exit status becomes `code`
+pub fn exit(code: u32) -> ! {
+    let block: [u64; 2] = [ADP_STOPPED_APPLICATION_EXIT, code as u64];
+    unsafe {
+        call(SYS_EXIT, block.as_ptr() as u64);
+    }
+    // semihosting is disabled, nobody to report to
+    loop {
+        unsafe { asm!("wfe", options(nomem, nostack)) };
+    }
+}
@@ -0,0 +1,55 @@
+use crate::aux::peripherals::MiniUart;
+use core::fmt;
+
+/// byte oriented serial port
+pub trait Serial {
+    fn write_byte(&mut self, byte: u8);
+
+    /// received byte, `None` when nothing is pending
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// wait until everything written has been sent
+    fn flush(&mut self);
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_byte(*byte);
+        }
+    }
+
+    fn read_byte_blocking(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.read_byte() {
+                return byte;
+            }
+        }
+    }
+}
+
+impl Serial for MiniUart {
+    fn write_byte(&mut self, byte: u8) {
+        while !self.transmitter_space_avaliable() {}
+        self.transmit(byte as u32);
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        match self.receiver_symbol_avaliable() {
+            true => Some(self.receive() as u8),
+            false => None,
+        }
+    }
+
+    fn flush(&mut self) {
+        while !self.tranmitter_idle() {}
+    }
+}
+
+/// `core::fmt::Write` adapter, `writeln!(SerialWriter(uart), ...)`
+pub struct SerialWriter<'a, S: Serial + ?Sized>(pub &'a mut S);
+
+impl<S: Serial + ?Sized> fmt::Write for SerialWriter<'_, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,821 @@
+//! Interactive shell on a serial port.
+//!
+//! Lines are edited with backspace, Ctrl-U and Ctrl-C, the up and down arrows walk the
+//! history and tab completes command names and subcommands. Commands come from tables of
+//! [`Command`], [`Shell::new`] registers the built-ins and [`Shell::register`] adds more.
+use crate::aux::peripherals::{BaudRate, MiniUart};
+use crate::gpio::*;
+use crate::platform::{self, Feature};
+use crate::pm::PMRegisters;
+use crate::serial::Serial;
+use crate::timer;
+use core::fmt::{self, Write};
+
+const LINE: usize = 128;
+const HISTORY: usize = 8;
+const MAX_TABLES: usize = 8;
+const MAX_ARGS: usize = 8;
+const PROMPT: &str = "> ";
+const CORES: usize = 4;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum CommandError {
+    /// wrong arguments, the usage line is printed
+    Usage,
+    Invalid(&'static str),
+}
+
+pub struct Command {
+    pub name: &'static str,
+    /// arguments, shown by `help` and after usage errors
+    pub usage: &'static str,
+    pub help: &'static str,
+    /// completions of the second word
+    pub subcommands: &'static [&'static str],
+    pub run: fn(&mut Shell, &[&str]) -> Result<(), CommandError>,
+}
+
+struct History {
+    lines: [[u8; LINE]; HISTORY],
+    lens: [usize; HISTORY],
+    /// lines added so far, the newest is at `(count - 1) % HISTORY`
+    count: usize,
+}
+
+impl History {
+    const fn new() -> History {
+        History {
+            lines: [[0; LINE]; HISTORY],
+            lens: [0; HISTORY],
+            count: 0,
+        }
+    }
+
+    /// empty lines and repetitions of the newest line are not recorded
+    fn push(&mut self, line: &[u8]) {
+        if line.is_empty() || self.get(0) == Some(line) {
+            return;
+        }
+        let slot = self.count % HISTORY;
+        self.lines[slot][..line.len()].copy_from_slice(line);
+        self.lens[slot] = line.len();
+        self.count += 1;
+    }
+
+    /// line entered `age` lines ago, 0 is the newest
+    fn get(&self, age: usize) -> Option<&[u8]> {
+        if age >= self.count.min(HISTORY) {
+            return None;
+        }
+        let slot = (self.count - 1 - age) % HISTORY;
+        Some(&self.lines[slot][..self.lens[slot]])
+    }
+}
+
+/// progress through a terminal escape sequence
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+pub struct Shell<'a> {
+    serial: &'a mut dyn Serial,
+    tables: [&'static [Command]; MAX_TABLES],
+    table_count: usize,
+    line: [u8; LINE],
+    len: usize,
+    history: History,
+    /// age of the history line being edited
+    browsing: Option<usize>,
+    escape: Escape,
+    /// the last line ended with CR, a following LF belongs to it
+    after_cr: bool,
+}
+
+impl<'a> Shell<'a> {
+    pub fn new(serial: &'a mut dyn Serial) -> Shell<'a> {
+        Shell {
+            serial,
+            tables: [BUILTINS; MAX_TABLES],
+            table_count: 1,
+            line: [0; LINE],
+            len: 0,
+            history: History::new(),
+            browsing: None,
+            escape: Escape::None,
+            after_cr: false,
+        }
+    }
+
+    /// add commands, earlier tables win on name clashes
+    pub fn register(&mut self, commands: &'static [Command]) {
+        assert!(self.table_count < MAX_TABLES, "too many command tables");
+        self.tables[self.table_count] = commands;
+        self.table_count += 1;
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &'static Command> + Clone + use<> {
+        let tables = self.tables;
+        let count = self.table_count;
+        (0..count).flat_map(move |i| tables[i].iter())
+    }
+
+    pub fn find(&self, name: &str) -> Option<&'static Command> {
+        self.commands().find(|command| command.name == name)
+    }
+
+    pub fn serial(&mut self) -> &mut dyn Serial {
+        self.serial
+    }
+
+    /// read and execute lines forever
+    pub fn run(&mut self) -> ! {
+        self.prompt();
+        loop {
+            let byte = self.serial.read_byte_blocking();
+            self.feed(byte);
+        }
+    }
+
+    pub fn prompt(&mut self) {
+        self.serial.write_bytes(PROMPT.as_bytes());
+    }
+
+    /// handle one received byte, completed lines are executed
+    pub fn feed(&mut self, byte: u8) {
+        match self.escape {
+            Escape::Esc => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi,
+                    _ => Escape::None,
+                };
+                return;
+            }
+            // parameters of keys like delete are skipped up to the final byte
+            Escape::Csi if byte.is_ascii_digit() || byte == b';' => return,
+            Escape::Csi => {
+                self.escape = Escape::None;
+                match byte {
+                    b'A' => self.browse(self.browsing.map_or(Some(0), |age| Some(age + 1))),
+                    b'B' => self.browse(self.browsing.and_then(|age| age.checked_sub(1))),
+                    _ => {}
+                }
+                return;
+            }
+            Escape::None => {}
+        }
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match byte {
+            b'\r' => {
+                self.after_cr = true;
+                self.enter();
+            }
+            b'\n' if after_cr => {}
+            b'\n' => self.enter(),
+            0x1b => self.escape = Escape::Esc,
+            // backspace and delete
+            0x08 | 0x7f if self.len > 0 => {
+                self.len -= 1;
+                self.serial.write_bytes(b"\x08 \x08");
+            }
+            // Ctrl-C
+            0x03 => {
+                self.serial.write_bytes(b"^C\r\n");
+                self.len = 0;
+                self.browsing = None;
+                self.prompt();
+            }
+            // Ctrl-U
+            0x15 => {
+                self.len = 0;
+                self.redraw();
+            }
+            b'\t' => self.complete(),
+            0x20..0x7f => self.insert(&[byte]),
+            _ => {}
+        }
+    }
+
+    fn insert(&mut self, text: &[u8]) {
+        if self.len + text.len() > LINE {
+            self.serial.write_byte(0x07);
+            return;
+        }
+        self.line[self.len..self.len + text.len()].copy_from_slice(text);
+        self.len += text.len();
+        self.serial.write_bytes(text);
+    }
+
+    fn redraw(&mut self) {
+        self.serial.write_bytes(b"\r\x1b[K");
+        self.prompt();
+        self.serial.write_bytes(&self.line[..self.len]);
+    }
+
+    /// show the history line of `age`, `None` goes back to an empty line
+    fn browse(&mut self, age: Option<usize>) {
+        match age {
+            Some(age) => {
+                let Some(line) = self.history.get(age) else {
+                    return;
+                };
+                self.line[..line.len()].copy_from_slice(line);
+                self.len = line.len();
+            }
+            None => self.len = 0,
+        }
+        self.browsing = age;
+        self.redraw();
+    }
+
+    fn enter(&mut self) {
+        self.serial.write_bytes(b"\r\n");
+        let line = self.line;
+        let len = core::mem::take(&mut self.len);
+        self.browsing = None;
+        self.history.push(&line[..len]);
+        // only printable ASCII gets into the line
+        self.execute(core::str::from_utf8(&line[..len]).unwrap());
+        self.prompt();
+    }
+
+    pub fn execute(&mut self, line: &str) {
+        let mut args = [""; MAX_ARGS];
+        let mut count = 0;
+        for word in line.split_ascii_whitespace() {
+            if count == MAX_ARGS {
+                let _ = writeln!(self, "too many arguments");
+                return;
+            }
+            args[count] = word;
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+        let Some(command) = self.find(args[0]) else {
+            let _ = writeln!(self, "{}: command not found", args[0]);
+            return;
+        };
+        let _ = match (command.run)(self, &args[1..count]) {
+            Ok(()) => Ok(()),
+            Err(CommandError::Usage) => writeln!(self, "usage: {} {}", command.name, command.usage),
+            Err(CommandError::Invalid(message)) => writeln!(self, "{}: {message}", command.name),
+        };
+    }
+
+    /// complete the last word, a command name or a subcommand
+    fn complete(&mut self) {
+        let line = self.line;
+        let line = core::str::from_utf8(&line[..self.len]).unwrap();
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let prefix = &line[start..];
+        let mut words = line[..start].split_ascii_whitespace();
+        let command = match (words.next(), words.next()) {
+            (None, _) => None,
+            (Some(name), None) => Some(name),
+            _ => return self.serial.write_byte(0x07),
+        };
+        let commands = self.commands();
+        let candidates = || {
+            commands
+                .clone()
+                .flat_map(move |c| match command {
+                    None => core::slice::from_ref(&c.name),
+                    Some(name) if c.name == name => c.subcommands,
+                    Some(_) => &[],
+                })
+                .copied()
+                .filter(move |candidate| candidate.starts_with(prefix))
+        };
+
+        let Some(first) = candidates().next() else {
+            return self.serial.write_byte(0x07);
+        };
+        let common = candidates().fold(first.len(), |len, candidate| {
+            first[..len]
+                .bytes()
+                .zip(candidate.bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        if candidates().count() == 1 {
+            self.insert(&first.as_bytes()[prefix.len()..]);
+            self.insert(b" ");
+        } else if common > prefix.len() {
+            self.insert(&first.as_bytes()[prefix.len()..common]);
+        } else {
+            self.serial.write_bytes(b"\r\n");
+            for candidate in candidates() {
+                self.serial.write_bytes(candidate.as_bytes());
+                self.serial.write_bytes(b"  ");
+            }
+            self.serial.write_bytes(b"\r\n");
+            self.redraw();
+        }
+    }
+}
+
+/// terminals want CR LF
+impl Write for Shell<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.serial.write_bytes(b"\r\n");
+            }
+            self.serial.write_bytes(part.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// decimal, or hexadecimal with 0x
+pub fn parse_number(arg: &str) -> Result<usize, CommandError> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| CommandError::Invalid("invalid number"))
+}
+
+fn parse_u32(arg: &str) -> Result<u32, CommandError> {
+    u32::try_from(parse_number(arg)?).map_err(|_| CommandError::Invalid("number too large"))
+}
+
+pub const BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "[COMMAND]",
+        help: "list commands, or describe one",
+        subcommands: &[],
+        run: help,
+    },
+    Command {
+        name: "peek",
+        usage: "ADDRESS [COUNT]",
+        help: "read 32 bit words at a physical address",
+        subcommands: &[],
+        run: peek,
+    },
+    Command {
+        name: "poke",
+        usage: "ADDRESS VALUE",
+        help: "write a 32 bit word to a physical address",
+        subcommands: &[],
+        run: poke,
+    },
+    Command {
+        name: "gpio",
+        usage: "get PIN | set PIN 0|1 | mode PIN [MODE] | pull PIN [none|up|down]",
+        help: "read and drive pins, modes are in, out and alt0-alt5",
+        subcommands: &["get", "set", "mode", "pull"],
+        run: gpio,
+    },
+    Command {
+        name: "uart",
+        usage: "baud [RATE]",
+        help: "show or change the mini UART baud rate",
+        subcommands: &["baud"],
+        run: uart,
+    },
+    Command {
+        name: "meminfo",
+        usage: "",
+        help: "memory layout and stack use",
+        subcommands: &[],
+        run: meminfo,
+    },
+    Command {
+        name: "uptime",
+        usage: "",
+        help: "time since the counter was reset",
+        subcommands: &[],
+        run: uptime,
+    },
+    Command {
+        name: "reboot",
+        usage: "",
+        help: "reset the board",
+        subcommands: &[],
+        run: reboot,
+    },
+    Command {
+        name: "poweroff",
+        usage: "",
+        help: "halt the board until power is cycled",
+        subcommands: &[],
+        run: poweroff,
+    },
+    Command {
+        name: "cores",
+        usage: "",
+        help: "state of the CPU cores",
+        subcommands: &[],
+        run: cores,
+    },
+];
+
+fn help(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let _ = match args {
+        [] => shell
+            .commands()
+            .try_for_each(|command| writeln!(shell, "{:<10}{}", command.name, command.help)),
+        [name] => {
+            let command = shell
+                .find(name)
+                .ok_or(CommandError::Invalid("no such command"))?;
+            writeln!(
+                shell,
+                "{} {}\n  {}",
+                command.name, command.usage, command.help
+            )
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    Ok(())
+}
+
+fn word_address(arg: &str) -> Result<usize, CommandError> {
+    let address = parse_number(arg)?;
+    match address.is_multiple_of(4) {
+        true => Ok(address),
+        false => Err(CommandError::Invalid("address is not 4 byte aligned")),
+    }
+}
+
+/// whatever is at the address is read, unmapped peripherals may hang the core
+fn peek(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let (address, count) = match args {
+        [address] => (word_address(address)?, 1),
+        [address, count] => (word_address(address)?, parse_number(count)?),
+        _ => return Err(CommandError::Usage),
+    };
+    for i in 0..count {
+        let address = address + i * 4;
+        if i % 4 == 0 {
+            let _ = write!(shell, "{}{address:#010x}:", if i > 0 { "\n" } else { "" });
+        }
+        let value = unsafe { core::ptr::read_volatile(address as *const u32) };
+        let _ = write!(shell, " {value:08x}");
+    }
+    let _ = writeln!(shell);
+    Ok(())
+}
+
+fn poke(_shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let [address, value] = args else {
+        return Err(CommandError::Usage);
+    };
+    let (address, value) = (word_address(address)?, parse_u32(value)?);
+    unsafe { core::ptr::write_volatile(address as *mut u32, value) };
+    Ok(())
+}
+
+const FUNCTIONS: [(&str, GPIOFunction); 8] = [
+    ("in", GPIOFunction::INPUT),
+    ("out", GPIOFunction::OUTPUT),
+    ("alt0", GPIOFunction::ALT0),
+    ("alt1", GPIOFunction::ALT1),
+    ("alt2", GPIOFunction::ALT2),
+    ("alt3", GPIOFunction::ALT3),
+    ("alt4", GPIOFunction::ALT4),
+    ("alt5", GPIOFunction::ALT5),
+];
+
+const PULLS: [(&str, GPIOPull); 3] = [
+    ("none", GPIOPull::None),
+    ("up", GPIOPull::Up),
+    ("down", GPIOPull::Down),
+];
+
+fn gpio(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let [subcommand, pin, rest @ ..] = args else {
+        return Err(CommandError::Usage);
+    };
+    let pin =
+        GPIOPin::try_from(parse_u32(pin)?).map_err(|_| CommandError::Invalid("no such pin"))?;
+    let gpio_ = &raw mut GPIO;
+    let gpio = unsafe { &mut *(*gpio_).take_gpio() };
+    let result = gpio_command(shell, gpio, subcommand, pin, rest);
+    unsafe { (*gpio_).return_gpio(gpio) };
+    result
+}
+
+fn gpio_command(
+    shell: &mut Shell,
+    gpio: &mut GPIORegisters,
+    subcommand: &str,
+    pin: GPIOPin,
+    args: &[&str],
+) -> Result<(), CommandError> {
+    let _ = match (subcommand, args) {
+        ("get", []) => match gpio.pin_level(pin) {
+            GPIOPinLevel::High => writeln!(shell, "1"),
+            GPIOPinLevel::Low => writeln!(shell, "0"),
+        },
+        ("set", ["1" | "high"]) => {
+            gpio.pin_set(pin);
+            Ok(())
+        }
+        ("set", ["0" | "low"]) => {
+            gpio.pin_clear(pin);
+            Ok(())
+        }
+        ("mode", []) => {
+            let function = gpio.pin_function_get(pin);
+            let (name, _) = FUNCTIONS.iter().find(|(_, f)| *f == function).unwrap();
+            writeln!(shell, "{name}")
+        }
+        ("mode", [mode]) => {
+            let (_, function) = FUNCTIONS
+                .iter()
+                .find(|(name, _)| name == mode)
+                .ok_or(CommandError::Invalid("modes are in, out and alt0-alt5"))?;
+            gpio.pin_function_set(pin, *function);
+            Ok(())
+        }
+        ("pull", []) => match gpio.pin_pull_get(pin) {
+            Some(pull) => {
+                let (name, _) = PULLS.iter().find(|(_, p)| *p == pull).unwrap();
+                writeln!(shell, "{name}")
+            }
+            None => writeln!(shell, "unknown, the pull cannot be read back on this board"),
+        },
+        ("pull", [pull]) => {
+            let (_, pull) = PULLS
+                .iter()
+                .find(|(name, _)| name == pull)
+                .ok_or(CommandError::Invalid("pulls are none, up and down"))?;
+            gpio.pin_pull_set(pin, *pull);
+            Ok(())
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    Ok(())
+}
+
+/// the shell may be running on the mini UART, so its registers are used directly
+fn uart(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let uart = unsafe { &mut *MiniUart::new() };
+    let _ = match args {
+        ["baud"] => writeln!(shell, "{}", uart.get_baudrate()),
+        ["baud", rate] => {
+            if !platform::available(Feature::MiniUartBaud) {
+                return Err(CommandError::Invalid("the emulator has a fixed baud rate"));
+            }
+            let rate = BaudRate::try_from(parse_u32(rate)?)
+                .map_err(|_| CommandError::Invalid("unsupported baud rate"))?;
+            let _ = writeln!(shell, "switching to {} baud", rate as u32);
+            shell.serial.flush();
+            uart.set_baudrate(rate);
+            Ok(())
+        }
+        _ => return Err(CommandError::Usage),
+    };
+    Ok(())
+}
+
+#[cfg(target_arch = "aarch64")]
+fn meminfo(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    // see script.ld
+    unsafe extern "C" {
+        static __ram_start: u8;
+        static __ram_end: u8;
+        static _STACK_START: u8;
+        static _STACK_SIZE: u8;
+    }
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let ram_start = &raw const __ram_start as usize;
+    let ram_end = &raw const __ram_end as usize;
+    let stack_top = &raw const _STACK_START as usize;
+    let stack_size = &raw const _STACK_SIZE as usize;
+    let sp: usize;
+    unsafe { core::arch::asm!("mov {}, sp", out(reg) sp, options(nomem, nostack)) };
+    let _ = writeln!(
+        shell,
+        "ram    {ram_start:#010x}-{ram_end:#010x} {} MiB",
+        (ram_end - ram_start) >> 20
+    );
+    let _ = writeln!(
+        shell,
+        "stack  {:#010x}-{stack_top:#010x} {} KiB, {} bytes in use",
+        stack_top - stack_size,
+        stack_size >> 10,
+        stack_top - sp
+    );
+    Ok(())
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn meminfo(_shell: &mut Shell, _args: &[&str]) -> Result<(), CommandError> {
+    Err(CommandError::Invalid("no memory map on this target"))
+}
+
+fn uptime(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let us = timer::uptime_us();
+    let seconds = us / 1_000_000;
+    let _ = writeln!(
+        shell,
+        "up {}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        us / 1000 % 1000
+    );
+    Ok(())
+}
+
+fn reboot(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let _ = writeln!(shell, "rebooting");
+    shell.serial.flush();
+    unsafe { (*PMRegisters::new()).reboot() }
+}
+
+fn poweroff(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let _ = writeln!(shell, "powering off");
+    shell.serial.flush();
+    unsafe { (*PMRegisters::new()).power_off() }
+}
+
+/// (core number, exception level, MIDR_EL1) of the running core
+fn cpu() -> (usize, u64, u64) {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let (mpidr, el, midr): (u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "mrs {}, mpidr_el1",
+                "mrs {}, CurrentEL",
+                "mrs {}, midr_el1",
+                out(reg) mpidr,
+                out(reg) el,
+                out(reg) midr,
+                options(nomem, nostack)
+            )
+        };
+        ((mpidr & 0xff) as usize, (el >> 2) & 0b11, midr)
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        (0, 1, 0x410f_d083)
+    }
+}
+
+/// secondary cores are parked by init.S
+fn cores(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+    let (current, el, midr) = cpu();
+    let part = match (midr >> 4) & 0xfff {
+        0xd03 => "Cortex-A53",
+        0xd08 => "Cortex-A72",
+        _ => "unknown core",
+    };
+    for core in 0..CORES {
+        let _ = match core == current {
+            true => writeln!(
+                shell,
+                "core {core}: running, EL{el}, {part} r{}p{}",
+                (midr >> 20) & 0xf,
+                midr & 0xf
+            ),
+            false => writeln!(shell, "core {core}: parked"),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockSerial, RegisterFile};
+
+    fn run(input: &[u8], setup: impl FnOnce(&mut Shell)) -> String {
+        let mut serial = MockSerial::new(&[]);
+        let mut shell = Shell::new(&mut serial);
+        setup(&mut shell);
+        for byte in input {
+            shell.feed(*byte);
+        }
+        String::from_utf8(serial.tx).unwrap()
+    }
+
+    #[test]
+    fn line_editing() {
+        let out = run(b"hlp\x08\x08elp\r\n", |_| {});
+        assert!(out.starts_with("hlp\x08 \x08\x08 \x08elp\r\n"));
+        assert!(out.contains("peek      read 32 bit words"));
+        assert!(out.ends_with("> "));
+
+        let out = run(b"foo\x15\x03bar baz\r", |_| {});
+        assert!(out.contains("foo\r\x1b[K> ^C\r\n> "));
+        assert!(out.ends_with("bar: command not found\r\n> "));
+
+        let out = run(b"peek\rpoke 1 2\r", |_| {});
+        assert!(out.contains("usage: peek ADDRESS [COUNT]\r\n"));
+        assert!(out.contains("poke: address is not 4 byte aligned\r\n"));
+    }
+
+    #[test]
+    fn history() {
+        let out = run(b"uptime\rhelp uptime\r\x1b[A\x1b[A\x1b[A\x1b[B\r", |_| {});
+        // host ticks are shared by all tests, only the format is known
+        assert_eq!(out.matches("uptime\r\nup 0:").count(), 1);
+        assert!(out.contains("\r\x1b[K> help uptime\r\x1b[K> uptime\r\x1b[K> help uptime\r\n"));
+        assert_eq!(out.matches("time since the counter").count(), 2);
+
+        let mut history = History::new();
+        for i in 0..10u8 {
+            history.push(&[b'0' + i]);
+            history.push(&[b'0' + i]);
+        }
+        history.push(b"");
+        assert_eq!(history.get(0), Some(&b"9"[..]));
+        assert_eq!(history.get(7), Some(&b"2"[..]));
+        assert_eq!(history.get(8), None);
+    }
+
+    #[test]
+    fn completion() {
+        assert!(run(b"upt\t", |_| {}).ends_with("uptime "));
+        assert!(run(b"gpio m\t", |_| {}).ends_with("gpio mode "));
+        // ambiguous prefixes list the candidates
+        let out = run(b"p\t", |_| {});
+        assert!(out.ends_with("p\r\npeek  poke  poweroff  \r\n\r\x1b[K> p"));
+        assert!(run(b"x\t", |_| {}).ends_with("x\x07"));
+    }
+
+    #[test]
+    fn register() {
+        static EXTRA: &[Command] = &[Command {
+            name: "hello",
+            usage: "NAME",
+            help: "greet",
+            subcommands: &[],
+            run: |shell, args| match args {
+                [name] => writeln!(shell, "hello {name}").map_err(|_| CommandError::Usage),
+                _ => Err(CommandError::Usage),
+            },
+        }];
+        let out = run(b"hel\tl\tworld\rhello\r", |shell| shell.register(EXTRA));
+        assert!(out.contains("help  hello  \r\n"));
+        assert!(out.contains("hello world\r\n"));
+        assert!(out.contains("usage: hello NAME\r\n"));
+    }
+
+    #[test]
+    fn peek_poke() {
+        let mut words = [0x1234_5678u32, 0, 0, 0, 0xdead_beef];
+        let address = words.as_mut_ptr() as usize;
+        let out = run(format!("poke {address:#x} 0xcafe\r").as_bytes(), |_| {});
+        assert!(out.ends_with(" 0xcafe\r\n> "));
+        assert_eq!(words[0], 0xcafe);
+
+        let out = run(format!("peek {address} 5\r").as_bytes(), |_| {});
+        let expected = format!(
+            "{address:#010x}: 0000cafe 00000000 00000000 00000000\r\n{:#010x}: deadbeef\r\n",
+            address + 16
+        );
+        assert!(out.contains(&expected));
+    }
+
+    #[test]
+    fn gpio_commands() {
+        let file = RegisterFile::new(core::mem::size_of::<GPIORegisters>());
+        let gpio_ = &raw mut GPIO;
+        let previous = unsafe { (*gpio_).take_gpio() };
+        unsafe { (*gpio_).return_gpio(file.block(0)) };
+
+        let out = run(
+            b"gpio mode 21 out\rgpio mode 21\rgpio pull 21 up\rgpio pull 21\rgpio set 21 1\r\
+              gpio get 22\rgpio mode 60\rgpio mode 21 alt9\r",
+            |_| {},
+        );
+        assert!(out.contains("gpio mode 21\r\nout\r\n"));
+        assert!(out.contains("gpio pull 21\r\nup\r\n"));
+        assert!(out.contains("gpio get 22\r\n0\r\n"));
+        assert!(out.contains("gpio: no such pin\r\n"));
+        assert!(out.contains("gpio: modes are in, out and alt0-alt5\r\n"));
+        assert_eq!(file.peek(0x08), 1 << 3);
+        assert_eq!(file.peek(0x1c), 1 << 21);
+        assert_eq!(file.peek(0xe4 + 4), 1 << 10);
+
+        unsafe {
+            (*gpio_).take_gpio();
+            (*gpio_).return_gpio(previous);
+        }
+    }
+}
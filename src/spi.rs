@@ -0,0 +1,536 @@
+//! BCM2711 SPI0 and SPI3-6 masters
+use crate::board::{self, CORE_CLOCK};
+use crate::dma::{
+    ControlBlock, DMA_TI, DMAChannelRegisters, DMAError, DREQ, bus_address, peripheral_bus_address,
+};
+use crate::registers::*;
+
+register_bitfields! {u32,
+    pub SPI_CS [
+        CS OFFSET(0) NUMBITS(2) [
+            CS0 = 0,
+            CS1 = 1,
+            CS2 = 2,
+        ],
+        CPHA OFFSET(2) NUMBITS(1) [],
+        CPOL OFFSET(3) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Tx = 1,
+            Rx = 2,
+            Both = 3,
+        ],
+        CSPOL OFFSET(6) NUMBITS(1) [],
+        /// transfer active
+        TA OFFSET(7) NUMBITS(1) [],
+        DMAEN OFFSET(8) NUMBITS(1) [],
+        INTD OFFSET(9) NUMBITS(1) [],
+        INTR OFFSET(10) NUMBITS(1) [],
+        /// automatically deassert chip select at the end of a DMA transfer
+        ADCS OFFSET(11) NUMBITS(1) [],
+        REN OFFSET(12) NUMBITS(1) [],
+        LEN OFFSET(13) NUMBITS(1) [],
+        DONE OFFSET(16) NUMBITS(1) [],
+        /// RX FIFO contains data
+        RXD OFFSET(17) NUMBITS(1) [],
+        /// TX FIFO can accept data
+        TXD OFFSET(18) NUMBITS(1) [],
+        RXR OFFSET(19) NUMBITS(1) [],
+        RXF OFFSET(20) NUMBITS(1) [],
+        CSPOL0 OFFSET(21) NUMBITS(1) [],
+        CSPOL1 OFFSET(22) NUMBITS(1) [],
+        CSPOL2 OFFSET(23) NUMBITS(1) [],
+        DMA_LEN OFFSET(24) NUMBITS(1) [],
+        LEN_LONG OFFSET(25) NUMBITS(1) [],
+    ],
+    pub SPI_CLK [
+        /// even divider of the core clock, 0 is 65536
+        CDIV OFFSET(0) NUMBITS(16) [],
+    ],
+    pub SPI_DLEN [
+        LEN OFFSET(0) NUMBITS(16) [],
+    ],
+    pub SPI_LTOH [
+        TOH OFFSET(0) NUMBITS(4) [],
+    ],
+    pub SPI_DC [
+        TDREQ OFFSET(0) NUMBITS(8) [],
+        TPANIC OFFSET(8) NUMBITS(8) [],
+        RDREQ OFFSET(16) NUMBITS(8) [],
+        RPANIC OFFSET(24) NUMBITS(8) [],
+    ],
+}
+
+#[repr(C)]
+pub struct SPIRegisters {
+    cs: ReadWrite<u32, SPI_CS::Register>, /* 0x00 CS SPI Master Control and Status */
+    fifo: ReadWrite<u32>,                 /* 0x04 FIFO SPI Master TX and RX FIFOs */
+    clk: ReadWrite<u32, SPI_CLK::Register>, /* 0x08 CLK SPI Master Clock Divider */
+    dlen: ReadWrite<u32, SPI_DLEN::Register>, /* 0x0c DLEN SPI Master Data Length */
+    ltoh: ReadWrite<u32, SPI_LTOH::Register>, /* 0x10 LTOH SPI LOSSI mode TOH */
+    dc: ReadWrite<u32, SPI_DC::Register>, /* 0x14 DC SPI DMA DREQ Controls */
+}
+
+register_layout! {
+    SPIRegisters @ 0x00 .. 0x18 {
+        0x00 => cs,
+        0x04 => fifo,
+        0x08 => clk,
+        0x0c => dlen,
+        0x10 => ltoh,
+        0x14 => dc,
+    }
+}
+
+/// clock polarity and phase
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SPIMode {
+    /// clock idles low, data sampled on the rising edge
+    Mode0,
+    /// clock idles low, data sampled on the falling edge
+    Mode1,
+    /// clock idles high, data sampled on the falling edge
+    Mode2,
+    /// clock idles high, data sampled on the rising edge
+    Mode3,
+}
+
+impl SPIMode {
+    pub fn polarity(self) -> bool {
+        matches!(self, SPIMode::Mode2 | SPIMode::Mode3)
+    }
+
+    pub fn phase(self) -> bool {
+        matches!(self, SPIMode::Mode1 | SPIMode::Mode3)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SPIChipSelect {
+    CS0,
+    CS1,
+    CS2,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SPIConfig {
+    pub mode: SPIMode,
+    pub speed_hz: u32,
+    pub chip_select: SPIChipSelect,
+    pub cs_active_high: bool,
+}
+
+impl SPIConfig {
+    pub const fn new() -> SPIConfig {
+        SPIConfig {
+            mode: SPIMode::Mode0,
+            speed_hz: 1_000_000,
+            chip_select: SPIChipSelect::CS0,
+            cs_active_high: false,
+        }
+    }
+}
+
+impl Default for SPIConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SPIInstance {
+    SPI0,
+    SPI3,
+    SPI4,
+    SPI5,
+    SPI6,
+}
+
+impl SPIInstance {
+    const ALL: [SPIInstance; 5] = [
+        SPIInstance::SPI0,
+        SPIInstance::SPI3,
+        SPIInstance::SPI4,
+        SPIInstance::SPI5,
+        SPIInstance::SPI6,
+    ];
+
+    pub const fn base(self) -> usize {
+        match self {
+            SPIInstance::SPI0 => board::peripheral(0x20_4000),
+            SPIInstance::SPI3 => board::peripheral(0x20_4600),
+            SPIInstance::SPI4 => board::peripheral(0x20_4800),
+            SPIInstance::SPI5 => board::peripheral(0x20_4a00),
+            SPIInstance::SPI6 => board::peripheral(0x20_4c00),
+        }
+    }
+
+    /// TX and RX DREQ lines, `None` when this driver can't pace DMA for the instance
+    pub const fn dreq(self) -> Option<(DREQ, DREQ)> {
+        match self {
+            SPIInstance::SPI0 => Some((DREQ::SPI0Tx, DREQ::SPI0Rx)),
+            SPIInstance::SPI3 => None,
+            SPIInstance::SPI4 => Some((DREQ::SPI4Tx, DREQ::SPI4Rx)),
+            SPIInstance::SPI5 => Some((DREQ::SPI5Tx, DREQ::SPI5Rx)),
+            SPIInstance::SPI6 => Some((DREQ::SPI6Tx, DREQ::SPI6Rx)),
+        }
+    }
+}
+
+/// pair of DMA channels paced by one SPI instance
+pub struct SPIDma<'a> {
+    tx_channel: &'a mut DMAChannelRegisters,
+    rx_channel: &'a mut DMAChannelRegisters,
+    /// control blocks of the transmit and receive channel
+    blocks: &'a mut [ControlBlock; 2],
+    tx_dreq: DREQ,
+    rx_dreq: DREQ,
+}
+
+impl<'a> SPIDma<'a> {
+    /// `blocks` have to be in reach of the engines like the buffers, e.g. in a static
+    pub fn new(
+        instance: SPIInstance,
+        tx_channel: &'a mut DMAChannelRegisters,
+        rx_channel: &'a mut DMAChannelRegisters,
+        blocks: &'a mut [ControlBlock; 2],
+    ) -> Option<SPIDma<'a>> {
+        let (tx_dreq, rx_dreq) = instance.dreq()?;
+        Some(SPIDma {
+            tx_channel,
+            rx_channel,
+            blocks,
+            tx_dreq,
+            rx_dreq,
+        })
+    }
+}
+
+impl SPIRegisters {
+    /// bytes a single DMA transfer can move, DLEN is 16 bits wide
+    pub const DMA_MAX_LEN: usize = 0xffff;
+
+    pub const fn new(instance: SPIInstance) -> *mut SPIRegisters {
+        Self::at(instance.base())
+    }
+
+    /// register block placed at `base` instead of the peripheral address
+    pub const fn at(base: usize) -> *mut SPIRegisters {
+        base as *mut SPIRegisters
+    }
+
+    /// returns the achieved clock in Hz
+    pub fn configure(&mut self, config: &SPIConfig) -> u32 {
+        let divider = CORE_CLOCK.div_ceil(config.speed_hz.max(1));
+        let divider = (divider + (divider & 1)).clamp(2, 65536);
+
+        let chip_select = match config.chip_select {
+            SPIChipSelect::CS0 => SPI_CS::CS::CS0,
+            SPIChipSelect::CS1 => SPI_CS::CS::CS1,
+            SPIChipSelect::CS2 => SPI_CS::CS::CS2,
+        };
+        let polarity = config.cs_active_high as u32;
+
+        self.cs.write(
+            chip_select
+                + SPI_CS::CPOL.val(config.mode.polarity() as u32)
+                + SPI_CS::CPHA.val(config.mode.phase() as u32)
+                + SPI_CS::CSPOL.val(polarity)
+                + SPI_CS::CSPOL0.val(polarity)
+                + SPI_CS::CSPOL1.val(polarity)
+                + SPI_CS::CSPOL2.val(polarity)
+                + SPI_CS::CLEAR::Both,
+        );
+        self.clk
+            .write(SPI_CLK::CDIV.val(divider & SPI_CLK::CDIV.mask));
+        self.dc.write(
+            SPI_DC::TDREQ.val(0x20)
+                + SPI_DC::TPANIC.val(0x10)
+                + SPI_DC::RDREQ.val(0x20)
+                + SPI_DC::RPANIC.val(0x30),
+        );
+        CORE_CLOCK / divider
+    }
+
+    /// full duplex transfer of `max(tx.len(), rx.len())` bytes with chip select active
+    /// throughout, missing `tx` bytes are sent as zeroes and surplus received bytes
+    /// are dropped
+    pub fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) {
+        let len = tx.len().max(rx.len());
+        self.cs.modify(SPI_CS::CLEAR::Both + SPI_CS::TA::SET);
+
+        let (mut sent, mut received) = (0, 0);
+        while received < len {
+            while sent < len && self.cs.is_set(SPI_CS::TXD) {
+                self.fifo.set(tx.get(sent).copied().unwrap_or(0) as u32);
+                sent += 1;
+            }
+            while received < sent && self.cs.is_set(SPI_CS::RXD) {
+                let byte = self.fifo.get() as u8;
+                if let Some(slot) = rx.get_mut(received) {
+                    *slot = byte;
+                }
+                received += 1;
+            }
+        }
+
+        while !self.cs.is_set(SPI_CS::DONE) {}
+        self.cs.modify(SPI_CS::TA::CLEAR);
+    }
+
+    pub fn write(&mut self, tx: &[u8]) {
+        self.transfer(tx, &mut []);
+    }
+
+    pub fn read(&mut self, rx: &mut [u8]) {
+        self.transfer(&[], rx);
+    }
+
+    /// Same as `transfer`, with both FIFOs serviced by DMA instead of the CPU. Buffers
+    /// longer than `DMA_MAX_LEN` are split into several transfers. Both buffers must be
+    /// word aligned and live in the first 1GB of RAM, [`DMAError::Unreachable`] otherwise.
+    /// `tx` is padded with zeroes from `rx` when shorter, so `rx` is overwritten even past
+    /// the received bytes.
+    pub fn transfer_dma(
+        &mut self,
+        dma: &mut SPIDma,
+        tx: &[u8],
+        rx: &mut [u8],
+    ) -> Result<(), DMAError> {
+        let len = tx.len().max(rx.len());
+        let mut offset = 0;
+        while offset < len {
+            // chunks never straddle the end of either buffer
+            let mut chunk = (len - offset).min(Self::DMA_MAX_LEN);
+            for end in [tx.len(), rx.len()] {
+                if offset < end {
+                    chunk = chunk.min(end - offset);
+                }
+            }
+            // with no data to send, the received buffer doubles as zeroed transmit data
+            let source = match offset < tx.len() {
+                true => bus_address(tx[offset..].as_ptr()),
+                false => {
+                    rx[offset..offset + chunk].fill(0);
+                    bus_address(rx[offset..].as_ptr())
+                }
+            };
+            let source = source.ok_or(DMAError::Unreachable)?;
+            // past the end of `rx` the received bytes are dropped
+            let dest = (offset < rx.len())
+                .then(|| bus_address(rx[offset..].as_ptr()).ok_or(DMAError::Unreachable))
+                .transpose()?;
+            self.dma_chunk(dma, source, dest, chunk as u32)?;
+            offset += chunk;
+        }
+        Ok(())
+    }
+
+    fn dma_chunk(
+        &mut self,
+        dma: &mut SPIDma,
+        source: u32,
+        dest: Option<u32>,
+        len: u32,
+    ) -> Result<(), DMAError> {
+        let fifo = peripheral_bus_address(&self.fifo);
+        dma.blocks[0] = ControlBlock::new(
+            DMA_TI::SRC_INC::SET
+                + DMA_TI::DEST_DREQ::SET
+                + DMA_TI::WAIT_RESP::SET
+                + DMA_TI::PERMAP.val(dma.tx_dreq as u32),
+            source,
+            fifo,
+            len,
+        );
+        // received data nobody asked for is read and dropped
+        dma.blocks[1] = ControlBlock::new(
+            DMA_TI::SRC_DREQ::SET
+                + DMA_TI::DEST_INC.val(dest.is_some() as u32)
+                + DMA_TI::DEST_IGNORE.val(dest.is_none() as u32)
+                + DMA_TI::PERMAP.val(dma.rx_dreq as u32),
+            fifo,
+            dest.unwrap_or(0),
+            len,
+        );
+
+        self.dlen.write(SPI_DLEN::LEN.val(len));
+        self.cs
+            .modify(SPI_CS::CLEAR::Both + SPI_CS::TA::SET + SPI_CS::DMAEN::SET + SPI_CS::ADCS::SET);
+        let [tx, rx] = &*dma.blocks;
+        let result = dma
+            .rx_channel
+            .start(rx)
+            .and_then(|_| dma.tx_channel.start(tx))
+            .and_then(|_| dma.rx_channel.wait())
+            .and_then(|_| dma.tx_channel.wait());
+        if result.is_err() {
+            dma.tx_channel.abort();
+            dma.rx_channel.abort();
+        }
+        self.cs
+            .modify(SPI_CS::TA::CLEAR + SPI_CS::DMAEN::CLEAR + SPI_CS::ADCS::CLEAR);
+        result
+    }
+}
+
+pub static mut SPI: SPI = SPI::new();
+pub struct SPI {
+    registers: [Option<*mut SPIRegisters>; 5],
+}
+
+impl SPI {
+    const fn new() -> SPI {
+        let mut registers = [None; 5];
+        let mut i = 0;
+        while i < registers.len() {
+            registers[i] = Some(SPIRegisters::new(SPIInstance::ALL[i]));
+            i += 1;
+        }
+        SPI { registers }
+    }
+
+    fn index(instance: SPIInstance) -> usize {
+        SPIInstance::ALL
+            .iter()
+            .position(|i| *i == instance)
+            .unwrap()
+    }
+
+    pub fn take_spi(&mut self, instance: SPIInstance) -> *mut SPIRegisters {
+        let p = self.registers[Self::index(instance)].take();
+        p.unwrap()
+    }
+
+    pub fn return_spi(&mut self, instance: SPIInstance, spi: *mut SPIRegisters) {
+        self.registers[Self::index(instance)].replace(spi);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma::tests::channel;
+    use crate::mock::{Access, Behavior, RegisterFile, low_memory};
+
+    const CS: usize = 0x00;
+    const FIFO: usize = 0x04;
+    const CLK: usize = 0x08;
+    const DC: usize = 0x14;
+
+    /// every byte written to the FIFO is received back, the transfer is done immediately
+    fn loopback() -> RegisterFile {
+        let file = RegisterFile::new(0x18);
+        file.on(
+            FIFO,
+            Behavior::Custom(Box::new(|storage, access| match access {
+                Access::Write(value) => {
+                    storage.tx(FIFO).push(value);
+                    storage.rx(FIFO).push_back(value & 0xff);
+                    0
+                }
+                Access::Read => storage.rx(FIFO).pop_front().unwrap_or(0),
+            })),
+        );
+        file.on(
+            CS,
+            Behavior::Custom(Box::new(|storage, access| {
+                if let Access::Write(value) = access {
+                    if value & (2 << 4) != 0 {
+                        storage.rx(FIFO).clear();
+                    }
+                    // CLEAR bits are self clearing
+                    storage.set(CS, value & !(0b11 << 4));
+                }
+                let rxd = !storage.rx(FIFO).is_empty() as u32;
+                storage.get(CS) | (1 << 16) | (rxd << 17) | (1 << 18)
+            })),
+        );
+        file
+    }
+
+    #[test]
+    fn configure() {
+        let file = loopback();
+        let spi = unsafe { &mut *file.block::<SPIRegisters>(0) };
+
+        let config = SPIConfig {
+            mode: SPIMode::Mode3,
+            speed_hz: 10_000_000,
+            chip_select: SPIChipSelect::CS1,
+            cs_active_high: false,
+        };
+        assert_eq!(spi.configure(&config), 9_615_384);
+        assert_eq!(file.peek(CLK), 26);
+        assert_eq!(file.peek(CS), (1 << 3) | (1 << 2) | 1);
+        assert_eq!(file.peek(DC), 0x3020_1020);
+
+        let config = SPIConfig {
+            speed_hz: 1,
+            cs_active_high: true,
+            ..SPIConfig::new()
+        };
+        // slowest clock wraps the divider to 0
+        assert_eq!(spi.configure(&config), 3814);
+        assert_eq!(file.peek(CLK), 0);
+        assert_eq!(file.peek(CS), (0b111 << 21) | (1 << 6));
+    }
+
+    #[test]
+    fn transfer() {
+        let file = loopback();
+        let spi = unsafe { &mut *file.block::<SPIRegisters>(0) };
+        spi.configure(&SPIConfig::new());
+
+        let mut rx = [0u8; 5];
+        spi.transfer(b"hello", &mut rx);
+        assert_eq!(&rx, b"hello");
+        assert_eq!(file.take_tx(FIFO), b"hello".map(u32::from));
+        // transfer no longer active
+        assert_eq!(file.peek(CS) & (1 << 7), 0);
+
+        let mut rx = [0xffu8; 4];
+        spi.transfer(&[1, 2], &mut rx);
+        assert_eq!(rx, [1, 2, 0, 0]);
+
+        spi.write(&[7, 8, 9]);
+        assert_eq!(file.take_tx(FIFO), [1, 2, 0, 0, 7, 8, 9]);
+    }
+
+    #[test]
+    fn transfer_dma() {
+        let file = loopback();
+        let spi = unsafe { &mut *file.block::<SPIRegisters>(0) };
+        let (tx_file, rx_file) = (channel(0), channel(0));
+        let tx_channel = unsafe { &mut *tx_file.block::<DMAChannelRegisters>(0) };
+        let rx_channel = unsafe { &mut *rx_file.block::<DMAChannelRegisters>(0) };
+        let blocks = low_memory(ControlBlock::default(), 2);
+        let blocks = blocks.try_into().unwrap();
+        let mut dma = SPIDma::new(SPIInstance::SPI0, tx_channel, rx_channel, blocks).unwrap();
+
+        // the second chunk has nowhere to receive to
+        let tx = low_memory(0x5au8, 8);
+        let rx = low_memory(0u8, 4);
+        assert_eq!(spi.transfer_dma(&mut dma, tx, rx), Ok(()));
+        let [sent, received] = *dma.blocks;
+        assert_eq!(sent.source_ad, bus_address(tx[4..].as_ptr()).unwrap());
+        assert_eq!(received.txfr_len, 4);
+        assert_eq!(received.dest_ad, 0);
+        assert_eq!(received.ti & ((1 << 7) | (1 << 4)), 1 << 7);
+
+        let mut far = [0u8; 4];
+        assert_eq!(
+            spi.transfer_dma(&mut dma, tx, &mut far),
+            Err(DMAError::Unreachable)
+        );
+    }
+
+    #[test]
+    fn dma_requests() {
+        assert_eq!(SPIInstance::SPI0.dreq(), Some((DREQ::SPI0Tx, DREQ::SPI0Rx)));
+        assert_eq!(SPIInstance::SPI3.dreq(), None);
+        assert_eq!(
+            peripheral_bus_address(SPIInstance::SPI6.base() as *const u32),
+            0x7e204c00
+        );
+    }
+}
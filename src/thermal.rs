@@ -0,0 +1,435 @@
+//! SoC temperature, voltages and throttling as reported by the firmware.
+//!
+//! [`Monitor::poll`] compares the temperature with a warning and a critical threshold and
+//! reports level changes and changes in throttling, to a callback and to the caller.
+//! [`COMMANDS`] adds `thermal` to the shell.
+use crate::mailbox::{
+    Firmware, MailboxError, MailboxRegisters, TAG_GET_MAX_TEMPERATURE, TAG_GET_TEMPERATURE,
+    TAG_GET_THROTTLED, TAG_GET_VOLTAGE,
+};
+use crate::registers::*;
+use crate::shell::{Command, CommandError, Shell, parse_number};
+use crate::timer;
+use core::fmt::{self, Write};
+
+/// thresholds in millidegrees Celsius, the firmware throttles at 80 and 85
+pub const DEFAULT_WARNING: u32 = 70_000;
+pub const DEFAULT_CRITICAL: u32 = 80_000;
+/// a level is left only this far below its threshold
+const HYSTERESIS: u32 = 2_000;
+/// temperature sensor of the SoC
+const SOC_SENSOR: u32 = 0;
+const WATCH_INTERVAL_US: u64 = 1_000_000;
+/// highest threshold the shell accepts, degrees Celsius
+const MAX_DEGREES: usize = 150;
+
+register_bitfields! {u32,
+    /// response of the get-throttled tag
+    pub THROTTLED [
+        UNDER_VOLTAGE OFFSET(0) NUMBITS(1) [],
+        FREQUENCY_CAPPED OFFSET(1) NUMBITS(1) [],
+        THROTTLED OFFSET(2) NUMBITS(1) [],
+        SOFT_TEMPERATURE_LIMIT OFFSET(3) NUMBITS(1) [],
+        /// the flags above as they are now
+        NOW OFFSET(0) NUMBITS(4) [],
+        /// the flags above as they were at some point since boot
+        OCCURRED OFFSET(16) NUMBITS(4) [],
+    ],
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ThermalError {
+    Mailbox(MailboxError),
+    /// the warning threshold has to be below the critical one
+    InvalidThresholds,
+}
+
+impl From<MailboxError> for ThermalError {
+    fn from(error: MailboxError) -> Self {
+        ThermalError::Mailbox(error)
+    }
+}
+
+/// voltage ids of the get-voltage tag
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Voltage {
+    Core = 1,
+    SDRAMController = 2,
+    SDRAMPhy = 3,
+    SDRAMIO = 4,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Level {
+    Normal,
+    Warning,
+    Critical,
+}
+
+/// get-throttled flags
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Throttled(pub u32);
+
+impl Throttled {
+    const NAMES: [&str; 4] = [
+        "under-voltage",
+        "frequency capped",
+        "throttled",
+        "soft temperature limit",
+    ];
+
+    pub fn now(self) -> u32 {
+        THROTTLED::NOW.read(self.0)
+    }
+
+    pub fn occurred(self) -> u32 {
+        THROTTLED::OCCURRED.read(self.0)
+    }
+
+    pub fn under_voltage(self) -> bool {
+        THROTTLED::UNDER_VOLTAGE.is_set(self.0)
+    }
+
+    pub fn throttled(self) -> bool {
+        THROTTLED::THROTTLED.is_set(self.0)
+    }
+}
+
+/// names of the set `flags`, "no" for none
+fn write_flags(f: &mut fmt::Formatter, flags: u32) -> fmt::Result {
+    if flags == 0 {
+        return f.write_str("no");
+    }
+    let mut names = Throttled::NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| flags & (1 << bit) != 0);
+    if let Some((_, name)) = names.next() {
+        f.write_str(name)?;
+    }
+    names.try_for_each(|(_, name)| write!(f, ", {name}"))
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_flags(f, self.now())?;
+        f.write_str(" (since boot: ")?;
+        write_flags(f, self.occurred())?;
+        f.write_str(")")
+    }
+}
+
+/// what [`Monitor::poll`] found
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Alert {
+    /// the temperature reached a new level, `Normal` once it cooled down
+    Temperature(Level),
+    /// the current throttling flags changed
+    Throttled(Throttled),
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Status {
+    /// millidegrees Celsius
+    pub temperature: u32,
+    /// where the firmware starts throttling, millidegrees Celsius
+    pub max_temperature: u32,
+    /// microvolts
+    pub core_voltage: u32,
+    pub throttled: Throttled,
+}
+
+/// `value` divided by `unit` with `decimals` digits after the point
+struct Fixed(u32, u32, usize);
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Fixed(value, unit, decimals) = *self;
+        let fraction = (value % unit) as u64 * 10u64.pow(decimals as u32) / unit as u64;
+        write!(f, "{}.{:0decimals$}", value / unit, fraction)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "temperature {} C, limit {} C",
+            Fixed(self.temperature, 1000, 1),
+            Fixed(self.max_temperature, 1000, 1)
+        )?;
+        writeln!(
+            f,
+            "core        {} V",
+            Fixed(self.core_voltage, 1_000_000, 4)
+        )?;
+        writeln!(f, "throttled   {}", self.throttled)
+    }
+}
+
+pub struct Monitor<'a> {
+    firmware: &'a mut dyn Firmware,
+    warning: u32,
+    critical: u32,
+    level: Level,
+    /// current throttling flags last reported
+    throttled: u32,
+    callback: Option<fn(Alert, &Status)>,
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new(firmware: &'a mut dyn Firmware) -> Monitor<'a> {
+        Monitor {
+            firmware,
+            warning: DEFAULT_WARNING,
+            critical: DEFAULT_CRITICAL,
+            level: Level::Normal,
+            throttled: 0,
+            callback: None,
+        }
+    }
+
+    /// millidegrees Celsius
+    pub fn set_thresholds(&mut self, warning: u32, critical: u32) -> Result<(), ThermalError> {
+        if warning >= critical {
+            return Err(ThermalError::InvalidThresholds);
+        }
+        self.warning = warning;
+        self.critical = critical;
+        Ok(())
+    }
+
+    pub fn thresholds(&self) -> (u32, u32) {
+        (self.warning, self.critical)
+    }
+
+    /// call `callback` for every alert [`Monitor::poll`] returns
+    pub fn on_alert(&mut self, callback: fn(Alert, &Status)) {
+        self.callback = Some(callback);
+    }
+
+    /// level of the last poll
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// SoC temperature in millidegrees Celsius
+    pub fn temperature(&mut self) -> Result<u32, ThermalError> {
+        self.sensor(TAG_GET_TEMPERATURE)
+    }
+
+    /// temperature the firmware throttles at in millidegrees Celsius
+    pub fn max_temperature(&mut self) -> Result<u32, ThermalError> {
+        self.sensor(TAG_GET_MAX_TEMPERATURE)
+    }
+
+    fn sensor(&mut self, tag: u32) -> Result<u32, ThermalError> {
+        let mut response = [0; 2];
+        self.firmware.property(tag, &[SOC_SENSOR], &mut response)?;
+        Ok(response[1])
+    }
+
+    /// microvolts
+    pub fn voltage(&mut self, voltage: Voltage) -> Result<u32, ThermalError> {
+        let mut response = [0; 2];
+        self.firmware
+            .property(TAG_GET_VOLTAGE, &[voltage as u32], &mut response)?;
+        Ok(response[1])
+    }
+
+    pub fn throttled(&mut self) -> Result<Throttled, ThermalError> {
+        let mut response = [0; 1];
+        self.firmware
+            .property(TAG_GET_THROTTLED, &[0], &mut response)?;
+        Ok(Throttled(response[0]))
+    }
+
+    pub fn status(&mut self) -> Result<Status, ThermalError> {
+        Ok(Status {
+            temperature: self.temperature()?,
+            max_temperature: self.max_temperature()?,
+            core_voltage: self.voltage(Voltage::Core)?,
+            throttled: self.throttled()?,
+        })
+    }
+
+    /// Read the status and check it against the thresholds. A temperature level change is
+    /// reported before a throttling change, the other one follows with the next poll.
+    pub fn poll(&mut self) -> Result<(Status, Option<Alert>), ThermalError> {
+        let status = self.status()?;
+        let level = self.next_level(status.temperature);
+        let alert = if level != self.level {
+            self.level = level;
+            Some(Alert::Temperature(level))
+        } else if status.throttled.now() != self.throttled {
+            self.throttled = status.throttled.now();
+            Some(Alert::Throttled(status.throttled))
+        } else {
+            None
+        };
+        if let (Some(alert), Some(callback)) = (alert, self.callback) {
+            callback(alert, &status);
+        }
+        Ok((status, alert))
+    }
+
+    fn next_level(&self, temperature: u32) -> Level {
+        let level = match temperature {
+            t if t >= self.critical => Level::Critical,
+            t if t >= self.warning => Level::Warning,
+            _ => Level::Normal,
+        };
+        let threshold = match self.level {
+            Level::Critical => self.critical,
+            Level::Warning => self.warning,
+            Level::Normal => 0,
+        };
+        match level < self.level && temperature + HYSTERESIS > threshold {
+            true => self.level,
+            false => level,
+        }
+    }
+}
+
+/// thresholds of the shell command, millidegrees Celsius
+static mut THRESHOLDS: (u32, u32) = (DEFAULT_WARNING, DEFAULT_CRITICAL);
+
+pub const COMMANDS: &[Command] = &[Command {
+    name: "thermal",
+    usage: "[watch | limits WARN CRIT]",
+    help: "temperature, voltage and throttling, watch until a key is pressed",
+    subcommands: &["watch", "limits"],
+    run: thermal,
+}];
+
+fn thermal(shell: &mut Shell, args: &[&str]) -> Result<(), CommandError> {
+    let mailbox = unsafe { &mut *MailboxRegisters::new() };
+    let mut monitor = Monitor::new(mailbox);
+    let (warning, critical) = unsafe { THRESHOLDS };
+    let _ = monitor.set_thresholds(warning, critical);
+    let failed = |_| CommandError::Invalid("firmware did not answer");
+    match args {
+        [] => {
+            let (status, _) = monitor.poll().map_err(failed)?;
+            let _ = write!(shell, "{status}");
+            let _ = writeln!(
+                shell,
+                "level       {:?} (warning {} C, critical {} C)",
+                monitor.level(),
+                warning / 1000,
+                critical / 1000
+            );
+        }
+        ["watch"] => loop {
+            let (status, alert) = monitor.poll().map_err(failed)?;
+            match alert {
+                Some(Alert::Temperature(level)) => {
+                    let _ = writeln!(shell, "{level:?}: {} C", Fixed(status.temperature, 1000, 1));
+                }
+                Some(Alert::Throttled(throttled)) => {
+                    let _ = writeln!(shell, "throttled: {throttled}");
+                }
+                None => {}
+            }
+            let next = timer::Timeout::after_us(WATCH_INTERVAL_US);
+            while !next.expired() {
+                if shell.serial().read_byte().is_some() {
+                    return Ok(());
+                }
+            }
+        },
+        ["limits", warning, critical] => {
+            // whole degrees
+            let degrees = |arg| match parse_number(arg)? {
+                degrees @ 0..=MAX_DEGREES => Ok(degrees as u32 * 1000),
+                _ => Err(CommandError::Invalid("not a temperature")),
+            };
+            let (warning, critical) = (degrees(warning)?, degrees(critical)?);
+            monitor
+                .set_thresholds(warning, critical)
+                .map_err(|_| CommandError::Invalid("warning has to be below critical"))?;
+            unsafe { THRESHOLDS = (warning, critical) };
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailbox::tests::FakeFirmware;
+
+    fn firmware(temperature: u32, throttled: u32) -> FakeFirmware {
+        FakeFirmware {
+            answers: vec![
+                (TAG_GET_TEMPERATURE, vec![0, temperature]),
+                (TAG_GET_MAX_TEMPERATURE, vec![0, 85_000]),
+                (TAG_GET_VOLTAGE, vec![1, 850_000]),
+                (TAG_GET_THROTTLED, vec![throttled]),
+            ],
+        }
+    }
+
+    #[test]
+    fn status() {
+        let mut firmware = firmware(48_312, 0x5_0000);
+        let mut monitor = Monitor::new(&mut firmware);
+        let status = monitor.status().unwrap();
+        assert_eq!(status.core_voltage, 850_000);
+        assert_eq!(
+            format!("{status}"),
+            "temperature 48.3 C, limit 85.0 C\n\
+             core        0.8500 V\n\
+             throttled   no (since boot: under-voltage, throttled)\n"
+        );
+        assert_eq!(
+            monitor.set_thresholds(80_000, 80_000),
+            Err(ThermalError::InvalidThresholds)
+        );
+
+        let mut silent = FakeFirmware { answers: vec![] };
+        let mut monitor = Monitor::new(&mut silent);
+        assert_eq!(
+            monitor.temperature(),
+            Err(ThermalError::Mailbox(MailboxError::UnknownTag))
+        );
+    }
+
+    static mut ALERTS: usize = 0;
+
+    #[test]
+    fn alerts() {
+        let poll = |temperature, throttled, level: &mut Level| {
+            let mut firmware = firmware(temperature, throttled);
+            let mut monitor = Monitor::new(&mut firmware);
+            monitor.level = *level;
+            monitor.throttled = 0;
+            monitor.on_alert(|_, _| unsafe { ALERTS += 1 });
+            let (_, alert) = monitor.poll().unwrap();
+            *level = monitor.level();
+            alert
+        };
+        let mut level = Level::Normal;
+        assert_eq!(poll(60_000, 0, &mut level), None);
+        assert_eq!(
+            poll(71_000, 0, &mut level),
+            Some(Alert::Temperature(Level::Warning))
+        );
+        // cooling down below the threshold is not enough
+        assert_eq!(poll(69_000, 0, &mut level), None);
+        assert_eq!(
+            poll(67_000, 0, &mut level),
+            Some(Alert::Temperature(Level::Normal))
+        );
+        assert_eq!(
+            poll(90_000, 0x4, &mut level),
+            Some(Alert::Temperature(Level::Critical))
+        );
+        assert_eq!(
+            poll(90_000, 0x4, &mut level),
+            Some(Alert::Throttled(Throttled(0x4)))
+        );
+        assert_eq!(unsafe { ALERTS }, 4);
+    }
+}
@@ -0,0 +1,69 @@
+//! ARM generic timer, the physical counter runs at a fixed frequency from reset.
+
+#[cfg(target_arch = "aarch64")]
+use core::arch::asm;
+
+/// current value of the physical counter
+pub fn ticks() -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let ticks: u64;
+        unsafe { asm!("isb", "mrs {}, cntpct_el0", out(reg) ticks, options(nomem, nostack)) };
+        ticks
+    }
+    // host builds count calls, which keeps timestamps deterministic
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        use core::sync::atomic::{AtomicU64, Ordering};
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        TICKS.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// counter frequency in Hz
+pub fn frequency() -> u64 {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let frequency: u64;
+        unsafe { asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack)) };
+        frequency
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        1_000_000
+    }
+}
+
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    ((ticks as u128 * 1_000_000) / frequency() as u128) as u64
+}
+
+/// time since the counter was reset
+pub fn uptime_us() -> u64 {
+    ticks_to_us(ticks())
+}
+
+/// deadline on the physical counter
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout {
+    deadline: u64,
+}
+
+impl Timeout {
+    pub fn after_us(us: u64) -> Timeout {
+        let ticks = (us as u128 * frequency() as u128 / 1_000_000) as u64;
+        Timeout {
+            deadline: self::ticks().saturating_add(ticks),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        ticks() >= self.deadline
+    }
+}
+
+/// busy wait for `us` microseconds
+pub fn delay_us(us: u64) {
+    let timeout = Timeout::after_us(us);
+    while !timeout.expired() {}
+}
@@ -0,0 +1,245 @@
+//! Register access trace, enabled with the `trace` feature.
+//!
+//! Every access made through `registers::mmio`, which backs the register cells and the
+//! `utils::bits` helpers, is appended to a ring buffer holding the last
+//! [`CAPACITY`] accesses. The buffer can be dumped over a serial port or compared with an
+//! expected sequence of accesses.
+use crate::serial::{Serial, SerialWriter};
+use crate::timer;
+use core::fmt::Write;
+
+pub const CAPACITY: usize = 256;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Access {
+    pub address: usize,
+    pub value: u64,
+    pub direction: Direction,
+}
+
+impl Access {
+    pub const fn read(address: usize, value: u64) -> Access {
+        Access {
+            address,
+            value,
+            direction: Direction::Read,
+        }
+    }
+
+    pub const fn write(address: usize, value: u64) -> Access {
+        Access {
+            address,
+            value,
+            direction: Direction::Write,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    pub access: Access,
+    /// generic timer ticks
+    pub timestamp: u64,
+}
+
+/// first difference between the trace and an expected sequence
+#[derive(PartialEq, Eq, Debug)]
+pub struct Mismatch {
+    pub index: usize,
+    pub expected: Option<Access>,
+    pub found: Option<Access>,
+}
+
+struct Ring {
+    records: [Record; CAPACITY],
+    next: usize,
+    len: usize,
+    enabled: bool,
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring {
+            records: [Record {
+                access: Access::read(0, 0),
+                timestamp: 0,
+            }; CAPACITY],
+            next: 0,
+            len: 0,
+            enabled: true,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        self.records[self.next] = record;
+        self.next = (self.next + 1) % CAPACITY;
+        self.len = (self.len + 1).min(CAPACITY);
+    }
+
+    /// `index`th record, oldest first
+    fn get(&self, index: usize) -> Option<Record> {
+        let start = (self.next + CAPACITY - self.len) % CAPACITY;
+        (index < self.len).then(|| self.records[(start + index) % CAPACITY])
+    }
+}
+
+#[cfg(not(test))]
+static mut TRACE: Ring = Ring::new();
+
+fn with_ring<R>(f: impl FnOnce(&mut Ring) -> R) -> R {
+    // tests run in parallel threads, each gets its own trace
+    #[cfg(test)]
+    {
+        std::thread_local! {
+            static TRACE: core::cell::RefCell<Ring> = const { core::cell::RefCell::new(Ring::new()) };
+        }
+        TRACE.with_borrow_mut(f)
+    }
+    #[cfg(not(test))]
+    {
+        let trace = &raw mut TRACE;
+        f(unsafe { &mut *trace })
+    }
+}
+
+pub(crate) fn record(address: usize, value: u64, direction: Direction) {
+    with_ring(|ring| {
+        if ring.enabled {
+            ring.push(Record {
+                access: Access {
+                    address,
+                    value,
+                    direction,
+                },
+                timestamp: timer::ticks(),
+            });
+        }
+    });
+}
+
+/// stop or resume recording
+pub fn enable(enabled: bool) {
+    with_ring(|ring| ring.enabled = enabled);
+}
+
+pub fn clear() {
+    with_ring(|ring| {
+        ring.next = 0;
+        ring.len = 0;
+    });
+}
+
+pub fn len() -> usize {
+    with_ring(|ring| ring.len)
+}
+
+/// `index`th record, oldest first
+pub fn get(index: usize) -> Option<Record> {
+    with_ring(|ring| ring.get(index))
+}
+
+/// call `f` on every record, oldest first; `f` may access registers
+pub fn for_each(mut f: impl FnMut(&Record)) {
+    let mut index = 0;
+    while let Some(record) = get(index) {
+        f(&record);
+        index += 1;
+    }
+}
+
+/// compare recorded accesses with `expected`, timestamps are ignored
+pub fn compare(expected: &[Access]) -> Result<(), Mismatch> {
+    let mut index = 0;
+    loop {
+        let found = get(index).map(|record| record.access);
+        match (expected.get(index).copied(), found) {
+            (None, None) => return Ok(()),
+            (expected, found) if expected == found => index += 1,
+            (expected, found) => {
+                return Err(Mismatch {
+                    index,
+                    expected,
+                    found,
+                });
+            }
+        }
+    }
+}
+
+/// print the trace, oldest record first; recording is paused while printing
+pub fn dump<S: Serial + ?Sized>(serial: &mut S) {
+    let enabled = with_ring(|ring| core::mem::replace(&mut ring.enabled, false));
+    let mut out = SerialWriter(serial);
+    let _ = writeln!(out, "register trace, {} records", len());
+    let mut index = 0;
+    for_each(|record| {
+        let direction = match record.access.direction {
+            Direction::Read => 'R',
+            Direction::Write => 'W',
+        };
+        let _ = writeln!(
+            out,
+            "{:4} {:>12} {} {:#010x} {:#010x}",
+            index, record.timestamp, direction, record.access.address, record.access.value
+        );
+        index += 1;
+    });
+    out.0.flush();
+    enable(enabled);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aux::peripherals::{BaudRate, MiniUart};
+    use crate::mock::RegisterFile;
+
+    #[test]
+    fn golden_sequence() {
+        let file = RegisterFile::new(0x6c);
+        let uart = unsafe { &mut *file.block::<MiniUart>(0x40) };
+        let base = file.base();
+
+        clear();
+        uart.transmitter_enable();
+        uart.set_baudrate(BaudRate::Baud115200);
+        uart.transmit(b'a' as u32);
+
+        assert_eq!(
+            compare(&[
+                Access::read(base + 0x60, 0),
+                Access::write(base + 0x60, 0b10),
+                Access::write(base + 0x68, 270),
+                Access::write(base + 0x40, b'a' as u64),
+            ]),
+            Ok(())
+        );
+        assert_eq!(
+            compare(&[Access::read(base + 0x60, 0)]),
+            Err(Mismatch {
+                index: 1,
+                expected: None,
+                found: Some(Access::write(base + 0x60, 0b10)),
+            })
+        );
+    }
+
+    #[test]
+    fn ring_keeps_latest() {
+        clear();
+        for i in 0..CAPACITY + 2 {
+            record(i, 0, Direction::Write);
+        }
+        let mut addresses = Vec::new();
+        for_each(|record| addresses.push(record.access.address));
+        assert_eq!(addresses.len(), CAPACITY);
+        assert_eq!(addresses[0], 2);
+        assert_eq!(addresses[CAPACITY - 1], CAPACITY + 1);
+    }
+}
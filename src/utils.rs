@@ -1,56 +1,140 @@
 pub mod bits {
-    use core::ptr::{read_volatile, write_volatile};
+    use crate::registers::{RegisterValue, mmio};
 
     /// read value from dst, perform bitwise or on result with provided value and write it back
-    pub fn register_volatile_or<T: core::ops::BitOr<Output = T>>(dst: *mut T, value: T) {
+    ///
+    /// # Safety
+    /// `dst` must be valid for volatile reads and writes
+    pub unsafe fn register_volatile_or<T: RegisterValue>(dst: *mut T, value: T) {
         unsafe {
-            let v = read_volatile(dst as *const T);
-            write_volatile(dst, v | value);
+            let v = mmio::read(dst as *const T);
+            mmio::write(dst, v | value);
         }
     }
 
     /// read value from dst, perform bitwise `and` on result with provided value and write it back
-    pub fn register_volatile_and<T: core::ops::BitAnd<Output = T>>(dst: *mut T, value: T) {
+    ///
+    /// # Safety
+    /// `dst` must be valid for volatile reads and writes
+    pub unsafe fn register_volatile_and<T: RegisterValue>(dst: *mut T, value: T) {
         unsafe {
-            let v = read_volatile(dst as *const T);
-            write_volatile(dst, v & value);
+            let v = mmio::read(dst as *const T);
+            mmio::write(dst, v & value);
         }
     }
-
-    macro_rules! BITu32 {
-        ($b: expr) => {
-            (1u32 << $b)
-        };
-    }
-
-    macro_rules! u32_register {
-        ($reg: expr) => {
-            &$reg as *const u32
-        };
-    }
-
-    macro_rules! u32_register_mut {
-        ($reg: expr) => {
-            &mut $reg as *mut u32
-        };
-    }
-
-    pub(crate) use BITu32;
-    pub(crate) use u32_register;
-    pub(crate) use u32_register_mut;
 }
 
 pub mod bariers {
+    #[cfg(target_arch = "aarch64")]
     use core::arch::asm;
+
     pub fn memory_write_barier() {
+        #[cfg(target_arch = "aarch64")]
         unsafe {
             asm!("dmb st");
         }
+        #[cfg(not(target_arch = "aarch64"))]
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
     }
 
     pub fn memory_read_barier() {
+        #[cfg(target_arch = "aarch64")]
         unsafe {
             asm!("dmb ld");
         }
+        #[cfg(not(target_arch = "aarch64"))]
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+    }
+}
+
+pub mod crc32 {
+    /// IEEE 802.3 CRC-32 (zlib, GPT, PNG), reflected polynomial 0xedb88320
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = match crc & 1 {
+                    1 => (crc >> 1) ^ 0xedb8_8320,
+                    _ => crc >> 1,
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    /// running checksum over data arriving in pieces
+    #[derive(Clone, Copy)]
+    pub struct Crc32(u32);
+
+    impl Crc32 {
+        pub const fn new() -> Crc32 {
+            Crc32(0xffff_ffff)
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            for byte in data {
+                self.0 = TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+            }
+        }
+
+        pub fn finish(&self) -> u32 {
+            !self.0
+        }
+    }
+
+    impl Default for Crc32 {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.finish()
+    }
+}
+
+pub mod crc16 {
+    /// CRC-16/XMODEM, polynomial 0x1021 and no reflection
+    pub fn checksum(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |crc, byte| {
+            let mut crc = crc ^ ((*byte as u16) << 8);
+            for _ in 0..8 {
+                crc = match crc & 0x8000 {
+                    0 => crc << 1,
+                    _ => (crc << 1) ^ 0x1021,
+                };
+            }
+            crc
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::crc32::*;
+
+    #[test]
+    fn crc16() {
+        assert_eq!(super::crc16::checksum(b""), 0);
+        assert_eq!(super::crc16::checksum(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"123456789"), 0xcbf4_3926);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf4_3926);
     }
 }
@@ -0,0 +1,936 @@
+//! XMODEM-CRC, XMODEM-1K and YMODEM batch transfers over a [`Serial`] port.
+//!
+//! Packets are a header, the block number and its complement, 128 (`SOH`) or 1024 (`STX`)
+//! data bytes and a CRC-16, each acknowledged with `ACK` or retransmitted after a `NAK` or
+//! a timeout. The receiver starts a transfer by sending `C`, falling back to plain XMODEM
+//! with an 8 bit checksum when the sender does not answer.
+//!
+//! ```text
+//! YMODEM  receiver  C                           sender  SOH 00 FF "name\0size\0" CRC
+//!                   ACK C                               STX 01 FE data CRC ...
+//!                   ACK                                 EOT
+//!                   NAK                                 EOT
+//!                   ACK C                               next file, or an empty block 0
+//! ```
+//!
+//! Received files go to a [`Sink`], sent files come from a [`Source`], both are implemented
+//! for RAM buffers and for files on a [`FileSystem`].
+use crate::block::BlockDevice;
+use crate::fat::{FatError, File, FileSystem};
+use crate::serial::Serial;
+use crate::timer::Timeout;
+use crate::utils::crc16;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// padding of the last XMODEM block
+const SUB: u8 = 0x1a;
+/// receiver asks for CRC-16 instead of checksums
+const CRC: u8 = b'C';
+
+/// pause between requests while waiting for a sender, 20 of them give a minute
+const START_TIMEOUT_US: u64 = 3_000_000;
+const START_RETRIES: u32 = 20;
+/// requests for CRC-16 before falling back to checksums
+const CRC_RETRIES: u32 = 3;
+/// longest wait for the next packet or a response
+const PACKET_TIMEOUT_US: u64 = 10_000_000;
+/// longest pause within a packet
+const BYTE_TIMEOUT_US: u64 = 1_000_000;
+/// consecutive errors before a transfer is given up
+const RETRIES: u32 = 10;
+/// longest path built by [`FatSink`]
+const MAX_PATH: usize = 256;
+/// longest file name taken from a YMODEM header, in bytes
+const MAX_NAME: usize = 128;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TransferError {
+    /// no answer, or too many retransmissions
+    Timeout,
+    /// the other side cancelled the transfer
+    Cancelled,
+    /// unexpected block number, packets were lost
+    OutOfSync,
+    /// invalid YMODEM file header
+    InvalidHeader,
+    /// file name of a YMODEM header longer than 128 bytes
+    NameTooLong,
+    /// received data does not fit the sink
+    Full,
+    Fat(FatError),
+}
+
+impl From<FatError> for TransferError {
+    fn from(error: FatError) -> TransferError {
+        TransferError::Fat(error)
+    }
+}
+
+/// data block size of the sender, receivers take both
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PacketSize {
+    /// 128 bytes, plain XMODEM
+    Normal,
+    /// 1024 bytes, XMODEM-1K, short final blocks still use 128
+    OneK,
+}
+
+/// destination of received files
+pub trait Sink {
+    /// start a file, `name` is empty for XMODEM and `size` is known for YMODEM
+    fn create(&mut self, name: &str, size: Option<u32>) -> Result<(), TransferError>;
+    fn write(&mut self, data: &[u8]) -> Result<(), TransferError>;
+    /// the current file is complete
+    fn finish(&mut self) -> Result<(), TransferError> {
+        Ok(())
+    }
+}
+
+/// files to send
+pub trait Source {
+    /// name and size of the next file, `None` at the end of the batch
+    fn next_file(&mut self) -> Result<Option<(&str, u32)>, TransferError>;
+    /// data of the current file, 0 at its end
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, TransferError>;
+}
+
+/// received files stored back to back in a buffer
+pub struct RamSink<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    files: usize,
+}
+
+impl<'a> RamSink<'a> {
+    pub fn new(buf: &'a mut [u8]) -> RamSink<'a> {
+        RamSink {
+            buf,
+            len: 0,
+            files: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn files(&self) -> usize {
+        self.files
+    }
+}
+
+impl Sink for RamSink<'_> {
+    fn create(&mut self, _name: &str, _size: Option<u32>) -> Result<(), TransferError> {
+        self.files += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), TransferError> {
+        let end = self.len + data.len();
+        let dest = self.buf.get_mut(self.len..end).ok_or(TransferError::Full)?;
+        dest.copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// files sent from RAM, as (name, data) pairs
+pub struct RamSource<'a> {
+    files: &'a [(&'a str, &'a [u8])],
+    next: usize,
+    offset: usize,
+}
+
+impl<'a> RamSource<'a> {
+    pub fn new(files: &'a [(&'a str, &'a [u8])]) -> RamSource<'a> {
+        RamSource {
+            files,
+            next: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl Source for RamSource<'_> {
+    fn next_file(&mut self) -> Result<Option<(&str, u32)>, TransferError> {
+        let Some((name, data)) = self.files.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        self.offset = 0;
+        Ok(Some((*name, data.len() as u32)))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, TransferError> {
+        let Some((_, data)) = self.next.checked_sub(1).and_then(|i| self.files.get(i)) else {
+            return Ok(0);
+        };
+        let len = buf.len().min(data.len() - self.offset);
+        buf[..len].copy_from_slice(&data[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+/// received files written to a filesystem
+///
+/// `path` names the file of an XMODEM transfer and the directory of YMODEM files, existing
+/// files are replaced.
+pub struct FatSink<'a, D: BlockDevice> {
+    fs: &'a mut FileSystem<D>,
+    path: &'a str,
+    file: Option<File>,
+}
+
+impl<'a, D: BlockDevice> FatSink<'a, D> {
+    pub fn new(fs: &'a mut FileSystem<D>, path: &'a str) -> FatSink<'a, D> {
+        FatSink {
+            fs,
+            path,
+            file: None,
+        }
+    }
+}
+
+impl<D: BlockDevice> Sink for FatSink<'_, D> {
+    fn create(&mut self, name: &str, _size: Option<u32>) -> Result<(), TransferError> {
+        let mut buf = [0u8; MAX_PATH];
+        let path = match name.is_empty() {
+            true => self.path,
+            false => {
+                let dir = self.path.trim_end_matches('/');
+                let len = dir.len() + 1 + name.len();
+                if len > MAX_PATH {
+                    return Err(FatError::InvalidName.into());
+                }
+                buf[..dir.len()].copy_from_slice(dir.as_bytes());
+                buf[dir.len()] = b'/';
+                buf[dir.len() + 1..len].copy_from_slice(name.as_bytes());
+                core::str::from_utf8(&buf[..len]).unwrap()
+            }
+        };
+        self.file = Some(self.fs.create(path)?);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), TransferError> {
+        let file = self.file.as_mut().ok_or(FatError::NotFound)?;
+        Ok(self.fs.write(file, data)?)
+    }
+
+    fn finish(&mut self) -> Result<(), TransferError> {
+        self.file = None;
+        Ok(self.fs.flush()?)
+    }
+}
+
+/// files sent from a filesystem, under the last component of their paths
+pub struct FatSource<'a, D: BlockDevice> {
+    fs: &'a mut FileSystem<D>,
+    paths: &'a [&'a str],
+    next: usize,
+    file: Option<File>,
+}
+
+impl<'a, D: BlockDevice> FatSource<'a, D> {
+    pub fn new(fs: &'a mut FileSystem<D>, paths: &'a [&'a str]) -> FatSource<'a, D> {
+        FatSource {
+            fs,
+            paths,
+            next: 0,
+            file: None,
+        }
+    }
+}
+
+impl<D: BlockDevice> Source for FatSource<'_, D> {
+    fn next_file(&mut self) -> Result<Option<(&str, u32)>, TransferError> {
+        let Some(path) = self.paths.get(self.next) else {
+            return Ok(None);
+        };
+        self.next += 1;
+        let file = self.fs.open(path)?;
+        let size = file.size();
+        self.file = Some(file);
+        let name = path.trim_end_matches('/').rsplit('/').next().unwrap();
+        Ok(Some((name, size)))
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, TransferError> {
+        match self.file.as_mut() {
+            Some(file) => Ok(self.fs.read(file, buf)?),
+            None => Ok(0),
+        }
+    }
+}
+
+enum Packet {
+    Data { number: u8, len: usize },
+    End,
+}
+
+enum PacketError {
+    Timeout,
+    /// bad complement or CRC, or cut short
+    Corrupt,
+    Cancelled,
+}
+
+fn read_byte<S: Serial + ?Sized>(serial: &mut S, timeout_us: u64) -> Option<u8> {
+    let timeout = Timeout::after_us(timeout_us);
+    loop {
+        if let Some(byte) = serial.read_byte() {
+            return Some(byte);
+        }
+        if timeout.expired() {
+            return None;
+        }
+    }
+}
+
+fn cancel<S: Serial + ?Sized>(serial: &mut S) {
+    serial.write_bytes(&[CAN; 3]);
+    serial.flush();
+}
+
+/// a single CAN may be line noise, two in a row cancel
+fn cancelled<S: Serial + ?Sized>(serial: &mut S) -> bool {
+    read_byte(serial, BYTE_TIMEOUT_US) == Some(CAN)
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+struct Receiver<'a, S: Serial + ?Sized> {
+    serial: &'a mut S,
+    crc: bool,
+    packet: [u8; 1024],
+    /// last data block, held back until it is known whether padding has to be removed
+    held: [u8; 1024],
+}
+
+impl<'a, S: Serial + ?Sized> Receiver<'a, S> {
+    fn new(serial: &'a mut S) -> Self {
+        Receiver {
+            serial,
+            crc: true,
+            packet: [0; 1024],
+            held: [0; 1024],
+        }
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.serial.write_byte(byte);
+        self.serial.flush();
+    }
+
+    fn packet(&mut self, timeout_us: u64) -> Result<Packet, PacketError> {
+        // anything but a header is skipped, it is noise or the tail of a lost packet
+        let len = loop {
+            match read_byte(self.serial, timeout_us).ok_or(PacketError::Timeout)? {
+                SOH => break 128,
+                STX => break 1024,
+                EOT => return Ok(Packet::End),
+                CAN if cancelled(self.serial) => return Err(PacketError::Cancelled),
+                _ => {}
+            }
+        };
+        let mut next = || read_byte(self.serial, BYTE_TIMEOUT_US).ok_or(PacketError::Corrupt);
+        let number = next()?;
+        let complement = next()?;
+        for i in 0..len {
+            self.packet[i] = next()?;
+        }
+        let valid = match self.crc {
+            true => {
+                let crc = u16::from_be_bytes([next()?, next()?]);
+                crc == crc16::checksum(&self.packet[..len])
+            }
+            false => next()? == checksum(&self.packet[..len]),
+        };
+        match valid && number == !complement {
+            true => Ok(Packet::Data { number, len }),
+            false => Err(PacketError::Corrupt),
+        }
+    }
+
+    /// request packets until the first one arrives, block 0 for YMODEM and 1 for XMODEM
+    fn start(&mut self, fallback: bool) -> Result<Packet, TransferError> {
+        for attempt in 1..=START_RETRIES {
+            self.send(if self.crc { CRC } else { NAK });
+            match self.packet(START_TIMEOUT_US) {
+                Ok(packet) => return Ok(packet),
+                Err(PacketError::Cancelled) => return Err(TransferError::Cancelled),
+                Err(_) if fallback && attempt == CRC_RETRIES => self.crc = false,
+                Err(_) => {}
+            }
+        }
+        cancel(self.serial);
+        Err(TransferError::Timeout)
+    }
+
+    fn fail<T>(&mut self, error: TransferError) -> Result<T, TransferError> {
+        cancel(self.serial);
+        Err(error)
+    }
+
+    /// data blocks of one file up to EOT, returns the number of bytes stored
+    ///
+    /// Without a known `size` the padding of the last block is removed. YMODEM senders expect
+    /// their first EOT to be refused.
+    fn file<K: Sink + ?Sized>(
+        &mut self,
+        sink: &mut K,
+        size: Option<u32>,
+        ymodem: bool,
+    ) -> Result<u32, TransferError> {
+        let mut packet = self.start(!ymodem)?;
+        let mut expected = 1u8;
+        let mut held = 0usize;
+        let mut stored = 0u32;
+        let mut errors = 0;
+        let mut refused_eot = false;
+        loop {
+            match packet {
+                Packet::Data { number, len } => {
+                    errors = 0;
+                    if number == expected {
+                        stored += self.store(sink, held, size, stored)?;
+                        self.held[..len].copy_from_slice(&self.packet[..len]);
+                        held = len;
+                        expected = expected.wrapping_add(1);
+                    } else if number != expected.wrapping_sub(1) {
+                        return self.fail(TransferError::OutOfSync);
+                    }
+                    // repeated blocks lost their ACK and are acknowledged again
+                    self.send(ACK);
+                }
+                Packet::End if ymodem && !refused_eot => {
+                    refused_eot = true;
+                    self.send(NAK);
+                }
+                Packet::End => {
+                    if size.is_none() {
+                        held = self.held[..held]
+                            .iter()
+                            .rposition(|byte| *byte != SUB)
+                            .map_or(0, |i| i + 1);
+                    }
+                    stored += self.store(sink, held, size, stored)?;
+                    self.send(ACK);
+                    return Ok(stored);
+                }
+            }
+            packet = loop {
+                match self.packet(PACKET_TIMEOUT_US) {
+                    Ok(packet) => break packet,
+                    Err(PacketError::Cancelled) => return Err(TransferError::Cancelled),
+                    Err(_) if errors == RETRIES => return self.fail(TransferError::Timeout),
+                    Err(_) => {
+                        errors += 1;
+                        self.send(NAK);
+                    }
+                }
+            };
+        }
+    }
+
+    /// the held block, cut at `size`
+    fn store<K: Sink + ?Sized>(
+        &mut self,
+        sink: &mut K,
+        held: usize,
+        size: Option<u32>,
+        stored: u32,
+    ) -> Result<u32, TransferError> {
+        let len = match size {
+            Some(size) => held.min((size - stored) as usize),
+            None => held,
+        };
+        if let Err(error) = sink.write(&self.held[..len]) {
+            return self.fail(error);
+        }
+        Ok(len as u32)
+    }
+}
+
+/// receive one file with XMODEM-CRC or XMODEM-1K, returns its size without padding
+pub fn receive_xmodem<S: Serial + ?Sized, K: Sink + ?Sized>(
+    serial: &mut S,
+    sink: &mut K,
+) -> Result<u32, TransferError> {
+    let mut receiver = Receiver::new(serial);
+    sink.create("", None)?;
+    let size = receiver.file(sink, None, false)?;
+    sink.finish()?;
+    Ok(size)
+}
+
+/// receive a YMODEM batch, returns the number of files
+pub fn receive_ymodem<S: Serial + ?Sized, K: Sink + ?Sized>(
+    serial: &mut S,
+    sink: &mut K,
+) -> Result<usize, TransferError> {
+    let mut receiver = Receiver::new(serial);
+    let mut files = 0;
+    loop {
+        let len = match receiver.start(false)? {
+            Packet::Data { number: 0, len } => len,
+            Packet::Data { .. } => return receiver.fail(TransferError::OutOfSync),
+            // a lost final ACK, the sender repeats its EOT
+            Packet::End => {
+                receiver.send(ACK);
+                continue;
+            }
+        };
+        receiver.send(ACK);
+        let header = &receiver.packet[..len];
+        if header[0] == 0 {
+            return Ok(files);
+        }
+        let (name, size) = match parse_header(header) {
+            Some(header) => header,
+            None => return receiver.fail(TransferError::InvalidHeader),
+        };
+        if name.len() > MAX_NAME {
+            return receiver.fail(TransferError::NameTooLong);
+        }
+        // the name borrows the packet buffer, which the next packets overwrite
+        let mut buf = [0u8; MAX_NAME];
+        buf[..name.len()].copy_from_slice(name.as_bytes());
+        let Ok(name) = core::str::from_utf8(&buf[..name.len()]) else {
+            return receiver.fail(TransferError::InvalidHeader);
+        };
+        if let Err(error) = sink.create(name, size) {
+            return receiver.fail(error);
+        }
+        receiver.file(sink, size, true)?;
+        sink.finish()?;
+        files += 1;
+    }
+}
+
+/// file name without directories and the size, if the sender gave one
+fn parse_header(header: &[u8]) -> Option<(&str, Option<u32>)> {
+    let end = header.iter().position(|byte| *byte == 0)?;
+    let name = core::str::from_utf8(&header[..end]).ok()?;
+    let name = name.rsplit('/').next().filter(|name| !name.is_empty())?;
+    let digits = header[end + 1..]
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit());
+    let mut size = None;
+    for digit in digits {
+        let value = size.unwrap_or(0u32);
+        size = Some(value.checked_mul(10)?.checked_add((digit - b'0') as u32)?);
+    }
+    Some((name, size))
+}
+
+struct Sender<'a, S: Serial + ?Sized> {
+    serial: &'a mut S,
+    crc: bool,
+    buf: [u8; 1024],
+}
+
+impl<'a, S: Serial + ?Sized> Sender<'a, S> {
+    fn new(serial: &'a mut S) -> Self {
+        Sender {
+            serial,
+            crc: true,
+            buf: [0; 1024],
+        }
+    }
+
+    /// wait for the receiver to ask for a file with `C`, or `NAK` for checksums
+    fn start(&mut self) -> Result<(), TransferError> {
+        for _ in 0..START_RETRIES {
+            match read_byte(self.serial, START_TIMEOUT_US) {
+                Some(CRC) => self.crc = true,
+                Some(NAK) => self.crc = false,
+                Some(CAN) if cancelled(self.serial) => return Err(TransferError::Cancelled),
+                _ => continue,
+            }
+            return Ok(());
+        }
+        Err(TransferError::Timeout)
+    }
+
+    /// ACK, NAK or a timeout as None
+    fn response(&mut self) -> Result<Option<u8>, TransferError> {
+        let timeout = Timeout::after_us(PACKET_TIMEOUT_US);
+        loop {
+            match self.serial.read_byte() {
+                Some(byte @ (ACK | NAK)) => return Ok(Some(byte)),
+                Some(CAN) if cancelled(self.serial) => return Err(TransferError::Cancelled),
+                _ if timeout.expired() => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+
+    /// send the first `len` bytes of the buffer until they are acknowledged
+    fn packet(&mut self, number: u8, len: usize) -> Result<(), TransferError> {
+        let header = if len == 1024 { STX } else { SOH };
+        for _ in 0..RETRIES {
+            let data = &self.buf[..len];
+            self.serial.write_bytes(&[header, number, !number]);
+            self.serial.write_bytes(data);
+            match self.crc {
+                true => self
+                    .serial
+                    .write_bytes(&crc16::checksum(data).to_be_bytes()),
+                false => self.serial.write_byte(checksum(data)),
+            }
+            self.serial.flush();
+            if self.response()? == Some(ACK) {
+                return Ok(());
+            }
+        }
+        cancel(self.serial);
+        Err(TransferError::Timeout)
+    }
+
+    /// data blocks of the current file and the final EOT, returns the bytes sent
+    fn file<R: Source + ?Sized>(
+        &mut self,
+        source: &mut R,
+        size: PacketSize,
+    ) -> Result<u32, TransferError> {
+        let chunk = match size {
+            PacketSize::Normal => 128,
+            PacketSize::OneK => 1024,
+        };
+        let mut number = 1u8;
+        let mut sent = 0u32;
+        loop {
+            let mut len = 0;
+            while len < chunk {
+                match source.read(&mut self.buf[len..chunk]) {
+                    Ok(0) => break,
+                    Ok(read) => len += read,
+                    Err(error) => {
+                        cancel(self.serial);
+                        return Err(error);
+                    }
+                }
+            }
+            if len == 0 {
+                break;
+            }
+            let packet = if len <= 128 { 128 } else { 1024 };
+            self.buf[len..packet].fill(SUB);
+            self.packet(number, packet)?;
+            number = number.wrapping_add(1);
+            sent += len as u32;
+        }
+        for _ in 0..RETRIES {
+            self.serial.write_byte(EOT);
+            self.serial.flush();
+            if self.response()? == Some(ACK) {
+                return Ok(sent);
+            }
+        }
+        Err(TransferError::Timeout)
+    }
+}
+
+/// send the next file of `source` with XMODEM-CRC, or XMODEM-1K with [`PacketSize::OneK`]
+///
+/// Receivers asking for checksums instead of CRC-16 are served as well.
+pub fn send_xmodem<S: Serial + ?Sized, R: Source + ?Sized>(
+    serial: &mut S,
+    source: &mut R,
+    size: PacketSize,
+) -> Result<u32, TransferError> {
+    let mut sender = Sender::new(serial);
+    if source.next_file()?.is_none() {
+        return Ok(0);
+    }
+    sender.start()?;
+    sender.file(source, size)
+}
+
+/// send all files of `source` as a YMODEM batch, returns the number of files
+pub fn send_ymodem<S: Serial + ?Sized, R: Source + ?Sized>(
+    serial: &mut S,
+    source: &mut R,
+) -> Result<usize, TransferError> {
+    let mut sender = Sender::new(serial);
+    let mut files = 0;
+    loop {
+        sender.start()?;
+        let Some((name, size)) = source.next_file()? else {
+            // an empty file header ends the batch
+            sender.buf[..128].fill(0);
+            sender.packet(0, 128)?;
+            return Ok(files);
+        };
+        let mut header = [0u8; 1024];
+        let mut len = 0;
+        let mut push = |bytes: &[u8]| {
+            let end = (len + bytes.len()).min(header.len());
+            header[len..end].copy_from_slice(&bytes[..end - len]);
+            len = end;
+        };
+        push(name.as_bytes());
+        push(&[0]);
+        let mut digits = [0u8; 10];
+        let mut value = size;
+        let mut count = 0;
+        loop {
+            digits[9 - count] = b'0' + (value % 10) as u8;
+            value /= 10;
+            count += 1;
+            if value == 0 {
+                break;
+            }
+        }
+        push(&digits[10 - count..]);
+        // the header always ends with a zero, it is sent as 128 bytes when it fits
+        let packet = if len < 128 { 128 } else { 1024 };
+        if len >= packet {
+            cancel(sender.serial);
+            return Err(TransferError::InvalidHeader);
+        }
+        sender.buf[..packet].copy_from_slice(&header[..packet]);
+        sender.packet(0, packet)?;
+        sender.start()?;
+        sender.file(source, PacketSize::OneK)?;
+        files += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockSerial;
+
+    fn packet(crc: bool, number: u8, data: &[u8], len: usize, pad: u8) -> Vec<u8> {
+        let mut block = data.to_vec();
+        block.resize(len, pad);
+        let header = if len == 1024 { STX } else { SOH };
+        let mut packet = vec![header, number, !number];
+        packet.extend_from_slice(&block);
+        match crc {
+            true => packet.extend(crc16::checksum(&block).to_be_bytes()),
+            false => packet.push(checksum(&block)),
+        }
+        packet
+    }
+
+    #[test]
+    fn xmodem_receive() {
+        let data: Vec<u8> = (0..300u32).map(|i| i as u8).collect();
+        let mut rx = b"noise".to_vec();
+        rx.extend(packet(true, 1, &data[..128], 128, SUB));
+        // corrupt retransmission of block 2 followed by a good one
+        let mut bad = packet(true, 2, &data[128..256], 128, SUB);
+        bad[10] ^= 0xff;
+        rx.extend(bad);
+        rx.extend(packet(true, 2, &data[128..256], 128, SUB));
+        // block 2 again, as if its ACK was lost
+        rx.extend(packet(true, 2, &data[128..256], 128, SUB));
+        rx.extend(packet(true, 3, &data[256..], 1024, SUB));
+        rx.push(EOT);
+        let mut serial = MockSerial::new(&rx);
+        let mut buf = [0u8; 1024];
+        let mut sink = RamSink::new(&mut buf);
+
+        assert_eq!(receive_xmodem(&mut serial, &mut sink), Ok(300));
+        assert_eq!(sink.data(), &data[..]);
+        assert_eq!(serial.tx, [CRC, ACK, NAK, ACK, ACK, ACK, ACK]);
+    }
+
+    #[test]
+    fn xmodem_checksum_fallback() {
+        // the sender ignores the first requests for CRC-16
+        let mut serial = MockSerial::new(&[]);
+        let mut buf = [0u8; 256];
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(
+            receive_xmodem(&mut serial, &mut sink),
+            Err(TransferError::Timeout)
+        );
+        assert_eq!(&serial.tx[..4], [CRC, CRC, CRC, NAK]);
+        assert_eq!(serial.tx.len(), START_RETRIES as usize + 3);
+
+        let mut receiver_serial = MockSerial::new(&[]);
+        let mut receiver = Receiver::new(&mut receiver_serial);
+        receiver.crc = false;
+        receiver
+            .serial
+            .rx
+            .extend(packet(false, 1, b"hello", 128, SUB));
+        receiver.serial.rx.push_back(EOT);
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(receiver.file(&mut sink, None, false), Ok(5));
+        assert_eq!(sink.data(), b"hello");
+        assert_eq!(receiver_serial.tx, [NAK, ACK, ACK]);
+    }
+
+    #[test]
+    fn xmodem_send() {
+        let data: Vec<u8> = (0..1200u32).map(|i| (i * 7) as u8).collect();
+        let files = [("", &data[..])];
+
+        // a NAK is answered by retransmission
+        let mut serial = MockSerial::new(&[CRC, NAK, ACK, ACK, ACK]);
+        let mut source = RamSource::new(&files);
+        assert_eq!(
+            send_xmodem(&mut serial, &mut source, PacketSize::OneK),
+            Ok(1200)
+        );
+        let first = packet(true, 1, &data[..1024], 1024, SUB);
+        let mut expected = first.clone();
+        expected.extend(first);
+        expected.extend(packet(true, 2, &data[1024..], 1024, SUB));
+        expected.push(EOT);
+        assert_eq!(serial.tx, expected);
+
+        // checksum receivers get 128 byte blocks with a sum
+        let mut serial = MockSerial::new(&[NAK, ACK]);
+        serial.rx.extend([ACK; 10]);
+        let mut source = RamSource::new(&files);
+        assert_eq!(
+            send_xmodem(&mut serial, &mut source, PacketSize::Normal),
+            Ok(1200)
+        );
+        assert_eq!(&serial.tx[..132], packet(false, 1, &data[..128], 128, SUB));
+        assert_eq!(serial.tx.len(), 10 * 132 + 1);
+
+        // a receiver giving up
+        let mut serial = MockSerial::new(&[CRC, CAN, CAN]);
+        let mut source = RamSource::new(&files);
+        assert_eq!(
+            send_xmodem(&mut serial, &mut source, PacketSize::OneK),
+            Err(TransferError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn ymodem() {
+        let first: Vec<u8> = (0..1100u32).map(|i| i as u8).collect();
+        let files = [
+            ("boot/kernel8.img", &first[..]),
+            ("config.txt", b"arm_64bit=1\n"),
+        ];
+        let responses = [
+            vec![CRC, ACK, CRC, ACK, ACK, NAK, ACK],
+            vec![CRC, ACK, CRC, ACK, NAK, ACK],
+            vec![CRC, ACK],
+        ]
+        .concat();
+
+        // the receiver's responses drive the sender, and the sender's packets the receiver
+        let mut sender = MockSerial::new(&responses);
+        let mut source = RamSource::new(&files);
+        assert_eq!(send_ymodem(&mut sender, &mut source), Ok(2));
+        assert_eq!(&sender.tx[3..24], b"boot/kernel8.img\x001100");
+
+        let mut receiver = MockSerial::new(&sender.tx);
+        let mut buf = [0u8; 2048];
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(receive_ymodem(&mut receiver, &mut sink), Ok(2));
+        assert_eq!(receiver.tx, responses);
+        assert_eq!(sink.files(), 2);
+        assert_eq!(&sink.data()[..1100], &first[..]);
+        assert_eq!(&sink.data()[1100..], b"arm_64bit=1\n");
+
+        assert_eq!(
+            parse_header(b"dir/name.bin\x00123 1234567 644\x00"),
+            Some(("name.bin", Some(123)))
+        );
+        assert_eq!(parse_header(b"name\x00\x00"), Some(("name", None)));
+        assert_eq!(parse_header(b"dir/\x00"), None);
+    }
+
+    #[test]
+    fn errors() {
+        let mut buf = [0u8; 100];
+
+        // a name longer than the receiver keeps, ending in a multibyte character
+        let mut header = "é".repeat(MAX_NAME / 2 + 1).into_bytes();
+        header.extend(b"\x0010\x00");
+        let mut serial = MockSerial::new(&packet(true, 0, &header, 1024, 0));
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(
+            receive_ymodem(&mut serial, &mut sink),
+            Err(TransferError::NameTooLong)
+        );
+        assert_eq!(serial.tx, [CRC, ACK, CAN, CAN, CAN]);
+        assert_eq!(sink.files(), 0);
+
+        // out of order block
+        let mut serial = MockSerial::new(&packet(true, 2, b"x", 128, SUB));
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(
+            receive_xmodem(&mut serial, &mut sink),
+            Err(TransferError::OutOfSync)
+        );
+        assert_eq!(serial.tx, [CRC, CAN, CAN, CAN]);
+
+        // more data than the sink takes
+        let mut serial = MockSerial::new(&packet(true, 1, b"x", 128, SUB));
+        serial.rx.extend(packet(true, 2, b"x", 128, SUB));
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(
+            receive_xmodem(&mut serial, &mut sink),
+            Err(TransferError::Full)
+        );
+
+        // sender cancels
+        let mut serial = MockSerial::new(&packet(true, 1, b"x", 128, SUB));
+        serial.rx.extend([CAN, CAN]);
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(
+            receive_xmodem(&mut serial, &mut sink),
+            Err(TransferError::Cancelled)
+        );
+
+        // sender stops after a block
+        let mut serial = MockSerial::new(&packet(true, 1, b"x", 128, SUB));
+        let mut sink = RamSink::new(&mut buf);
+        assert_eq!(
+            receive_xmodem(&mut serial, &mut sink),
+            Err(TransferError::Timeout)
+        );
+        assert_eq!(serial.tx.len(), 2 + RETRIES as usize + 3);
+    }
+
+    #[test]
+    fn filesystem() {
+        use crate::block::RamDisk;
+        use crate::fat::tests::mkfs;
+
+        let mut image = mkfs(2048, 64);
+        let mut fs = FileSystem::mount(RamDisk::new(&mut image, 512)).unwrap();
+        fs.create_dir("/upload").unwrap();
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+
+        let files = [("a long file name.bin", &data[..])];
+        let mut sender = MockSerial::new(&[CRC, ACK, CRC, ACK, ACK, ACK, NAK, ACK, CRC, ACK]);
+        assert_eq!(send_ymodem(&mut sender, &mut RamSource::new(&files)), Ok(1));
+
+        let mut receiver = MockSerial::new(&sender.tx);
+        let mut sink = FatSink::new(&mut fs, "/upload/");
+        assert_eq!(receive_ymodem(&mut receiver, &mut sink), Ok(1));
+        let mut file = fs.open("/upload/a long file name.bin").unwrap();
+        assert_eq!(file.size(), 3000);
+        let mut read = vec![0u8; 3000];
+        assert_eq!(fs.read(&mut file, &mut read), Ok(3000));
+        assert_eq!(read, data);
+
+        // and back from the filesystem
+        let paths = ["/upload/a long file name.bin"];
+        let mut sender = MockSerial::new(&[CRC, ACK, CRC, ACK, ACK, ACK, NAK, ACK, CRC, ACK]);
+        let mut source = FatSource::new(&mut fs, &paths);
+        assert_eq!(send_ymodem(&mut sender, &mut source), Ok(1));
+        assert_eq!(&sender.tx[3..28], b"a long file name.bin\x003000");
+    }
+}
@@ -0,0 +1,245 @@
+//! Sends a kernel to the serial chainloader of the `chainload` kernel feature.
+//!
+//! ```text
+//! chainload [--baud RATE] [--load-address ADDRESS] [--terminal] PORT IMAGE
+//! ```
+//!
+//! PORT is a serial device, configured with `stty`, or the unix socket of QEMU's
+//! `-serial unix:...`. IMAGE is an ELF file, whose loadable segments are sent as one image
+//! loaded at the lowest of them, or a raw binary loaded at `--load-address`. With
+//! `--terminal` the port is connected to stdin and stdout once the kernel is booted.
+use raspi4b::chainload::{ACK, ChainloadError, HEADER_LEN, Header, MAX_IMAGE, NAK, REQUEST};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::process::{Command, ExitCode};
+
+/// raw binaries are loaded where the firmware puts kernel8.img
+const DEFAULT_LOAD_ADDRESS: u64 = 0x8_0000;
+const DEFAULT_BAUD: u32 = 115_200;
+const CHUNK: usize = 4096;
+
+struct Options {
+    baud: u32,
+    load_address: u64,
+    terminal: bool,
+    port: String,
+    image: String,
+}
+
+type Port = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+fn usage() -> String {
+    "usage: chainload [--baud RATE] [--load-address ADDRESS] [--terminal] PORT IMAGE".into()
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number {value}"))
+}
+
+fn options() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut baud = DEFAULT_BAUD;
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut terminal = false;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baud" => baud = parse_number(&args.next().ok_or_else(usage)?)? as u32,
+            "--load-address" => load_address = parse_number(&args.next().ok_or_else(usage)?)?,
+            "--terminal" => terminal = true,
+            "-h" | "--help" => return Err(usage()),
+            _ => positional.push(arg),
+        }
+    }
+    let [port, image] = <[String; 2]>::try_from(positional).map_err(|_| usage())?;
+    Ok(Options {
+        baud,
+        load_address,
+        terminal,
+        port,
+        image,
+    })
+}
+
+/// loadable segments of an ELF64 file flattened into one image, with its load address
+fn flatten_elf(elf: &[u8]) -> Result<(Vec<u8>, u64), String> {
+    let u16_at = |offset: usize| u16::from_le_bytes(elf[offset..offset + 2].try_into().unwrap());
+    let u32_at = |offset: usize| u32::from_le_bytes(elf[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(elf[offset..offset + 8].try_into().unwrap());
+    if elf.len() < 64 || elf[4] != 2 || elf[5] != 1 {
+        return Err("only little-endian ELF64 images are supported".into());
+    }
+    let entry = u64_at(0x18);
+    let (phoff, phentsize, phnum) = (u64_at(0x20) as usize, u16_at(0x36), u16_at(0x38));
+
+    // (address, file data, size in memory) of each PT_LOAD segment
+    let mut segments = Vec::new();
+    for i in 0..phnum as usize {
+        let ph = phoff + i * phentsize as usize;
+        if elf.len() < ph + 56 {
+            return Err("truncated program header".into());
+        }
+        let (offset, address) = (u64_at(ph + 8) as usize, u64_at(ph + 0x18));
+        let (file_size, memory_size) = (u64_at(ph + 0x20) as usize, u64_at(ph + 0x28));
+        if u32_at(ph) != 1 || memory_size == 0 {
+            continue;
+        }
+        let data = elf
+            .get(offset..offset + file_size)
+            .ok_or("segment outside of the file")?;
+        segments.push((address, data, memory_size));
+    }
+    let base = segments
+        .iter()
+        .map(|s| s.0)
+        .min()
+        .ok_or("no loadable segments")?;
+    let end = segments.iter().map(|s| s.0 + s.2).max().unwrap();
+    if entry != base {
+        return Err(format!(
+            "entry point {entry:#x} is not at the start of the image {base:#x}"
+        ));
+    }
+    if end - base > MAX_IMAGE as u64 {
+        return Err(format!("image of {} bytes is too large", end - base));
+    }
+    // gaps and .bss are sent as zeros
+    let mut image = vec![0u8; (end - base) as usize];
+    for (address, data, _) in segments {
+        let start = (address - base) as usize;
+        image[start..start + data.len()].copy_from_slice(data);
+    }
+    Ok((image, base))
+}
+
+fn open(options: &Options) -> Result<Port, String> {
+    let path = &options.port;
+    let kind = std::fs::metadata(path)
+        .map_err(|e| format!("{path}: {e}"))?
+        .file_type();
+    if kind.is_socket() {
+        let stream = UnixStream::connect(path).map_err(|e| format!("{path}: {e}"))?;
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+        return Ok((Box::new(reader), Box::new(stream)));
+    }
+    let status = Command::new("stty")
+        .args([
+            "-F",
+            path,
+            &options.baud.to_string(),
+            "raw",
+            "-echo",
+            "-crtscts",
+        ])
+        .status()
+        .map_err(|e| format!("stty: {e}"))?;
+    if !status.success() {
+        return Err(format!("stty could not configure {path}"));
+    }
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("{path}: {e}"))?;
+    let reader = file.try_clone().map_err(|e| e.to_string())?;
+    Ok((Box::new(reader), Box::new(file)))
+}
+
+fn read_byte(reader: &mut dyn Read) -> Result<u8, String> {
+    let mut byte = [0u8];
+    reader
+        .read_exact(&mut byte)
+        .map_err(|e| format!("port: {e}"))?;
+    Ok(byte[0])
+}
+
+/// wait for the response to a header or image
+fn response(reader: &mut dyn Read, step: &str) -> Result<(), String> {
+    let answer = [read_byte(reader)?, read_byte(reader)?];
+    match answer {
+        ACK => Ok(()),
+        [NAK, code] => Err(match ChainloadError::from_code(code) {
+            Some(error) => format!("{step} rejected: {error:?}"),
+            None => format!("{step} rejected with code {code}"),
+        }),
+        _ => Err(format!("unexpected response {answer:02x?} to {step}")),
+    }
+}
+
+fn upload(port: &mut Port, image: &[u8], load_address: u64) -> Result<(), String> {
+    let (reader, writer) = port;
+    let header = Header::new(image, load_address);
+    eprintln!(
+        "waiting for the chainloader, {} bytes for {load_address:#x}, crc32 {:08x}",
+        image.len(),
+        header.crc
+    );
+
+    // output of the board before the request is passed through
+    let mut last = [0u8; 3];
+    let mut stdout = io::stdout();
+    while last != REQUEST {
+        let byte = read_byte(reader.as_mut())?;
+        if byte != REQUEST[0] {
+            let _ = stdout.write_all(&[byte]);
+        }
+        last = [last[1], last[2], byte];
+    }
+    let _ = stdout.flush();
+
+    let bytes: [u8; HEADER_LEN] = header.to_bytes();
+    writer.write_all(&bytes).map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+    response(reader.as_mut(), "header")?;
+
+    for (i, chunk) in image.chunks(CHUNK).enumerate() {
+        writer.write_all(chunk).map_err(|e| e.to_string())?;
+        eprint!("\r{:3}%", ((i * CHUNK + chunk.len()) * 100) / image.len());
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    eprintln!();
+    response(reader.as_mut(), "image")?;
+    eprintln!("booting");
+    Ok(())
+}
+
+/// connect the port to stdin and stdout until either side closes
+fn terminal(port: Port) -> ! {
+    let (mut reader, mut writer) = port;
+    std::thread::spawn(move || {
+        let _ = io::copy(&mut io::stdin(), &mut writer);
+    });
+    let _ = io::copy(&mut reader, &mut io::stdout());
+    std::process::exit(0)
+}
+
+fn run() -> Result<(), String> {
+    let options = options()?;
+    let data = std::fs::read(&options.image).map_err(|e| format!("{}: {e}", options.image))?;
+    let (image, load_address) = match data.starts_with(b"\x7fELF") {
+        true => flatten_elf(&data)?,
+        false => (data, options.load_address),
+    };
+    let mut port = open(&options)?;
+    upload(&mut port, &image, load_address)?;
+    if options.terminal {
+        terminal(port);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("chainload: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,37 @@
+#!/bin/sh
+# FAT12, FAT16 and FAT32 images for the tests of src/fat.rs, made with mkfs.fat
+# (dosfstools 4.2 or later) and mtools, written gzip compressed to src/testdata:
+#
+#   tools/fat-images.sh
+#
+# The files put on every image are checked by `fat::tests::mkfs_fat_images`.
+set -eu
+
+out=$(cd "$(dirname "$0")/.." && pwd)/src/testdata
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+export MTOOLS_SKIP_CHECK=1
+
+mkdir -p "$out" "$tmp/files"
+cd "$tmp/files"
+printf 'hello, world\n' > HELLO.TXT
+printf 'long file name\n' > 'Long File Name.txt'
+# 20000 bytes, several clusters
+seq 0 3999 | awk '{ printf "%04d\n", $1 }' > NUMBERS.TXT
+printf 'arm_64bit=1\n' > CONFIG.TXT
+touch -d '2024-01-01 12:00:00' ./*
+
+# name, FAT size, image size in KiB, all with 1 sector clusters and a volume label
+image() {
+    img="$tmp/$1.img"
+    label=$(echo "$1" | tr a-z A-Z)
+    mkfs.fat --invariant -F "$2" -s 1 -n "$label" -C "$img" "$3" > /dev/null
+    mcopy -m -i "$img" HELLO.TXT 'Long File Name.txt' NUMBERS.TXT ::
+    mmd -i "$img" ::BOOT
+    mcopy -m -i "$img" CONFIG.TXT ::BOOT/CONFIG.TXT
+    gzip -9n < "$img" > "$out/$1.img.gz"
+}
+
+image fat12 12 1024
+image fat16 16 8192
+image fat32 32 33792